@@ -1,3 +1,146 @@
-fn main() {
-    println!("Hello, world!");
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::ExitCode;
+
+// 【AGW 运维命令行】
+// 直接访问数据面的管理端口 (AGW_ADMIN_ADDR，默认 127.0.0.1:9901)，不经过 Control Plane。
+//
+//   cli override list
+//   cli override set <cluster> [endpoint] [--ttl SECS] [--reason TEXT]
+//   cli override clear <cluster> [endpoint]
+//
+// 不带 endpoint 表示 drain 整个集群。全局参数 --admin ADDR 可以覆盖管理端口地址。
+const USAGE: &str = "usage:
+  cli [--admin ADDR] override list
+  cli [--admin ADDR] override set <cluster> [endpoint] [--ttl SECS] [--reason TEXT]
+  cli [--admin ADDR] override clear <cluster> [endpoint]";
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let admin = take_flag(&mut args, "--admin")
+        .or_else(|| std::env::var("AGW_ADMIN_ADDR").ok())
+        .unwrap_or_else(|| "127.0.0.1:9901".to_string());
+
+    let mut rest = args.split_off(1.min(args.len()));
+    let request = match args.first().map(String::as_str) {
+        Some("override") => override_request(&mut rest),
+        _ => Err(USAGE.to_string()),
+    };
+    let (method, body) = match request {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+    match send(&admin, method, "/override/endpoints", body.as_deref()) {
+        Ok((status, body)) => {
+            print!("{}", body);
+            if !body.ends_with('\n') {
+                println!();
+            }
+            if (200..300).contains(&status) {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("admin API {} unreachable: {}", admin, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// 解析 override 子命令，返回 (HTTP 方法, 请求体)
+fn override_request(args: &mut Vec<String>) -> Result<(&'static str, Option<String>), String> {
+    let ttl = take_flag(args, "--ttl");
+    let reason = take_flag(args, "--reason");
+    let (action, rest) = args.split_first().ok_or(USAGE)?;
+    match (action.as_str(), rest) {
+        ("list", []) => Ok(("GET", None)),
+        ("set", [cluster]) | ("set", [cluster, _]) => {
+            let mut fields = vec![("cluster", json_string(cluster))];
+            if let Some(endpoint) = rest.get(1) {
+                fields.push(("endpoint", json_string(endpoint)));
+            }
+            if let Some(ttl) = ttl {
+                let secs: u64 = ttl.parse().map_err(|_| format!("invalid --ttl {:?}", ttl))?;
+                fields.push(("ttl_secs", secs.to_string()));
+            }
+            if let Some(reason) = reason {
+                fields.push(("reason", json_string(&reason)));
+            }
+            Ok(("POST", Some(json_object(&fields))))
+        }
+        ("clear", [cluster]) | ("clear", [cluster, _]) => {
+            let mut fields = vec![("cluster", json_string(cluster))];
+            if let Some(endpoint) = rest.get(1) {
+                fields.push(("endpoint", json_string(endpoint)));
+            }
+            Ok(("DELETE", Some(json_object(&fields))))
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+// 取出 "--name value" 形式的参数并从 args 中移除
+fn take_flag(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let pos = args.iter().position(|a| a == name)?;
+    if pos + 1 >= args.len() {
+        return None;
+    }
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    Some(value)
+}
+
+fn json_object(fields: &[(&str, String)]) -> String {
+    let body: Vec<String> = fields
+        .iter()
+        .map(|(k, v)| format!("{}:{}", json_string(k), v))
+        .collect();
+    format!("{{{}}}", body.join(","))
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// 最小的 HTTP/1.1 客户端：一次请求一条连接 (Connection: close)，读到 EOF 为止
+fn send(addr: &str, method: &str, path: &str, body: Option<&str>) -> std::io::Result<(u16, String)> {
+    let mut stream = TcpStream::connect(addr)?;
+    let body = body.unwrap_or("");
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        body.len(),
+        body
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((response.as_str(), ""));
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    Ok((status, body.to_string()))
 }
@@ -123,6 +123,9 @@ func (s *AgwServer) broadcastMerged() {
 		Clusters: staticCfg.Clusters,
 		// 【合并资源】：Redis 和数据库配置 (直接引用静态配置，因为目前 K8s 侧没有对应 CRD)
 		Resources: staticCfg.Resources,
+		// 透传 "允许大幅缩减" 标记，否则数据面的健全性守卫会拒绝这份快照
+		AllowMajorReduction: staticCfg.AllowMajorReduction,
+		Environment:         staticCfg.Environment,
 	}
 
 	// 继续追加 K8s 中发现的服务集群 (EndpointSlices 转换而来)
@@ -223,7 +226,11 @@ func (s *AgwServer) unregisterClient(id int64) {
 // StreamConfig 是 gRPC 接口的具体实现。
 // 每一个连接上来的 Data Plane 都会触发一个新的 StreamConfig Goroutine。
 func (s *AgwServer) StreamConfig(req *agwv1.Node, stream grpc.ServerStreamingServer[agwv1.ConfigSnapshot]) error {
-	log.Printf("New node connected: ID=%s Region=%s Version=%s", req.Id, req.Region, req.Version)
+	log.Printf("New node connected: ID=%s Region=%s Zone=%s Version=%s Labels=%v", req.Id, req.Region, req.Zone, req.Version, req.Labels)
+	if b := req.Build; b != nil {
+		log.Printf("Node %s build: commit=%s built=%s features=%v pingora=%s wasmtime=%s plugin_abi=%s config_api=%s",
+			req.Id, b.GitCommit, b.BuildTimestamp, b.Features, b.PingoraVersion, b.WasmtimeVersion, b.PluginAbi, b.ConfigApi)
+	}
 
 	// 1. 创建一个专属的通道 (信箱)
 	// 这个通道用来接收来自 broadcastMerged 的配置快照
@@ -6,11 +6,32 @@ type Config struct {
 	Resources *Resources `yaml:"resources,omitempty"`
 	Listeners []Listener `yaml:"listeners"`
 	Clusters  []Cluster  `yaml:"clusters"`
+	// AllowMajorReduction 显式允许路由/集群数量大幅下降 (否则数据面会拒绝该快照)
+	AllowMajorReduction bool `yaml:"allow_major_reduction"`
+	// Environment 环境名 (例如 "staging", "prod")，插件可通过 runtime-info 读取
+	Environment string `yaml:"environment"`
+	// DefaultRoute 没有路由命中时的兜底路由 (如迁移期间转给旧的单体服务)；不能设置 Match / Path / Hosts / Methods 等匹配条件
+	DefaultRoute *Route `yaml:"default_route"`
+	// NotFoundResponse 没有兜底路由时 404 的响应体 / ContentType / 响应头，Status 只能不填或填 404
+	NotFoundResponse *DirectResponse `yaml:"not_found_response"`
+	// GlobalPlugins 对所有路由生效的插件 (如统一鉴权)，在路由自己的 Plugins 之前执行
+	GlobalPlugins []Plugin `yaml:"global_plugins"`
+	// ErrorResponses 按状态码 (400-599) 定制网关生成的错误响应，Body 支持 %STATUS% / %REASON% / %REQUEST_ID% 占位符
+	ErrorResponses []DirectResponse `yaml:"error_responses"`
 }
 
 type Resources struct {
 	Redis     []RedisConfig    `yaml:"redis"`
 	Databases []DatabaseConfig `yaml:"databases"`
+	// HttpClient 插件的出站 HTTP (agw_http_fetch)，不设置时插件不能发 HTTP 请求
+	HttpClient *HttpClientConfig `yaml:"http_client"`
+}
+
+// HttpClientConfig 各数值字段为 0 时使用数据面的默认值
+type HttpClientConfig struct {
+	TimeoutMs        uint32   `yaml:"timeout_ms"`         // 默认 2000
+	AllowedHosts     []string `yaml:"allowed_hosts"`      // "host" 或 "host:port"，为空表示不限
+	MaxResponseBytes uint32   `yaml:"max_response_bytes"` // 默认 1 MiB
 }
 
 type RedisConfig struct {
@@ -29,7 +50,10 @@ type Listener struct {
 	Address string     `yaml:"address"`
 	Port    uint32     `yaml:"port"`
 	Tls     *TlsConfig `yaml:"tls"`
-	Routes  []Route    `yaml:"routes"`
+	// 监听器前面是 L4 负载均衡 (NLB / HAProxy) 时打开：每个连接都必须带 PROXY protocol 头，
+	// 头里的源地址作为客户端 IP，并以 X-Real-IP 转发给上游
+	ProxyProtocol bool    `yaml:"proxy_protocol"`
+	Routes        []Route `yaml:"routes"`
 }
 
 type TlsConfig struct {
@@ -38,23 +62,264 @@ type TlsConfig struct {
 
 type Route struct {
 	Match   string   `yaml:"match"`   // e.g. "/api"
-	Domain  string   `yaml:"domain"`  // e.g. "example.com"
+	Domain  string   `yaml:"domain"`  // e.g. "example.com"，等价于只有一个元素的 Hosts
 	Cluster string   `yaml:"cluster"` // Cluster reference
 	Plugins []Plugin `yaml:"plugins"`
+	// Trailers: "propagate" (default) or "drop"
+	Trailers string `yaml:"trailers"`
+	// MaxResponseBytes 上游响应的最大字节数，0 表示不限制
+	MaxResponseBytes uint64 `yaml:"max_response_bytes"`
+	// TruncateContentTypes 这些类型 (如 text/event-stream) 的超限响应截断后关闭，而不是报错
+	TruncateContentTypes []string `yaml:"truncate_content_types"`
+	// EffectiveAt 路由生效时间 (RFC3339)，在此之前请求会落到后面的路由
+	EffectiveAt string `yaml:"effective_at"`
+	// Ramp 灰度放量：从 start 开始，在 duration 内把流量从 0% 提升到 100%
+	Ramp *Ramp `yaml:"ramp"`
+	// Path 通用路径匹配器，设置后优先于 Match (前缀)
+	Path *StringMatch `yaml:"path"`
+	// Cache 响应缓存策略，不设置表示不缓存
+	Cache *CachePolicy `yaml:"cache"`
+	// SubsetSelector 只把请求转发给 metadata 匹配的 Endpoint 子集
+	SubsetSelector *SubsetSelector `yaml:"subset_selector"`
+	// Canary 名单内的用户/租户直接转发到 canary 集群
+	Canary *CanaryOverride `yaml:"canary"`
+	// Hosts 路由生效的域名，为空表示任意域名。"*.example.com" 只通配一层子域名，"**.example.com" 通配任意层；
+	// 都不匹配 example.com 本身。精确域名的路由优先于通配域名的路由
+	Hosts []string `yaml:"hosts"`
+	// MatchType "prefix" (默认)、"exact" 或 "regex"，决定 Match 按前缀、精确还是正则 (全匹配) 比较
+	MatchType string `yaml:"match_type"`
+	// PluginExclusions 命中的请求 (如探针 GET /healthz、OPTIONS 预检) 跳过插件链
+	PluginExclusions []PluginExclusion `yaml:"plugin_exclusions"`
+	// HashRequestBody 流式计算请求体的 SHA-256 并写进访问日志 (合规审计)
+	HashRequestBody bool `yaml:"hash_request_body"`
+	// Idempotency 带幂等键的 POST/PUT 重试直接回放第一次的响应
+	Idempotency *IdempotencyPolicy `yaml:"idempotency"`
+	// Methods 路由只匹配这些 HTTP 方法 (如 GET 走只读副本、POST 走主库)，为空表示任意方法
+	Methods []string `yaml:"methods"`
+	// Headers 请求头条件 (全部满足才匹配)，如 X-Canary: true 或按 X-Tenant-Id 分流
+	Headers []HeaderMatch `yaml:"headers"`
+	// QueryParams 查询参数条件 (全部满足才匹配)，如 version=2；同路径的路由之间条件多的优先
+	QueryParams []QueryParamMatch `yaml:"query_params"`
+	// StripPrefix 转发前去掉命中的路由前缀 ("/backend-a/v1/users" -> "/v1/users")，只对前缀路由有效
+	StripPrefix bool `yaml:"strip_prefix"`
+	// RewritePrefix 转发前加在 (去掉前缀后的) 路径前面，必须以 "/" 开头
+	RewritePrefix string `yaml:"rewrite_prefix"`
+	// ClusterSelector 按请求头 / 请求属性计算集群名的表达式，如 `"shard-" + hash(header("x-user-id")) % 8`
+	ClusterSelector string `yaml:"cluster_selector"`
+	// HostRewrite 转发给上游的 Host 头，覆盖集群上的 host_rewrite
+	HostRewrite string `yaml:"host_rewrite"`
+	// Retry 上游失败 (连接失败、超时、retry_on 里的状态码) 时换一个节点重试
+	Retry *RetryPolicy `yaml:"retry"`
+	// Redirect 直接返回重定向 (如 http -> https、旧域名 -> 新域名)，不转发；此时不需要 Cluster
+	Redirect *RedirectAction `yaml:"redirect"`
+	// DirectResponse 直接返回固定的响应 (维护页、接口桩)，不转发；此时不能再设置 Cluster 或 Redirect
+	DirectResponse *DirectResponse `yaml:"direct_response"`
+	// TimeoutMs 请求整体的截止时间 (包括重试和接收完整响应)，超时返回 504；0 表示使用网关默认值
+	TimeoutMs uint64 `yaml:"timeout_ms"`
+	// WeightedClusters 按权重把流量分到多个集群 (如 users-v1: 95、users-v2: 5)，代替 Cluster
+	WeightedClusters []WeightedCluster `yaml:"weighted_clusters"`
+	// MirrorCluster 把转发的请求另外复制一份 (带 X-Mirrored: true) 发给这个集群，响应丢弃
+	MirrorCluster string `yaml:"mirror_cluster"`
+	// MirrorSampleRate 镜像请求的比例 (0 到 1，如 0.1 约镜像十分之一的请求，0 表示不镜像)；不填表示全部镜像
+	MirrorSampleRate *float32 `yaml:"mirror_sample_rate"`
+	// PluginBypass 安全模式下 (插件全部停用) 这个路由怎么处理："allow" 不执行插件直接放行，"deny" 返回 503；
+	// 配置了插件的路由必须填写
+	PluginBypass string `yaml:"plugin_bypass"`
+	// RequestHeaders 转发前改写请求头 (注入 X-Gateway-Route、去掉客户端带来的 X-Debug-* 等)
+	RequestHeaders *HeaderTransform `yaml:"request_headers"`
+	// ResponseHeaders 改写上游的响应头 (去掉 Server、X-Powered-By，加 Cache-Control 等)；BeforePlugins 不起作用
+	ResponseHeaders *HeaderTransform `yaml:"response_headers"`
+	// CaseInsensitivePath 匹配路径时不区分大小写 (对 prefix / exact / regex 都生效)，转发给上游的仍是原始路径
+	CaseInsensitivePath bool `yaml:"case_insensitive_path"`
+	// Protocol "grpc" 时只匹配 gRPC 请求 (Content-Type: application/grpc)，网关的错误以 grpc-status 返回；默认 "http"
+	Protocol string `yaml:"protocol"`
+	// AllowWebsocket 接受 WebSocket 升级请求 (如实时推送服务)，升级后的长连接不受 TimeoutMs / MaxResponseBytes 限制；
+	// 不开启时升级请求返回 400
+	AllowWebsocket bool `yaml:"allow_websocket"`
+	// SkipGlobalPlugins 本路由不执行的全局插件名 (如健康检查接口跳过统一鉴权)
+	SkipGlobalPlugins []string `yaml:"skip_global_plugins"`
+	// Metadata 以 route.metadata.<key> 属性交给插件 (如限流阈值)，同一个插件文件在不同路由上可以表现不同
+	Metadata map[string]string `yaml:"metadata"`
+	// Disabled 临时停用路由 (故障处理)，保留配置：命中的请求直接返回 DisabledResponse (默认 503 + Retry-After)
+	Disabled bool `yaml:"disabled"`
+	// DisabledResponse 停用时的响应，Status 不填为 503
+	DisabledResponse *DirectResponse `yaml:"disabled_response"`
+	// TrailingSlash "add" / "remove" 时 GET、HEAD 请求的末尾斜杠不符合要求就 301 到规范写法；默认 "ignore"
+	TrailingSlash string `yaml:"trailing_slash"`
+	// MaxRequestBodyBytes 请求体上限 (如公开接口限制 1 MiB 的上传)，超过返回 413；0 表示不限制
+	MaxRequestBodyBytes uint64 `yaml:"max_request_body_bytes"`
+}
+
+// HeaderTransform 按 remove -> set -> set_if_absent -> add 的顺序执行；remove 大小写无关，支持 "*" 通配。
+// Host、Content-Length、Connection 等逐跳头不能改
+type HeaderTransform struct {
+	Remove        []string          `yaml:"remove"`
+	Set           map[string]string `yaml:"set"`
+	SetIfAbsent   map[string]string `yaml:"set_if_absent"`
+	Add           map[string]string `yaml:"add"`
+	BeforePlugins bool              `yaml:"before_plugins"` // true 时在插件链之前执行，插件看到改写后的请求头
+}
+
+// WeightedCluster 权重为 0 表示不再分流量过去 (drain)，权重之和必须大于 0
+type WeightedCluster struct {
+	Cluster string `yaml:"cluster"`
+	Weight  uint32 `yaml:"weight"`
+}
+
+// DirectResponse 的响应体最大 512 KiB；有响应体但没有 ContentType 时按 text/plain 返回
+type DirectResponse struct {
+	Status      uint32            `yaml:"status"` // 默认 200
+	Body        string            `yaml:"body"`
+	ContentType string            `yaml:"content_type"`
+	Headers     map[string]string `yaml:"headers"`
+}
+
+// RedirectAction 没有设置的部分沿用请求自己的 scheme / host / path
+type RedirectAction struct {
+	StatusCode    uint32 `yaml:"status_code"` // 301 (默认)、302、303、307、308
+	Scheme        string `yaml:"scheme"`      // "http" / "https"
+	Host          string `yaml:"host"`
+	Path          string `yaml:"path"`
+	PreserveQuery bool   `yaml:"preserve_query"`
+}
+
+type RetryPolicy struct {
+	MaxRetries uint32   `yaml:"max_retries"`
+	RetryOn    []uint32 `yaml:"retry_on"` // e.g. [502, 503, 504]
+	// PerTryTimeoutMs 每次尝试的连接 / 读超时，0 表示使用网关全局的上游超时
+	PerTryTimeoutMs uint64 `yaml:"per_try_timeout_ms"`
+	// RetryNonIdempotent 默认只重试 GET / HEAD，设置后 POST 等方法也会重试
+	RetryNonIdempotent bool `yaml:"retry_non_idempotent"`
+	// BaseIntervalMs / MaxIntervalMs 指数退避的起始值和上限 (默认 25 / 250)
+	BaseIntervalMs uint64 `yaml:"base_interval_ms"`
+	MaxIntervalMs  uint64 `yaml:"max_interval_ms"`
+}
+
+type IdempotencyPolicy struct {
+	Header        string   `yaml:"header"`
+	TtlSeconds    uint32   `yaml:"ttl_seconds"`
+	MaxBodyBytes  uint64   `yaml:"max_body_bytes"`
+	ReplayHeaders []string `yaml:"replay_headers"`
+	// InFlight 同一个 key 的请求还在处理时："reject" (默认，409) 或 "wait"
+	InFlight      string `yaml:"in_flight"`
+	WaitTimeoutMs uint32 `yaml:"wait_timeout_ms"`
+	// TenantSource "header:<name>" 或请求属性 key，默认 tenant.id
+	TenantSource string `yaml:"tenant_source"`
+}
+
+// HeaderMatch 不设置 Value 表示只要求请求带这个头；Absent 表示请求不能带这个头 (不能和 Value 一起用)
+type HeaderMatch struct {
+	Name   string       `yaml:"name"`
+	Value  *StringMatch `yaml:"value"`
+	Absent bool         `yaml:"absent"`
+}
+
+// QueryParamMatch 不设置 Value 表示只要求带这个参数
+type QueryParamMatch struct {
+	Name  string       `yaml:"name"`
+	Value *StringMatch `yaml:"value"`
+}
+
+type PluginExclusion struct {
+	Methods []string     `yaml:"methods"`
+	Path    *StringMatch `yaml:"path"`
+}
+
+type CanaryOverride struct {
+	Cluster string `yaml:"cluster"`
+	// Source "header:<name>" 或请求属性 key (如 jwt.sub、tenant.id)
+	Source   string   `yaml:"source"`
+	Exact    []string `yaml:"exact"`
+	Prefixes []string `yaml:"prefixes"`
+}
+
+type SubsetSelector struct {
+	// Metadata 静态匹配条件，如 tier: premium
+	Metadata map[string]string `yaml:"metadata"`
+	// FromAttributes Endpoint metadata key -> 请求属性 key，如 tier: tenant.tier
+	FromAttributes map[string]string `yaml:"from_attributes"`
+	// FallbackToAll 子集为空时退回整个集群
+	FallbackToAll bool `yaml:"fallback_to_all"`
+}
+
+type CachePolicy struct {
+	TtlSeconds                uint32 `yaml:"ttl_seconds"`
+	RespectOriginCacheControl bool   `yaml:"respect_origin_cache_control"`
+	MaxVariants               uint32 `yaml:"max_variants"`
+	MaxBodyBytes              uint64 `yaml:"max_body_bytes"`
+}
+
+// StringMatch 通用字符串匹配，exact/prefix/suffix/contains/regex 只能设置其中一个
+type StringMatch struct {
+	Exact      string `yaml:"exact"`
+	Prefix     string `yaml:"prefix"`
+	Suffix     string `yaml:"suffix"`
+	Contains   string `yaml:"contains"`
+	Regex      string `yaml:"regex"`
+	IgnoreCase bool   `yaml:"ignore_case"`
+}
+
+type Ramp struct {
+	Start    string `yaml:"start"`    // RFC3339, e.g. "2026-01-01T08:00:00Z"
+	Duration string `yaml:"duration"` // Go duration, e.g. "30m"
 }
 
 type Plugin struct {
-	Name     string            `yaml:"name"`
-	WasmPath string            `yaml:"wasm_path"`
-	Config   map[string]string `yaml:"config"`
+	Name      string            `yaml:"name"`
+	WasmPath  string            `yaml:"wasm_path"`
+	Config    map[string]string `yaml:"config"`
+	TimeoutMs uint32            `yaml:"timeout_ms"` // 单次执行的超时 (毫秒)，0 表示使用网关默认值
+	MaxFuel   uint64            `yaml:"max_fuel"`   // 单次执行的 fuel (约等于 Wasm 指令数) 上限，0 表示使用网关默认值
+	Version   string            `yaml:"version"`    // 插件文件的版本 (如 sha256)，变化时数据面重新加载文件，不用重启
+	Priority  int32             `yaml:"priority"`   // 执行顺序，小的先执行 (如鉴权插件 -100)，相同时按声明顺序；默认 0
 }
 
 type Cluster struct {
 	Name      string     `yaml:"name"`
 	Endpoints []Endpoint `yaml:"endpoints"`
+	// LbPolicy "round_robin" (默认)、"random"、"first_alive"、"weighted_random"、"least_connections" 或 "peak_ewma"
+	LbPolicy string `yaml:"lb_policy"`
+	// HealthCheck 主动健康检查，不设置时只有被动健康检查
+	HealthCheck *HealthCheck `yaml:"health_check"`
+	// HostRewrite 转发给上游的 Host 头：固定值，或 "$endpoint" 表示选中节点的地址；为空时透传客户端的 Host
+	HostRewrite string `yaml:"host_rewrite"`
+	// PeakEwma peak_ewma 策略的参数，不设置时使用数据面的默认值
+	PeakEwma *PeakEwma `yaml:"peak_ewma"`
+	// CircuitBreaker 最近的请求失败比例过高时暂停向集群转发 (直接 503)，不设置时不熔断
+	CircuitBreaker *CircuitBreaker `yaml:"circuit_breaker"`
+	// Protocol "grpc" 时用 HTTP/2 (h2c) 连接节点，trailer 端到端保留；默认 "http" (HTTP/1.1)
+	Protocol string `yaml:"protocol"`
+}
+
+// CircuitBreaker 各字段为 0 时使用数据面的默认值
+type CircuitBreaker struct {
+	FailureThreshold uint32 `yaml:"failure_threshold"`  // 失败百分比，默认 50
+	WindowSize       uint32 `yaml:"window_size"`        // 统计最近多少个请求，默认 20，最多 1000
+	OpenDurationSecs uint32 `yaml:"open_duration_secs"` // 默认 30
+}
+
+// PeakEwma 延迟均值的衰减时间常数，以及还没有延迟样本的节点按多少延迟计算
+type PeakEwma struct {
+	DecayMs      uint32 `yaml:"decay_ms"`       // 默认 10000
+	DefaultRttMs uint32 `yaml:"default_rtt_ms"` // 默认 10
+}
+
+// HealthCheck 对每个节点周期性发 GET 请求，2xx 为健康；各字段为 0 时使用数据面的默认值
+type HealthCheck struct {
+	Path               string `yaml:"path"`
+	IntervalMs         uint32 `yaml:"interval_ms"`
+	TimeoutMs          uint32 `yaml:"timeout_ms"`
+	UnhealthyThreshold uint32 `yaml:"unhealthy_threshold"`
+	HealthyThreshold   uint32 `yaml:"healthy_threshold"`
 }
 
 type Endpoint struct {
 	Address string `yaml:"address"`
 	Port    uint32 `yaml:"port"`
+	// UnixPath 本地 sidecar 的 Unix Socket 路径，设置后忽略 Address/Port
+	UnixPath string `yaml:"unix_path"`
+	// Metadata 节点标签，供路由的 subset_selector 使用
+	Metadata map[string]string `yaml:"metadata"`
+	// Weight weighted_random 策略下的相对权重，0 (不填) 不分流量；候选全部为 0 时均匀分布
+	Weight uint32 `yaml:"weight"`
 }
@@ -4,9 +4,13 @@ import (
 	"crypto/sha256"
 	"encoding/hex"
 	"fmt"
+	"log"
 	"os"
+	"time"
 
 	agwv1 "github.com/masallsome/masapigateway/control-plane/pkg/proto"
+	"google.golang.org/protobuf/types/known/durationpb"
+	"google.golang.org/protobuf/types/known/timestamppb"
 	"gopkg.in/yaml.v3"
 )
 
@@ -36,12 +40,24 @@ func ToProto(dsl *Config, rawData []byte) *agwv1.ConfigSnapshot {
 		Listeners: make([]*agwv1.Listener, 0),
 		Clusters:  make([]*agwv1.Cluster, 0),
 		Routes:    make([]*agwv1.Route, 0),
+
+		AllowMajorReduction: dsl.AllowMajorReduction,
+		Environment:         dsl.Environment,
+		NotFoundResponse:    toDirectResponse(dsl.NotFoundResponse),
+		GlobalPlugins:       toPlugins(dsl.GlobalPlugins),
+	}
+	if dsl.DefaultRoute != nil {
+		snapshot.DefaultRoute = toRoute(*dsl.DefaultRoute)
+	}
+	for i := range dsl.ErrorResponses {
+		snapshot.ErrorResponses = append(snapshot.ErrorResponses, toDirectResponse(&dsl.ErrorResponses[i]))
 	}
 
 	if dsl.Resources != nil {
 		snapshot.Resources = &agwv1.ExternalResources{
-			Redis:     make([]*agwv1.RedisConfig, 0),
-			Databases: make([]*agwv1.DatabaseConfig, 0),
+			Redis:      make([]*agwv1.RedisConfig, 0),
+			Databases:  make([]*agwv1.DatabaseConfig, 0),
+			HttpClient: toHttpClientConfig(dsl.Resources.HttpClient),
 		}
 		for _, r := range dsl.Resources.Redis {
 			snapshot.Resources.Redis = append(snapshot.Resources.Redis, &agwv1.RedisConfig{
@@ -60,9 +76,10 @@ func ToProto(dsl *Config, rawData []byte) *agwv1.ConfigSnapshot {
 
 	for _, l := range dsl.Listeners {
 		listener := &agwv1.Listener{
-			Name:    l.Name,
-			Address: l.Address,
-			Port:    l.Port,
+			Name:          l.Name,
+			Address:       l.Address,
+			Port:          l.Port,
+			ProxyProtocol: l.ProxyProtocol,
 		}
 		if l.Tls != nil {
 			listener.Tls = &agwv1.TlsConfig{
@@ -72,33 +89,28 @@ func ToProto(dsl *Config, rawData []byte) *agwv1.ConfigSnapshot {
 		snapshot.Listeners = append(snapshot.Listeners, listener)
 		
 		for _, r := range l.Routes {
-			var protoPlugins []*agwv1.Plugin
-			for _, p := range r.Plugins {
-				protoPlugins = append(protoPlugins, &agwv1.Plugin{
-					Name:     p.Name,
-					WasmPath: p.WasmPath,
-					Config:   p.Config,
-				})
-			}
-
-			route := &agwv1.Route{
-				PathPrefix: r.Match,
-				ClusterId:  r.Cluster,
-				Plugins:    protoPlugins,
-			}
-			snapshot.Routes = append(snapshot.Routes, route)
+			snapshot.Routes = append(snapshot.Routes, toRoute(r))
 		}
 	}
 
 	for _, c := range dsl.Clusters {
 		cluster := &agwv1.Cluster{
-			Name:      c.Name,
-			Endpoints: make([]*agwv1.Endpoint, 0),
+			Name:           c.Name,
+			Endpoints:      make([]*agwv1.Endpoint, 0),
+			LbPolicy:       toLbPolicy(c.LbPolicy),
+			HealthCheck:    toHealthCheck(c.HealthCheck),
+			HostRewrite:    c.HostRewrite,
+			PeakEwma:       toPeakEwma(c.PeakEwma),
+			CircuitBreaker: toCircuitBreaker(c.CircuitBreaker),
+			Protocol:       toProtocol(c.Protocol),
 		}
 		for _, e := range c.Endpoints {
 			cluster.Endpoints = append(cluster.Endpoints, &agwv1.Endpoint{
-				Address: e.Address,
-				Port:    e.Port,
+				Address:  e.Address,
+				Port:     e.Port,
+				UnixPath: e.UnixPath,
+				Metadata: e.Metadata,
+				Weight:   e.Weight,
 			})
 		}
 		snapshot.Clusters = append(snapshot.Clusters, cluster)
@@ -107,6 +119,397 @@ func ToProto(dsl *Config, rawData []byte) *agwv1.ConfigSnapshot {
 	return snapshot
 }
 
+// toRoute 将 DSL 中的一条路由 (或兜底路由) 转换为 proto
+func toRoute(r Route) *agwv1.Route {
+	return &agwv1.Route{
+		PathPrefix:           r.Match,
+		ClusterId:            r.Cluster,
+		Plugins:              toPlugins(r.Plugins),
+		TrailerPolicy:        toTrailerPolicy(r.Trailers),
+		MaxResponseBytes:     r.MaxResponseBytes,
+		EffectiveAt:          toTimestamp(r.EffectiveAt),
+		Ramp:                 toRamp(r.Ramp),
+		Path:                 ToStringMatch(r.Path),
+		Cache:                toCachePolicy(r.Cache),
+		SubsetSelector:       toSubsetSelector(r.SubsetSelector),
+		Canary:               toCanaryOverride(r.Canary),
+		Hosts:                toHosts(r.Domain, r.Hosts),
+		MatchType:            toPathMatchType(r.MatchType),
+		PluginExclusions:     toPluginExclusions(r.PluginExclusions),
+		HashRequestBody:      r.HashRequestBody,
+		Idempotency:          toIdempotencyPolicy(r.Idempotency),
+		Methods:              r.Methods,
+		TruncateContentTypes: r.TruncateContentTypes,
+		Headers:              toHeaderMatches(r.Headers),
+		QueryParams:          toQueryParamMatches(r.QueryParams),
+		StripPrefix:          r.StripPrefix,
+		RewritePrefix:        r.RewritePrefix,
+		ClusterSelector:      r.ClusterSelector,
+		HostRewrite:          r.HostRewrite,
+		Retry:                toRetryPolicy(r.Retry),
+		Redirect:             toRedirectAction(r.Redirect),
+		DirectResponse:       toDirectResponse(r.DirectResponse),
+		TimeoutMs:            r.TimeoutMs,
+		WeightedClusters:     toWeightedClusters(r.WeightedClusters),
+		MirrorCluster:        r.MirrorCluster,
+		MirrorSampleRate:     r.MirrorSampleRate,
+		PluginBypassPolicy:   toPluginBypassPolicy(r.PluginBypass),
+		RequestHeaders:       toHeaderTransform(r.RequestHeaders),
+		ResponseHeaders:      toHeaderTransform(r.ResponseHeaders),
+		CaseInsensitivePath:  r.CaseInsensitivePath,
+		Protocol:             toProtocol(r.Protocol),
+		AllowWebsocket:       r.AllowWebsocket,
+		SkipGlobalPlugins:    r.SkipGlobalPlugins,
+		Metadata:             r.Metadata,
+		Disabled:             r.Disabled,
+		DisabledResponse:     toDirectResponse(r.DisabledResponse),
+		TrailingSlash:        toTrailingSlashPolicy(r.TrailingSlash),
+		MaxRequestBodyBytes:  r.MaxRequestBodyBytes,
+	}
+}
+
+// toPlugins 路由插件和全局插件共用的转换
+func toPlugins(plugins []Plugin) []*agwv1.Plugin {
+	var protoPlugins []*agwv1.Plugin
+	for _, p := range plugins {
+		protoPlugins = append(protoPlugins, &agwv1.Plugin{
+			Name:      p.Name,
+			WasmPath:  p.WasmPath,
+			Config:    p.Config,
+			TimeoutMs: p.TimeoutMs,
+			MaxFuel:   p.MaxFuel,
+			Version:   p.Version,
+			Priority:  p.Priority,
+		})
+	}
+	return protoPlugins
+}
+
+// toTrailerPolicy 将 DSL 中的字符串转换为 proto 枚举，未知值按默认 (propagate) 处理
+func toTrailerPolicy(s string) agwv1.TrailerPolicy {
+	switch s {
+	case "drop":
+		return agwv1.TrailerPolicy_TRAILER_DROP
+	default:
+		return agwv1.TrailerPolicy_TRAILER_PROPAGATE
+	}
+}
+
+func toPluginExclusions(in []PluginExclusion) []*agwv1.PluginExclusion {
+	var out []*agwv1.PluginExclusion
+	for _, e := range in {
+		out = append(out, &agwv1.PluginExclusion{
+			Methods: e.Methods,
+			Path:    ToStringMatch(e.Path),
+		})
+	}
+	return out
+}
+
+func toHeaderMatches(in []HeaderMatch) []*agwv1.HeaderMatch {
+	var out []*agwv1.HeaderMatch
+	for _, h := range in {
+		out = append(out, &agwv1.HeaderMatch{
+			Name:   h.Name,
+			Value:  ToStringMatch(h.Value),
+			Absent: h.Absent,
+		})
+	}
+	return out
+}
+
+func toQueryParamMatches(in []QueryParamMatch) []*agwv1.QueryParamMatch {
+	var out []*agwv1.QueryParamMatch
+	for _, q := range in {
+		out = append(out, &agwv1.QueryParamMatch{
+			Name:  q.Name,
+			Value: ToStringMatch(q.Value),
+		})
+	}
+	return out
+}
+
+func toHttpClientConfig(in *HttpClientConfig) *agwv1.HttpClientConfig {
+	if in == nil {
+		return nil
+	}
+	return &agwv1.HttpClientConfig{
+		TimeoutMs:        in.TimeoutMs,
+		AllowedHosts:     in.AllowedHosts,
+		MaxResponseBytes: in.MaxResponseBytes,
+	}
+}
+
+func toHealthCheck(in *HealthCheck) *agwv1.HealthCheck {
+	if in == nil {
+		return nil
+	}
+	return &agwv1.HealthCheck{
+		Path:               in.Path,
+		IntervalMs:         in.IntervalMs,
+		TimeoutMs:          in.TimeoutMs,
+		UnhealthyThreshold: in.UnhealthyThreshold,
+		HealthyThreshold:   in.HealthyThreshold,
+	}
+}
+
+func toPeakEwma(in *PeakEwma) *agwv1.PeakEwma {
+	if in == nil {
+		return nil
+	}
+	return &agwv1.PeakEwma{
+		DecayMs:      in.DecayMs,
+		DefaultRttMs: in.DefaultRttMs,
+	}
+}
+
+func toCircuitBreaker(in *CircuitBreaker) *agwv1.CircuitBreaker {
+	if in == nil {
+		return nil
+	}
+	return &agwv1.CircuitBreaker{
+		FailureThreshold: in.FailureThreshold,
+		WindowSize:       in.WindowSize,
+		OpenDurationSecs: in.OpenDurationSecs,
+	}
+}
+
+func toRetryPolicy(in *RetryPolicy) *agwv1.RetryPolicy {
+	if in == nil {
+		return nil
+	}
+	return &agwv1.RetryPolicy{
+		MaxRetries:         in.MaxRetries,
+		RetryOn:            in.RetryOn,
+		PerTryTimeoutMs:    in.PerTryTimeoutMs,
+		RetryNonIdempotent: in.RetryNonIdempotent,
+		BaseIntervalMs:     in.BaseIntervalMs,
+		MaxIntervalMs:      in.MaxIntervalMs,
+	}
+}
+
+func toRedirectAction(in *RedirectAction) *agwv1.RedirectAction {
+	if in == nil {
+		return nil
+	}
+	return &agwv1.RedirectAction{
+		StatusCode:    in.StatusCode,
+		Scheme:        in.Scheme,
+		Host:          in.Host,
+		Path:          in.Path,
+		PreserveQuery: in.PreserveQuery,
+	}
+}
+
+func toDirectResponse(in *DirectResponse) *agwv1.DirectResponse {
+	if in == nil {
+		return nil
+	}
+	return &agwv1.DirectResponse{
+		Status:      in.Status,
+		Body:        []byte(in.Body),
+		ContentType: in.ContentType,
+		Headers:     in.Headers,
+	}
+}
+
+func toHeaderTransform(in *HeaderTransform) *agwv1.HeaderTransform {
+	if in == nil {
+		return nil
+	}
+	return &agwv1.HeaderTransform{
+		Remove:        in.Remove,
+		Set:           in.Set,
+		SetIfAbsent:   in.SetIfAbsent,
+		Add:           in.Add,
+		BeforePlugins: in.BeforePlugins,
+	}
+}
+
+func toWeightedClusters(in []WeightedCluster) []*agwv1.WeightedCluster {
+	var out []*agwv1.WeightedCluster
+	for _, w := range in {
+		out = append(out, &agwv1.WeightedCluster{
+			ClusterId: w.Cluster,
+			Weight:    w.Weight,
+		})
+	}
+	return out
+}
+
+func toPluginBypassPolicy(s string) agwv1.PluginBypassPolicy {
+	switch s {
+	case "allow":
+		return agwv1.PluginBypassPolicy_PLUGIN_BYPASS_ALLOW
+	case "deny":
+		return agwv1.PluginBypassPolicy_PLUGIN_BYPASS_DENY
+	default:
+		return agwv1.PluginBypassPolicy_PLUGIN_BYPASS_UNSPECIFIED
+	}
+}
+
+// toProtocol 路由 / 集群的协议，未知值按 HTTP 处理
+func toProtocol(s string) agwv1.Protocol {
+	switch s {
+	case "grpc":
+		return agwv1.Protocol_PROTOCOL_GRPC
+	default:
+		return agwv1.Protocol_PROTOCOL_HTTP
+	}
+}
+
+// toTrailingSlashPolicy 未知值按默认 (ignore) 处理
+func toTrailingSlashPolicy(s string) agwv1.TrailingSlashPolicy {
+	switch s {
+	case "add":
+		return agwv1.TrailingSlashPolicy_TRAILING_SLASH_ADD
+	case "remove":
+		return agwv1.TrailingSlashPolicy_TRAILING_SLASH_REMOVE
+	default:
+		return agwv1.TrailingSlashPolicy_TRAILING_SLASH_IGNORE
+	}
+}
+
+func toLbPolicy(s string) agwv1.LbPolicy {
+	switch s {
+	case "random":
+		return agwv1.LbPolicy_LB_RANDOM
+	case "first_alive":
+		return agwv1.LbPolicy_LB_FIRST_ALIVE
+	case "weighted_random":
+		return agwv1.LbPolicy_LB_WEIGHTED_RANDOM
+	case "least_connections":
+		return agwv1.LbPolicy_LB_LEAST_CONNECTIONS
+	case "peak_ewma":
+		return agwv1.LbPolicy_LB_PEAK_EWMA
+	default:
+		return agwv1.LbPolicy_LB_ROUND_ROBIN
+	}
+}
+
+func toPathMatchType(s string) agwv1.PathMatchType {
+	switch s {
+	case "exact":
+		return agwv1.PathMatchType_PATH_MATCH_EXACT
+	case "regex":
+		return agwv1.PathMatchType_PATH_MATCH_REGEX
+	default:
+		return agwv1.PathMatchType_PATH_MATCH_PREFIX
+	}
+}
+
+// toTimestamp 解析 RFC3339 时间，空字符串或格式错误返回 nil (即不设置)
+func toTimestamp(s string) *timestamppb.Timestamp {
+	if s == "" {
+		return nil
+	}
+	t, err := time.Parse(time.RFC3339, s)
+	if err != nil {
+		log.Printf("Warning: invalid RFC3339 time %q: %v", s, err)
+		return nil
+	}
+	return timestamppb.New(t)
+}
+
+func toRamp(r *Ramp) *agwv1.RouteRamp {
+	if r == nil {
+		return nil
+	}
+	start := toTimestamp(r.Start)
+	d, err := time.ParseDuration(r.Duration)
+	if start == nil || err != nil {
+		log.Printf("Warning: invalid ramp %+v, ignoring", *r)
+		return nil
+	}
+	return &agwv1.RouteRamp{
+		Start:    start,
+		Duration: durationpb.New(d),
+	}
+}
+
+func toCachePolicy(c *CachePolicy) *agwv1.CachePolicy {
+	if c == nil {
+		return nil
+	}
+	return &agwv1.CachePolicy{
+		TtlSeconds:                c.TtlSeconds,
+		RespectOriginCacheControl: c.RespectOriginCacheControl,
+		MaxVariants:               c.MaxVariants,
+		MaxBodyBytes:              c.MaxBodyBytes,
+	}
+}
+
+func toIdempotencyPolicy(p *IdempotencyPolicy) *agwv1.IdempotencyPolicy {
+	if p == nil {
+		return nil
+	}
+	inFlight := agwv1.IdempotencyInFlight_IDEMPOTENCY_REJECT
+	if p.InFlight == "wait" {
+		inFlight = agwv1.IdempotencyInFlight_IDEMPOTENCY_WAIT
+	}
+	return &agwv1.IdempotencyPolicy{
+		Header:        p.Header,
+		TtlSeconds:    p.TtlSeconds,
+		MaxBodyBytes:  p.MaxBodyBytes,
+		ReplayHeaders: p.ReplayHeaders,
+		InFlight:      inFlight,
+		WaitTimeoutMs: p.WaitTimeoutMs,
+		TenantSource:  p.TenantSource,
+	}
+}
+
+func toSubsetSelector(s *SubsetSelector) *agwv1.SubsetSelector {
+	if s == nil {
+		return nil
+	}
+	return &agwv1.SubsetSelector{
+		Metadata:       s.Metadata,
+		FromAttributes: s.FromAttributes,
+		FallbackToAll:  s.FallbackToAll,
+	}
+}
+
+func toCanaryOverride(c *CanaryOverride) *agwv1.CanaryOverride {
+	if c == nil {
+		return nil
+	}
+	return &agwv1.CanaryOverride{
+		ClusterId: c.Cluster,
+		Source:    c.Source,
+		Exact:     c.Exact,
+		Prefixes:  c.Prefixes,
+	}
+}
+
+// toHosts 合并旧的单个 domain 字段与 hosts 列表
+func toHosts(domain string, hosts []string) []string {
+	if domain == "" {
+		return hosts
+	}
+	return append([]string{domain}, hosts...)
+}
+
+// ToStringMatch 将 DSL 的 StringMatch 转换为 proto oneof
+func ToStringMatch(m *StringMatch) *agwv1.StringMatch {
+	if m == nil {
+		return nil
+	}
+	out := &agwv1.StringMatch{IgnoreCase: m.IgnoreCase}
+	switch {
+	case m.Exact != "":
+		out.Pattern = &agwv1.StringMatch_Exact{Exact: m.Exact}
+	case m.Prefix != "":
+		out.Pattern = &agwv1.StringMatch_Prefix{Prefix: m.Prefix}
+	case m.Suffix != "":
+		out.Pattern = &agwv1.StringMatch_Suffix{Suffix: m.Suffix}
+	case m.Contains != "":
+		out.Pattern = &agwv1.StringMatch_Contains{Contains: m.Contains}
+	case m.Regex != "":
+		out.Pattern = &agwv1.StringMatch_Regex{Regex: m.Regex}
+	}
+	return out
+}
+
 func GenerateVersion(data []byte) string {
 	hash := sha256.Sum256(data)
 	return hex.EncodeToString(hash[:])[:8]
@@ -238,10 +238,71 @@ func (c *Controller) parseRoute(u *unstructured.Unstructured) *agwv1.Route {
 	// 5. 解析插件配置
 	plugins := c.parsePlugins(spec)
 
+	// 6. 可选的 "spec.hosts"，为空表示任意域名
+	hosts, _, _ := unstructured.NestedStringSlice(spec, "hosts")
+
+	// 7. 可选的 "spec.match_type"："prefix" (默认)、"exact" 或 "regex"
+	matchType := agwv1.PathMatchType_PATH_MATCH_PREFIX
+	switch mt, _, _ := unstructured.NestedString(spec, "match_type"); mt {
+	case "exact":
+		matchType = agwv1.PathMatchType_PATH_MATCH_EXACT
+	case "regex":
+		matchType = agwv1.PathMatchType_PATH_MATCH_REGEX
+	}
+
+	// 8. 可选的 "spec.case_insensitive_path"：匹配路径时不区分大小写
+	caseInsensitive, _, _ := unstructured.NestedBool(spec, "case_insensitive_path")
+
+	// 9. 可选的 "spec.methods"：只接受这些方法 (大小写无关)，为空表示任意方法
+	methods, _, _ := unstructured.NestedStringSlice(spec, "methods")
+
+	// 10. 可选的 "spec.strip_prefix" / "spec.rewrite_prefix"：转发前改写路径 (见数据面 rewrite.rs)
+	stripPrefix, _, _ := unstructured.NestedBool(spec, "strip_prefix")
+	rewritePrefix, _, _ := unstructured.NestedString(spec, "rewrite_prefix")
+
+	// 11. 可选的 "spec.protocol"："grpc" 时只匹配 gRPC 请求
+	protocol := agwv1.Protocol_PROTOCOL_HTTP
+	if p, _, _ := unstructured.NestedString(spec, "protocol"); p == "grpc" {
+		protocol = agwv1.Protocol_PROTOCOL_GRPC
+	}
+
+	// 12. 可选的 "spec.allow_websocket"：接受 WebSocket 升级请求
+	allowWebsocket, _, _ := unstructured.NestedBool(spec, "allow_websocket")
+
+	// 13. 可选的 "spec.skip_global_plugins"：本路由不执行的全局插件名
+	skipGlobal, _, _ := unstructured.NestedStringSlice(spec, "skip_global_plugins")
+
+	// 14. 可选的 "spec.metadata"：以 route.metadata.<key> 属性交给插件
+	metadata, _, _ := unstructured.NestedStringMap(spec, "metadata")
+
+	// 15. 可选的 "spec.disabled"：临时停用路由，命中的请求直接返回 503 (配置保留)
+	disabled, _, _ := unstructured.NestedBool(spec, "disabled")
+
+	// 16. 带插件时必填的 "spec.plugin_bypass"："allow" 或 "deny" (安全模式下的处理方式)
+	bypass := agwv1.PluginBypassPolicy_PLUGIN_BYPASS_UNSPECIFIED
+	switch pb, _, _ := unstructured.NestedString(spec, "plugin_bypass"); pb {
+	case "allow":
+		bypass = agwv1.PluginBypassPolicy_PLUGIN_BYPASS_ALLOW
+	case "deny":
+		bypass = agwv1.PluginBypassPolicy_PLUGIN_BYPASS_DENY
+	}
+
 	return &agwv1.Route{
-		PathPrefix: match,
-		ClusterId:  clusterName,
-		Plugins:    plugins,
+		PathPrefix:          match,
+		ClusterId:           clusterName,
+		Plugins:             plugins,
+		Hosts:               hosts,
+		Methods:             methods,
+		MatchType:           matchType,
+		PluginBypassPolicy:  bypass,
+		CaseInsensitivePath: caseInsensitive,
+		StripPrefix:         stripPrefix,
+		RewritePrefix:       rewritePrefix,
+		Protocol:            protocol,
+		AllowWebsocket:      allowWebsocket,
+		SkipGlobalPlugins:   skipGlobal,
+		Metadata:            metadata,
+		Disabled:            disabled,
 	}
 }
 
@@ -272,6 +333,10 @@ func (c *Controller) parsePlugins(spec map[string]interface{}) []*agwv1.Plugin {
 		name, _, _ := unstructured.NestedString(pmap, "name")
 		wasmPath, _, _ := unstructured.NestedString(pmap, "wasm_path")
 		rawConfig, _, _ := unstructured.NestedMap(pmap, "config") // config 是一个 map[string]string
+		timeoutMs, _, _ := unstructured.NestedInt64(pmap, "timeout_ms")
+		maxFuel, _, _ := unstructured.NestedInt64(pmap, "max_fuel")
+		version, _, _ := unstructured.NestedString(pmap, "version")
+		priority, _, _ := unstructured.NestedInt64(pmap, "priority")
 		
 		// 3. 转换 config map (map[string]interface{} -> map[string]string)
 		config := make(map[string]string)
@@ -283,9 +348,13 @@ func (c *Controller) parsePlugins(spec map[string]interface{}) []*agwv1.Plugin {
 		}
 
 		plugins = append(plugins, &agwv1.Plugin{
-			Name:     name,
-			WasmPath: wasmPath,
-			Config:   config,
+			Name:      name,
+			WasmPath:  wasmPath,
+			Config:    config,
+			TimeoutMs: uint32(timeoutMs),
+			MaxFuel:   uint64(maxFuel),
+			Version:   version,
+			Priority:  int32(priority),
 		})
 	}
 	return plugins
@@ -373,15 +442,25 @@ func (c *Controller) processSlice(obj interface{}) {
 		})
 	}
 
-	// 4. 构建内部 Cluster 对象
+	// 4. 端口的 appProtocol 为 "grpc" 或 "kubernetes.io/h2c" 时按 gRPC 集群处理 (HTTP/2 连接上游)
+	protocol := agwv1.Protocol_PROTOCOL_HTTP
+	if len(slice.Ports) > 0 && slice.Ports[0].AppProtocol != nil {
+		switch *slice.Ports[0].AppProtocol {
+		case "grpc", "kubernetes.io/h2c":
+			protocol = agwv1.Protocol_PROTOCOL_GRPC
+		}
+	}
+
+	// 5. 构建内部 Cluster 对象
 	// 命名规则：k8s/{namespace}/{serviceName}
 	// 这样网关的核心逻辑就可以通过这个 ID 找到对应的后端列表
 	cluster := &agwv1.Cluster{
 		Name:      fmt.Sprintf("k8s/%s/%s", slice.Namespace, svcName),
 		Endpoints: endpoints,
+		Protocol:  protocol,
 	}
 
-	// 5. 更新 Registry
+	// 6. 更新 Registry
 	// 将转换好的 Cluster 数据存入内存，并触发变更通知
 	c.registry.UpdateEndpointSlice(slice, cluster)
 }
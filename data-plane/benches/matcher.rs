@@ -0,0 +1,55 @@
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use data_plane::client::agw::config::v1::StringMatch;
+use data_plane::client::agw::config::v1::string_match::Pattern;
+use data_plane::matcher::CompiledMatch;
+
+// StringMatcher 在请求路径上的开销：每种匹配方式各匹配一个命中和一个不命中的路径，
+// 大小写敏感 / 不敏感各测一次 (不敏感时非正则的方式要先把输入转成小写)。
+const HIT: &str = "/api/v2/users/42/orders.json";
+const MISS: &str = "/static/img/logo.png";
+
+fn matchers() -> Vec<(&'static str, Pattern)> {
+    vec![
+        ("exact", Pattern::Exact(HIT.to_string())),
+        ("prefix", Pattern::Prefix("/api/v2/".to_string())),
+        ("suffix", Pattern::Suffix(".json".to_string())),
+        ("contains", Pattern::Contains("/users/".to_string())),
+        (
+            "regex",
+            Pattern::Regex(r"/api/v\d+/users/\d+/orders\.json".to_string()),
+        ),
+    ]
+}
+
+fn bench_matchers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("matcher");
+    for (name, pattern) in matchers() {
+        for ignore_case in [false, true] {
+            let matcher = CompiledMatch::compile(&StringMatch {
+                pattern: Some(pattern.clone()),
+                ignore_case,
+            })
+            .expect("bench pattern rejected");
+            assert!(matcher.matches(HIT) && !matcher.matches(MISS));
+            let id = if ignore_case {
+                format!("{}/ignore_case", name)
+            } else {
+                name.to_string()
+            };
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    (
+                        matcher.matches(black_box(HIT)),
+                        matcher.matches(black_box(MISS)),
+                    )
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_matchers);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use data_plane::wasm::{ExternalResources, PluginLimits, WasmRuntime};
+
+// 插件实例池的收益：同一个 deny-all 插件连续执行 1000 次，对比每次都实例化 (池大小 0) 和复用实例 (池大小 16)。
+// 先构建插件：cd plugins/deny-all && cargo build --release --target wasm32-unknown-unknown
+// 也可以用 AGW_BENCH_PLUGIN 指定别的 .wasm 文件。
+const ITERATIONS: u64 = 1000;
+
+fn plugin_path() -> String {
+    std::env::var("AGW_BENCH_PLUGIN").unwrap_or_else(|_| {
+        "../plugins/deny-all/target/wasm32-unknown-unknown/release/deny_all.wasm".to_string()
+    })
+}
+
+fn bench_pool(c: &mut Criterion) {
+    let path = plugin_path();
+    assert!(
+        std::path::Path::new(&path).exists(),
+        "{} not found; build the deny-all plugin first",
+        path
+    );
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let limits = PluginLimits {
+        timeout: Duration::from_secs(1),
+        fuel: 1_000_000_000,
+    };
+    let headers = HashMap::from([("user-agent".to_string(), "bench".to_string())]);
+    let attributes = Arc::new(HashMap::new());
+
+    let mut group = c.benchmark_group("deny_all_1000_requests");
+    group.throughput(Throughput::Elements(ITERATIONS));
+    group.sample_size(10);
+    for (name, pool_size) in [("non_pooled", 0), ("pooled", 16)] {
+        let wasm = WasmRuntime::with_pool_size(ExternalResources::default(), pool_size);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    for _ in 0..ITERATIONS {
+                        wasm.run_plugin(&path, limits, headers.clone(), attributes.clone(), None)
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_pool);
+criterion_main!(benches);
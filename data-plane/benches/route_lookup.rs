@@ -0,0 +1,95 @@
+use std::hint::black_box;
+use std::time::SystemTime;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use data_plane::client::agw::config::v1::{Cluster, Route};
+use data_plane::client::agw::v1::ConfigSnapshot;
+use data_plane::router::{ActiveConfig, Resolution, RouteQuery};
+
+// 路由查找的开销随路由数的变化：10 / 1k / 10k 条前缀路由，对比
+// - linear_scan: 索引之前的做法，每个请求遍历整个路由表，取命中的最长前缀
+// - index: ActiveConfig::resolve (前缀树，见 router.rs 的 RouteIndex)
+// 请求路径命中路由表中间的一条路由，另外再查一个所有路由都不命中的路径。
+const ROUTE_COUNTS: [usize; 3] = [10, 1_000, 10_000];
+
+fn config(routes: usize) -> ActiveConfig {
+    let snapshot = ConfigSnapshot {
+        clusters: vec![Cluster {
+            name: "backend".to_string(),
+            ..Default::default()
+        }],
+        routes: (0..routes)
+            .map(|i| Route {
+                path_prefix: format!("/svc-{}/v{}/resource", i, i % 3),
+                cluster_id: "backend".to_string(),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    ActiveConfig::compile(snapshot).expect("bench snapshot rejected")
+}
+
+fn linear_scan(config: &ActiveConfig, path: &str) -> Option<usize> {
+    config
+        .routes
+        .iter()
+        .enumerate()
+        .filter(|(_, compiled)| compiled.path.matches(path))
+        .max_by_key(|(_, compiled)| compiled.route.path_prefix.len())
+        .map(|(i, _)| i)
+}
+
+fn indexed(config: &ActiveConfig, headers: &http::HeaderMap, path: &str) -> Option<usize> {
+    let request = RouteQuery {
+        method: "GET",
+        path,
+        host: None,
+        headers,
+        query: None,
+        rollout_key: b"",
+        at: SystemTime::now(),
+    };
+    match config.resolve(&request) {
+        Resolution::Matched { index, .. } => Some(index),
+        _ => None,
+    }
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let headers = http::HeaderMap::new();
+    let mut group = c.benchmark_group("route_lookup");
+    for routes in ROUTE_COUNTS {
+        let config = config(routes);
+        let middle = routes / 2;
+        let hit = format!("/svc-{}/v{}/resource/42", middle, middle % 3);
+        let miss = "/unknown/path";
+        assert_eq!(linear_scan(&config, &hit), Some(middle));
+        assert_eq!(indexed(&config, &headers, &hit), Some(middle));
+
+        group.bench_with_input(
+            BenchmarkId::new("linear_scan", routes),
+            &config,
+            |b, config| {
+                b.iter(|| {
+                    (
+                        linear_scan(config, black_box(&hit)),
+                        linear_scan(config, black_box(miss)),
+                    )
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("index", routes), &config, |b, config| {
+            b.iter(|| {
+                (
+                    indexed(config, &headers, black_box(&hit)),
+                    indexed(config, &headers, black_box(miss)),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_lookup);
+criterion_main!(benches);
@@ -1,3 +1,7 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // We need to compile both protos or ensure compilation includes config.proto
     // However, tonic_build::compile_protos might only take one entry point.
@@ -8,5 +12,65 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build_server(false)
         .build_client(true)
         .compile_protos(&["../proto/agw.proto"], &["../proto"])?;
+
+    // 构建信息 (见 src/buildinfo.rs)：git commit、构建时间、启用的 cargo feature、关键依赖的版本
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let built_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(k, _)| k.strip_prefix("CARGO_FEATURE_").map(|f| f.to_lowercase()))
+        .collect();
+    features.sort();
+    let lock = std::fs::read_to_string("../Cargo.lock").unwrap_or_default();
+    println!("cargo:rustc-env=AGW_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=AGW_BUILD_TIMESTAMP={}", built_at);
+    println!("cargo:rustc-env=AGW_FEATURES={}", features.join(","));
+    println!("cargo:rustc-env=AGW_PINGORA_VERSION={}", locked_version(&lock, "pingora"));
+    println!("cargo:rustc-env=AGW_WASMTIME_VERSION={}", locked_version(&lock, "wasmtime"));
+    // commit 变化时重新生成：HEAD 只在切换分支时变化，在分支上提交改的是 HEAD 指向的 ref 文件
+    // (被 git gc 打包之后改的是 packed-refs)
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    for path in git_ref_paths() {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    println!("cargo:rerun-if-changed=../Cargo.lock");
+    println!("cargo:rerun-if-changed=../proto");
     Ok(())
 }
+
+// HEAD 指向的 ref 文件和 packed-refs。只登记存在的路径 (不存在的路径会让每次构建都重新运行 build.rs)：
+// ref 还只在 packed-refs 里时登记它所在的目录，下一次提交新建 ref 文件时目录会变化
+fn git_ref_paths() -> Vec<String> {
+    let mut paths = vec!["../.git/packed-refs".to_string()];
+    let head = std::fs::read_to_string("../.git/HEAD").unwrap_or_default();
+    if let Some(reference) = head.strip_prefix("ref: ").map(str::trim) {
+        let file = Path::new("../.git").join(reference);
+        let watched = if file.exists() {
+            Some(file)
+        } else {
+            file.parent().map(Path::to_path_buf)
+        };
+        paths.extend(watched.map(|p| p.to_string_lossy().into_owned()));
+    }
+    paths.retain(|p| Path::new(p).exists());
+    paths
+}
+
+// Cargo.lock 里某个包的版本 ("name = ..." 的下一行就是 "version = ...")
+fn locked_version(lock: &str, name: &str) -> String {
+    let needle = format!("name = \"{}\"", name);
+    let mut lines = lock.lines();
+    lines
+        .by_ref()
+        .find(|line| *line == needle)
+        .and_then(|_| lines.next())
+        .and_then(|l| l.strip_prefix("version = "))
+        .map(|v| v.trim_matches('"').to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,900 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::outcome::RequestOutcome;
+
+// 【访问日志输出 (Access Log Sinks)】
+// "去抓 stdout" 并不适用于所有部署形态 (比如一体机式安装)。访问日志统一经过 AccessLog 分发给一个或多个 Sink：
+// - stdout:                   单行 key=value (与之前的输出一致)
+// - file:<path>:              NDJSON，按大小 / 时间滚动
+// - syslog-udp:<host:port> / syslog-tcp:<host:port>: RFC 5424
+// - http://<host:port>/<path>: NDJSON 批量 POST，失败重试，目标不可用时先落盘 (spill)，恢复后补发
+//
+// 通过 AGW_ACCESS_LOG_SINKS 配置 (逗号分隔，默认 "stdout")。每个 Sink 都有独立的有界队列和后台写入任务，
+// 队列满时的背压策略必须明确，可以用 "@策略" 后缀覆盖默认值：
+// - block: 等待队列有空位 (会拖慢请求的 logging 阶段)
+// - drop:  直接丢弃并计数
+// - spill: 写到本地磁盘的 spill 文件，稍后由写入任务补发 (仅 http)
+// 例如: "stdout,file:/var/log/agw/access.log,syslog-udp:10.0.0.5:514@drop,http://logs:8080/bulk@spill"
+//
+// 每种 Sink 有默认的行格式 (stdout / syslog 为 key=value，file / http 为 JSON)，
+// AGW_ACCESS_LOG_FORMAT=json|text 可以统一覆盖，例如让 stdout 也输出 JSON 供日志采集器解析。
+// JSON 行就是序列化后的 RequestOutcome (timestamp_ms、method、path、status、endpoint、duration_us、route、plugin_denied ...)。
+//
+// 各 Sink 的 written / dropped / spilled 计数通过管理端口 /access_log 查看。
+pub struct AccessLog {
+    sinks: Vec<SinkHandle>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backpressure {
+    Block,
+    Drop,
+    Spill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    // "access key=value ..."
+    Text,
+    // serde_json 序列化的 RequestOutcome
+    Json,
+}
+
+#[derive(Debug, Default)]
+struct SinkStats {
+    written: AtomicU64,
+    dropped: AtomicU64,
+    spilled: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct SinkStatus {
+    pub sink: String,
+    pub backpressure: Backpressure,
+    pub written: u64,
+    pub dropped: u64,
+    pub spilled: u64,
+    pub errors: u64,
+}
+
+struct SinkHandle {
+    name: String,
+    format: Format,
+    backpressure: Backpressure,
+    tx: mpsc::Sender<String>,
+    stats: Arc<SinkStats>,
+    // spill 策略下队列满时直接追加到这个文件
+    spill: Option<Arc<SpillFile>>,
+}
+
+// 每个 Sink 队列的容量 (行数)
+const QUEUE_CAPACITY: usize = 8192;
+// 写入任务一次最多攒多少行
+const MAX_BATCH: usize = 512;
+
+impl AccessLog {
+    // 解析 AGW_ACCESS_LOG_SINKS 并在后台 Runtime 上启动各 Sink 的写入任务
+    pub fn from_env(rt: &tokio::runtime::Runtime, node_id: &str) -> Self {
+        let specs = std::env::var("AGW_ACCESS_LOG_SINKS").unwrap_or_else(|_| "stdout".to_string());
+        let format = match std::env::var("AGW_ACCESS_LOG_FORMAT").ok().as_deref() {
+            Some("json") => Some(Format::Json),
+            Some("text") => Some(Format::Text),
+            Some(other) => {
+                eprintln!("Unknown AGW_ACCESS_LOG_FORMAT {:?}, using each sink's default", other);
+                None
+            }
+            None => None,
+        };
+        let mut sinks = Vec::new();
+        for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match SinkHandle::start(spec, rt, node_id, format) {
+                Ok(handle) => {
+                    println!(
+                        "Access log sink: {} (format={:?}, backpressure={:?})",
+                        handle.name, handle.format, handle.backpressure
+                    );
+                    sinks.push(handle);
+                }
+                Err(e) => eprintln!("Invalid access log sink {:?}: {}", spec, e),
+            }
+        }
+        Self { sinks }
+    }
+
+    pub async fn log(&self, outcome: &RequestOutcome) {
+        let mut text = None;
+        let mut json = None;
+        for sink in &self.sinks {
+            let line = match sink.format {
+                Format::Text => text
+                    .get_or_insert_with(|| format!("access {}", outcome))
+                    .clone(),
+                Format::Json => json
+                    .get_or_insert_with(|| serde_json::to_string(outcome).unwrap_or_default())
+                    .clone(),
+            };
+            sink.send(line).await;
+        }
+    }
+
+    pub fn status(&self) -> Vec<SinkStatus> {
+        self.sinks
+            .iter()
+            .map(|s| SinkStatus {
+                sink: s.name.clone(),
+                backpressure: s.backpressure,
+                written: s.stats.written.load(Ordering::Relaxed),
+                dropped: s.stats.dropped.load(Ordering::Relaxed),
+                spilled: s.stats.spilled.load(Ordering::Relaxed),
+                errors: s.stats.errors.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+impl SinkHandle {
+    fn start(
+        spec: &str,
+        rt: &tokio::runtime::Runtime,
+        node_id: &str,
+        format_override: Option<Format>,
+    ) -> Result<Self, String> {
+        let (target, policy) = match spec.rsplit_once('@') {
+            Some((target, policy)) => (target, Some(policy)),
+            None => (spec, None),
+        };
+        let (writer, format, default_policy): (Box<dyn SinkWriter>, Format, Backpressure) =
+            if target == "stdout" {
+                (Box::new(StdoutSink), Format::Text, Backpressure::Block)
+            } else if let Some(path) = target.strip_prefix("file:") {
+                (
+                    Box::new(FileSink::from_env(PathBuf::from(path))),
+                    Format::Json,
+                    Backpressure::Block,
+                )
+            } else if let Some(addr) = target.strip_prefix("syslog-udp:") {
+                (
+                    Box::new(SyslogSink::new(addr, false, node_id)),
+                    Format::Text,
+                    Backpressure::Drop,
+                )
+            } else if let Some(addr) = target.strip_prefix("syslog-tcp:") {
+                (
+                    Box::new(SyslogSink::new(addr, true, node_id)),
+                    Format::Text,
+                    Backpressure::Block,
+                )
+            } else if target.starts_with("http://") {
+                (Box::new(HttpSink::parse(target)?), Format::Json, Backpressure::Spill)
+            } else {
+                return Err("unknown sink type".to_string());
+            };
+        let format = format_override.unwrap_or(format);
+        let backpressure = match policy {
+            None => default_policy,
+            Some("block") => Backpressure::Block,
+            Some("drop") => Backpressure::Drop,
+            Some("spill") => Backpressure::Spill,
+            Some(other) => return Err(format!("unknown backpressure policy {:?}", other)),
+        };
+        let spill = match backpressure {
+            Backpressure::Spill if writer.supports_spill() => Some(Arc::new(SpillFile::for_sink(target))),
+            Backpressure::Spill => return Err("spill is only supported by http sinks".to_string()),
+            _ => None,
+        };
+
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let stats = Arc::new(SinkStats::default());
+        rt.spawn(run_writer(writer, rx, stats.clone(), spill.clone()));
+        Ok(Self {
+            name: target.to_string(),
+            format,
+            backpressure,
+            tx,
+            stats,
+            spill,
+        })
+    }
+
+    async fn send(&self, line: String) {
+        match self.backpressure {
+            Backpressure::Block => {
+                if self.tx.send(line).await.is_err() {
+                    self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Backpressure::Drop => {
+                if self.tx.try_send(line).is_err() {
+                    self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Backpressure::Spill => {
+                if let Err(e) = self.tx.try_send(line) {
+                    let line = match e {
+                        mpsc::error::TrySendError::Full(l) | mpsc::error::TrySendError::Closed(l) => l,
+                    };
+                    self.spill_lines(&[line]);
+                }
+            }
+        }
+    }
+
+    fn spill_lines(&self, lines: &[String]) {
+        match &self.spill {
+            Some(spill) if spill.append(lines).is_ok() => {
+                self.stats.spilled.fetch_add(lines.len() as u64, Ordering::Relaxed);
+            }
+            _ => {
+                self.stats.dropped.fetch_add(lines.len() as u64, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// 后台写入任务：攒批 -> 写入；失败时 spill (如果启用) 或计入 errors
+async fn run_writer(
+    mut writer: Box<dyn SinkWriter>,
+    mut rx: mpsc::Receiver<String>,
+    stats: Arc<SinkStats>,
+    spill: Option<Arc<SpillFile>>,
+) {
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+    while rx.recv_many(&mut batch, MAX_BATCH).await > 0 {
+        // 目标恢复后先补发之前落盘的内容，保证顺序
+        if let Some(spill) = &spill {
+            if let Some(spilled) = spill.take() {
+                if writer.write_batch(&spilled).await.is_ok() {
+                    stats.written.fetch_add(spilled.len() as u64, Ordering::Relaxed);
+                } else if spill.append(&spilled).is_err() {
+                    stats.dropped.fetch_add(spilled.len() as u64, Ordering::Relaxed);
+                }
+            }
+        }
+        match writer.write_batch(&batch).await {
+            Ok(()) => {
+                stats.written.fetch_add(batch.len() as u64, Ordering::Relaxed);
+            }
+            Err(e) => {
+                stats.errors.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Access log sink write failed: {}", e);
+                match &spill {
+                    Some(spill) if spill.append(&batch).is_ok() => {
+                        stats.spilled.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                    }
+                    _ => {
+                        stats.dropped.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+        batch.clear();
+    }
+}
+
+#[async_trait]
+trait SinkWriter: Send {
+    async fn write_batch(&mut self, lines: &[String]) -> std::io::Result<()>;
+
+    fn supports_spill(&self) -> bool {
+        false
+    }
+}
+
+struct StdoutSink;
+
+#[async_trait]
+impl SinkWriter for StdoutSink {
+    async fn write_batch(&mut self, lines: &[String]) -> std::io::Result<()> {
+        let mut out = std::io::stdout().lock();
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+// 文件 Sink：超过 AGW_ACCESS_LOG_FILE_MAX_MB (默认 100) 或者打开超过 AGW_ACCESS_LOG_FILE_MAX_AGE_SECS (默认 86400)
+// 就把当前文件重命名为 "<path>.<unix 秒>" 并重新打开 (同一秒内滚动多次时再加序号 "<path>.<unix 秒>.<n>")。
+struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+    file: Option<(tokio::fs::File, u64, Instant)>,
+}
+
+impl FileSink {
+    fn from_env(path: PathBuf) -> Self {
+        let env = |key: &str, default: u64| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            path,
+            max_bytes: env("AGW_ACCESS_LOG_FILE_MAX_MB", 100) * 1024 * 1024,
+            max_age: Duration::from_secs(env("AGW_ACCESS_LOG_FILE_MAX_AGE_SECS", 86400)),
+            file: None,
+        }
+    }
+
+    async fn rotate_if_needed(&mut self, incoming: u64) -> std::io::Result<()> {
+        let rotate = match &self.file {
+            Some((_, size, opened)) => {
+                (*size > 0 && size + incoming > self.max_bytes) || opened.elapsed() >= self.max_age
+            }
+            None => false,
+        };
+        if rotate {
+            self.file = None;
+            tokio::fs::rename(&self.path, self.rotated_path()).await?;
+        }
+        if self.file.is_none() {
+            let file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            let size = file.metadata().await?.len();
+            self.file = Some((file, size, Instant::now()));
+        }
+        Ok(())
+    }
+}
+
+impl FileSink {
+    // 不会覆盖已有文件的滚动文件名
+    fn rotated_path(&self) -> PathBuf {
+        let secs = unix_secs();
+        (0..)
+            .map(|n| {
+                let mut rotated = self.path.clone().into_os_string();
+                match n {
+                    0 => rotated.push(format!(".{}", secs)),
+                    n => rotated.push(format!(".{}.{}", secs, n)),
+                }
+                PathBuf::from(rotated)
+            })
+            .find(|rotated| !rotated.exists())
+            .unwrap()
+    }
+}
+
+#[async_trait]
+impl SinkWriter for FileSink {
+    async fn write_batch(&mut self, lines: &[String]) -> std::io::Result<()> {
+        let mut buf = Vec::new();
+        for line in lines {
+            buf.extend_from_slice(line.as_bytes());
+            buf.push(b'\n');
+        }
+        self.rotate_if_needed(buf.len() as u64).await?;
+        if let Some((file, size, _)) = &mut self.file {
+            file.write_all(&buf).await?;
+            *size += buf.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+// RFC 5424: <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+// PRI = local0 (16) * 8 + informational (6) = 134；TIMESTAMP 使用 NILVALUE，由收集端打时间戳。
+// TCP 使用 octet-counting 分帧 (RFC 6587)。
+struct SyslogSink {
+    addr: String,
+    tcp: bool,
+    hostname: String,
+    udp: Option<tokio::net::UdpSocket>,
+    stream: Option<tokio::net::TcpStream>,
+}
+
+impl SyslogSink {
+    fn new(addr: &str, tcp: bool, hostname: &str) -> Self {
+        Self {
+            addr: addr.to_string(),
+            tcp,
+            hostname: if hostname.is_empty() { "-".to_string() } else { hostname.to_string() },
+            udp: None,
+            stream: None,
+        }
+    }
+
+    fn frame(&self, line: &str) -> String {
+        format!(
+            "<134>1 - {} agw {} access - {}",
+            self.hostname,
+            std::process::id(),
+            line
+        )
+    }
+}
+
+#[async_trait]
+impl SinkWriter for SyslogSink {
+    async fn write_batch(&mut self, lines: &[String]) -> std::io::Result<()> {
+        if self.tcp {
+            if self.stream.is_none() {
+                self.stream = Some(tokio::net::TcpStream::connect(&self.addr).await?);
+            }
+            let mut buf = Vec::new();
+            for line in lines {
+                let msg = self.frame(line);
+                buf.extend_from_slice(format!("{} {}", msg.len(), msg).as_bytes());
+            }
+            let result = match &mut self.stream {
+                Some(stream) => stream.write_all(&buf).await,
+                None => Ok(()),
+            };
+            if result.is_err() {
+                // 连接断了，下一批重新连接
+                self.stream = None;
+            }
+            result
+        } else {
+            if self.udp.is_none() {
+                let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(&self.addr).await?;
+                self.udp = Some(socket);
+            }
+            for line in lines {
+                let msg = self.frame(line);
+                if let Some(socket) = &self.udp {
+                    socket.send(msg.as_bytes()).await?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+// HTTP 批量 Sink：每批一个 NDJSON POST，最多重试 3 次 (指数退避)。
+// 只支持明文 http://，目标通常是同机房的日志收集器。
+struct HttpSink {
+    addr: String,
+    host: String,
+    path: String,
+}
+
+const HTTP_RETRIES: u32 = 3;
+
+impl HttpSink {
+    fn parse(url: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("http://").ok_or("only http:// is supported")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err("missing host".to_string());
+        }
+        let addr = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        Ok(Self {
+            addr,
+            host: authority.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    async fn post(&self, body: &[u8]) -> std::io::Result<()> {
+        let mut stream = tokio::time::timeout(
+            Duration::from_secs(5),
+            tokio::net::TcpStream::connect(&self.addr),
+        )
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "connect timed out"))??;
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-ndjson\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body).await?;
+        // 只需要状态行
+        let mut buf = [0u8; 64];
+        let n = tokio::time::timeout(Duration::from_secs(10), stream.read(&mut buf))
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "response timed out"))??;
+        let status_line = String::from_utf8_lossy(&buf[..n]);
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok())
+            .unwrap_or(0);
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!("bulk endpoint returned {}", status)))
+        }
+    }
+}
+
+#[async_trait]
+impl SinkWriter for HttpSink {
+    async fn write_batch(&mut self, lines: &[String]) -> std::io::Result<()> {
+        let mut body = Vec::new();
+        for line in lines {
+            body.extend_from_slice(line.as_bytes());
+            body.push(b'\n');
+        }
+        let mut last_err = None;
+        for attempt in 0..HTTP_RETRIES {
+            match self.post(&body).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    last_err = Some(e);
+                    tokio::time::sleep(Duration::from_millis(200 << attempt)).await;
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| std::io::Error::other("bulk post failed")))
+    }
+
+    fn supports_spill(&self) -> bool {
+        true
+    }
+}
+
+// 磁盘 spill 文件 (AGW_ACCESS_LOG_SPILL_DIR，默认 /tmp)，上限 AGW_ACCESS_LOG_SPILL_MAX_MB (默认 512)，
+// 超过上限的部分直接丢弃并计数，避免把磁盘写满。
+struct SpillFile {
+    path: PathBuf,
+    max_bytes: u64,
+    lock: std::sync::Mutex<()>,
+}
+
+impl SpillFile {
+    fn for_sink(target: &str) -> Self {
+        let dir = std::env::var("AGW_ACCESS_LOG_SPILL_DIR").unwrap_or_else(|_| "/tmp".to_string());
+        let name: String = target
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let max_mb = std::env::var("AGW_ACCESS_LOG_SPILL_MAX_MB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(512u64);
+        Self {
+            path: PathBuf::from(dir).join(format!("agw-access-spill-{}.ndjson", name)),
+            max_bytes: max_mb * 1024 * 1024,
+            lock: std::sync::Mutex::new(()),
+        }
+    }
+
+    fn append(&self, lines: &[String]) -> std::io::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let size = std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size >= self.max_bytes {
+            return Err(std::io::Error::other("spill file full"));
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        for line in lines {
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    // 取出全部已 spill 的内容并清空文件
+    fn take(&self) -> Option<Vec<String>> {
+        let _guard = self.lock.lock().unwrap();
+        let content = std::fs::read_to_string(&self.path).ok()?;
+        let _ = std::fs::remove_file(&self.path);
+        let lines: Vec<String> = content.lines().map(str::to_string).collect();
+        if lines.is_empty() { None } else { Some(lines) }
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicBool;
+
+    // 每个测试一个空的临时目录
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("agw-accesslog-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_sink(path: PathBuf, max_bytes: u64, max_age: Duration) -> FileSink {
+        FileSink {
+            path,
+            max_bytes,
+            max_age,
+            file: None,
+        }
+    }
+
+    fn lines(prefix: &str, n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("{}-{:07}", prefix, i)).collect()
+    }
+
+    // 目录里的文件：(文件名, 内容的各行)，按文件名排序 (滚动文件在前，当前文件 access.log 排最前)
+    fn files(dir: &PathBuf) -> Vec<(String, Vec<String>)> {
+        let mut files: Vec<(String, Vec<String>)> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let content = std::fs::read_to_string(&path).unwrap();
+                (
+                    path.file_name().unwrap().to_string_lossy().into_owned(),
+                    content.lines().map(str::to_string).collect(),
+                )
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn file_sink_rotates_by_size_without_overwriting() {
+        let dir = temp_dir("size");
+        // 每行 9 字节 + 换行，两行一批 20 字节：第二批开始每批都会滚动
+        let mut sink = file_sink(dir.join("access.log"), 32, Duration::from_secs(3600));
+        for batch in ["a", "b", "c"] {
+            sink.write_batch(&lines(batch, 2)).await.unwrap();
+        }
+        let files = files(&dir);
+        assert_eq!(files.len(), 3, "{:?}", files);
+        assert_eq!(files[0], ("access.log".to_string(), lines("c", 2)));
+        // 同一秒内滚动两次：两个滚动文件都在，内容各是一批
+        let mut rotated: Vec<Vec<String>> = files[1..].iter().map(|(_, l)| l.clone()).collect();
+        rotated.sort();
+        assert_eq!(rotated, [lines("a", 2), lines("b", 2)]);
+        assert!(
+            files[1..]
+                .iter()
+                .all(|(name, _)| name.starts_with("access.log."))
+        );
+    }
+
+    #[tokio::test]
+    async fn file_sink_keeps_an_oversized_batch_in_an_empty_file() {
+        let dir = temp_dir("oversized");
+        let mut sink = file_sink(dir.join("access.log"), 8, Duration::from_secs(3600));
+        sink.write_batch(&lines("a", 3)).await.unwrap();
+        assert_eq!(files(&dir), [("access.log".to_string(), lines("a", 3))]);
+    }
+
+    #[tokio::test]
+    async fn file_sink_rotates_by_age() {
+        let dir = temp_dir("age");
+        let mut sink = file_sink(dir.join("access.log"), u64::MAX, Duration::ZERO);
+        sink.write_batch(&lines("a", 1)).await.unwrap();
+        sink.write_batch(&lines("b", 1)).await.unwrap();
+        let files = files(&dir);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].1, lines("b", 1));
+        assert_eq!(files[1].1, lines("a", 1));
+    }
+
+    // 可以切换成失败的 Sink，记录写成功的行
+    struct FlakySink {
+        failing: Arc<AtomicBool>,
+        written: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl SinkWriter for FlakySink {
+        async fn write_batch(&mut self, lines: &[String]) -> std::io::Result<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(std::io::Error::other("collector down"));
+            }
+            self.written.lock().unwrap().extend_from_slice(lines);
+            Ok(())
+        }
+    }
+
+    async fn wait_for(stats: &SinkStats, done: impl Fn(&SinkStats) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(stats) {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting for {:?}",
+                stats
+            );
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_writes_spill_and_are_replayed_in_order() {
+        let dir = temp_dir("spill");
+        let spill = Arc::new(SpillFile {
+            path: dir.join("spill.ndjson"),
+            max_bytes: 1024 * 1024,
+            lock: std::sync::Mutex::new(()),
+        });
+        let failing = Arc::new(AtomicBool::new(true));
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let writer = Box::new(FlakySink {
+            failing: failing.clone(),
+            written: written.clone(),
+        });
+        let (tx, rx) = mpsc::channel(16);
+        let stats = Arc::new(SinkStats::default());
+        for line in lines("a", 2) {
+            tx.send(line).await.unwrap();
+        }
+        let task = tokio::spawn(run_writer(writer, rx, stats.clone(), Some(spill.clone())));
+
+        // 目标不可用：整批落盘，计一次错误
+        wait_for(&stats, |s| s.spilled.load(Ordering::SeqCst) == 2).await;
+        assert_eq!(stats.errors.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            std::fs::read_to_string(&spill.path).unwrap(),
+            "a-0000000\na-0000001\n"
+        );
+        assert!(written.lock().unwrap().is_empty());
+
+        // 恢复后先补发落盘的内容，再写新的一批
+        failing.store(false, Ordering::SeqCst);
+        tx.send("b-0000000".to_string()).await.unwrap();
+        wait_for(&stats, |s| s.written.load(Ordering::SeqCst) == 3).await;
+        assert_eq!(
+            *written.lock().unwrap(),
+            ["a-0000000", "a-0000001", "b-0000000"]
+        );
+        assert!(!spill.path.exists());
+        assert_eq!(stats.dropped.load(Ordering::SeqCst), 0);
+
+        drop(tx);
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_writes_without_spill_are_dropped() {
+        let dir = temp_dir("unwritable");
+        // 父目录不存在：打开文件失败
+        let writer = Box::new(file_sink(
+            dir.join("missing").join("access.log"),
+            u64::MAX,
+            Duration::from_secs(3600),
+        ));
+        let (tx, rx) = mpsc::channel(16);
+        let stats = Arc::new(SinkStats::default());
+        for line in lines("a", 3) {
+            tx.send(line).await.unwrap();
+        }
+        drop(tx);
+        run_writer(writer, rx, stats.clone(), None).await;
+        assert_eq!(stats.errors.load(Ordering::SeqCst), 1);
+        assert_eq!(stats.dropped.load(Ordering::SeqCst), 3);
+        assert_eq!(stats.written.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn full_spill_file_refuses_more_lines() {
+        let dir = temp_dir("full");
+        let spill = SpillFile {
+            path: dir.join("spill.ndjson"),
+            max_bytes: 16,
+            lock: std::sync::Mutex::new(()),
+        };
+        assert!(spill.append(&lines("a", 2)).is_ok());
+        assert!(spill.append(&lines("b", 1)).is_err());
+        assert_eq!(spill.take(), Some(lines("a", 2)));
+        assert_eq!(spill.take(), None);
+    }
+
+    #[test]
+    fn spill_is_only_accepted_for_http_sinks() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let dir = temp_dir("policy");
+        let file = format!("file:{}", dir.join("access.log").display());
+        let error = |spec: &str| SinkHandle::start(spec, &rt, "node-1", None).err();
+        assert_eq!(
+            error(&format!("{}@spill", file)).as_deref(),
+            Some("spill is only supported by http sinks")
+        );
+        assert_eq!(
+            error("stdout@later").as_deref(),
+            Some("unknown backpressure policy \"later\"")
+        );
+        assert_eq!(error("ftp://logs").as_deref(), Some("unknown sink type"));
+        assert!(error(&format!("{}@drop", file)).is_none());
+    }
+
+    // 一个被插件拒绝的请求
+    fn denied_outcome() -> RequestOutcome {
+        let mut outcome = RequestOutcome {
+            method: "GET".to_string(),
+            path: "/api/orders/42".to_string(),
+            route: Some("/api".to_string()),
+            cluster: Some("orders".to_string()),
+            endpoint: Some("10.0.0.7:8080".to_string()),
+            ..Default::default()
+        };
+        outcome.record_plugin("auth", "deny");
+        outcome.finish(403, Instant::now(), SystemTime::now());
+        outcome
+    }
+
+    // 把 outcome 交给一个 file Sink，等写入任务落盘后返回文件里的各行
+    fn logged_lines(name: &str, format: Option<Format>) -> Vec<String> {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let path = temp_dir(name).join("access.log");
+        let spec = format!("file:{}", path.display());
+        let log = AccessLog {
+            sinks: vec![SinkHandle::start(&spec, &rt, "node-1", format).unwrap()],
+        };
+        rt.block_on(async {
+            log.log(&denied_outcome()).await;
+            wait_for(&log.sinks[0].stats, |s| {
+                s.written.load(Ordering::SeqCst) == 1
+            })
+            .await;
+        });
+        std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn json_lines_carry_the_request_fields() {
+        let lines = logged_lines("json", None);
+        assert_eq!(lines.len(), 1);
+        let line: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(line["method"], "GET");
+        assert_eq!(line["path"], "/api/orders/42");
+        assert_eq!(line["status"], 403);
+        assert_eq!(line["route"], "/api");
+        assert_eq!(line["endpoint"], "10.0.0.7:8080");
+        assert_eq!(line["plugin_denied"], true);
+        assert_eq!(
+            line["plugins"],
+            serde_json::json!([{"name": "auth", "decision": "deny"}])
+        );
+        assert!(line["timestamp_ms"].as_u64().unwrap() > 0);
+        assert!(line["duration_us"].is_u64());
+    }
+
+    #[test]
+    fn text_format_overrides_the_sink_default() {
+        let lines = logged_lines("text", Some(Format::Text));
+        assert_eq!(lines.len(), 1);
+        assert!(
+            lines[0].starts_with("access method=GET path=/api/orders/42 "),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[0].contains(" route=/api "), "{}", lines[0]);
+        assert!(
+            lines[0].contains(" endpoint=10.0.0.7:8080 "),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[0].contains(" plugins=[auth:deny] "), "{}", lines[0]);
+        assert!(lines[0].contains(" status=403 "), "{}", lines[0]);
+        assert!(serde_json::from_str::<serde_json::Value>(&lines[0]).is_err());
+    }
+}
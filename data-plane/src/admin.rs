@@ -0,0 +1,372 @@
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use http::Response;
+use pingora::apps::http_app::ServeHttp;
+use pingora::protocols::http::ServerSession;
+use prost::Message;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use crate::accesslog::AccessLog;
+use crate::buildinfo::BuildInfo;
+use crate::health::EndpointRegistry;
+use crate::outlier::OutlierTracker;
+use crate::panics::PanicTracker;
+use crate::recent::{RecentQuery, RecentRequests};
+use crate::replay;
+use crate::router::ActiveConfig;
+use crate::routestats::ROUTE_STATS;
+use crate::safemode::SafeMode;
+use crate::upstream;
+use crate::watchdog::Watchdog;
+use crate::validate::{self, ConfigStatus};
+use crate::warmup::Readiness;
+use crate::wasm::WasmRuntime;
+
+// 【管理端口 (Admin API)】
+// 与业务流量端口分离的一个独立 HTTP 服务，专门给运维和 Kubernetes 探针使用。
+// - /healthz: 存活探针 (Liveness)。只要进程还活着就返回 200，哪怕还没拿到配置。
+//             资源看门狗触发降级时，响应体里会列出当前生效的降级项；
+//             最近一分钟捕获的 panic 达到 AGW_PANIC_DEGRADED_PER_MINUTE 时同样报告 degraded。
+// - /readyz:  就绪探针 (Readiness)。只有当第一份有效配置被应用后才返回 200。
+// - /config_dump?format=proto: 当前生效快照的 protobuf 编码 (密钥已脱敏)，可用 `data-plane replay` 离线回放。
+// - /version: 构建信息 (版本、git commit、构建时间、cargo feature、pingora / wasmtime 版本、插件 ABI)。
+// - /status:  配置应用状态 (当前版本、已应用/被拒绝/被取代的快照计数、最近一次拒绝原因、最近一次应用的分阶段耗时)，
+//             以及当前生效的运维覆盖。
+// - POST /selftest: 运行内置的 Wasm 自检插件，逐个宿主接口报告 pass/fail。
+// - /upstreams: 每个上游节点的协议错误统计 (按原因分类)。
+// - /recent_requests?route=X&status=5xx&limit=50: 最近请求的摘要 (最新的在前)。
+// - /stats/routes?route=X: 各路由最近 1m / 5m / 15m 的请求速率、错误率和 p50 / p90 / p99 / p999 延迟 (毫秒)。
+// - /access_log: 各访问日志 Sink 的 written / dropped / spilled 计数。
+// - /clusters/{name}/endpoints: 集群各节点的可用性结论、原因以及各健康输入的状态。
+// - /safe_mode: 安全模式 (见 safemode.rs)。GET 查看；PUT 切换，请求体: {"enabled": true, "reason": "..."}
+// - /override/endpoints: 运维覆盖。GET 列出；POST 人工下线节点或 drain 集群；DELETE 清除。
+//   请求体: {"cluster": "c", "endpoint": "10.0.0.1:8080", "ttl_secs": 300, "reason": "..."}
+//   不带 endpoint 表示整个集群；不带 ttl_secs 表示一直生效直到清除。
+//
+// 这样在 Control Plane 宕机期间，Pod 不会因为 liveness 失败被 K8s 反复杀掉，
+// 但也不会因为 readiness 通过而被 Service 提前导入流量。
+pub struct AdminApp {
+    pub ready: Arc<Readiness>,
+    pub config_status: Arc<ConfigStatus>,
+    pub wasm: WasmRuntime,
+    pub outliers: Arc<OutlierTracker>,
+    pub recent: Arc<RecentRequests>,
+    pub config: Arc<ArcSwap<ActiveConfig>>,
+    pub health: Arc<EndpointRegistry>,
+    pub watchdog: Arc<Watchdog>,
+    pub access_log: Arc<AccessLog>,
+    pub panics: Arc<PanicTracker>,
+    pub safe_mode: Arc<SafeMode>,
+}
+
+#[async_trait]
+impl ServeHttp for AdminApp {
+    async fn response(&self, session: &mut ServerSession) -> Response<Vec<u8>> {
+        let path = session.req_header().uri.path().to_string();
+        match path.as_str() {
+            "/healthz" => {
+                let mut body = "ok\n".to_string();
+                let degradations = self.watchdog.degradations();
+                if !degradations.is_empty() {
+                    body.push_str(&format!(
+                        "degraded: {} ({})\n",
+                        degradations.join(","),
+                        self.watchdog.reason()
+                    ));
+                }
+                // panic 不影响存活判定 (仍然 200)，重启进程解决不了同一个请求再次触发的 panic
+                if self.panics.degraded() {
+                    body.push_str(&format!(
+                        "degraded: panics ({} in the last minute, {} total)\n",
+                        self.panics.last_minute(),
+                        self.panics.total()
+                    ));
+                }
+                if self.safe_mode.enabled() {
+                    body.push_str("degraded: safe_mode (wasm plugins disabled)\n");
+                }
+                text_response(200, &body)
+            }
+            "/readyz" => {
+                if self.ready.is_ready() {
+                    text_response(200, "ready\n")
+                } else {
+                    text_response(503, "warming up\n")
+                }
+            }
+            "/version" => json_response(
+                200,
+                &serde_json::to_value(BuildInfo::current()).unwrap_or_default(),
+            ),
+            "/status" => {
+                let status = &self.config_status;
+                let last_rejection = status.last_rejection.read().unwrap().clone();
+                let body = serde_json::json!({
+                    "ready": self.ready.is_ready(),
+                    "applied_version": status.applied_version.read().unwrap().clone(),
+                    "applied_total": status.applied_total.load(Ordering::Relaxed),
+                    "rejected_total": status.rejected_total.load(Ordering::Relaxed),
+                    "superseded_total": status.superseded_total.load(Ordering::Relaxed),
+                    "last_apply": status.last_apply.read().unwrap().clone(),
+                    "state_resets_total": status.state_resets_total.load(Ordering::Relaxed),
+                    "last_carryover": status.last_carryover.read().unwrap().clone(),
+                    "plugin_reload_failures": status.plugin_reload_failures.read().unwrap().clone(),
+                    "last_rejection": last_rejection.map(|(version, errors)| {
+                        serde_json::json!({
+                            "version": version,
+                            "errors": validate::errors_json(&errors),
+                        })
+                    }),
+                    "overrides": self.health.overrides(),
+                    "safe_mode": self.safe_mode.status(),
+                });
+                json_response(200, &body)
+            }
+            "/selftest" => {
+                if session.req_header().method != http::Method::POST {
+                    return text_response(405, "method not allowed\n");
+                }
+                let report = self.wasm.self_test().await;
+                let status = if report.passed { 200 } else { 500 };
+                json_response(status, &serde_json::to_value(&report).unwrap_or_default())
+            }
+            "/upstreams" => json_response(
+                200,
+                &serde_json::to_value(self.outliers.snapshot()).unwrap_or_default(),
+            ),
+            "/access_log" => json_response(
+                200,
+                &serde_json::to_value(self.access_log.status()).unwrap_or_default(),
+            ),
+            "/recent_requests" => {
+                if !self.recent.enabled() {
+                    return text_response(404, "recent requests disabled\n");
+                }
+                let query = RecentQuery::parse(session.req_header().uri.query());
+                json_response(
+                    200,
+                    &serde_json::to_value(self.recent.query(&query)).unwrap_or_default(),
+                )
+            }
+            "/stats/routes" => {
+                let query = session.req_header().uri.query().unwrap_or("");
+                let route = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("route="));
+                json_response(
+                    200,
+                    &serde_json::json!({ "routes": ROUTE_STATS.summary(route) }),
+                )
+            }
+            "/safe_mode" => self.safe_mode(session).await,
+            "/override/endpoints" => self.endpoint_override(session).await,
+            "/config_dump" => self.config_dump(session),
+            _ => match cluster_endpoints_path(&path) {
+                Some(name) => self.cluster_endpoints(name),
+                None => text_response(404, "not found\n"),
+            },
+        }
+    }
+}
+
+// POST / DELETE /override/endpoints 的请求体
+#[derive(Deserialize)]
+struct OverrideRequest {
+    cluster: String,
+    endpoint: Option<String>,
+    // 0 与不填相同：不过期
+    ttl_secs: Option<u64>,
+    #[serde(default)]
+    reason: String,
+}
+
+// PUT /safe_mode 的请求体
+#[derive(Deserialize)]
+struct SafeModeRequest {
+    enabled: bool,
+    reason: Option<String>,
+}
+
+// 管理请求体 (覆盖、安全模式) 的大小上限
+const MAX_ADMIN_BODY: usize = 64 * 1024;
+
+async fn read_body(session: &mut ServerSession) -> Result<Vec<u8>, Response<Vec<u8>>> {
+    let mut body = Vec::new();
+    loop {
+        match session.read_request_body().await {
+            Ok(Some(chunk)) => {
+                body.extend_from_slice(&chunk);
+                if body.len() > MAX_ADMIN_BODY {
+                    return Err(text_response(413, "request body too large\n"));
+                }
+            }
+            Ok(None) => return Ok(body),
+            Err(e) => return Err(text_response(400, &format!("failed to read body: {}\n", e))),
+        }
+    }
+}
+
+impl AdminApp {
+    // 不需要配置下发：只改本进程的开关，重启后回到启动参数 (--safe-mode / AGW_SAFE_MODE)
+    async fn safe_mode(&self, session: &mut ServerSession) -> Response<Vec<u8>> {
+        let method = session.req_header().method.clone();
+        if method == http::Method::PUT {
+            let body = match read_body(session).await {
+                Ok(body) => body,
+                Err(response) => return response,
+            };
+            let request: SafeModeRequest = match serde_json::from_slice(&body) {
+                Ok(request) => request,
+                Err(e) => {
+                    return text_response(400, &format!("invalid safe mode request: {}\n", e));
+                }
+            };
+            let reason = request.reason.filter(|r| !r.is_empty());
+            self.safe_mode.set(request.enabled, "admin", reason);
+        } else if method != http::Method::GET {
+            return text_response(405, "method not allowed\n");
+        }
+        json_response(
+            200,
+            &serde_json::to_value(self.safe_mode.status()).unwrap_or_default(),
+        )
+    }
+
+    async fn endpoint_override(&self, session: &mut ServerSession) -> Response<Vec<u8>> {
+        let method = session.req_header().method.clone();
+        if method == http::Method::GET {
+            return json_response(
+                200,
+                &serde_json::to_value(self.health.overrides()).unwrap_or_default(),
+            );
+        }
+        if method != http::Method::POST && method != http::Method::DELETE {
+            return text_response(405, "method not allowed\n");
+        }
+        let body = match read_body(session).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let request: OverrideRequest = match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => return text_response(400, &format!("invalid override request: {}\n", e)),
+        };
+        let endpoint = request.endpoint.as_deref().filter(|e| !e.is_empty());
+
+        if method == http::Method::DELETE {
+            return if self.health.clear_override(&request.cluster, endpoint) {
+                text_response(200, "override cleared\n")
+            } else {
+                text_response(404, "no active override\n")
+            };
+        }
+
+        // 只能覆盖当前配置里存在的集群 / 节点，防止手误写错名字却以为已经生效
+        let config = self.config.load();
+        let Some(cluster) = config
+            .snapshot
+            .clusters
+            .iter()
+            .find(|c| c.name == request.cluster)
+        else {
+            return text_response(404, "cluster not found\n");
+        };
+        if let Some(endpoint) = endpoint {
+            if !cluster
+                .endpoints
+                .iter()
+                .any(|e| upstream::endpoint_label(e) == endpoint)
+            {
+                return text_response(404, "endpoint not found in cluster\n");
+            }
+        }
+        let reason = if request.reason.is_empty() {
+            "manual override"
+        } else {
+            request.reason.as_str()
+        };
+        let entry = self.health.set_override(
+            &request.cluster,
+            endpoint,
+            request
+                .ttl_secs
+                .filter(|secs| *secs > 0)
+                .map(std::time::Duration::from_secs),
+            reason,
+        );
+        json_response(200, &serde_json::to_value(entry).unwrap_or_default())
+    }
+
+    // 目前只有 protobuf 一种格式 (不带 format 参数时同样返回 protobuf)
+    fn config_dump(&self, session: &ServerSession) -> Response<Vec<u8>> {
+        let format = session
+            .req_header()
+            .uri
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("format="));
+        if format.is_some_and(|f| f != "proto") {
+            return text_response(400, "unsupported format, expected format=proto\n");
+        }
+        let body = replay::redact(&self.config.load().snapshot).encode_to_vec();
+        Response::builder()
+            .status(200)
+            .header(http::header::CONTENT_TYPE, "application/x-protobuf")
+            .header(
+                http::header::CONTENT_DISPOSITION,
+                "attachment; filename=\"config_dump.pb\"",
+            )
+            .header(http::header::CONTENT_LENGTH, body.len())
+            .body(body)
+            .unwrap()
+    }
+
+    fn cluster_endpoints(&self, name: &str) -> Response<Vec<u8>> {
+        let config = self.config.load();
+        let Some(cluster) = config.snapshot.clusters.iter().find(|c| c.name == name) else {
+            return text_response(404, "cluster not found\n");
+        };
+        let labels: Vec<String> = cluster.endpoints.iter().map(upstream::endpoint_label).collect();
+        let endpoints: Vec<serde_json::Value> = self
+            .health
+            .cluster_snapshot(name, &labels)
+            .into_iter()
+            .map(|(endpoint, state)| {
+                let mut value = serde_json::to_value(state).unwrap_or_default();
+                value["endpoint"] = endpoint.into();
+                value
+            })
+            .collect();
+        json_response(200, &serde_json::json!({ "cluster": name, "endpoints": endpoints }))
+    }
+}
+
+// "/clusters/{name}/endpoints" -> Some(name)
+fn cluster_endpoints_path(path: &str) -> Option<&str> {
+    path.strip_prefix("/clusters/")?
+        .strip_suffix("/endpoints")
+        .filter(|name| !name.is_empty())
+}
+
+pub fn json_response(status: u16, body: &serde_json::Value) -> Response<Vec<u8>> {
+    let body = body.to_string().into_bytes();
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .header(http::header::CONTENT_LENGTH, body.len())
+        .body(body)
+        .unwrap()
+}
+
+pub fn text_response(status: u16, body: &str) -> Response<Vec<u8>> {
+    let body = body.as_bytes().to_vec();
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "text/plain")
+        .header(http::header::CONTENT_LENGTH, body.len())
+        .body(body)
+        .unwrap()
+}
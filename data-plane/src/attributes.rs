@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+// 【请求属性 (Request Attributes)】
+// 每个请求一份的 key/value 表，用来在内置过滤器和 Wasm 插件之间共享 "已经算出来的事实"，
+// 避免插件再自己解析一遍 (比如在 wasm 里重新实现 JWT 解析)。
+//
+// 约定：
+// - key 带命名空间，"<来源>.<名字>"，如 client.ip、route.cluster、jwt.sub、geo.country、tenant.id。
+// - 【顺序】内置过滤器全部在插件链之前执行并写入属性；插件开始执行后属性表被冻结，
+//   插件只能通过 agw_get_attribute 只读访问，不能修改。
+// - 访问日志只输出 AGW_LOG_ATTRIBUTES (逗号分隔的 key 列表) 里列出的属性，默认不输出，
+//   防止把用户标识之类的信息意外写进日志。
+#[derive(Debug, Default, Clone)]
+pub struct RequestAttributes {
+    values: HashMap<String, String>,
+}
+
+impl RequestAttributes {
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        self.values.insert(key.to_string(), value.into());
+    }
+
+    // 路由的 metadata 写成 route.metadata.<key>：同一个插件文件挂在不同路由上时，
+    // 插件据此区分行为 (如各自的限流阈值)，不需要为每个路由单独打包一份配置
+    pub fn set_route_metadata(&mut self, metadata: &HashMap<String, String>) {
+        for (key, value) in metadata {
+            self.set(&format!("route.metadata.{}", key), value.clone());
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    // 冻结属性表，交给插件只读使用 (同一个请求的多个插件共享同一份)
+    pub fn freeze(&self) -> Arc<HashMap<String, String>> {
+        Arc::new(self.values.clone())
+    }
+
+    // 按 key 列表挑出要写进访问日志的属性
+    pub fn select(&self, keys: &[String]) -> BTreeMap<String, String> {
+        keys.iter()
+            .filter_map(|k| self.values.get(k).map(|v| (k.clone(), v.clone())))
+            .collect()
+    }
+}
+
+// 从请求里取一个值的位置，配置写法为 "header:<name>" 或请求属性 key (如 "jwt.sub" / "tenant.id")。
+// 金丝雀名单、幂等键的租户作用域等按 "用户/租户" 区分的功能共用这一写法。
+#[derive(Debug)]
+pub enum ValueSource {
+    // 请求头 (已小写)
+    Header(String),
+    Attribute(String),
+}
+
+impl ValueSource {
+    // 空字符串返回 None
+    pub fn parse(source: &str) -> Option<Self> {
+        match source.strip_prefix("header:") {
+            Some(name) => Some(ValueSource::Header(name.to_ascii_lowercase())),
+            None if !source.is_empty() => Some(ValueSource::Attribute(source.to_string())),
+            None => None,
+        }
+    }
+
+    pub fn get<'a>(
+        &self,
+        headers: &'a http::HeaderMap,
+        attributes: &'a RequestAttributes,
+    ) -> Option<&'a str> {
+        match self {
+            ValueSource::Header(name) => headers.get(name).and_then(|v| v.to_str().ok()),
+            ValueSource::Attribute(key) => attributes.get(key),
+        }
+    }
+}
+
+// 读取 AGW_LOG_ATTRIBUTES
+pub fn log_keys_from_env() -> Vec<String> {
+    std::env::var("AGW_LOG_ATTRIBUTES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .map(str::to_string)
+        .collect()
+}
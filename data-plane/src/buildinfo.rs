@@ -0,0 +1,135 @@
+use serde::Serialize;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::client::agw::v1::BuildInfo as BuildInfoProto;
+use crate::node::DATA_PLANE_VERSION;
+
+// 【构建信息 (Build Info)】
+// 排查多个环境的问题时，第一件事是确认 "这台网关跑的是哪个构建"。
+// 构建信息由 build.rs 在编译时写入 (git commit、构建时间、cargo feature、依赖版本)，出现在：
+// - 启动时的一行 JSON 日志 ("build_info {...}")
+// - 管理端口 GET /version
+// - 与 Control Plane 握手的 Node.build，控制面据此维护节点清单
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    // HTTP-date 格式，如 "Tue, 13 Oct 2026 08:00:00 GMT"
+    pub build_timestamp: String,
+    pub features: Vec<&'static str>,
+    pub pingora_version: &'static str,
+    pub wasmtime_version: &'static str,
+    // 插件 ABI：核心 Wasm 模块导出 on_request() -> i32，通过 env.agw_* 宿主函数交互 (还没有 WIT world)
+    pub plugin_abi: &'static str,
+    // 接受的配置快照 API (proto package)
+    pub config_api: &'static str,
+}
+
+pub const PLUGIN_ABI: &str = "core-module/v1";
+pub const CONFIG_API: &str = "agw.v1";
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let built_at = env!("AGW_BUILD_TIMESTAMP").parse().unwrap_or(0);
+        Self {
+            version: DATA_PLANE_VERSION,
+            git_commit: env!("AGW_GIT_COMMIT"),
+            build_timestamp: httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(built_at)),
+            features: env!("AGW_FEATURES")
+                .split(',')
+                .filter(|f| !f.is_empty())
+                .collect(),
+            pingora_version: env!("AGW_PINGORA_VERSION"),
+            wasmtime_version: env!("AGW_WASMTIME_VERSION"),
+            plugin_abi: PLUGIN_ABI,
+            config_api: CONFIG_API,
+        }
+    }
+
+    pub fn to_proto(&self) -> BuildInfoProto {
+        BuildInfoProto {
+            version: self.version.to_string(),
+            git_commit: self.git_commit.to_string(),
+            build_timestamp: self.build_timestamp.clone(),
+            features: self.features.iter().map(|f| f.to_string()).collect(),
+            pingora_version: self.pingora_version.to_string(),
+            wasmtime_version: self.wasmtime_version.to_string(),
+            plugin_abi: self.plugin_abi.to_string(),
+            config_api: self.config_api.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::json_response;
+
+    // 管理端口 GET /version 返回的 JSON
+    fn version_json() -> serde_json::Value {
+        let response = json_response(200, &serde_json::to_value(BuildInfo::current()).unwrap());
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/json"
+        );
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    #[test]
+    fn version_json_has_the_documented_fields() {
+        let json = version_json();
+        let object = json.as_object().unwrap();
+        let mut keys: Vec<&str> = object.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "build_timestamp",
+                "config_api",
+                "features",
+                "git_commit",
+                "pingora_version",
+                "plugin_abi",
+                "version",
+                "wasmtime_version",
+            ]
+        );
+        for key in keys.iter().filter(|k| **k != "features") {
+            let value = object[*key]
+                .as_str()
+                .unwrap_or_else(|| panic!("{} is not a string", key));
+            assert!(!value.is_empty(), "{} is empty", key);
+        }
+        assert!(
+            object["features"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .all(|f| f.is_string())
+        );
+        assert_eq!(json["version"], DATA_PLANE_VERSION);
+        assert_eq!(json["plugin_abi"], PLUGIN_ABI);
+        assert_eq!(json["config_api"], CONFIG_API);
+    }
+
+    #[test]
+    fn build_timestamp_is_an_http_date() {
+        let json = version_json();
+        let timestamp = json["build_timestamp"].as_str().unwrap();
+        let built = httpdate::parse_http_date(timestamp).unwrap();
+        assert!(built > UNIX_EPOCH);
+        assert!(built <= std::time::SystemTime::now());
+    }
+
+    #[test]
+    fn proto_carries_the_same_values() {
+        let info = BuildInfo::current();
+        let proto = info.to_proto();
+        assert_eq!(proto.version, info.version);
+        assert_eq!(proto.git_commit, info.git_commit);
+        assert_eq!(proto.build_timestamp, info.build_timestamp);
+        assert_eq!(proto.features, info.features);
+        assert_eq!(proto.plugin_abi, PLUGIN_ABI);
+        assert_eq!(proto.config_api, CONFIG_API);
+    }
+}
@@ -0,0 +1,576 @@
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::client::agw::config::v1::CachePolicy;
+
+// 【响应缓存 (Response Cache)】
+// 一个简单的进程内缓存，按路由的 CachePolicy 开启：
+// - ttl_seconds: 路由的静态 TTL。
+// - respect_origin_cache_control: 以后端的 Cache-Control / Expires / Vary 为准决定是否缓存、缓存多久；
+//   后端没给任何缓存指令时，退回路由的静态 TTL。
+//
+// 缓存 Key = 方法 + Host + URI；如果后端返回了 Vary，则把 Vary 指定的请求头的值也拼进 Key，
+// 同一个 URL 最多保存 max_variants 个变体，防止 Vary: User-Agent 之类的头把缓存撑爆。
+pub struct ResponseCache {
+    max_entries: usize,
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<String, CachedResponse>,
+    // base key -> 后端声明的 Vary 请求头 (已小写)
+    vary: HashMap<String, Vec<String>>,
+    // base key -> 已存储的变体数量
+    variants: HashMap<String, usize>,
+}
+
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Bytes,
+    stored_at: Instant,
+    expires_at: Instant,
+    base_key: String,
+}
+
+impl CachedResponse {
+    pub fn age(&self) -> Duration {
+        self.stored_at.elapsed()
+    }
+}
+
+// 一次未命中请求在响应阶段需要累积的状态
+pub struct PendingEntry {
+    pub base_key: String,
+    pub request_headers: HashMap<String, String>,
+    pub max_variants: usize,
+    pub max_body_bytes: u64,
+    pub status: u16,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub vary: Vec<String>,
+    pub ttl: Duration,
+    pub body: BytesMut,
+}
+
+// 默认的单个 URL 最大变体数与最大可缓存响应体
+const DEFAULT_MAX_VARIANTS: usize = 8;
+const DEFAULT_MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+impl ResponseCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    pub fn base_key(method: &str, host: &str, uri: &str) -> String {
+        format!("{}|{}|{}", method, host, uri)
+    }
+
+    // 查找缓存。request_headers 的 key 必须是小写。
+    pub fn lookup(
+        &self,
+        base_key: &str,
+        request_headers: &HashMap<String, String>,
+    ) -> Option<CachedResponse> {
+        let mut inner = self.inner.lock().unwrap();
+        let key = match inner.vary.get(base_key) {
+            Some(vary) => variant_key(base_key, vary, request_headers),
+            None => base_key.to_string(),
+        };
+        let entry = inner.entries.get(&key)?;
+        if entry.expires_at > Instant::now() {
+            return Some(entry.clone());
+        }
+        let base = entry.base_key.clone();
+        inner.entries.remove(&key);
+        inner.release_variant(&base);
+        None
+    }
+
+    // 根据上游响应头决定这次响应能否缓存；返回 None 表示不缓存 (bypass)
+    pub fn begin(
+        &self,
+        policy: &CachePolicy,
+        base_key: String,
+        request_headers: HashMap<String, String>,
+        status: u16,
+        headers: &http::HeaderMap,
+    ) -> Option<PendingEntry> {
+        // 只缓存 200
+        if status != 200 {
+            return None;
+        }
+        let static_ttl = Duration::from_secs(policy.ttl_seconds as u64);
+        let (ttl, vary) = if policy.respect_origin_cache_control {
+            let vary = parse_vary(headers)?;
+            let ttl = match origin_ttl(headers) {
+                OriginTtl::Bypass => return None,
+                OriginTtl::Ttl(ttl) => ttl,
+                OriginTtl::Absent => static_ttl,
+            };
+            (ttl, vary)
+        } else {
+            (static_ttl, Vec::new())
+        };
+        if ttl.is_zero() {
+            return None;
+        }
+        // 逐跳头和长度相关的头不保存，命中时会按缓存的 body 重新生成 Content-Length
+        let stored_headers = headers
+            .iter()
+            .filter(|(k, _)| {
+                !matches!(
+                    k.as_str(),
+                    "connection" | "transfer-encoding" | "content-length" | "keep-alive"
+                )
+            })
+            .map(|(k, v)| (k.as_str().to_string(), v.as_bytes().to_vec()))
+            .collect();
+        Some(PendingEntry {
+            base_key,
+            request_headers,
+            max_variants: if policy.max_variants == 0 {
+                DEFAULT_MAX_VARIANTS
+            } else {
+                policy.max_variants as usize
+            },
+            max_body_bytes: if policy.max_body_bytes == 0 {
+                DEFAULT_MAX_BODY_BYTES
+            } else {
+                policy.max_body_bytes
+            },
+            status,
+            headers: stored_headers,
+            vary,
+            ttl,
+            body: BytesMut::new(),
+        })
+    }
+
+    // 响应体读完后写入缓存
+    pub fn store(&self, pending: PendingEntry) {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let key = if pending.vary.is_empty() {
+            inner.vary.remove(&pending.base_key);
+            pending.base_key.clone()
+        } else {
+            inner
+                .vary
+                .insert(pending.base_key.clone(), pending.vary.clone());
+            variant_key(&pending.base_key, &pending.vary, &pending.request_headers)
+        };
+
+        let is_new = !inner.entries.contains_key(&key);
+        if is_new {
+            // 变体上限
+            let variants = inner.variants.get(&pending.base_key).copied().unwrap_or(0);
+            if !pending.vary.is_empty() && variants >= pending.max_variants {
+                return;
+            }
+            // 总容量上限：先清理过期条目，仍然满则放弃本次写入
+            if inner.entries.len() >= self.max_entries {
+                inner.evict_expired(now);
+                if inner.entries.len() >= self.max_entries {
+                    return;
+                }
+            }
+            *inner.variants.entry(pending.base_key.clone()).or_insert(0) += 1;
+        }
+        inner.entries.insert(
+            key,
+            CachedResponse {
+                status: pending.status,
+                headers: pending.headers,
+                body: pending.body.freeze(),
+                stored_at: now,
+                expires_at: now + pending.ttl,
+                base_key: pending.base_key,
+            },
+        );
+    }
+}
+
+impl PendingEntry {
+    // 累积响应体；超过上限返回 false (不再缓存)
+    pub fn append(&mut self, chunk: &[u8]) -> bool {
+        if self.body.len() as u64 + chunk.len() as u64 > self.max_body_bytes {
+            return false;
+        }
+        self.body.extend_from_slice(chunk);
+        true
+    }
+}
+
+impl CacheInner {
+    fn release_variant(&mut self, base_key: &str) {
+        if let Some(n) = self.variants.get_mut(base_key) {
+            *n = n.saturating_sub(1);
+            if *n == 0 {
+                self.variants.remove(base_key);
+                self.vary.remove(base_key);
+            }
+        }
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        let expired: Vec<(String, String)> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.expires_at <= now)
+            .map(|(k, e)| (k.clone(), e.base_key.clone()))
+            .collect();
+        for (key, base) in expired {
+            self.entries.remove(&key);
+            self.release_variant(&base);
+        }
+    }
+}
+
+fn variant_key(base_key: &str, vary: &[String], request_headers: &HashMap<String, String>) -> String {
+    let mut key = base_key.to_string();
+    for name in vary {
+        key.push('|');
+        key.push_str(name);
+        key.push('=');
+        key.push_str(request_headers.get(name).map(String::as_str).unwrap_or(""));
+    }
+    key
+}
+
+enum OriginTtl {
+    // no-store / private / no-cache / max-age=0 等：不缓存
+    Bypass,
+    Ttl(Duration),
+    // 后端没有给出任何缓存指令
+    Absent,
+}
+
+// 解析 Cache-Control 与 Expires。s-maxage 优先于 max-age，二者都优先于 Expires。
+fn origin_ttl(headers: &http::HeaderMap) -> OriginTtl {
+    let mut max_age = None;
+    let mut s_maxage = None;
+    for value in headers.get_all(http::header::CACHE_CONTROL) {
+        let Ok(value) = value.to_str() else { continue };
+        for directive in value.split(',') {
+            let directive = directive.trim().to_ascii_lowercase();
+            let (name, arg) = match directive.split_once('=') {
+                Some((n, a)) => (n.trim().to_string(), Some(a.trim().trim_matches('"').to_string())),
+                None => (directive.clone(), None),
+            };
+            match name.as_str() {
+                "no-store" | "private" | "no-cache" => return OriginTtl::Bypass,
+                "max-age" => max_age = arg.and_then(|a| a.parse::<u64>().ok()),
+                "s-maxage" => s_maxage = arg.and_then(|a| a.parse::<u64>().ok()),
+                _ => {}
+            }
+        }
+    }
+    if let Some(secs) = s_maxage.or(max_age) {
+        return if secs == 0 {
+            OriginTtl::Bypass
+        } else {
+            OriginTtl::Ttl(Duration::from_secs(secs))
+        };
+    }
+    if let Some(expires) = headers
+        .get(http::header::EXPIRES)
+        .and_then(|v| v.to_str().ok())
+    {
+        // 无法解析的 Expires (如 "0") 按已过期处理
+        return match httpdate::parse_http_date(expires) {
+            Ok(at) => match at.duration_since(SystemTime::now()) {
+                Ok(ttl) if !ttl.is_zero() => OriginTtl::Ttl(ttl),
+                _ => OriginTtl::Bypass,
+            },
+            Err(_) => OriginTtl::Bypass,
+        };
+    }
+    // 没有 Cache-Control 时长 (或只有 "public" 之类的指令)：按路由 TTL
+    OriginTtl::Absent
+}
+
+// 解析 Vary；"Vary: *" 表示无法缓存，返回 None
+fn parse_vary(headers: &http::HeaderMap) -> Option<Vec<String>> {
+    let mut vary = Vec::new();
+    for value in headers.get_all(http::header::VARY) {
+        let Ok(value) = value.to_str() else { continue };
+        for name in value.split(',') {
+            let name = name.trim().to_ascii_lowercase();
+            if name == "*" {
+                return None;
+            }
+            if !name.is_empty() && !vary.contains(&name) {
+                vary.push(name);
+            }
+        }
+    }
+    vary.sort();
+    Some(vary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> http::HeaderMap {
+        let mut map = http::HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(
+                http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                http::HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        map
+    }
+
+    fn ttl(pairs: &[(&str, &str)]) -> Option<Duration> {
+        match origin_ttl(&headers(pairs)) {
+            OriginTtl::Bypass => None,
+            OriginTtl::Ttl(ttl) => Some(ttl),
+            OriginTtl::Absent => panic!("expected a caching directive in {:?}", pairs),
+        }
+    }
+
+    fn origin_policy() -> CachePolicy {
+        CachePolicy {
+            ttl_seconds: 60,
+            respect_origin_cache_control: true,
+            max_variants: 2,
+            ..Default::default()
+        }
+    }
+
+    fn request(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    // 按响应头决定能否缓存，能缓存时写入 body
+    fn store(
+        cache: &ResponseCache,
+        request_headers: HashMap<String, String>,
+        response: &[(&str, &str)],
+        body: &str,
+    ) -> bool {
+        let key = ResponseCache::base_key("GET", "example.com", "/page");
+        let Some(mut pending) = cache.begin(
+            &origin_policy(),
+            key,
+            request_headers,
+            200,
+            &headers(response),
+        ) else {
+            return false;
+        };
+        assert!(pending.append(body.as_bytes()));
+        cache.store(pending);
+        true
+    }
+
+    fn cached_body(
+        cache: &ResponseCache,
+        request_headers: &HashMap<String, String>,
+    ) -> Option<Bytes> {
+        let key = ResponseCache::base_key("GET", "example.com", "/page");
+        cache.lookup(&key, request_headers).map(|c| c.body)
+    }
+
+    #[test]
+    fn bypass_directives_are_not_cached() {
+        for directive in [
+            "no-store",
+            "private",
+            "no-cache",
+            "max-age=60, no-store",
+            "Private",
+        ] {
+            assert_eq!(ttl(&[("cache-control", directive)]), None, "{}", directive);
+        }
+        let cache = ResponseCache::new(16);
+        assert!(!store(
+            &cache,
+            HashMap::new(),
+            &[("cache-control", "no-store")],
+            "x"
+        ));
+        assert_eq!(cached_body(&cache, &HashMap::new()), None);
+    }
+
+    #[test]
+    fn max_age_zero_is_not_cached() {
+        assert_eq!(ttl(&[("cache-control", "max-age=0")]), None);
+        assert_eq!(
+            ttl(&[("cache-control", "public, s-maxage=0, max-age=60")]),
+            None
+        );
+        assert_eq!(
+            ttl(&[("cache-control", "public, max-age=\"30\"")]),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn s_maxage_takes_precedence_over_max_age() {
+        assert_eq!(
+            ttl(&[("cache-control", "max-age=10, s-maxage=300")]),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(
+            ttl(&[
+                ("cache-control", "s-maxage=300"),
+                ("cache-control", "max-age=10")
+            ]),
+            Some(Duration::from_secs(300))
+        );
+        // Cache-Control 的时长优先于 Expires
+        assert_eq!(
+            ttl(&[
+                ("cache-control", "max-age=10"),
+                ("expires", "Thu, 01 Jan 1970 00:00:00 GMT")
+            ]),
+            Some(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn expires_sets_the_ttl_and_unparseable_values_bypass() {
+        let future = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(120));
+        let from_expires = ttl(&[("expires", &future)]).unwrap();
+        assert!(
+            from_expires > Duration::from_secs(100) && from_expires <= Duration::from_secs(120)
+        );
+
+        assert_eq!(ttl(&[("expires", "Thu, 01 Jan 1970 00:00:00 GMT")]), None);
+        for unparseable in ["0", "-1", "tomorrow"] {
+            assert_eq!(ttl(&[("expires", unparseable)]), None, "{}", unparseable);
+        }
+    }
+
+    #[test]
+    fn no_directives_fall_back_to_the_route_ttl() {
+        assert!(matches!(origin_ttl(&headers(&[])), OriginTtl::Absent));
+        assert!(matches!(
+            origin_ttl(&headers(&[("cache-control", "public")])),
+            OriginTtl::Absent
+        ));
+        let cache = ResponseCache::new(16);
+        let key = ResponseCache::base_key("GET", "example.com", "/page");
+        let pending = cache
+            .begin(&origin_policy(), key, HashMap::new(), 200, &headers(&[]))
+            .unwrap();
+        assert_eq!(pending.ttl, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn vary_star_is_not_cached() {
+        assert_eq!(
+            parse_vary(&headers(&[("vary", "Accept-Encoding, *")])),
+            None
+        );
+        assert_eq!(
+            parse_vary(&headers(&[
+                ("vary", "User-Agent, accept-encoding"),
+                ("vary", "Accept-Encoding")
+            ])),
+            Some(vec![
+                "accept-encoding".to_string(),
+                "user-agent".to_string()
+            ])
+        );
+        let cache = ResponseCache::new(16);
+        assert!(!store(
+            &cache,
+            HashMap::new(),
+            &[("cache-control", "max-age=60"), ("vary", "*")],
+            "x"
+        ));
+    }
+
+    #[test]
+    fn vary_keys_variants_by_request_header() {
+        let cache = ResponseCache::new(16);
+        let response = [("cache-control", "max-age=60"), ("vary", "Accept-Encoding")];
+        let gzip = request(&[("accept-encoding", "gzip")]);
+        let br = request(&[("accept-encoding", "br")]);
+        assert!(store(&cache, gzip.clone(), &response, "gzip body"));
+        assert_eq!(
+            cached_body(&cache, &gzip).as_deref(),
+            Some(&b"gzip body"[..])
+        );
+        assert_eq!(cached_body(&cache, &br), None);
+    }
+
+    #[test]
+    fn max_variants_caps_new_variants_until_one_expires() {
+        let cache = ResponseCache::new(16);
+        let response = [("cache-control", "max-age=60"), ("vary", "Accept-Language")];
+        let lang = |l: &str| request(&[("accept-language", l)]);
+        assert!(store(&cache, lang("en"), &response, "en"));
+        assert!(store(&cache, lang("de"), &response, "de"));
+        // 上限是 2：第三个变体不写入，已有的不受影响
+        store(&cache, lang("fr"), &response, "fr");
+        assert_eq!(cached_body(&cache, &lang("fr")), None);
+        assert_eq!(
+            cached_body(&cache, &lang("en")).as_deref(),
+            Some(&b"en"[..])
+        );
+        // 已有变体刷新不占新名额
+        store(&cache, lang("de"), &response, "de v2");
+        assert_eq!(
+            cached_body(&cache, &lang("de")).as_deref(),
+            Some(&b"de v2"[..])
+        );
+
+        // 一个变体过期被清掉后，空出的名额可以给新变体
+        let key = ResponseCache::base_key("GET", "example.com", "/page");
+        let mut short = cache
+            .begin(
+                &origin_policy(),
+                key.clone(),
+                lang("en"),
+                200,
+                &headers(&response),
+            )
+            .unwrap();
+        short.ttl = Duration::from_millis(20);
+        short.append(b"en short");
+        cache.store(short);
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(cached_body(&cache, &lang("en")), None);
+        store(&cache, lang("fr"), &response, "fr");
+        assert_eq!(
+            cached_body(&cache, &lang("fr")).as_deref(),
+            Some(&b"fr"[..])
+        );
+    }
+
+    #[test]
+    fn body_over_the_limit_stops_caching() {
+        let cache = ResponseCache::new(16);
+        let policy = CachePolicy {
+            max_body_bytes: 4,
+            ..origin_policy()
+        };
+        let key = ResponseCache::base_key("GET", "example.com", "/page");
+        let mut pending = cache
+            .begin(
+                &policy,
+                key,
+                HashMap::new(),
+                200,
+                &headers(&[("cache-control", "max-age=60")]),
+            )
+            .unwrap();
+        assert!(pending.append(b"1234"));
+        assert!(!pending.append(b"5"));
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::HashSet;
+
+use crate::attributes::{RequestAttributes, ValueSource};
+use crate::client::agw::config::v1::CanaryOverride;
+
+// 【粘性金丝雀 (Sticky Canary)】
+// 在按比例灰度之前，先用一批指定的内部用户/租户 "吃自己的狗粮"：
+// 路由上配置一个名单，请求的某个属性 (Header 或者 jwt.sub / tenant.id 这类请求属性) 命中名单时，
+// 无论灰度比例如何都转发到 canary 集群。
+// 名单在校验阶段编译成 HashSet (精确匹配) + 前缀列表，请求路径上是 O(1) 查找；
+// 编译结果挂在 ActiveConfig 上，所有 worker 共享同一份，不会按请求复制。
+#[derive(Debug)]
+pub struct CompiledCanary {
+    pub cluster: String,
+    source: ValueSource,
+    exact: HashSet<String>,
+    prefixes: Vec<String>,
+}
+
+// 名单上限：超过说明应该改用比例灰度
+const MAX_ENTRIES: usize = 10_000;
+
+impl CompiledCanary {
+    pub fn compile(canary: &CanaryOverride) -> Result<Self, String> {
+        if canary.cluster_id.is_empty() {
+            return Err("canary override without cluster_id".to_string());
+        }
+        let entries = canary.exact.len() + canary.prefixes.len();
+        if entries > MAX_ENTRIES {
+            return Err(format!(
+                "canary list too large ({} > {} entries)",
+                entries, MAX_ENTRIES
+            ));
+        }
+        let source = ValueSource::parse(&canary.source)
+            .ok_or_else(|| "canary override without source".to_string())?;
+        Ok(Self {
+            cluster: canary.cluster_id.clone(),
+            source,
+            exact: canary.exact.iter().cloned().collect(),
+            prefixes: canary.prefixes.clone(),
+        })
+    }
+
+    // 请求是否在名单上
+    pub fn matches(&self, headers: &http::HeaderMap, attributes: &RequestAttributes) -> bool {
+        let Some(value) = self.source.get(headers, attributes) else {
+            return false;
+        };
+        self.exact.contains(value) || self.prefixes.iter().any(|p| value.starts_with(p.as_str()))
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::client::agw::config::v1::Cluster;
+
+// 【熔断 (Circuit Breaker)】
+// 按集群统计最近 window_size 个上游请求的结果，失败比例达到 failure_threshold (百分比) 时打开熔断：
+// open_duration_secs 之内发往这个集群的请求在 upstream_peer 直接返回 503 (CIRCUIT_OPEN)，
+// 不再排队等一个已经扛不住的上游，避免慢上游把网关的连接和 worker 一起拖住。
+//
+// - Closed: 正常转发，记录结果。窗口攒满之前不判定 (流量很小时几次失败不至于熔断)。
+// - Open(t): t 之后的第一个请求进入 HalfOpen。
+// - HalfOpen: 只放一个试探请求过去，其余照旧 503；试探成功 -> Closed (窗口清空)，失败 -> 重新 Open。
+//   试探请求没有结果 (客户端先断开等) 时，过了 open_duration 再放下一个试探。
+//
+// 和被动健康检查的区别：健康检查按节点摘除，熔断看的是整个集群 (节点全都慢、全都失败时)。
+// 失败的定义与被动健康检查相同：连接失败、超时等上游错误，以及 (AGW_OUTLIER_5XX 开启时) 5xx。
+// 集群没有配置 circuit_breaker 时不熔断。状态只在本数据面内存里，配置更新后按集群名延续。
+#[derive(Default)]
+pub struct CircuitBreakers {
+    clusters: Mutex<HashMap<String, CircuitBreaker>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed,
+    Open(Instant),
+    HalfOpen,
+}
+
+struct CircuitBreaker {
+    state: State,
+    // 最近的请求结果 (true = 失败)，最多 window_size 个
+    window: VecDeque<bool>,
+    // HalfOpen 时已放行的试探请求的放行时刻
+    probe: Option<Instant>,
+}
+
+// upstream_peer 询问能不能转发
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    // HalfOpen 下放行的试探请求，结果决定熔断关闭还是重新打开
+    Probe,
+    Rejected,
+}
+
+// window_size 的上限：每个集群的窗口都常驻内存，配置写错 (比如多打几个 0) 不能按它分配
+const MAX_WINDOW_SIZE: usize = 1000;
+
+struct Settings {
+    failure_threshold: u32,
+    window_size: usize,
+    open_duration: Duration,
+}
+
+fn settings(cluster: &Cluster) -> Option<Settings> {
+    let config = cluster.circuit_breaker.as_ref()?;
+    Some(Settings {
+        failure_threshold: match config.failure_threshold {
+            0 => 50,
+            pct => pct.min(100),
+        },
+        window_size: match config.window_size {
+            0 => 20,
+            n => (n as usize).min(MAX_WINDOW_SIZE),
+        },
+        open_duration: Duration::from_secs(match config.open_duration_secs {
+            0 => 30,
+            secs => secs as u64,
+        }),
+    })
+}
+
+impl CircuitBreakers {
+    pub fn admit(&self, cluster: &Cluster) -> Admission {
+        self.admit_at(cluster, Instant::now())
+    }
+
+    fn admit_at(&self, cluster: &Cluster, now: Instant) -> Admission {
+        let Some(settings) = settings(cluster) else {
+            return Admission::Allowed;
+        };
+        let mut clusters = self.clusters.lock().unwrap();
+        let Some(breaker) = clusters.get_mut(&cluster.name) else {
+            return Admission::Allowed;
+        };
+        match breaker.state {
+            State::Closed => Admission::Allowed,
+            State::Open(until) if now < until => Admission::Rejected,
+            State::Open(_) | State::HalfOpen => {
+                if breaker
+                    .probe
+                    .is_some_and(|t| now < t + settings.open_duration)
+                {
+                    return Admission::Rejected;
+                }
+                if breaker.state != State::HalfOpen {
+                    println!("circuit breaker {}: open -> half_open", cluster.name);
+                }
+                breaker.state = State::HalfOpen;
+                breaker.probe = Some(now);
+                Admission::Probe
+            }
+        }
+    }
+
+    // 上游请求结束后调用；probe 为 admit 返回 Probe 的那个请求
+    pub fn record(&self, cluster: &Cluster, failed: bool, probe: bool) {
+        self.record_at(cluster, failed, probe, Instant::now())
+    }
+
+    fn record_at(&self, cluster: &Cluster, failed: bool, probe: bool, now: Instant) {
+        let Some(settings) = settings(cluster) else {
+            return;
+        };
+        let mut clusters = self.clusters.lock().unwrap();
+        let breaker = clusters
+            .entry(cluster.name.clone())
+            .or_insert_with(|| CircuitBreaker {
+                state: State::Closed,
+                window: VecDeque::with_capacity(settings.window_size),
+                probe: None,
+            });
+        match breaker.state {
+            State::Closed => {
+                breaker.window.push_back(failed);
+                while breaker.window.len() > settings.window_size {
+                    breaker.window.pop_front();
+                }
+                let failures = breaker.window.iter().filter(|&&f| f).count();
+                if breaker.window.len() == settings.window_size
+                    && failures * 100 >= settings.failure_threshold as usize * settings.window_size
+                {
+                    println!(
+                        "circuit breaker {}: closed -> open ({}/{} failed, open for {:?})",
+                        cluster.name, failures, settings.window_size, settings.open_duration
+                    );
+                    breaker.state = State::Open(now + settings.open_duration);
+                    breaker.window.clear();
+                }
+            }
+            // 打开之前就已经发出去的请求，结果不再计入
+            State::Open(_) => {}
+            State::HalfOpen if !probe => {}
+            State::HalfOpen => {
+                breaker.probe = None;
+                if failed {
+                    println!(
+                        "circuit breaker {}: half_open -> open (probe failed)",
+                        cluster.name
+                    );
+                    breaker.state = State::Open(now + settings.open_duration);
+                } else {
+                    println!("circuit breaker {}: half_open -> closed", cluster.name);
+                    breaker.state = State::Closed;
+                }
+            }
+        }
+    }
+
+    // 配置更新后调用：删除已经不在快照里的集群，返回删除的条数
+    pub fn retain(&self, live: &HashMap<String, HashSet<String>>) -> usize {
+        let mut clusters = self.clusters.lock().unwrap();
+        let before = clusters.len();
+        clusters.retain(|name, _| live.contains_key(name));
+        before - clusters.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::agw::config::v1::CircuitBreaker as CircuitBreakerConfig;
+
+    const OPEN: Duration = Duration::from_secs(30);
+
+    // 窗口 10 个请求，失败一半打开，打开 30 秒
+    fn cluster() -> Cluster {
+        Cluster {
+            name: "backend".to_string(),
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 50,
+                window_size: 10,
+                open_duration_secs: OPEN.as_secs() as u32,
+            }),
+            ..Default::default()
+        }
+    }
+
+    // 在 now 打开熔断：5 次成功 + 5 次失败刚好达到 50%
+    fn opened(now: Instant) -> CircuitBreakers {
+        let breakers = CircuitBreakers::default();
+        let cluster = cluster();
+        for i in 0..10 {
+            assert_eq!(breakers.admit_at(&cluster, now), Admission::Allowed);
+            breakers.record_at(&cluster, i % 2 == 0, false, now);
+        }
+        breakers
+    }
+
+    #[test]
+    fn closed_opens_at_the_threshold() {
+        let now = Instant::now();
+        let cluster = cluster();
+        let breakers = CircuitBreakers::default();
+        // 4/10 失败：低于阈值，保持关闭
+        for i in 0..10 {
+            breakers.record_at(&cluster, i < 4, false, now);
+        }
+        assert_eq!(breakers.admit_at(&cluster, now), Admission::Allowed);
+
+        let breakers = opened(now);
+        assert_eq!(breakers.admit_at(&cluster, now), Admission::Rejected);
+    }
+
+    #[test]
+    fn window_must_fill_before_opening() {
+        let now = Instant::now();
+        let cluster = cluster();
+        let breakers = CircuitBreakers::default();
+        for _ in 0..9 {
+            breakers.record_at(&cluster, true, false, now);
+        }
+        assert_eq!(breakers.admit_at(&cluster, now), Admission::Allowed);
+        breakers.record_at(&cluster, true, false, now);
+        assert_eq!(breakers.admit_at(&cluster, now), Admission::Rejected);
+    }
+
+    #[test]
+    fn open_lets_exactly_one_probe_through_after_open_duration() {
+        let now = Instant::now();
+        let cluster = cluster();
+        let breakers = opened(now);
+        let before = now + OPEN - Duration::from_millis(1);
+        assert_eq!(breakers.admit_at(&cluster, before), Admission::Rejected);
+
+        let after = now + OPEN;
+        assert_eq!(breakers.admit_at(&cluster, after), Admission::Probe);
+        for _ in 0..5 {
+            assert_eq!(breakers.admit_at(&cluster, after), Admission::Rejected);
+        }
+        // 试探请求迟迟没有结果：过了 open_duration 才放下一个
+        assert_eq!(
+            breakers.admit_at(&cluster, after + OPEN - Duration::from_millis(1)),
+            Admission::Rejected
+        );
+        assert_eq!(breakers.admit_at(&cluster, after + OPEN), Admission::Probe);
+    }
+
+    #[test]
+    fn probe_success_closes() {
+        let now = Instant::now();
+        let cluster = cluster();
+        let breakers = opened(now);
+        let after = now + OPEN;
+        assert_eq!(breakers.admit_at(&cluster, after), Admission::Probe);
+        // 非试探请求 (打开之前发出去的) 的结果不影响状态
+        breakers.record_at(&cluster, false, false, after);
+        assert_eq!(breakers.admit_at(&cluster, after), Admission::Rejected);
+
+        breakers.record_at(&cluster, false, true, after);
+        assert_eq!(breakers.admit_at(&cluster, after), Admission::Allowed);
+        // 关闭时窗口是空的：一次失败不会立刻重新打开
+        breakers.record_at(&cluster, true, false, after);
+        assert_eq!(breakers.admit_at(&cluster, after), Admission::Allowed);
+    }
+
+    #[test]
+    fn probe_failure_reopens() {
+        let now = Instant::now();
+        let cluster = cluster();
+        let breakers = opened(now);
+        let after = now + OPEN;
+        assert_eq!(breakers.admit_at(&cluster, after), Admission::Probe);
+        breakers.record_at(&cluster, true, true, after);
+        assert_eq!(breakers.admit_at(&cluster, after), Admission::Rejected);
+        assert_eq!(
+            breakers.admit_at(&cluster, after + OPEN - Duration::from_millis(1)),
+            Admission::Rejected
+        );
+        assert_eq!(breakers.admit_at(&cluster, after + OPEN), Admission::Probe);
+    }
+
+    #[test]
+    fn clusters_without_circuit_breaker_never_open() {
+        let now = Instant::now();
+        let cluster = Cluster {
+            circuit_breaker: None,
+            ..cluster()
+        };
+        let breakers = CircuitBreakers::default();
+        for _ in 0..100 {
+            breakers.record_at(&cluster, true, false, now);
+        }
+        assert_eq!(breakers.admit_at(&cluster, now), Admission::Allowed);
+    }
+
+    #[test]
+    fn window_size_is_capped() {
+        let mut cluster = cluster();
+        cluster.circuit_breaker.as_mut().unwrap().window_size = u32::MAX;
+        assert_eq!(settings(&cluster).unwrap().window_size, MAX_WINDOW_SIZE);
+
+        // 按上限判定：攒满 MAX_WINDOW_SIZE 个失败就打开
+        let now = Instant::now();
+        let breakers = CircuitBreakers::default();
+        for _ in 0..MAX_WINDOW_SIZE {
+            breakers.record_at(&cluster, true, false, now);
+        }
+        assert_eq!(breakers.admit_at(&cluster, now), Admission::Rejected);
+    }
+}
@@ -34,6 +34,7 @@ impl AgwClient {
             id: self.node_id.clone(),
             region: "us-east-1".to_string(), // Placeholder
             version: "0.1.0".to_string(),
+            ..Default::default()
         });
 
         let mut stream = self.client.stream_config(request).await?.into_inner();
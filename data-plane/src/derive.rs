@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::client::agw::v1::{ConfigError, ConfigErrorCode, ConfigSnapshot};
+use crate::router::ActiveConfig;
+use crate::validate::config_error;
+
+// 【快照派生 (Derive)】
+// 编译 ActiveConfig (路由索引、正则、插件链……) 是应用配置里最重的一步，放到 blocking 线程上做，
+// 后台 Runtime 的 worker 继续读配置流，不会因为一份大快照卡住 gRPC 流 (keepalive、背压)。
+// 派生期间来了更新的快照时放弃这一份，由调用方接着派生最新的。
+// 只有这一步可以被打断：原子替换之后的收尾 (状态延续、证书、插件热更新、ready) 一旦开始就必须做完，
+// 否则被打断的那份配置已经生效，收尾却没做，下一次应用也看不出差异。
+pub enum Derivation {
+    Ready(ActiveConfig),
+    // 快照有问题 (校验失败、派生超时或任务崩溃)，整份拒绝
+    Rejected(Vec<ConfigError>),
+    // 派生完成前通道里来了更新的快照
+    Superseded,
+}
+
+// newer 是配置流的 watch 通道：调用方已经 borrow_and_update 取走了 snapshot，之后的任何新值都会打断派生。
+// 发送端关闭 (流重连) 不算打断，派生照常完成。
+pub async fn derive<T>(
+    snapshot: ConfigSnapshot,
+    timeout: Duration,
+    newer: &mut watch::Receiver<T>,
+) -> Derivation {
+    let task = tokio::task::spawn_blocking(move || ActiveConfig::compile(snapshot));
+    tokio::select! {
+        result = tokio::time::timeout(timeout, task) => match result {
+            Ok(Ok(Ok(active))) => Derivation::Ready(active),
+            Ok(Ok(Err(errors))) => Derivation::Rejected(errors),
+            Ok(Err(e)) => Derivation::Rejected(vec![config_error(
+                ConfigErrorCode::Internal,
+                "",
+                format!("derivation task failed: {}", e),
+            )]),
+            Err(_) => Derivation::Rejected(vec![config_error(
+                ConfigErrorCode::ApplyTimeout,
+                "",
+                format!("apply timed out after {:?}", timeout),
+            )]),
+        },
+        Ok(()) = newer.changed() => Derivation::Superseded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::agw::config::v1::{Cluster, PathMatchType, Route};
+    use std::time::Instant;
+
+    // 几千条正则路由：debug 构建下编译要花上百毫秒
+    fn heavy_snapshot(routes: usize) -> ConfigSnapshot {
+        ConfigSnapshot {
+            version_id: "heavy".to_string(),
+            clusters: vec![Cluster {
+                name: "backend".to_string(),
+                ..Default::default()
+            }],
+            routes: (0..routes)
+                .map(|i| {
+                    let mut route = Route {
+                        path_prefix: format!(r"/svc-{}/(users|orders)/[a-z0-9-]+/\d+", i),
+                        cluster_id: "backend".to_string(),
+                        ..Default::default()
+                    };
+                    route.set_match_type(PathMatchType::PathMatchRegex);
+                    route
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    // current_thread：派生如果在 worker 上同步执行，模拟读流的任务在派生结束前一次也跑不了
+    #[tokio::test(flavor = "current_thread")]
+    async fn heavy_derivation_does_not_stall_the_stream() {
+        let (tx, mut rx) = watch::channel(());
+        let streaming = tokio::spawn(async move {
+            let mut worst = Duration::ZERO;
+            let mut ticks = 0;
+            let mut last = Instant::now();
+            loop {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                worst = worst.max(last.elapsed());
+                last = Instant::now();
+                ticks += 1;
+                if tx.is_closed() {
+                    return (ticks, worst);
+                }
+            }
+        });
+
+        let started = Instant::now();
+        let derivation = derive(heavy_snapshot(5_000), TIMEOUT, &mut rx).await;
+        let took = started.elapsed();
+        drop(rx);
+        let (ticks, worst) = streaming.await.unwrap();
+
+        assert!(matches!(derivation, Derivation::Ready(ref active) if active.routes.len() == 5_000));
+        assert!(ticks as u128 >= took.as_millis() / 50, "{} ticks in {:?}", ticks, took);
+        assert!(worst < Duration::from_millis(250), "stream stalled for {:?}", worst);
+    }
+
+    #[tokio::test]
+    async fn newer_snapshot_supersedes_the_derivation() {
+        let (tx, mut rx) = watch::channel(0);
+        let streaming = tokio::spawn(async move {
+            for version in 1..=20 {
+                tokio::time::sleep(Duration::from_millis(2)).await;
+                tx.send_replace(version);
+            }
+        });
+        let started = Instant::now();
+        let derivation = derive(heavy_snapshot(20_000), TIMEOUT, &mut rx).await;
+        assert!(matches!(derivation, Derivation::Superseded));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        streaming.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn closed_stream_does_not_supersede() {
+        let (tx, mut rx) = watch::channel(());
+        drop(tx);
+        let derivation = derive(heavy_snapshot(10), TIMEOUT, &mut rx).await;
+        assert!(matches!(derivation, Derivation::Ready(_)));
+    }
+
+    #[tokio::test]
+    async fn invalid_snapshot_is_rejected() {
+        let (_tx, mut rx) = watch::channel(());
+        let mut snapshot = heavy_snapshot(1);
+        snapshot.routes[0].path_prefix = "/svc/(".to_string();
+        match derive(snapshot, TIMEOUT, &mut rx).await {
+            Derivation::Rejected(errors) => {
+                assert_eq!(errors[0].code(), ConfigErrorCode::InvalidRegex)
+            }
+            _ => panic!("expected the snapshot to be rejected"),
+        }
+    }
+}
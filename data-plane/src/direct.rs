@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::client::agw::config::v1::DirectResponse;
+use crate::reason::ReasonCode;
+
+// 【固定响应 (Direct Response)】
+// 路由配置了 direct_response 时，网关在 request_filter 里直接返回配置好的状态码、响应头和响应体，
+// 不经过任何上游。典型场景：维护页、/robots.txt、给还没上线的接口先挂一个桩。
+//
+// 和重定向一样，路由的插件链先执行，之后不再做幂等、缓存和转发；
+// 这类路由不能再配置 cluster_id 或 redirect (快照校验时拒绝)。
+// 响应体随配置下发、常驻内存，因此限制大小；HEAD 请求只返回响应头。
+#[derive(Debug)]
+pub struct CompiledDirectResponse {
+    pub status: u16,
+    pub headers: Vec<(http::HeaderName, http::HeaderValue)>,
+    pub body: Bytes,
+}
+
+// 响应体上限：放得下一个维护页，又不至于让配置快照膨胀
+const MAX_BODY_BYTES: usize = 512 * 1024;
+
+const DEFAULT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+impl CompiledDirectResponse {
+    pub fn compile(action: &DirectResponse) -> Result<Self, String> {
+        let status = match action.status {
+            0 => 200,
+            code @ 200..=599 => code as u16,
+            code => return Err(format!("status {} must be between 200 and 599", code)),
+        };
+        if action.body.len() > MAX_BODY_BYTES {
+            return Err(format!(
+                "body is {} bytes, the limit is {}",
+                action.body.len(),
+                MAX_BODY_BYTES
+            ));
+        }
+        let mut headers = Vec::with_capacity(action.headers.len() + 1);
+        if !action.content_type.is_empty() {
+            let value = http::HeaderValue::from_str(&action.content_type)
+                .map_err(|_| format!("invalid content_type {:?}", action.content_type))?;
+            headers.push((http::header::CONTENT_TYPE, value));
+        }
+        // map 的遍历顺序不固定，排序后响应头顺序在每次下发之间保持一致
+        let mut extra: Vec<_> = action.headers.iter().collect();
+        extra.sort();
+        for (name, value) in extra {
+            let name: http::HeaderName = name
+                .parse()
+                .map_err(|_| format!("invalid header name {:?}", name))?;
+            if name == http::header::CONTENT_LENGTH || name == http::header::TRANSFER_ENCODING {
+                return Err(format!("header {} is set by the gateway", name));
+            }
+            if name == http::header::CONTENT_TYPE && !action.content_type.is_empty() {
+                return Err("Content-Type is set both in content_type and in headers".to_string());
+            }
+            let value = http::HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value for header {}", name))?;
+            headers.push((name, value));
+        }
+        if !action.body.is_empty() && !headers.iter().any(|(n, _)| n == http::header::CONTENT_TYPE)
+        {
+            headers.push((
+                http::header::CONTENT_TYPE,
+                http::HeaderValue::from_static(DEFAULT_CONTENT_TYPE),
+            ));
+        }
+        Ok(Self {
+            status,
+            headers,
+            body: Bytes::copy_from_slice(&action.body),
+        })
+    }
+}
+
+// 【停用的路由 (disabled)】
+// 故障处理时把路由标成 disabled，不用从控制面删掉它 (删掉就丢了配置)：下一份快照生效后，
+// 命中这条路由的请求在插件链之前直接返回 disabled_response，默认是 503 + JSON 错误体 (原因码 ROUTE_DISABLED)。
+// 响应总是带 Retry-After (disabled_response.headers 里没写时补上默认值)，客户端据此退避。
+// 这类请求单独计入 agw_route_disabled_total{route}，和上游真实的 503 区分开。
+const DISABLED_RETRY_AFTER: &str = "30";
+
+impl CompiledDirectResponse {
+    // disabled_response 的 status 不填时为 503
+    pub fn disabled(action: Option<&DirectResponse>) -> Result<Self, String> {
+        let mut response = match action {
+            Some(action) => {
+                let mut compiled = Self::compile(action)?;
+                if action.status == 0 {
+                    compiled.status = 503;
+                }
+                compiled
+            }
+            None => Self {
+                status: 503,
+                headers: vec![(
+                    http::header::CONTENT_TYPE,
+                    http::HeaderValue::from_static("application/json"),
+                )],
+                body: Bytes::from(ReasonCode::RouteDisabled.error_body(503)),
+            },
+        };
+        if !response
+            .headers
+            .iter()
+            .any(|(name, _)| name == http::header::RETRY_AFTER)
+        {
+            response.headers.push((
+                http::header::RETRY_AFTER,
+                http::HeaderValue::from_static(DISABLED_RETRY_AFTER),
+            ));
+        }
+        Ok(response)
+    }
+}
+
+// 【自定义错误响应 (error_responses)】
+// 网关自己生成的错误 (插件拒绝、没有可用节点、上游超时……) 默认返回带原因码的 JSON 错误体
+// (见 reason.rs)。快照的 error_responses 按状态码把它换成自己的响应体，例如和业务接口一致的
+// 错误格式，或者给浏览器看的 HTML 错误页。响应体是模板，发送时替换占位符：
+// - %STATUS%: 状态码
+// - %REASON%: 原因码，如 PLUGIN_DENY
+// - %REQUEST_ID%: 请求的 trace_id，和访问日志、traceparent 一致，用于客户端报障时对账
+//
+// 只影响网关生成的错误，上游返回的 4xx / 5xx 原样转发；gRPC 请求仍然返回 Trailers-Only 响应。
+// 没有路由时的 404 优先使用 not_found_response。
+#[derive(Debug, Default)]
+pub struct ErrorPages {
+    by_status: HashMap<u16, CompiledDirectResponse>,
+}
+
+impl ErrorPages {
+    // 返回编译好的模板，或者出错的下标和原因
+    pub fn compile(responses: &[DirectResponse]) -> Result<Self, Vec<(usize, String)>> {
+        let mut pages = Self::default();
+        let mut errors = Vec::new();
+        for (i, action) in responses.iter().enumerate() {
+            if !(400..=599).contains(&action.status) {
+                errors.push((
+                    i,
+                    format!("status {} must be between 400 and 599", action.status),
+                ));
+                continue;
+            }
+            if std::str::from_utf8(&action.body).is_err() {
+                errors.push((i, "body template is not valid UTF-8".to_string()));
+                continue;
+            }
+            match CompiledDirectResponse::compile(action) {
+                Ok(page) => {
+                    if pages.by_status.insert(page.status, page).is_some() {
+                        errors.push((i, format!("duplicate status {}", action.status)));
+                    }
+                }
+                Err(e) => errors.push((i, e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(pages)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // 这个状态码配置了模板时返回替换好占位符的响应
+    pub fn render(
+        &self,
+        status: u16,
+        reason: ReasonCode,
+        request_id: Option<&str>,
+    ) -> Option<CompiledDirectResponse> {
+        let page = self.by_status.get(&status)?;
+        let body = String::from_utf8_lossy(&page.body)
+            .replace("%STATUS%", &status.to_string())
+            .replace("%REASON%", reason.as_str())
+            .replace("%REQUEST_ID%", request_id.unwrap_or("-"));
+        Some(CompiledDirectResponse {
+            status,
+            headers: page.headers.clone(),
+            body: Bytes::from(body),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON_TEMPLATE: &str =
+        r#"{"code":%STATUS%,"reason":"%REASON%","request_id":"%REQUEST_ID%"}"#;
+
+    fn page(status: u32, body: &str, content_type: &str) -> DirectResponse {
+        DirectResponse {
+            status,
+            body: body.as_bytes().to_vec(),
+            content_type: content_type.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn content_type(response: &CompiledDirectResponse) -> Option<&str> {
+        response
+            .headers
+            .iter()
+            .find(|(name, _)| name == http::header::CONTENT_TYPE)
+            .map(|(_, value)| value.to_str().unwrap())
+    }
+
+    fn json_pages() -> ErrorPages {
+        ErrorPages::compile(&[
+            page(403, JSON_TEMPLATE, "application/json"),
+            page(404, JSON_TEMPLATE, "application/json"),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn plugin_denial_renders_the_json_page() {
+        let response = json_pages()
+            .render(403, ReasonCode::PluginDeny, Some("4bf92f3577b34da6"))
+            .unwrap();
+        assert_eq!(response.status, 403);
+        assert_eq!(content_type(&response), Some("application/json"));
+        let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "code": 403,
+                "reason": "PLUGIN_DENY",
+                "request_id": "4bf92f3577b34da6",
+            })
+        );
+    }
+
+    #[test]
+    fn no_route_renders_the_json_page() {
+        let response = json_pages().render(404, ReasonCode::NoRoute, None).unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(content_type(&response), Some("application/json"));
+        let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"code": 404, "reason": "NO_ROUTE", "request_id": "-"})
+        );
+    }
+
+    #[test]
+    fn statuses_without_a_page_fall_back_to_the_default_body() {
+        assert!(
+            json_pages()
+                .render(502, ReasonCode::NoEndpoint, None)
+                .is_none()
+        );
+        assert!(
+            ErrorPages::default()
+                .render(404, ReasonCode::NoRoute, None)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn render_replaces_every_placeholder_occurrence() {
+        let pages = ErrorPages::compile(&[page(
+            503,
+            "<h1>%STATUS%</h1><p>%REASON%</p><!-- %REQUEST_ID% %STATUS% -->",
+            "text/html",
+        )])
+        .unwrap();
+        let response = pages
+            .render(503, ReasonCode::RouteDisabled, Some("abc"))
+            .unwrap();
+        assert_eq!(
+            response.body,
+            "<h1>503</h1><p>ROUTE_DISABLED</p><!-- abc 503 -->"
+        );
+        assert_eq!(content_type(&response), Some("text/html"));
+    }
+
+    #[test]
+    fn compile_rejects_statuses_outside_4xx_5xx() {
+        let errors = ErrorPages::compile(&[
+            page(404, "{}", "application/json"),
+            page(302, "{}", "application/json"),
+            page(0, "{}", "application/json"),
+        ])
+        .unwrap_err();
+        let indexes: Vec<usize> = errors.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indexes, vec![1, 2]);
+        assert!(errors[0].1.contains("between 400 and 599"));
+    }
+
+    #[test]
+    fn compile_rejects_duplicate_statuses() {
+        let errors = ErrorPages::compile(&[
+            page(500, "first", ""),
+            page(502, "other", ""),
+            page(500, "second", ""),
+        ])
+        .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 2);
+        assert_eq!(errors[0].1, "duplicate status 500");
+    }
+}
@@ -0,0 +1,69 @@
+use crate::client::agw::config::v1::PluginExclusion;
+use crate::client::agw::config::v1::string_match::Pattern;
+use crate::matcher::CompiledMatch;
+
+// 【插件链豁免 (Plugin Exclusion)】
+// K8s 存活探针、上游健康检查、CORS 预检 (OPTIONS) 这类请求不带业务凭证，
+// 走完整的插件链 (比如 JWT 校验) 只会被拒绝，导致探针失败、Pod 反复重启。
+// 路由上可以配置一组 "方法 + 路径" 规则，命中的请求跳过插件链，但照常路由、照常记访问日志，
+// 并且在日志里标出命中的是哪条豁免，方便审计有哪些请求绕过了鉴权。
+#[derive(Debug)]
+pub struct CompiledExclusion {
+    // 大写的方法名；为空表示任意方法
+    methods: Vec<String>,
+    path: CompiledMatch,
+    // 访问日志里的标签，如 "GET /healthz"
+    pub label: String,
+}
+
+// 看起来像健康检查的路径关键字 (用于配置校验时的误用提示)
+const HEALTH_HINTS: [&str; 5] = ["health", "ready", "live", "ping", "status"];
+
+impl CompiledExclusion {
+    pub fn compile(exclusion: &PluginExclusion) -> Result<Self, String> {
+        let methods: Vec<String> = exclusion
+            .methods
+            .iter()
+            .map(|m| m.trim().to_ascii_uppercase())
+            .filter(|m| !m.is_empty())
+            .collect();
+        let (path, pattern) = match &exclusion.path {
+            Some(m) => (CompiledMatch::compile(m)?, pattern_text(m.pattern.as_ref())),
+            None => (CompiledMatch::prefix(""), "*".to_string()),
+        };
+        let label = format!(
+            "{} {}",
+            if methods.is_empty() { "*".to_string() } else { methods.join("|") },
+            pattern
+        );
+        Ok(Self {
+            methods,
+            path,
+            label,
+        })
+    }
+
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        (self.methods.is_empty() || self.methods.iter().any(|m| m == method))
+            && self.path.matches(path)
+    }
+
+    // 误用提示：除了纯 OPTIONS 预检以外，豁免路径应该看起来像健康检查。
+    // 像 "任意方法 /api" 这样的规则等于把整个路由的鉴权关掉了，值得在日志里提醒一下。
+    pub fn looks_suspicious(&self) -> bool {
+        let preflight_only = !self.methods.is_empty() && self.methods.iter().all(|m| m == "OPTIONS");
+        let label = self.label.to_ascii_lowercase();
+        !preflight_only && !HEALTH_HINTS.iter().any(|hint| label.contains(hint))
+    }
+}
+
+fn pattern_text(pattern: Option<&Pattern>) -> String {
+    match pattern {
+        Some(Pattern::Exact(s)) => s.clone(),
+        Some(Pattern::Prefix(s)) => format!("{}*", s),
+        Some(Pattern::Suffix(s)) => format!("*{}", s),
+        Some(Pattern::Contains(s)) => format!("*{}*", s),
+        Some(Pattern::Regex(s)) => format!("~{}", s),
+        None => "*".to_string(),
+    }
+}
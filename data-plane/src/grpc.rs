@@ -0,0 +1,54 @@
+use pingora::http::ResponseHeader;
+
+use crate::reason::ReasonCode;
+
+// 【gRPC 路由与转发】
+// gRPC 是 HTTP/2 上的 POST /package.Service/Method，请求和响应的 Content-Type 都是 application/grpc[+proto|+json]，
+// 结果放在响应末尾的 trailer (grpc-status / grpc-message) 里。网关这边需要三件事：
+// - 匹配：路由 protocol = GRPC 时只匹配 gRPC 请求 (按 Content-Type 判断)，
+//   同一个路径前缀可以再配一条普通路由接住浏览器 / REST 流量。
+// - 转发：集群 protocol = GRPC 时用 HTTP/2 连接上游 (明文节点是 h2c prior knowledge)，
+//   监听端口本身同时接受 HTTP/1.1 和 HTTP/2 (TLS 通过 ALPN，明文通过 h2c 前导)。
+//   trailer 按路由的 trailer_policy 透传 (gRPC 客户端都会带 TE: trailers)，带 trailer 的响应不进缓存。
+// - 出错：网关自己生成的错误 (连不上上游、超时、插件拒绝、没有路由……) 对 gRPC 客户端返回
+//   "Trailers-Only" 响应：HTTP 200，grpc-status / grpc-message 直接放在响应头里，没有响应体。
+//   JSON 错误体对 gRPC 客户端没有意义，它们只会报一个看不懂的 "malformed response"。
+
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+
+// 请求是不是 gRPC：Content-Type 为 application/grpc，或带子类型的 application/grpc+proto 等
+pub fn is_grpc(headers: &http::HeaderMap) -> bool {
+    headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .is_some_and(|v| {
+            v == GRPC_CONTENT_TYPE
+                || v.strip_prefix(GRPC_CONTENT_TYPE)
+                    .is_some_and(|rest| rest.starts_with('+'))
+        })
+}
+
+// 网关的 HTTP 状态码对应的 gRPC 状态码，按 gRPC 官方的 "HTTP to gRPC Status Code Mapping"；
+// 上游超时 (504) 例外，返回更准确的 DEADLINE_EXCEEDED
+pub fn status_for_http(status: u16) -> u32 {
+    match status {
+        400 => 13,             // INTERNAL
+        401 => 16,             // UNAUTHENTICATED
+        403 => 7,              // PERMISSION_DENIED
+        404 => 12,             // UNIMPLEMENTED
+        504 => 4,              // DEADLINE_EXCEEDED
+        429 | 502 | 503 => 14, // UNAVAILABLE
+        _ => 2,                // UNKNOWN
+    }
+}
+
+// Trailers-Only 错误响应：只有响应头，调用方以 end_of_stream 写出
+pub fn trailers_only(status: u16, reason: ReasonCode) -> pingora::Result<ResponseHeader> {
+    let mut header = ResponseHeader::build(200, Some(3))?;
+    header.insert_header("Content-Type", GRPC_CONTENT_TYPE)?;
+    header.insert_header("grpc-status", status_for_http(status).to_string())?;
+    header.insert_header("grpc-message", reason.as_str())?;
+    Ok(header)
+}
@@ -0,0 +1,982 @@
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+use crate::client::agw::config::v1::{Endpoint, HealthCheck};
+use crate::reason::ReasonCode;
+use crate::router::ActiveConfig;
+use crate::upstream::endpoint_label;
+
+// 【节点状态注册表 (Endpoint State Registry)】
+// "节点 X 现在能不能用" 只有一个答案，就在这里。
+// 所有健康信号 (被动的请求失败统计、主动探测 (HealthChecker)，以及以后的 DNS 解析结果等) 都按来源写入各自的 input，
+// 负载均衡、管理端口 /clusters/{name}/endpoints 都只读这里算出的综合结论。
+//
+// 综合结论 (Availability)：
+// - 任一 input 不健康 -> Ejected (原因取自该 input)
+// - 只有被动 input 不健康且摘除时间已过 -> Probing (放一些请求过去试探)
+// - Probing 期间请求成功 -> Healthy；再次失败 -> 重新 Ejected
+// 每次状态变化都会打一条日志 (healthy -> ejected, ejected -> probing, probing -> healthy ...)。
+//
+// 【运维覆盖 (Operator Override)】
+// 故障时运维可以通过管理端口把某个节点标记为 "人工下线"，或者把整个集群 drain 掉，不用等 CP 推配置。
+// - 覆盖只存在内存里，与配置快照无关：配置更新不会清掉它，只有显式清除或 TTL 到期才会失效。
+// - 覆盖只能让节点下线，不能让节点上线：配置里已经删掉的节点不会因为覆盖而复活。
+// - 覆盖作为 "admin" input 出现在节点状态里；负载均衡对人工下线的节点没有 "全部不可用时兜底" 的例外。
+pub struct EndpointRegistry {
+    states: Mutex<HashMap<(String, String), EndpointState>>,
+    // (cluster, endpoint) -> 覆盖；endpoint 为 None 表示整个集群
+    overrides: Mutex<HashMap<(String, Option<String>), Override>>,
+    // 连续失败多少次后摘除
+    consecutive_failures: u32,
+    // 摘除时长，之后进入 Probing
+    ejection: Duration,
+    // 连续失败的统计窗口：距上一次失败超过窗口时重新计数 (None = 不限)
+    window: Option<Duration>,
+    // 上游返回 5xx 是否算失败
+    count_5xx: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Availability {
+    Healthy,
+    Ejected,
+    Probing,
+}
+
+// 被动健康检查 (根据真实请求的结果) 的来源名
+const PASSIVE: &str = "passive";
+// 运维覆盖的来源名
+const ADMIN: &str = "admin";
+// 主动健康检查的来源名
+const ACTIVE: &str = "active";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Override {
+    pub cluster: String,
+    // None = 整个集群被 drain
+    pub endpoint: Option<String>,
+    pub reason: String,
+    pub created_ms: u64,
+    // None = 不过期，直到显式清除
+    pub expires_ms: Option<u64>,
+    #[serde(skip)]
+    expires_at: Option<Instant>,
+}
+
+impl Override {
+    fn expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|t| now >= t)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputVerdict {
+    pub healthy: bool,
+    pub reason: String,
+    pub at_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointState {
+    pub availability: Availability,
+    pub reason: String,
+    // 进入当前状态的时间 (unix 毫秒)
+    pub since_ms: u64,
+    pub consecutive_failures: u32,
+    // 主动探测的连续成功 / 失败次数 (没有配置 health_check 时为 0)
+    pub probe_successes: u32,
+    pub probe_failures: u32,
+    pub inputs: BTreeMap<&'static str, InputVerdict>,
+    #[serde(skip)]
+    ejected_until: Option<Instant>,
+    #[serde(skip)]
+    last_failure_at: Option<Instant>,
+}
+
+impl Default for EndpointState {
+    fn default() -> Self {
+        Self {
+            availability: Availability::Healthy,
+            reason: String::new(),
+            since_ms: now_ms(),
+            consecutive_failures: 0,
+            probe_successes: 0,
+            probe_failures: 0,
+            inputs: BTreeMap::new(),
+            ejected_until: None,
+            last_failure_at: None,
+        }
+    }
+}
+
+impl EndpointRegistry {
+    pub fn from_env() -> Self {
+        let consecutive_failures = std::env::var("AGW_OUTLIER_CONSECUTIVE_FAILURES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5);
+        let ejection_secs = std::env::var("AGW_OUTLIER_EJECTION_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        let window_secs: u64 = std::env::var("AGW_OUTLIER_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let count_5xx = std::env::var("AGW_OUTLIER_5XX")
+            .map(|v| v != "false" && v != "0")
+            .unwrap_or(true);
+        Self {
+            states: Mutex::new(HashMap::new()),
+            overrides: Mutex::new(HashMap::new()),
+            consecutive_failures,
+            ejection: Duration::from_secs(ejection_secs),
+            window: (window_secs > 0).then(|| Duration::from_secs(window_secs)),
+            count_5xx,
+        }
+    }
+
+    // 负载均衡使用：节点当前是否可以接收请求 (Healthy 或 Probing)
+    pub fn is_available(&self, cluster: &str, endpoint: &str) -> bool {
+        let active = self.active_override(cluster, endpoint);
+        let key = (cluster.to_string(), endpoint.to_string());
+        let mut states = self.states.lock().unwrap();
+        if active.is_none() && !states.contains_key(&key) {
+            // 没有任何记录的节点默认可用
+            return true;
+        }
+        let state = states.entry(key).or_default();
+        sync_override(state, active.as_ref());
+        refresh(cluster, endpoint, state);
+        state.availability != Availability::Ejected
+    }
+
+    // upstream_peer 使用：从候选节点里去掉人工下线的，再跳过被摘除的；
+    // 全部被摘除时仍然使用 (去掉人工下线后的) 完整列表，总比直接 503 好
+    pub fn usable<'a>(&self, cluster: &str, candidates: Vec<&'a Endpoint>) -> Vec<&'a Endpoint> {
+        let candidates: Vec<_> = candidates
+            .into_iter()
+            .filter(|e| !self.is_admin_down(cluster, &endpoint_label(e)))
+            .collect();
+        let available: Vec<_> = candidates
+            .iter()
+            .copied()
+            .filter(|e| self.is_available(cluster, &endpoint_label(e)))
+            .collect();
+        if available.is_empty() {
+            candidates
+        } else {
+            available
+        }
+    }
+
+    // 负载均衡使用：节点是否被运维人工下线 (不参与 "全部不可用时兜底")
+    pub fn is_admin_down(&self, cluster: &str, endpoint: &str) -> bool {
+        self.active_override(cluster, endpoint).is_some()
+    }
+
+    // 设置覆盖：endpoint 为 None 时 drain 整个集群。同一对象重复设置会替换旧的覆盖。
+    pub fn set_override(
+        &self,
+        cluster: &str,
+        endpoint: Option<&str>,
+        ttl: Option<Duration>,
+        reason: &str,
+    ) -> Override {
+        let now = Instant::now();
+        let created_ms = now_ms();
+        let entry = Override {
+            cluster: cluster.to_string(),
+            endpoint: endpoint.map(str::to_string),
+            reason: reason.to_string(),
+            created_ms,
+            expires_ms: ttl.map(|ttl| created_ms + ttl.as_millis() as u64),
+            expires_at: ttl.map(|ttl| now + ttl),
+        };
+        println!(
+            "operator override set: {} / {} ({}, ttl {:?})",
+            cluster,
+            endpoint.unwrap_or("*"),
+            reason,
+            ttl
+        );
+        self.overrides.lock().unwrap().insert(
+            (cluster.to_string(), endpoint.map(str::to_string)),
+            entry.clone(),
+        );
+        entry
+    }
+
+    // 清除覆盖；返回是否真的清掉了一条 (未过期的) 覆盖
+    pub fn clear_override(&self, cluster: &str, endpoint: Option<&str>) -> bool {
+        let removed = self
+            .overrides
+            .lock()
+            .unwrap()
+            .remove(&(cluster.to_string(), endpoint.map(str::to_string)))
+            .is_some_and(|o| !o.expired(Instant::now()));
+        if removed {
+            println!(
+                "operator override cleared: {} / {}",
+                cluster,
+                endpoint.unwrap_or("*")
+            );
+        }
+        removed
+    }
+
+    // 当前生效的全部覆盖 (顺带清理已过期的)
+    pub fn overrides(&self) -> Vec<Override> {
+        let now = Instant::now();
+        let mut overrides = self.overrides.lock().unwrap();
+        overrides.retain(|_, o| !o.expired(now));
+        let mut list: Vec<Override> = overrides.values().cloned().collect();
+        list.sort_by(|a, b| (&a.cluster, &a.endpoint).cmp(&(&b.cluster, &b.endpoint)));
+        list
+    }
+
+    // 节点级覆盖优先于集群级覆盖 (原因更具体)
+    fn active_override(&self, cluster: &str, endpoint: &str) -> Option<Override> {
+        let now = Instant::now();
+        let mut overrides = self.overrides.lock().unwrap();
+        for key in [
+            (cluster.to_string(), Some(endpoint.to_string())),
+            (cluster.to_string(), None),
+        ] {
+            match overrides.get(&key) {
+                Some(o) if o.expired(now) => {
+                    println!(
+                        "operator override expired: {} / {}",
+                        cluster,
+                        key.1.as_deref().unwrap_or("*")
+                    );
+                    overrides.remove(&key);
+                }
+                Some(o) => return Some(o.clone()),
+                None => {}
+            }
+        }
+        None
+    }
+
+    // 被动输入：请求成功
+    pub fn record_success(&self, cluster: &str, endpoint: &str) {
+        let mut states = self.states.lock().unwrap();
+        let Some(state) = states.get_mut(&(cluster.to_string(), endpoint.to_string())) else {
+            return;
+        };
+        state.consecutive_failures = 0;
+        state.ejected_until = None;
+        state.inputs.insert(
+            PASSIVE,
+            InputVerdict {
+                healthy: true,
+                reason: "request succeeded".to_string(),
+                at_ms: now_ms(),
+            },
+        );
+        refresh(cluster, endpoint, state);
+    }
+
+    // 被动输入是否把上游 5xx 响应算作失败 (AGW_OUTLIER_5XX，默认开启)
+    pub fn counts_5xx(&self) -> bool {
+        self.count_5xx
+    }
+
+    // 被动输入：上游错误 (或 5xx)。窗口内连续失败达到阈值，或者 Probing 期间失败，都会摘除节点
+    pub fn record_failure(&self, cluster: &str, endpoint: &str, reason: ReasonCode) {
+        let mut states = self.states.lock().unwrap();
+        let state = states
+            .entry((cluster.to_string(), endpoint.to_string()))
+            .or_default();
+        let now = Instant::now();
+        let window_expired = self
+            .window
+            .zip(state.last_failure_at)
+            .is_some_and(|(window, last)| now.duration_since(last) > window);
+        if window_expired {
+            state.consecutive_failures = 0;
+        }
+        state.last_failure_at = Some(now);
+        state.consecutive_failures += 1;
+        let eject = state.consecutive_failures >= self.consecutive_failures
+            || state.availability == Availability::Probing;
+        if eject {
+            state.ejected_until = Some(Instant::now() + self.ejection);
+            state.inputs.insert(
+                PASSIVE,
+                InputVerdict {
+                    healthy: false,
+                    reason: format!(
+                        "{} consecutive failures (last: {})",
+                        state.consecutive_failures, reason
+                    ),
+                    at_ms: now_ms(),
+                },
+            );
+        }
+        refresh(cluster, endpoint, state);
+    }
+
+    // 主动输入：一次探测的结果。健康的节点连续失败 unhealthy_threshold 次后摘除，
+    // 摘除的节点连续成功 healthy_threshold 次后恢复。与被动输入互相独立，任一不健康都会摘除。
+    pub fn record_probe(
+        &self,
+        cluster: &str,
+        endpoint: &str,
+        result: Result<(), String>,
+        unhealthy_threshold: u32,
+        healthy_threshold: u32,
+    ) {
+        let mut states = self.states.lock().unwrap();
+        let state = states
+            .entry((cluster.to_string(), endpoint.to_string()))
+            .or_default();
+        let was_healthy = state.inputs.get(ACTIVE).is_none_or(|v| v.healthy);
+        if result.is_ok() {
+            state.probe_successes = state.probe_successes.saturating_add(1);
+            state.probe_failures = 0;
+        } else {
+            state.probe_failures = state.probe_failures.saturating_add(1);
+            state.probe_successes = 0;
+        }
+        let healthy = if was_healthy {
+            state.probe_failures < unhealthy_threshold
+        } else {
+            state.probe_successes >= healthy_threshold
+        };
+        let reason = match &result {
+            Ok(()) if healthy => "probe succeeded".to_string(),
+            Ok(()) => format!(
+                "recovering: {}/{} successful probes",
+                state.probe_successes, healthy_threshold
+            ),
+            Err(e) => format!(
+                "{} consecutive probe failures (last: {})",
+                state.probe_failures, e
+            ),
+        };
+        state.inputs.insert(
+            ACTIVE,
+            InputVerdict {
+                healthy,
+                reason,
+                at_ms: now_ms(),
+            },
+        );
+        refresh(cluster, endpoint, state);
+    }
+
+    // 节点不再被主动探测 (集群去掉了 health_check，或节点被删除)：移除主动输入，避免残留的摘除永远生效
+    pub fn clear_probe(&self, cluster: &str, endpoint: &str) {
+        let mut states = self.states.lock().unwrap();
+        let Some(state) = states.get_mut(&(cluster.to_string(), endpoint.to_string())) else {
+            return;
+        };
+        if state.inputs.remove(ACTIVE).is_some() {
+            state.probe_successes = 0;
+            state.probe_failures = 0;
+            refresh(cluster, endpoint, state);
+        }
+    }
+
+    // 配置更新后调用：清掉已经不在快照里的节点的状态，返回清掉的条数。
+    // 运维覆盖不在这里清理：它只会让节点下线，不会复活被删除的节点，由 TTL 或显式清除结束。
+    pub fn retain(&self, live: &HashMap<String, HashSet<String>>) -> usize {
+        let mut states = self.states.lock().unwrap();
+        let before = states.len();
+        states.retain(|(cluster, endpoint), _| {
+            live.get(cluster).is_some_and(|endpoints| endpoints.contains(endpoint))
+        });
+        before - states.len()
+    }
+
+    // 管理端口使用：某个集群下各节点的状态
+    pub fn cluster_snapshot(&self, cluster: &str, endpoints: &[String]) -> Vec<(String, EndpointState)> {
+        endpoints
+            .iter()
+            .map(|endpoint| {
+                let active = self.active_override(cluster, endpoint);
+                let mut states = self.states.lock().unwrap();
+                let key = (cluster.to_string(), endpoint.clone());
+                let state = if active.is_none() && !states.contains_key(&key) {
+                    EndpointState::default()
+                } else {
+                    let state = states.entry(key).or_default();
+                    sync_override(state, active.as_ref());
+                    refresh(cluster, endpoint, state);
+                    state.clone()
+                };
+                (endpoint.clone(), state)
+            })
+            .collect()
+    }
+}
+
+// 把当前生效的覆盖同步成 "admin" input
+fn sync_override(state: &mut EndpointState, active: Option<&Override>) {
+    match active {
+        Some(o) => {
+            let scope = if o.endpoint.is_some() { "endpoint" } else { "cluster" };
+            state.inputs.insert(
+                ADMIN,
+                InputVerdict {
+                    healthy: false,
+                    reason: format!("{} marked down by operator: {}", scope, o.reason),
+                    at_ms: o.created_ms,
+                },
+            );
+        }
+        None => {
+            state.inputs.remove(ADMIN);
+        }
+    }
+}
+
+// 根据各 input 重新计算综合结论，状态变化时打日志
+fn refresh(cluster: &str, endpoint: &str, state: &mut EndpointState) {
+    let unhealthy = state.inputs.iter().find(|(_, v)| !v.healthy);
+    let (availability, reason) = match unhealthy {
+        None => (Availability::Healthy, String::new()),
+        Some((&PASSIVE, v)) if state.ejected_until.is_some_and(|t| Instant::now() >= t) => {
+            (Availability::Probing, format!("probing after: {}", v.reason))
+        }
+        Some((source, v)) => (Availability::Ejected, format!("{}: {}", source, v.reason)),
+    };
+    if availability != state.availability {
+        println!(
+            "endpoint {} / {}: {:?} -> {:?} ({})",
+            cluster,
+            endpoint,
+            state.availability,
+            availability,
+            if reason.is_empty() { "recovered" } else { reason.as_str() }
+        );
+        state.availability = availability;
+        state.since_ms = now_ms();
+    }
+    state.reason = reason;
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// 【主动健康检查 (Active Health Check)】
+// 被动健康检查要等真实请求失败才能发现问题；没有流量的节点、或者挂掉的 Pod 在下一次被选中之前一直留在轮转里。
+// 配置了 health_check 的集群，由这个后台任务对每个节点周期性发送 "GET <path>"，2xx 为成功，
+// 结果作为 "active" input 写入 EndpointRegistry，upstream_peer 通过 is_available 自然跳过被摘除的节点。
+//
+// - 探测直接用一个新连接发 HTTP/1.1 请求 (Connection: close)，只读状态行，不经过转发的连接池，
+//   UDS 节点 (unix_path) 同样支持。
+// - 每个节点按自己集群的 interval 调度，互相独立；调度精度为 TICK。
+// - 配置更新后下一轮就按新的快照探测；不再探测的节点清掉 active input。
+pub struct HealthChecker {
+    config: Arc<ArcSwap<ActiveConfig>>,
+    registry: Arc<EndpointRegistry>,
+}
+
+const TICK: Duration = Duration::from_millis(250);
+const DEFAULT_PATH: &str = "/health";
+const DEFAULT_INTERVAL_MS: u32 = 10_000;
+const DEFAULT_TIMEOUT_MS: u32 = 2_000;
+const DEFAULT_UNHEALTHY_THRESHOLD: u32 = 3;
+const DEFAULT_HEALTHY_THRESHOLD: u32 = 2;
+// 状态行最多读这么多字节
+const MAX_STATUS_LINE: usize = 1024;
+
+// 填好默认值的 HealthCheck
+#[derive(Debug, Clone)]
+struct ProbeSpec {
+    path: String,
+    interval: Duration,
+    timeout: Duration,
+    unhealthy_threshold: u32,
+    healthy_threshold: u32,
+}
+
+fn or_default(value: u32, default: u32) -> u32 {
+    if value == 0 { default } else { value }
+}
+
+impl ProbeSpec {
+    fn from_proto(hc: &HealthCheck) -> Self {
+        let interval =
+            Duration::from_millis(or_default(hc.interval_ms, DEFAULT_INTERVAL_MS) as u64);
+        let timeout = Duration::from_millis(or_default(hc.timeout_ms, DEFAULT_TIMEOUT_MS) as u64);
+        Self {
+            path: if hc.path.is_empty() {
+                DEFAULT_PATH.to_string()
+            } else {
+                hc.path.clone()
+            },
+            interval,
+            // 超时不超过间隔，同一个节点不会有两个探测同时进行
+            timeout: timeout.min(interval),
+            unhealthy_threshold: or_default(hc.unhealthy_threshold, DEFAULT_UNHEALTHY_THRESHOLD),
+            healthy_threshold: or_default(hc.healthy_threshold, DEFAULT_HEALTHY_THRESHOLD),
+        }
+    }
+}
+
+impl HealthChecker {
+    pub fn new(config: Arc<ArcSwap<ActiveConfig>>, registry: Arc<EndpointRegistry>) -> Self {
+        Self { config, registry }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        // (cluster, endpoint) -> 下一次探测时间
+        let mut next_due: HashMap<(String, String), Instant> = HashMap::new();
+        loop {
+            let config = self.config.load_full();
+            let now = Instant::now();
+            let mut probed = HashSet::new();
+            for cluster in &config.snapshot.clusters {
+                let Some(hc) = &cluster.health_check else {
+                    continue;
+                };
+                let spec = ProbeSpec::from_proto(hc);
+                for endpoint in &cluster.endpoints {
+                    let key = (cluster.name.clone(), endpoint_label(endpoint));
+                    probed.insert(key.clone());
+                    let due = next_due.entry(key.clone()).or_insert(now);
+                    if *due > now {
+                        continue;
+                    }
+                    *due = now + spec.interval;
+                    let registry = self.registry.clone();
+                    let endpoint = endpoint.clone();
+                    let spec = spec.clone();
+                    tokio::spawn(async move {
+                        let result = probe(&endpoint, &spec).await;
+                        registry.record_probe(
+                            &key.0,
+                            &key.1,
+                            result,
+                            spec.unhealthy_threshold,
+                            spec.healthy_threshold,
+                        );
+                    });
+                }
+            }
+            next_due.retain(|(cluster, endpoint), _| {
+                let keep = probed.contains(&(cluster.clone(), endpoint.clone()));
+                if !keep {
+                    self.registry.clear_probe(cluster, endpoint);
+                }
+                keep
+            });
+            tokio::time::sleep(TICK).await;
+        }
+    }
+}
+
+// 一次探测：连接 + 发请求 + 读状态行，整体受 timeout 限制。非 2xx 也算失败
+async fn probe(endpoint: &Endpoint, spec: &ProbeSpec) -> Result<(), String> {
+    let exchange = async {
+        if endpoint.unix_path.is_empty() {
+            let mut stream =
+                TcpStream::connect((endpoint.address.as_str(), endpoint.port as u16)).await?;
+            let host = format!("{}:{}", endpoint.address, endpoint.port);
+            request_status(&mut stream, &spec.path, &host).await
+        } else {
+            let mut stream = UnixStream::connect(&endpoint.unix_path).await?;
+            request_status(&mut stream, &spec.path, "localhost").await
+        }
+    };
+    match tokio::time::timeout(spec.timeout, exchange).await {
+        Err(_) => Err(format!("timed out after {}ms", spec.timeout.as_millis())),
+        Ok(Err(e)) => Err(e.to_string()),
+        Ok(Ok(status)) if (200..300).contains(&status) => Ok(()),
+        Ok(Ok(status)) => Err(format!("status {}", status)),
+    }
+}
+
+async fn request_status<S>(stream: &mut S, path: &str, host: &str) -> std::io::Result<u16>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: agw-health-check\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut head = Vec::new();
+    let mut buf = [0u8; 256];
+    while !head.windows(2).any(|w| w == b"\r\n") {
+        if head.len() >= MAX_STATUS_LINE {
+            break;
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    // "HTTP/1.1 200 OK"
+    let line = String::from_utf8_lossy(&head);
+    line.strip_prefix("HTTP/1.")
+        .and_then(|rest| rest.get(2..5))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed status line")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::agw::config::v1::Cluster;
+    use crate::client::agw::v1::ConfigSnapshot;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    // 本地 HTTP 服务：读完请求头后按 status 回应 (0 = 一直不回应)；返回地址和收到的探测数
+    async fn serve(status: Arc<AtomicU16>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let probes = Arc::new(AtomicUsize::new(0));
+        let counted = probes.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (status, counted) = (status.clone(), counted.clone());
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+                    let mut buf = [0u8; 256];
+                    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => head.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    counted.fetch_add(1, Ordering::SeqCst);
+                    match status.load(Ordering::SeqCst) {
+                        0 => tokio::time::sleep(Duration::from_secs(60)).await,
+                        code => {
+                            let response =
+                                format!("HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", code);
+                            let _ = stream.write_all(response.as_bytes()).await;
+                        }
+                    }
+                });
+            }
+        });
+        (addr, probes)
+    }
+
+    fn endpoint(addr: SocketAddr) -> Endpoint {
+        Endpoint {
+            address: addr.ip().to_string(),
+            port: addr.port() as u32,
+            ..Default::default()
+        }
+    }
+
+    fn spec(timeout: Duration) -> ProbeSpec {
+        ProbeSpec {
+            path: DEFAULT_PATH.to_string(),
+            interval: Duration::from_secs(1),
+            timeout,
+            unhealthy_threshold: 3,
+            healthy_threshold: 2,
+        }
+    }
+
+    // 探测一次并写入 registry
+    async fn probe_into(
+        registry: &EndpointRegistry,
+        endpoint: &Endpoint,
+        spec: &ProbeSpec,
+    ) -> Availability {
+        let label = endpoint_label(endpoint);
+        let result = probe(endpoint, spec).await;
+        registry.record_probe(
+            "backend",
+            &label,
+            result,
+            spec.unhealthy_threshold,
+            spec.healthy_threshold,
+        );
+        registry.cluster_snapshot("backend", &[label])[0]
+            .1
+            .availability
+    }
+
+    #[tokio::test]
+    async fn probe_reports_status_and_timeout() {
+        let status = Arc::new(AtomicU16::new(200));
+        let (addr, probes) = serve(status.clone()).await;
+        let endpoint = endpoint(addr);
+        let spec = spec(Duration::from_millis(200));
+
+        assert_eq!(probe(&endpoint, &spec).await, Ok(()));
+        status.store(503, Ordering::SeqCst);
+        assert_eq!(probe(&endpoint, &spec).await, Err("status 503".to_string()));
+
+        // 不回应的节点在 timeout 后算失败，不会一直等下去
+        status.store(0, Ordering::SeqCst);
+        let started = Instant::now();
+        assert_eq!(
+            probe(&endpoint, &spec).await,
+            Err("timed out after 200ms".to_string())
+        );
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(probes.load(Ordering::SeqCst), 3);
+
+        // 连接被拒绝
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused = endpoint(closed.local_addr().unwrap());
+        drop(closed);
+        assert!(probe(&refused, &spec).await.is_err());
+    }
+
+    #[test]
+    fn timeout_is_capped_at_interval() {
+        let spec = ProbeSpec::from_proto(&HealthCheck {
+            interval_ms: 500,
+            timeout_ms: 5_000,
+            ..Default::default()
+        });
+        assert_eq!(spec.interval, Duration::from_millis(500));
+        assert_eq!(spec.timeout, Duration::from_millis(500));
+        assert_eq!(spec.path, DEFAULT_PATH);
+        assert_eq!(
+            (spec.unhealthy_threshold, spec.healthy_threshold),
+            (DEFAULT_UNHEALTHY_THRESHOLD, DEFAULT_HEALTHY_THRESHOLD)
+        );
+    }
+
+    #[tokio::test]
+    async fn ejected_after_unhealthy_threshold_and_back_after_healthy_threshold() {
+        let status = Arc::new(AtomicU16::new(200));
+        let (addr, _) = serve(status.clone()).await;
+        let endpoint = endpoint(addr);
+        let spec = spec(Duration::from_millis(200));
+        let registry = EndpointRegistry::from_env();
+        assert_eq!(
+            probe_into(&registry, &endpoint, &spec).await,
+            Availability::Healthy
+        );
+
+        // 连续失败 3 次才摘除；中间一次成功会重新计数
+        status.store(500, Ordering::SeqCst);
+        for _ in 0..2 {
+            assert_eq!(
+                probe_into(&registry, &endpoint, &spec).await,
+                Availability::Healthy
+            );
+        }
+        status.store(200, Ordering::SeqCst);
+        assert_eq!(
+            probe_into(&registry, &endpoint, &spec).await,
+            Availability::Healthy
+        );
+        status.store(0, Ordering::SeqCst);
+        for _ in 0..2 {
+            assert_eq!(
+                probe_into(&registry, &endpoint, &spec).await,
+                Availability::Healthy
+            );
+        }
+        assert_eq!(
+            probe_into(&registry, &endpoint, &spec).await,
+            Availability::Ejected
+        );
+
+        // 恢复要连续成功 2 次；中间一次失败会重新计数
+        status.store(200, Ordering::SeqCst);
+        assert_eq!(
+            probe_into(&registry, &endpoint, &spec).await,
+            Availability::Ejected
+        );
+        status.store(503, Ordering::SeqCst);
+        assert_eq!(
+            probe_into(&registry, &endpoint, &spec).await,
+            Availability::Ejected
+        );
+        status.store(200, Ordering::SeqCst);
+        assert_eq!(
+            probe_into(&registry, &endpoint, &spec).await,
+            Availability::Ejected
+        );
+        assert_eq!(
+            probe_into(&registry, &endpoint, &spec).await,
+            Availability::Healthy
+        );
+    }
+
+    #[tokio::test]
+    async fn checker_probes_each_endpoint_on_its_interval() {
+        let status = Arc::new(AtomicU16::new(503));
+        let (addr, probes) = serve(status).await;
+        let snapshot = ConfigSnapshot {
+            clusters: vec![Cluster {
+                name: "backend".to_string(),
+                endpoints: vec![endpoint(addr)],
+                health_check: Some(HealthCheck {
+                    interval_ms: 500,
+                    timeout_ms: 200,
+                    unhealthy_threshold: 2,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let config = Arc::new(ArcSwap::from_pointee(
+            ActiveConfig::compile(snapshot).unwrap(),
+        ));
+        let registry = Arc::new(EndpointRegistry::from_env());
+        let checker = Arc::new(HealthChecker::new(config, registry.clone()));
+        let running = tokio::spawn(checker.run());
+
+        // 第一次探测立即进行，之后每 500ms 一次 (调度精度为 TICK)
+        tokio::time::sleep(Duration::from_millis(1_300)).await;
+        running.abort();
+        let count = probes.load(Ordering::SeqCst);
+        assert!((2..=4).contains(&count), "{} probes", count);
+
+        // 连续失败 2 次后摘除
+        let label = endpoint_label(&endpoint(addr));
+        let state = &registry.cluster_snapshot("backend", &[label])[0].1;
+        assert_eq!(state.availability, Availability::Ejected);
+        assert!(state.reason.contains("status 503"), "{}", state.reason);
+    }
+
+    #[test]
+    fn usable_skips_ejected_and_admin_down_endpoints() {
+        let endpoints: Vec<Endpoint> = (1..=3)
+            .map(|port| endpoint(SocketAddr::from(([10, 0, 0, 1], port))))
+            .collect();
+        let candidates = || endpoints.iter().collect::<Vec<_>>();
+        let ports = |usable: Vec<&Endpoint>| usable.iter().map(|e| e.port).collect::<Vec<_>>();
+        let registry = EndpointRegistry::from_env();
+        let eject = |e: &Endpoint| {
+            registry.record_probe("backend", &endpoint_label(e), Err("down".into()), 1, 1)
+        };
+        assert_eq!(ports(registry.usable("backend", candidates())), [1, 2, 3]);
+
+        eject(&endpoints[0]);
+        assert_eq!(ports(registry.usable("backend", candidates())), [2, 3]);
+        // 其他集群里同一地址的节点不受影响
+        assert_eq!(ports(registry.usable("other", candidates())), [1, 2, 3]);
+
+        // 全部被摘除：兜底用完整列表
+        eject(&endpoints[1]);
+        eject(&endpoints[2]);
+        assert_eq!(ports(registry.usable("backend", candidates())), [1, 2, 3]);
+
+        // 人工下线的节点连兜底也不参与
+        registry.set_override(
+            "backend",
+            Some(&endpoint_label(&endpoints[2])),
+            None,
+            "maintenance",
+        );
+        assert_eq!(ports(registry.usable("backend", candidates())), [1, 2]);
+        registry.record_probe("backend", &endpoint_label(&endpoints[1]), Ok(()), 1, 1);
+        assert_eq!(ports(registry.usable("backend", candidates())), [2]);
+    }
+
+    // 被动健康检查：连续 3 次失败摘除 50ms
+    fn passive_registry(window: Option<Duration>) -> EndpointRegistry {
+        EndpointRegistry {
+            states: Mutex::new(HashMap::new()),
+            overrides: Mutex::new(HashMap::new()),
+            consecutive_failures: 3,
+            ejection: Duration::from_millis(50),
+            window,
+            count_5xx: true,
+        }
+    }
+
+    fn state(registry: &EndpointRegistry, endpoint: &Endpoint) -> EndpointState {
+        registry.cluster_snapshot("backend", &[endpoint_label(endpoint)])[0]
+            .1
+            .clone()
+    }
+
+    #[test]
+    fn upstream_5xx_ejects_then_probes_and_recovers() {
+        let endpoints: Vec<Endpoint> = (1..=2)
+            .map(|port| endpoint(SocketAddr::from(([10, 0, 0, 1], port))))
+            .collect();
+        let candidates = || endpoints.iter().collect::<Vec<_>>();
+        let ports = |usable: Vec<&Endpoint>| usable.iter().map(|e| e.port).collect::<Vec<_>>();
+        let registry = passive_registry(None);
+        let label = endpoint_label(&endpoints[0]);
+        let fail = || registry.record_failure("backend", &label, ReasonCode::Upstream5xx);
+
+        // 阈值之前照常分流量
+        fail();
+        fail();
+        assert_eq!(ports(registry.usable("backend", candidates())), [1, 2]);
+        fail();
+        assert_eq!(ports(registry.usable("backend", candidates())), [2]);
+        let ejected = state(&registry, &endpoints[0]);
+        assert_eq!(ejected.availability, Availability::Ejected);
+        assert_eq!(
+            ejected.reason,
+            "passive: 3 consecutive failures (last: UPSTREAM_5XX)"
+        );
+
+        // 摘除时间过后放请求试探；试探期间再失败一次就重新摘除
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(ports(registry.usable("backend", candidates())), [1, 2]);
+        assert_eq!(
+            state(&registry, &endpoints[0]).availability,
+            Availability::Probing
+        );
+        fail();
+        assert_eq!(ports(registry.usable("backend", candidates())), [2]);
+
+        // 试探成功后恢复，计数清零
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(ports(registry.usable("backend", candidates())), [1, 2]);
+        registry.record_success("backend", &label);
+        let recovered = state(&registry, &endpoints[0]);
+        assert_eq!(recovered.availability, Availability::Healthy);
+        assert_eq!(recovered.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn success_between_failures_resets_the_count() {
+        let endpoint = endpoint(SocketAddr::from(([10, 0, 0, 1], 1)));
+        let label = endpoint_label(&endpoint);
+        let registry = passive_registry(None);
+        for _ in 0..2 {
+            registry.record_failure("backend", &label, ReasonCode::Upstream5xx);
+        }
+        registry.record_success("backend", &label);
+        for _ in 0..2 {
+            registry.record_failure("backend", &label, ReasonCode::Upstream5xx);
+        }
+        assert!(registry.is_available("backend", &label));
+        assert_eq!(state(&registry, &endpoint).consecutive_failures, 2);
+    }
+
+    #[test]
+    fn failures_outside_the_window_start_a_new_count() {
+        let endpoint = endpoint(SocketAddr::from(([10, 0, 0, 1], 1)));
+        let label = endpoint_label(&endpoint);
+        let registry = passive_registry(Some(Duration::from_millis(30)));
+        for _ in 0..2 {
+            registry.record_failure("backend", &label, ReasonCode::Upstream5xx);
+        }
+        std::thread::sleep(Duration::from_millis(40));
+        registry.record_failure("backend", &label, ReasonCode::Upstream5xx);
+        assert!(registry.is_available("backend", &label));
+        assert_eq!(state(&registry, &endpoint).consecutive_failures, 1);
+    }
+}
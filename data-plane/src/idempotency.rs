@@ -0,0 +1,246 @@
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::attributes::{RequestAttributes, ValueSource};
+use crate::client::agw::config::v1::{IdempotencyInFlight, IdempotencyPolicy, Route};
+
+// 【幂等键去重 (Idempotency)】
+// 客户端超时重试时会带着同一个 Idempotency-Key 重发 POST/PUT，后端虽然支持幂等，仍然要承受全部重复请求。
+// 路由开启 idempotency 后，网关按 (路由, 租户, key) 记录第一次请求：
+// - 第一次请求：占位 (in-flight)，照常转发，响应完成后把状态码、选定的响应头和响应体存下来 (TTL 内有效)；
+// - 之后的重试：直接回放存下来的响应，不访问上游，响应带 "Idempotent-Replayed: true"；
+// - 第一次请求还没完成时的重试：按配置立即返回 409，或者等它完成 (有上限) 再回放。
+//
+// 不存储的情况 (之后的重试照常访问上游)：5xx、响应体超过 max_body_bytes、转发中途失败。
+// 存储是进程内的 (与响应缓存一样，多副本之间不共享)；容量满时降级为直接透传并打印告警，不会拒绝请求。
+pub struct IdempotencyStore {
+    max_entries: usize,
+    next_id: AtomicU64,
+    slots: Mutex<HashMap<String, Slot>>,
+}
+
+enum Slot {
+    // 第一次请求处理中；占位者结束 (Reservation 释放) 时 Sender 被丢弃，等待者随之醒来
+    InFlight { id: u64, done: watch::Receiver<()> },
+    Completed(StoredResponse),
+}
+
+#[derive(Clone)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Bytes,
+    expires_at: Instant,
+}
+
+pub enum Claim {
+    // 第一次请求：转发上游，响应阶段写入 Reservation
+    Owner(Reservation),
+    Replay(StoredResponse),
+    // 同一个 key 正在处理中，可以等待这个 Receiver (changed() 返回即表示占位者已结束)
+    InFlight(watch::Receiver<()>),
+    // 存储不可用 (容量已满)，直接透传
+    Unavailable,
+}
+
+// 路由上编译好的幂等策略 (默认值已填好)
+#[derive(Debug)]
+pub struct CompiledIdempotency {
+    // 请求头名 (已小写)
+    header: String,
+    ttl: Duration,
+    max_body_bytes: u64,
+    // 回放的响应头 (已小写)
+    replay_headers: Vec<String>,
+    pub wait: Option<Duration>,
+    tenant: Option<ValueSource>,
+    // 路由标识：key 只在同一条路由内有效
+    scope: String,
+}
+
+const DEFAULT_HEADER: &str = "idempotency-key";
+const DEFAULT_TTL_SECS: u64 = 24 * 3600;
+const DEFAULT_MAX_BODY_BYTES: u64 = 64 * 1024;
+const DEFAULT_WAIT_MS: u64 = 5000;
+const DEFAULT_TENANT_SOURCE: &str = "tenant.id";
+
+impl CompiledIdempotency {
+    pub fn compile(route: &Route, policy: &IdempotencyPolicy) -> Self {
+        let header = if policy.header.is_empty() {
+            DEFAULT_HEADER.to_string()
+        } else {
+            policy.header.to_ascii_lowercase()
+        };
+        let replay_headers = if policy.replay_headers.is_empty() {
+            vec!["content-type".to_string()]
+        } else {
+            policy.replay_headers.iter().map(|h| h.to_ascii_lowercase()).collect()
+        };
+        let wait = (policy.in_flight() == IdempotencyInFlight::IdempotencyWait).then(|| {
+            Duration::from_millis(match policy.wait_timeout_ms {
+                0 => DEFAULT_WAIT_MS,
+                ms => ms as u64,
+            })
+        });
+        let tenant_source = if policy.tenant_source.is_empty() {
+            DEFAULT_TENANT_SOURCE
+        } else {
+            policy.tenant_source.as_str()
+        };
+        Self {
+            header,
+            ttl: Duration::from_secs(match policy.ttl_seconds {
+                0 => DEFAULT_TTL_SECS,
+                secs => secs as u64,
+            }),
+            max_body_bytes: match policy.max_body_bytes {
+                0 => DEFAULT_MAX_BODY_BYTES,
+                n => n,
+            },
+            replay_headers,
+            wait,
+            tenant: ValueSource::parse(tenant_source),
+            scope: format!("{}|{}|{:?}", route.hosts.join(","), route.path_prefix, route.path),
+        }
+    }
+
+    // 只处理带幂等键的 POST / PUT；返回存储 key (路由 + 租户 + 方法 + 路径 + 幂等键)
+    pub fn key(
+        &self,
+        method: &http::Method,
+        path: &str,
+        headers: &http::HeaderMap,
+        attributes: &RequestAttributes,
+    ) -> Option<String> {
+        if method != http::Method::POST && method != http::Method::PUT {
+            return None;
+        }
+        let key = headers.get(&self.header)?.to_str().ok()?.trim();
+        if key.is_empty() {
+            return None;
+        }
+        let tenant = self
+            .tenant
+            .as_ref()
+            .and_then(|t| t.get(headers, attributes))
+            .unwrap_or("-");
+        Some(format!("{}|{}|{} {}|{}", self.scope, tenant, method, path, key))
+    }
+}
+
+impl IdempotencyStore {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            next_id: AtomicU64::new(0),
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn claim(self: &Arc<Self>, key: String, policy: &CompiledIdempotency) -> Claim {
+        let mut slots = self.slots.lock().unwrap();
+        let now = Instant::now();
+        match slots.get(&key) {
+            Some(Slot::Completed(stored)) if stored.expires_at > now => {
+                return Claim::Replay(stored.clone());
+            }
+            Some(Slot::InFlight { done, .. }) => return Claim::InFlight(done.clone()),
+            Some(Slot::Completed(_)) => {}
+            None => {
+                // 容量上限：先清理过期条目，仍然满则降级为透传
+                if slots.len() >= self.max_entries {
+                    slots.retain(|_, slot| match slot {
+                        Slot::Completed(stored) => stored.expires_at > now,
+                        Slot::InFlight { .. } => true,
+                    });
+                    if slots.len() >= self.max_entries {
+                        return Claim::Unavailable;
+                    }
+                }
+            }
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = watch::channel(());
+        slots.insert(key.clone(), Slot::InFlight { id, done: rx });
+        Claim::Owner(Reservation {
+            store: self.clone(),
+            key,
+            id,
+            _done: tx,
+            ttl: policy.ttl,
+            max_body_bytes: policy.max_body_bytes,
+            replay_headers: policy.replay_headers.clone(),
+            status: 0,
+            headers: Vec::new(),
+            body: BytesMut::new(),
+        })
+    }
+}
+
+// 第一次请求持有的占位，随请求的 CTX 一起释放：
+// 没有调用 complete (出错、5xx、响应体超限) 时释放会移除占位，下一次重试重新访问上游。
+pub struct Reservation {
+    store: Arc<IdempotencyStore>,
+    key: String,
+    id: u64,
+    _done: watch::Sender<()>,
+    ttl: Duration,
+    max_body_bytes: u64,
+    replay_headers: Vec<String>,
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    body: BytesMut,
+}
+
+impl Reservation {
+    // 响应头阶段：记下状态码和需要回放的响应头
+    pub fn capture_head(&mut self, status: u16, headers: &http::HeaderMap) {
+        self.status = status;
+        self.headers = headers
+            .iter()
+            .filter(|(k, _)| self.replay_headers.iter().any(|h| h == k.as_str()))
+            .map(|(k, v)| (k.as_str().to_string(), v.as_bytes().to_vec()))
+            .collect();
+    }
+
+    // 累积响应体；超过上限返回 false (不再存储)
+    pub fn append(&mut self, chunk: &[u8]) -> bool {
+        if self.body.len() as u64 + chunk.len() as u64 > self.max_body_bytes {
+            return false;
+        }
+        self.body.extend_from_slice(chunk);
+        true
+    }
+
+    // 响应完整转发后调用；返回是否存储 (5xx 不存储，重试应该能再次到达上游)
+    pub fn complete(mut self) -> bool {
+        if self.status == 0 || self.status >= 500 {
+            return false;
+        }
+        let stored = StoredResponse {
+            status: self.status,
+            headers: std::mem::take(&mut self.headers),
+            body: std::mem::take(&mut self.body).freeze(),
+            expires_at: Instant::now() + self.ttl,
+        };
+        self.store
+            .slots
+            .lock()
+            .unwrap()
+            .insert(self.key.clone(), Slot::Completed(stored));
+        true
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut slots = self.store.slots.lock().unwrap();
+        if matches!(slots.get(&self.key), Some(Slot::InFlight { id, .. }) if *id == self.id) {
+            slots.remove(&self.key);
+        }
+    }
+}
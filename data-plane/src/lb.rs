@@ -0,0 +1,430 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::client::agw::config::v1::{Cluster, Endpoint, LbPolicy};
+use crate::upstream;
+
+// 【负载均衡 (Load Balancing)】
+// 在 "子集过滤 -> 去掉人工下线 -> 去掉被摘除" 之后的候选节点里选一个，策略由集群的 lb_policy 决定：
+// - RoundRobin (默认): 每个集群一个原子计数器，idx = counter++ % 候选数。
+// - Random: 均匀随机。
+// - FirstAlive: 总是第一个候选 (旧行为，适合主备)。
+// - WeightedRandom: 按 Endpoint.weight 加权随机 (如 v1:90 / v2:10)。权重为 0 (未设置) 的节点不分流量，
+//   只有候选的权重全部为 0 时才均匀分布。累积权重表在每次选择时按当前候选构建：
+//   候选经过了健康过滤和子集过滤，每个请求都可能不同，预先算好的整集群表反而会选到被排除的节点。
+// - LeastConnections: 选本数据面上进行中请求最少的节点，一样少时随机选一个。
+// - PeakEwma: 随机取两个候选 (power of two choices)，选 "延迟均值 x (进行中请求数 + 1)" 较小的那个。
+//   延迟均值来自上游耗时 (选中节点到收到响应头)，按 peak EWMA 更新：比均值高的样本直接成为新均值
+//   (变慢立刻反映出来)，比均值低的按时间衰减慢慢拉低。没有新样本的节点均值随时间衰减，
+//   慢节点因此仍会偶尔被选中、有机会证明自己恢复了；还没有样本的节点按 default_rtt_ms 计算，
+//   新节点不会被饿死。只比较两个候选，选择是 O(1) 的。
+//
+// 计数器按集群名保存。候选列表每个请求都可能不同 (健康状态、子集)，取模即可适应，
+// 不需要在节点列表变化时重建计数器；配置更新后只清理已经不存在的集群 / 节点 (retain)。
+//
+// 进行中请求数和延迟均值按 (集群, "ip:port") 保存，所有策略都会统计：节点列表替换后，同一地址的数据自然延续，
+// 切换到 LeastConnections / PeakEwma 时也不会从 0 开始。每个请求持有一个 InFlight，释放时计数减一。
+#[derive(Default)]
+pub struct LoadBalancer {
+    round_robin: RwLock<HashMap<String, AtomicUsize>>,
+    endpoints: RwLock<HashMap<String, HashMap<String, Arc<EndpointLoad>>>>,
+}
+
+// 一个节点在本数据面上的负载
+#[derive(Default)]
+struct EndpointLoad {
+    in_flight: AtomicU32,
+    // 延迟均值 (纳秒) 和最后一次更新的时刻；None = 还没有样本
+    latency: Mutex<Option<(f64, Instant)>>,
+}
+
+// 一个进行中的上游请求；Drop 时计数减一 (随请求的 CTX 一起释放，出错、重试时也不会漏减)
+pub struct InFlight {
+    load: Arc<EndpointLoad>,
+    decay: Duration,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.load.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl InFlight {
+    // 收到上游响应头时调用，rtt 为选中节点到收到响应头的耗时
+    pub fn observe(&self, rtt: Duration) {
+        let sample = rtt.as_nanos() as f64;
+        let now = Instant::now();
+        let mut latency = self.load.latency.lock().unwrap();
+        let value = match *latency {
+            Some((value, stamp)) if sample < value => {
+                let w = decay_weight(now.saturating_duration_since(stamp), self.decay);
+                value * w + sample * (1.0 - w)
+            }
+            _ => sample,
+        };
+        *latency = Some((value, now));
+    }
+}
+
+// 距上一次更新过了 elapsed 之后，旧均值还保留多少权重
+fn decay_weight(elapsed: Duration, decay: Duration) -> f64 {
+    (-elapsed.as_secs_f64() / decay.as_secs_f64().max(f64::EPSILON)).exp()
+}
+
+// PeakEwma 的参数 (Cluster.peak_ewma)，没有设置的取默认值
+fn peak_ewma_params(cluster: &Cluster) -> (Duration, Duration) {
+    let config = cluster.peak_ewma.clone().unwrap_or_default();
+    let decay = match config.decay_ms {
+        0 => 10_000,
+        ms => ms,
+    };
+    let default_rtt = match config.default_rtt_ms {
+        0 => 10,
+        ms => ms,
+    };
+    (
+        Duration::from_millis(decay as u64),
+        Duration::from_millis(default_rtt as u64),
+    )
+}
+
+impl LoadBalancer {
+    pub fn pick<'a>(&self, cluster: &Cluster, candidates: &[&'a Endpoint]) -> Option<&'a Endpoint> {
+        self.pick_with(cluster, candidates, &mut rand::thread_rng())
+    }
+
+    // 随机数来源由调用方给出 (测试里用固定种子，结果可复现)
+    fn pick_with<'a>(
+        &self,
+        cluster: &Cluster,
+        candidates: &[&'a Endpoint],
+        rng: &mut impl Rng,
+    ) -> Option<&'a Endpoint> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let idx = match cluster.lb_policy() {
+            LbPolicy::LbRoundRobin => self.next(&cluster.name) % candidates.len(),
+            LbPolicy::LbRandom => rng.gen_range(0..candidates.len()),
+            LbPolicy::LbFirstAlive => 0,
+            LbPolicy::LbWeightedRandom => weighted_index(candidates, rng.r#gen::<f64>()),
+            LbPolicy::LbLeastConnections => self.least_connections(&cluster.name, candidates, rng),
+            LbPolicy::LbPeakEwma => self.peak_ewma(cluster, candidates, rng),
+        };
+        Some(candidates[idx])
+    }
+
+    // 选中节点后调用，返回的 InFlight 需要保存到请求结束
+    pub fn begin(&self, cluster: &Cluster, endpoint: &str) -> InFlight {
+        let existing = self
+            .endpoints
+            .read()
+            .unwrap()
+            .get(&cluster.name)
+            .and_then(|endpoints| endpoints.get(endpoint))
+            .cloned();
+        let load = existing.unwrap_or_else(|| {
+            self.endpoints
+                .write()
+                .unwrap()
+                .entry(cluster.name.clone())
+                .or_default()
+                .entry(endpoint.to_string())
+                .or_default()
+                .clone()
+        });
+        load.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight {
+            load,
+            decay: peak_ewma_params(cluster).0,
+        }
+    }
+
+    fn least_connections(
+        &self,
+        cluster: &str,
+        candidates: &[&Endpoint],
+        rng: &mut impl Rng,
+    ) -> usize {
+        let endpoints = self.endpoints.read().unwrap();
+        let loads_by_label = endpoints.get(cluster);
+        let loads: Vec<u32> = candidates
+            .iter()
+            .map(|e| {
+                loads_by_label
+                    .and_then(|c| c.get(&upstream::endpoint_label(e)))
+                    .map(|c| c.in_flight.load(Ordering::Relaxed))
+                    .unwrap_or(0)
+            })
+            .collect();
+        let min = loads.iter().copied().min().unwrap_or(0);
+        let ties: Vec<usize> = (0..loads.len()).filter(|&i| loads[i] == min).collect();
+        ties[rng.gen_range(0..ties.len())]
+    }
+
+    fn peak_ewma(&self, cluster: &Cluster, candidates: &[&Endpoint], rng: &mut impl Rng) -> usize {
+        if candidates.len() == 1 {
+            return 0;
+        }
+        // 两个不同的随机下标
+        let a = rng.gen_range(0..candidates.len());
+        let b = (a + 1 + rng.gen_range(0..candidates.len() - 1)) % candidates.len();
+        let (decay, default_rtt) = peak_ewma_params(cluster);
+        let now = Instant::now();
+        let endpoints = self.endpoints.read().unwrap();
+        let loads = endpoints.get(&cluster.name);
+        let score = |endpoint: &Endpoint| {
+            let Some(load) = loads.and_then(|l| l.get(&upstream::endpoint_label(endpoint))) else {
+                return default_rtt.as_nanos() as f64;
+            };
+            let latency = match *load.latency.lock().unwrap() {
+                Some((value, stamp)) => {
+                    value * decay_weight(now.saturating_duration_since(stamp), decay)
+                }
+                None => default_rtt.as_nanos() as f64,
+            };
+            latency * (load.in_flight.load(Ordering::Relaxed) + 1) as f64
+        };
+        if score(candidates[b]) < score(candidates[a]) {
+            b
+        } else {
+            a
+        }
+    }
+
+    // 配置更新后调用：删除已经不在快照里的集群 / 节点的计数器，返回删除的条数。
+    // 仍在进行中的请求持有自己的 Arc，删除不影响它们正常减一。
+    pub fn retain(&self, live: &HashMap<String, HashSet<String>>) -> usize {
+        let mut removed = 0;
+        let mut round_robin = self.round_robin.write().unwrap();
+        let before = round_robin.len();
+        round_robin.retain(|name, _| live.contains_key(name));
+        removed += before - round_robin.len();
+
+        let mut endpoints = self.endpoints.write().unwrap();
+        endpoints.retain(|name, endpoints| {
+            let before = endpoints.len();
+            match live.get(name) {
+                Some(live) => endpoints.retain(|endpoint, _| live.contains(endpoint)),
+                None => endpoints.clear(),
+            }
+            removed += before - endpoints.len();
+            !endpoints.is_empty()
+        });
+        removed
+    }
+
+    fn next(&self, cluster: &str) -> usize {
+        if let Some(counter) = self.round_robin.read().unwrap().get(cluster) {
+            return counter.fetch_add(1, Ordering::Relaxed);
+        }
+        self.round_robin
+            .write()
+            .unwrap()
+            .entry(cluster.to_string())
+            .or_insert_with(|| AtomicUsize::new(0))
+            .fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+// 在累积权重表里找 r (0.0 ~ 1.0) 落在哪个节点。权重为 0 的节点不占区间，永远选不到；
+// 全部为 0 时每个节点按 1 计算 (均匀分布)
+fn weighted_index(candidates: &[&Endpoint], r: f64) -> usize {
+    let uniform = candidates.iter().all(|e| e.weight == 0);
+    let weight = |e: &Endpoint| if uniform { 1 } else { e.weight as u64 };
+    let total: u64 = candidates.iter().map(|e| weight(e)).sum();
+    let target = (r * total as f64) as u64;
+    let mut cumulative = 0;
+    let mut last = 0;
+    for (i, endpoint) in candidates.iter().enumerate() {
+        if weight(endpoint) == 0 {
+            continue;
+        }
+        cumulative += weight(endpoint);
+        last = i;
+        if target < cumulative {
+            return i;
+        }
+    }
+    // r 非常接近 1.0 时浮点舍入可能让 target == total：取最后一个权重非 0 的节点
+    last
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    const SELECTIONS: usize = 10_000;
+
+    fn endpoint(port: u32, weight: u32) -> Endpoint {
+        Endpoint {
+            address: "10.0.0.1".to_string(),
+            port,
+            weight,
+            ..Default::default()
+        }
+    }
+
+    fn weighted_cluster() -> Cluster {
+        Cluster {
+            name: "backend".to_string(),
+            lb_policy: LbPolicy::LbWeightedRandom as i32,
+            ..Default::default()
+        }
+    }
+
+    // 用固定种子选 SELECTIONS 次，返回每个候选被选中的比例
+    fn shares(endpoints: &[Endpoint]) -> Vec<f64> {
+        let lb = LoadBalancer::default();
+        let cluster = weighted_cluster();
+        let candidates: Vec<&Endpoint> = endpoints.iter().collect();
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut counts = vec![0usize; endpoints.len()];
+        for _ in 0..SELECTIONS {
+            let picked = lb.pick_with(&cluster, &candidates, &mut rng).unwrap();
+            counts[candidates
+                .iter()
+                .position(|e| std::ptr::eq(*e, picked))
+                .unwrap()] += 1;
+        }
+        counts
+            .iter()
+            .map(|&c| c as f64 / SELECTIONS as f64)
+            .collect()
+    }
+
+    // 每个候选的实际比例和期望比例相差不超过 5 个百分点
+    fn assert_distribution(actual: &[f64], expected: &[f64]) {
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (a - e).abs() <= 0.05,
+                "endpoint {}: share {} expected {}",
+                i,
+                a,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn weights_split_traffic_proportionally() {
+        let actual = shares(&[endpoint(1, 90), endpoint(2, 10)]);
+        assert_distribution(&actual, &[0.9, 0.1]);
+
+        let actual = shares(&[endpoint(1, 1), endpoint(2, 2), endpoint(3, 7)]);
+        assert_distribution(&actual, &[0.1, 0.2, 0.7]);
+    }
+
+    #[test]
+    fn zero_weight_endpoints_receive_no_traffic() {
+        let actual = shares(&[
+            endpoint(1, 0),
+            endpoint(2, 3),
+            endpoint(3, 0),
+            endpoint(4, 1),
+        ]);
+        assert_eq!((actual[0], actual[2]), (0.0, 0.0));
+        assert_distribution(&actual, &[0.0, 0.75, 0.0, 0.25]);
+    }
+
+    #[test]
+    fn all_zero_weights_are_uniform() {
+        let actual = shares(&[endpoint(1, 0), endpoint(2, 0), endpoint(3, 0)]);
+        assert_distribution(&actual, &[1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn boundaries_never_land_on_a_zero_weight_endpoint() {
+        let endpoints = [
+            endpoint(1, 0),
+            endpoint(2, 5),
+            endpoint(3, 5),
+            endpoint(4, 0),
+        ];
+        let candidates: Vec<&Endpoint> = endpoints.iter().collect();
+        assert_eq!(weighted_index(&candidates, 0.0), 1);
+        assert_eq!(weighted_index(&candidates, 0.5), 2);
+        assert_eq!(weighted_index(&candidates, 1.0), 2);
+        assert_eq!(weighted_index(&candidates, 1.0 - f64::EPSILON), 2);
+    }
+
+    // 按 tick (1ms) 推进的模拟：每个 tick 到达 ARRIVALS 个请求，节点 i 的响应耗时为 rtts[i] 个 tick，
+    // 请求在途期间一直持有 InFlight，完成时把耗时报给 observe。返回每个节点承接的请求比例
+    fn simulate(rtts: &[u64], ticks: u64) -> Vec<f64> {
+        const ARRIVALS: usize = 20;
+        let lb = LoadBalancer::default();
+        let cluster = Cluster {
+            name: "backend".to_string(),
+            lb_policy: LbPolicy::LbPeakEwma as i32,
+            ..Default::default()
+        };
+        let endpoints: Vec<Endpoint> = (1..=rtts.len() as u32)
+            .map(|port| endpoint(port, 0))
+            .collect();
+        let candidates: Vec<&Endpoint> = endpoints.iter().collect();
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut counts = vec![0usize; endpoints.len()];
+        let mut pending: Vec<(u64, Duration, InFlight)> = Vec::new();
+        for tick in 0..ticks {
+            pending.retain(|(done, rtt, in_flight)| {
+                if *done > tick {
+                    return true;
+                }
+                in_flight.observe(*rtt);
+                false
+            });
+            for _ in 0..ARRIVALS {
+                let picked = lb.pick_with(&cluster, &candidates, &mut rng).unwrap();
+                let i = picked.port as usize - 1;
+                counts[i] += 1;
+                let in_flight = lb.begin(&cluster, &upstream::endpoint_label(picked));
+                pending.push((tick + rtts[i], Duration::from_millis(rtts[i]), in_flight));
+            }
+        }
+        let total = counts.iter().sum::<usize>() as f64;
+        counts.iter().map(|&c| c as f64 / total).collect()
+    }
+
+    #[test]
+    fn peak_ewma_spreads_evenly_across_equal_endpoints() {
+        let actual = simulate(&[10, 10, 10, 10], 1_000);
+        assert_distribution(&actual, &[0.25, 0.25, 0.25, 0.25]);
+    }
+
+    // 慢 10 倍的节点分到的流量远低于 1/N，但不会被饿死 (在途请求多了之后快节点的得分会超过它)
+    #[test]
+    fn peak_ewma_biases_away_from_a_slow_endpoint() {
+        let actual = simulate(&[10, 10, 100, 10], 2_000);
+        assert!(actual[2] > 0.0, "slow endpoint starved: {:?}", actual);
+        assert!(actual[2] < 0.05, "slow endpoint share {:?}", actual);
+        let fast = (1.0 - actual[2]) / 3.0;
+        assert_distribution(&actual, &[fast, fast, actual[2], fast]);
+    }
+
+    #[test]
+    fn peak_ewma_prefers_a_new_endpoint_over_a_busy_one() {
+        let lb = LoadBalancer::default();
+        let cluster = Cluster {
+            name: "backend".to_string(),
+            lb_policy: LbPolicy::LbPeakEwma as i32,
+            ..Default::default()
+        };
+        let endpoints = [endpoint(1, 0), endpoint(2, 0)];
+        let candidates: Vec<&Endpoint> = endpoints.iter().collect();
+        let busy = lb.begin(&cluster, &upstream::endpoint_label(&endpoints[0]));
+        busy.observe(Duration::from_millis(10));
+        // 没有样本的节点按 default_rtt_ms (10ms) 计算，在途请求更少，得分更低
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..100 {
+            let picked = lb.pick_with(&cluster, &candidates, &mut rng).unwrap();
+            assert_eq!(picked.port, 2);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// 数据面的全部模块都在这个 library 里，main.rs 只负责启动 (读环境变量、连接 Control Plane、组装 Pingora 服务)。
+// 单独成库是为了让 benches/ 下的基准测试能直接调用路由表、插件运行时等内部实现。
+pub mod accesslog;
+pub mod admin;
+pub mod attributes;
+pub mod buildinfo;
+pub mod cache;
+pub mod canary;
+pub mod circuit_breaker;
+pub mod client;
+pub mod derive;
+pub mod direct;
+pub mod exclusion;
+pub mod grpc;
+pub mod health;
+pub mod idempotency;
+pub mod lb;
+pub mod matcher;
+pub mod metrics;
+pub mod mirror;
+pub mod node;
+pub mod outcome;
+pub mod outlier;
+pub mod panics;
+pub mod proxy_protocol;
+pub mod reason;
+pub mod recent;
+pub mod redirect;
+pub mod replay;
+pub mod retry;
+pub mod rewrite;
+pub mod rollout;
+pub mod router;
+pub mod routestats;
+pub mod runtime;
+pub mod safemode;
+pub mod selector;
+pub mod split;
+pub mod tls;
+pub mod trace;
+pub mod transform;
+pub mod upstream;
+pub mod validate;
+pub mod warmup;
+pub mod wasm;
+pub mod watchdog;
@@ -1,17 +1,54 @@
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
-use pingora::proxy::ProxyHttp;
+use bytes::Bytes;
+use pingora::OrErr;
+use pingora::apps::HttpServerOptions;
+use pingora::apps::http_app::HttpServer;
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::listeners::TcpSocketOptions;
+use pingora::proxy::{FailToProxy, ProxyHttp};
 use pingora::proxy::Session;
 use pingora::proxy::http_proxy_service;
 use pingora::server::Server;
 use pingora::server::configuration::Opt;
+use pingora::services::listening::Service;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
-mod client;
-use client::AgwClient;
-mod wasm;
-use wasm::WasmRuntime;
-use wasm::ExternalResources; // Import struct
+use data_plane::{
+    attributes, cache, client, derive, direct, grpc, idempotency, metrics, mirror, outlier,
+    panics, proxy_protocol, redirect, replay, retry, runtime, tls, trace, upstream, validate,
+    wasm,
+};
+use data_plane::accesslog::AccessLog;
+use data_plane::admin::AdminApp;
+use data_plane::buildinfo::BuildInfo;
+use data_plane::attributes::RequestAttributes;
+use data_plane::cache::{PendingEntry, ResponseCache};
+use data_plane::circuit_breaker::{Admission, CircuitBreakers};
+use data_plane::client::AgwClient;
+use data_plane::derive::Derivation;
+use data_plane::node::{NodeIdentity, RuntimeInfo};
+use data_plane::outcome::RequestOutcome;
+use data_plane::outlier::OutlierTracker;
+use data_plane::panics::PanicTracker;
+use data_plane::reason::ReasonCode;
+use data_plane::recent::RecentRequests;
+use data_plane::client::agw::config::v1::{CachePolicy, TrailerPolicy};
+use data_plane::health::{EndpointRegistry, HealthChecker};
+use data_plane::idempotency::{Claim, CompiledIdempotency, IdempotencyStore, Reservation};
+use data_plane::lb::{InFlight, LoadBalancer};
+use data_plane::mirror::{Mirror, PendingMirror};
+use data_plane::router::{ActiveConfig, Resolution, RouteQuery};
+use data_plane::runtime::RuntimeLayout;
+use data_plane::safemode::SafeMode;
+use data_plane::trace::TraceContext;
+use data_plane::validate::{ApplyTiming, ConfigStatus, SanityGuard, StateCarryover};
+use data_plane::warmup::{self, Readiness};
+use data_plane::watchdog::{Budgets, ProcSampler, Watchdog};
+use data_plane::wasm::{PluginResult, WasmRuntime};
+use data_plane::wasm::ExternalResources; // Import struct
 // We need to import the proto types. They are re-exported in client usually or accessible.
 // client.rs exposes Node. We need ConfigSnapshot too.
 // Let's rely on client code to return us something or expose it.
@@ -27,15 +64,464 @@ pub struct AgwProxy {
     //    - 读 (Read): 当成千上万个请求进来时，它们通过 `load()` 拿到当前的配置快照。这个操作极快，不需要抢锁 (Mutex)。
     //    - 写 (Write): 当配置更新时，后台线程通过 `store()` 将旧配置原子替换为新配置。
     //    - 效果: 更新配置的一瞬间，正在处理的旧请求继续用旧配置跑完，新进来的请求立刻用新配置。
-    config: Arc<ArcSwap<client::agw::v1::ConfigSnapshot>>,
+    config: Arc<ArcSwap<ActiveConfig>>,
     wasm: WasmRuntime,
+    // 是否已经应用过第一份有效配置。
+    // 在 AGW_BIND_BEFORE_CONFIG 模式下，端口会先于配置绑定，此时所有请求都返回 503 (WARMING_UP)。
+    ready: Arc<Readiness>,
+    // 上游节点协议错误统计 (超大响应、帧格式错误等)
+    outliers: Arc<OutlierTracker>,
+    // 节点可用性的唯一来源 (被动摘除等)，负载均衡只看这里
+    health: Arc<EndpointRegistry>,
+    // 上游读超时：防止后端声明的 Content-Length 大于实际 body 时请求永远挂住
+    upstream_read_timeout: std::time::Duration,
+    // 路由没有设置 timeout_ms 时的请求截止时间 (AGW_DEFAULT_TIMEOUT_MS，None = 不限)
+    default_timeout: Option<std::time::Duration>,
+    // WebSocket 连接上游方向多久没有数据就断开 (AGW_WEBSOCKET_IDLE_TIMEOUT_SECS，None = 不限)
+    websocket_idle_timeout: Option<std::time::Duration>,
+    // 路由级响应缓存 (所有 worker 共享)
+    cache: Arc<ResponseCache>,
+    // 最近请求环形缓冲 (管理端口 /recent_requests)
+    recent: Arc<RecentRequests>,
+    // 写进访问日志的请求属性 key (AGW_LOG_ATTRIBUTES)
+    log_attributes: Vec<String>,
+    // 资源看门狗：超过软预算时关闭可选功能，超过硬预算时拒绝新请求
+    watchdog: Arc<Watchdog>,
+    // 访问日志输出 (stdout / 文件 / syslog / HTTP 批量)
+    access_log: Arc<AccessLog>,
+    // 负载均衡状态 (轮询计数器)
+    lb: Arc<LoadBalancer>,
+    // 按集群的熔断状态
+    breakers: Arc<CircuitBreakers>,
+    // 幂等键占位与已完成响应 (所有 worker 共享)
+    idempotency: Arc<IdempotencyStore>,
+    // 阶段边界捕获的 panic 计数 (/healthz 的 degraded 判定)
+    panics: Arc<PanicTracker>,
+    // 流量镜像 (路由的 mirror_cluster)
+    mirror: Arc<Mirror>,
+    // 安全模式：打开时不执行任何插件 (见 safemode.rs)
+    safe_mode: Arc<SafeMode>,
+    // PROXY protocol Listener 上中继连接对应的真实客户端地址 (见 proxy_protocol.rs)
+    client_addrs: Arc<proxy_protocol::ClientAddrs>,
+    // 插件通过 agw_get_body 能拿到的最大请求体 (AGW_PLUGIN_MAX_BODY_BYTES)
+    plugin_max_body: usize,
+    // 插件没有设置 timeout_ms 时单次执行的超时 (AGW_PLUGIN_TIMEOUT_MS)
+    plugin_timeout: std::time::Duration,
+    // 插件没有设置 max_fuel 时单次执行的 fuel 上限 (AGW_PLUGIN_MAX_FUEL)
+    plugin_max_fuel: u64,
+}
+
+// 每个请求独享的上下文 (CTX)，贯穿 request_filter -> upstream_peer -> logging 各个阶段
+pub struct RequestCtx {
+    start: Instant,
+    // 请求到达时的墙上时钟，路由的定时生效/灰度比例都基于它计算
+    received_at: SystemTime,
+    // 灰度哈希使用的 key (客户端 IP)
+    rollout_key: String,
+    outcome: RequestOutcome,
+    // 客户端是否在请求里声明了 "TE: trailers" (只有这种客户端才会收到上游的 trailer)
+    accepts_trailers: bool,
+    // 命中路由的 trailer 策略
+    trailer_policy: TrailerPolicy,
+    // 命中路由允许的最大响应字节数 (0 = 不限制) 以及目前已转发的字节数
+    max_response_bytes: u64,
+    response_bytes: u64,
+    // 超限时截断而不是报错 (响应的 Content-Type 在路由的 truncate_content_types 里)
+    truncate_oversize: bool,
+    // 命中路由的缓存策略；未命中缓存时记下 key 和请求头，响应阶段据此决定是否写入
+    cache_policy: Option<CachePolicy>,
+    cache_key: String,
+    cache_request_headers: std::collections::HashMap<String, String>,
+    cache_pending: Option<PendingEntry>,
+    // 内置过滤器写入、插件只读的请求属性
+    attributes: RequestAttributes,
+    // 金丝雀名单命中时的目标集群，优先于路由自身的 cluster_id
+    canary_cluster: Option<String>,
+    // 分流路由在 request_filter 里按权重选出的集群，代替路由的 cluster_id (重试也用它)
+    split_cluster: Option<String>,
+    // request_filter 命中的路由，upstream_peer 直接使用，不再重新匹配
+    matched: Option<MatchedRoute>,
+    // 选中节点的进行中请求计数 (最少连接数负载均衡)，释放时减一
+    in_flight: Option<InFlight>,
+    // 路由开启 hash_request_body 时，随请求体流式更新的哈希
+    body_hasher: Option<Sha256>,
+    // 幂等键的第一次请求持有的占位，响应完整转发后写入存储
+    idempotency: Option<Reservation>,
+    // 选中上游节点的时刻，收到上游响应头时统计上游耗时
+    upstream_started: Option<Instant>,
+    // 上游响应的状态码 (被动健康检查把 5xx 算作节点失败)
+    upstream_status: Option<u16>,
+    // 选中节点后按 host_rewrite 算出的上游 Host 头 (None = 透传客户端的 Host)
+    upstream_host: Option<String>,
+    // W3C 追踪上下文 (网关自己的 span)，转发上游时注入 traceparent
+    trace: Option<TraceContext>,
+    // 路由重试策略已经触发的重试次数，以及下一次选节点之前要等待的退避时间
+    retry_count: u32,
+    retry_backoff: Option<std::time::Duration>,
+    // 请求的截止时间 (start + 路由的 timeout_ms)，重试、建连和接收响应都要在它之前完成
+    deadline: Option<Instant>,
+    // WebSocket 升级请求 (路由开启了 allow_websocket)：不设截止时间，上游读写超时换成空闲超时
+    websocket: bool,
+    // 这个请求是熔断半开时放行的试探请求 (结果记录一次后清除)
+    breaker_probe: bool,
+    // 等待请求体收完的镜像请求，发出后清除
+    mirror: Option<PendingMirror>,
+    // 路由的请求体上限 (max_request_body_bytes，0 = 不限制) 和目前收到的请求体字节数
+    max_request_body_bytes: u64,
+    request_body_received: u64,
+    // 网关生成错误响应时用的模板 (快照的 error_responses)，request_filter 开始时设置
+    error_pages: Option<Arc<direct::ErrorPages>>,
+    // agw_active_requests 计数，随 CTX 释放减一
+    _active: metrics::ActiveRequest,
+}
+
+impl RequestCtx {
+    // 距截止时间还剩多少 (已过期为 0)；路由没有截止时间时为 None
+    fn remaining(&self) -> Option<std::time::Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn timed_out(&self) -> bool {
+        self.remaining().is_some_and(|r| r.is_zero())
+    }
+}
+
+// 【命中的路由 (MatchedRoute)】
+// 连同匹配时的配置快照一起保存：即使两个阶段之间配置被替换，
+// upstream_peer 看到的路由、集群和 request_filter 仍然来自同一份快照。
+struct MatchedRoute {
+    config: Arc<ActiveConfig>,
+    index: usize,
 }
 
 #[async_trait]
 impl ProxyHttp for AgwProxy {
-    type CTX = ();
+    type CTX = RequestCtx;
     fn new_ctx(&self) -> Self::CTX {
-        ()
+        RequestCtx {
+            start: Instant::now(),
+            received_at: SystemTime::now(),
+            rollout_key: String::new(),
+            outcome: RequestOutcome::default(),
+            accepts_trailers: false,
+            trailer_policy: TrailerPolicy::TrailerPropagate,
+            max_response_bytes: 0,
+            response_bytes: 0,
+            truncate_oversize: false,
+            cache_policy: None,
+            cache_key: String::new(),
+            cache_request_headers: std::collections::HashMap::new(),
+            cache_pending: None,
+            attributes: RequestAttributes::default(),
+            canary_cluster: None,
+            split_cluster: None,
+            matched: None,
+            in_flight: None,
+            body_hasher: None,
+            idempotency: None,
+            upstream_started: None,
+            upstream_status: None,
+            upstream_host: None,
+            trace: None,
+            retry_count: 0,
+            retry_backoff: None,
+            deadline: None,
+            websocket: false,
+            breaker_probe: false,
+            mirror: None,
+            max_request_body_bytes: 0,
+            request_body_received: 0,
+            error_pages: None,
+            _active: metrics::ActiveRequest::begin(),
+        }
+    }
+
+    // 各阶段的处理逻辑在 impl AgwProxy 的 *_phase 方法里；这里只在阶段边界捕获 panic (见 panics.rs)
+    async fn request_filter(
+        &self,
+        session: &mut Session,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<bool> {
+        let result = panics::catch(self.request_filter_phase(session, ctx)).await;
+        self.phase_result(result, "request_filter", ctx)
+    }
+
+    async fn upstream_peer(
+        &self,
+        session: &mut Session,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<Box<pingora::upstreams::peer::HttpPeer>> {
+        let result = panics::catch(self.upstream_peer_phase(session, ctx)).await;
+        self.phase_result(result, "upstream_peer", ctx)
+    }
+
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<()> {
+        let result =
+            panics::catch(self.upstream_request_filter_phase(session, upstream_request, ctx)).await;
+        self.phase_result(result, "upstream_request_filter", ctx)
+    }
+
+    async fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        let result =
+            panics::catch(self.request_body_filter_phase(session, body, end_of_stream, ctx)).await;
+        self.phase_result(result, "request_body_filter", ctx)
+    }
+
+    async fn response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<()> {
+        let result =
+            panics::catch(self.response_filter_phase(session, upstream_response, ctx)).await;
+        self.phase_result(result, "response_filter", ctx)
+    }
+
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<Option<std::time::Duration>> {
+        let result = panics::catch_sync(|| {
+            self.response_body_filter_phase(session, body, end_of_stream, ctx)
+        });
+        self.phase_result(result, "response_body_filter", ctx)
+    }
+
+    async fn response_trailer_filter(
+        &self,
+        session: &mut Session,
+        upstream_trailers: &mut http::HeaderMap,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<Option<Bytes>> {
+        let result =
+            panics::catch(self.response_trailer_filter_phase(session, upstream_trailers, ctx))
+                .await;
+        self.phase_result(result, "response_trailer_filter", ctx)
+    }
+
+    // 【连接上游失败】按路由的重试策略换一个节点重试
+    fn fail_to_connect(
+        &self,
+        session: &mut Session,
+        _peer: &pingora::upstreams::peer::HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<pingora::Error>,
+    ) -> Box<pingora::Error> {
+        let reason =
+            outlier::classify_upstream_error(&e).unwrap_or(ReasonCode::UpstreamConnectFailed);
+        if !e.retry() && self.take_retry(session, ctx, Some(reason)) {
+            e.set_retry(true);
+        }
+        e
+    }
+
+    // 【转发过程中出错】
+    // 先保持 Pingora 的默认行为 (复用的连接可能已经被上游关掉，请求体还能重放时重试)，
+    // 再按路由的重试策略处理上游超时、连接重置等错误。超大响应换节点也没用，不重试。
+    fn error_while_proxy(
+        &self,
+        peer: &pingora::upstreams::peer::HttpPeer,
+        session: &mut Session,
+        e: Box<pingora::Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<pingora::Error> {
+        let mut e = e.more_context(format!("Peer: {}", peer));
+        e.retry
+            .decide_reuse(client_reused && !session.as_ref().retry_buffer_truncated());
+        if !e.retry() {
+            let retry = outlier::classify_upstream_error(&e)
+                .filter(|r| *r != ReasonCode::UpstreamResponseTooLarge)
+                .is_some_and(|reason| self.take_retry(session, ctx, Some(reason)));
+            if retry {
+                e.set_retry(true);
+            }
+        }
+        e
+    }
+
+    // 【代理失败 (Fail to Proxy)】
+    // request_filter / upstream_peer / 转发过程中返回错误时，Pingora 调用这里生成错误响应。
+    // 状态码的选择与 Pingora 默认行为一致 (上游超时除外，返回 504)，只是响应体换成带原因码的 JSON。
+    async fn fail_to_proxy(
+        &self,
+        session: &mut Session,
+        e: &pingora::Error,
+        ctx: &mut Self::CTX,
+    ) -> FailToProxy
+    where
+        Self::CTX: Send + Sync,
+    {
+        // 过了截止时间之后的上游错误 (多半就是被截止时间截断的读 / 建连) 一律按 504 处理
+        if ctx.timed_out() && outlier::classify_upstream_error(e).is_some() {
+            ctx.outcome.reason = Some(ReasonCode::RouteTimeout);
+        }
+        let code = match e.etype() {
+            _ if ctx.outcome.reason == Some(ReasonCode::RouteTimeout) => 504,
+            pingora::ErrorType::HTTPStatus(code) => *code,
+            // 上游在读 / 写超时之内没有响应 (AGW_UPSTREAM_READ_TIMEOUT_SECS 或重试策略的 per_try_timeout_ms)
+            pingora::ErrorType::ReadTimedout | pingora::ErrorType::WriteTimedout
+                if e.esource() == &pingora::ErrorSource::Upstream =>
+            {
+                504
+            }
+            _ => match e.esource() {
+                pingora::ErrorSource::Upstream => 502,
+                pingora::ErrorSource::Downstream => match e.etype() {
+                    pingora::ErrorType::WriteError
+                    | pingora::ErrorType::ReadError
+                    | pingora::ErrorType::ConnectionClosed => 0,
+                    _ => 400,
+                },
+                pingora::ErrorSource::Internal | pingora::ErrorSource::Unset => 500,
+            },
+        };
+        let reason = *ctx.outcome.reason.get_or_insert_with(|| {
+            outlier::classify_upstream_error(e).unwrap_or_else(|| error_reason(e))
+        });
+        // 响应头已经发出 (如转发中途超限) 时只能断开连接
+        if code > 0 && session.response_written().is_none() {
+            if let Err(err) = respond_reason(session, ctx, code, reason).await {
+                eprintln!("Failed to send error response: {}", err);
+            }
+        }
+        FailToProxy {
+            error_code: code,
+            can_reuse_downstream: false,
+        }
+    }
+
+    async fn logging(
+        &self,
+        session: &mut Session,
+        e: Option<&pingora::Error>,
+        ctx: &mut Self::CTX,
+    ) {
+        if let Err(panic) = panics::catch(self.logging_phase(session, e, ctx)).await {
+            self.panics.record_request(&panic, "logging", &ctx.outcome);
+        }
+    }
+}
+
+impl AgwProxy {
+    // 【重试判定】
+    // 当前这次上游尝试失败后，按路由的重试策略决定要不要换个节点再试。
+    // 要重试时先把失败记到这个节点名下 (logging 只看得到最后一次尝试)，再记下退避时间。
+    fn take_retry(
+        &self,
+        session: &Session,
+        ctx: &mut RequestCtx,
+        failure: Option<ReasonCode>,
+    ) -> bool {
+        let backoff = {
+            let Some(retry) = ctx
+                .matched
+                .as_ref()
+                .and_then(|m| m.config.routes[m.index].retry.as_ref())
+            else {
+                return false;
+            };
+            // 响应头已经发出，或者请求体超过了重试缓冲，都没法再重放这个请求
+            if !retry.allows(&session.req_header().method, ctx.retry_count)
+                || ctx.timed_out()
+                || session.response_written().is_some()
+                || session.as_ref().retry_buffer_truncated()
+            {
+                return false;
+            }
+            retry.backoff(ctx.retry_count + 1)
+        };
+        if let (Some(reason), Some(cluster), Some(endpoint)) =
+            (failure, &ctx.outcome.cluster, &ctx.outcome.endpoint)
+        {
+            self.outliers.record_failure(endpoint, reason);
+            self.health.record_failure(cluster, endpoint, reason);
+        }
+        if failure.is_some() {
+            self.record_breaker(ctx, true);
+        }
+        ctx.in_flight = None;
+        ctx.retry_count += 1;
+        ctx.retry_backoff = Some(backoff);
+        true
+    }
+
+    // 上游尝试的结果计入所选集群的熔断统计
+    fn record_breaker(&self, ctx: &mut RequestCtx, failed: bool) {
+        let probe = std::mem::take(&mut ctx.breaker_probe);
+        let (Some(matched), Some(name)) = (&ctx.matched, &ctx.outcome.cluster) else {
+            return;
+        };
+        let snapshot = &matched.config.snapshot;
+        if let Some(cluster) = snapshot.clusters.iter().find(|c| &c.name == name) {
+            self.breakers.record(cluster, failed, probe);
+        }
+    }
+
+    // 请求体收完之后发出镜像请求：镜像集群里随机选一个可用节点 (不参与负载均衡和健康统计)
+    fn dispatch_mirror(&self, ctx: &mut RequestCtx) {
+        let (Some(pending), Some(matched)) = (ctx.mirror.take(), &ctx.matched) else {
+            return;
+        };
+        let snapshot = &matched.config.snapshot;
+        let cluster = snapshot.clusters.iter().find(|c| c.name == pending.cluster);
+        let endpoint = cluster.and_then(|c| {
+            let available: Vec<_> = c
+                .endpoints
+                .iter()
+                .filter(|e| {
+                    let label = upstream::endpoint_label(e);
+                    !self.health.is_admin_down(&c.name, &label)
+                        && self.health.is_available(&c.name, &label)
+                })
+                .collect();
+            available
+                .get(rand::random::<usize>() % available.len().max(1))
+                .copied()
+        });
+        let host = cluster
+            .zip(endpoint)
+            .and_then(|(c, e)| upstream::rewritten_host(&c.host_rewrite, e));
+        self.mirror.dispatch(pending, endpoint, host);
+    }
+
+    // 阶段里发生 panic：记录日志和指标，转换成 500 (原因码 INTERNAL_PANIC，由 fail_to_proxy 生成响应)
+    fn phase_result<T>(
+        &self,
+        result: Result<pingora::Result<T>, panics::PanicReport>,
+        phase: &'static str,
+        ctx: &mut RequestCtx,
+    ) -> pingora::Result<T> {
+        match result {
+            Ok(result) => result,
+            Err(panic) => {
+                self.panics.record_request(&panic, phase, &ctx.outcome);
+                ctx.outcome.reason = Some(ReasonCode::InternalPanic);
+                Err(pingora::Error::explain(
+                    pingora::ErrorType::HTTPStatus(500),
+                    format!("panic in {}", phase),
+                ))
+            }
+        }
     }
 
     // 【阶段 1: 请求过滤器 (Request Filter)】
@@ -45,124 +531,535 @@ impl ProxyHttp for AgwProxy {
     // 2. 匹配路由 (Routing)
     // 3. 执行 Wasm 插件 (鉴权、限流等)
     // 4. 决定请求是继续转发 (return false) 还是直接拦截响应 (return true)
-    async fn request_filter(
+    async fn request_filter_phase(
         &self,
         session: &mut Session,
-        _ctx: &mut Self::CTX,
+        ctx: &mut RequestCtx,
     ) -> pingora::Result<bool> {
+        ctx.outcome.method = session.req_header().method.to_string();
+        ctx.outcome.path = session.req_header().uri.path().to_string();
+        ctx.rollout_key = session
+            .client_addr()
+            .and_then(|a| a.as_inet())
+            .map(|a| a.ip().to_string())
+            .unwrap_or_default();
+        // 经过 PROXY protocol 中继的连接：用 PROXY 头里的真实客户端地址，并告诉上游。
+        // 其他连接上客户端自己带的 X-Real-IP 也要删掉，否则谁都能伪造客户端地址
+        let real_client = match (
+            session.client_addr().and_then(|a| a.as_inet()),
+            session.server_addr().and_then(|a| a.as_inet()),
+        ) {
+            (Some(peer), Some(server)) => self.client_addrs.lookup(*peer, *server),
+            _ => None,
+        };
+        if let Some(real) = real_client {
+            ctx.rollout_key = real.ip().to_string();
+        }
+        proxy_protocol::forward_real_ip(session.req_header_mut(), real_client)?;
+        ctx.accepts_trailers = session
+            .req_header()
+            .headers
+            .get_all(http::header::TE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case("trailers")));
+        // 入口请求带 traceparent 时作为它的子 span，否则开始一条新的 trace
+        let trace = TraceContext::from_request(&session.req_header().headers);
+        ctx.outcome.trace_id = Some(trace::hex(&trace.trace_id));
+        ctx.outcome.span_id = Some(trace::hex(&trace.span_id));
+        ctx.outcome.parent_span_id = trace.parent_span_id.map(|id| trace::hex(&id));
+        ctx.trace = Some(trace);
+        ctx.error_pages = Some(self.config.load().error_pages.clone());
+
+        // 0. 预热阶段：还没拿到第一份配置，直接返回 503，让客户端稍后重试
+        if !self.ready.is_ready() {
+            ctx.outcome.reason = Some(ReasonCode::WarmingUp);
+            respond_reason(session, ctx, 503, ReasonCode::WarmingUp).await?;
+            return Ok(true);
+        }
+        // 资源超过硬预算：拒绝新请求，保护正在处理中的请求
+        if self.watchdog.shedding() {
+            ctx.outcome.reason = Some(ReasonCode::Overloaded);
+            respond_reason(session, ctx, 503, ReasonCode::Overloaded).await?;
+            return Ok(true);
+        }
+
         // 1. 获取最新配置 (RCU - 用于读)
-        // load() 返回一个临时的 Guard，保证我们在使用期间配置不会被释放
-        let config = self.config.load();
-        let path = session.req_header().uri.path();
-        let _host = session.req_header().uri.host().unwrap_or("");
+        // load_full() 拿到一个 Arc：命中路由后把它存进 CTX，后续阶段都用这同一份快照
+        let config = self.config.load_full();
+        // 拷贝一份：读请求体、改写请求头时要可变借用 session
+        let path = session.req_header().uri.path().to_string();
+        let path = path.as_str();
+        let host = request_host(session.req_header()).map(str::to_string);
+        let host = host.as_deref();
+        let method = session.req_header().method.as_str().to_string();
+        let method = method.as_str();
 
         // 2. 匹配路由 (Routing)
-        // MVP: 简单遍历路由表 (生产环境通常使用线段树、radix tree 或者 hash map)
-        for route in &config.routes {
-            // 前缀匹配 (Prefix Match)
-            if path.starts_with(&route.path_prefix) {
-                // 3. 执行插件链 (Wasm Plugins)
-                if !route.plugins.is_empty() {
-                    // 准备工作：把 Pingora 的 Header 转换成 Wasm 能懂的 HashMap
-                    let mut headers = std::collections::HashMap::new();
-                    for (name, value) in session.req_header().headers.iter() {
-                        if let Ok(v_str) = value.to_str() {
-                            headers.insert(name.to_string(), v_str.to_string());
-                        }
+        // 通过路由索引 (前缀树) 只取出路径可能命中的候选，按优先级逐个检查其余条件 (见 ActiveConfig::resolve)
+        let lookup = RouteQuery {
+            method,
+            path,
+            host,
+            headers: &session.req_header().headers,
+            query: session.req_header().uri.query(),
+            rollout_key: ctx.rollout_key.as_bytes(),
+            at: ctx.received_at,
+        };
+        let index = match config.resolve(&lookup) {
+            Resolution::Matched {
+                index,
+                rollout_fraction,
+            } => {
+                ctx.outcome.rollout_fraction = rollout_fraction;
+                index
+            }
+            // 路径命中了路由，但没有路由接受这个方法 -> 405 Method Not Allowed
+            Resolution::MethodNotAllowed(allow) => {
+                ctx.outcome.reason = Some(ReasonCode::MethodNotAllowed);
+                respond_method_not_allowed(session, &allow).await?;
+                return Ok(true);
+            }
+            // 没有匹配到任何路由：交给兜底路由 (default_route)，和普通路由一样执行插件链再转发 / 应答；
+            // 也没有兜底路由 -> 404 Not Found (响应体可以用 not_found_response 定制)
+            Resolution::NoRoute => match config.default_route {
+                Some(index) => index,
+                None => {
+                    ctx.outcome.reason = Some(ReasonCode::NoRoute);
+                    match &config.not_found {
+                        Some(not_found) => respond_direct(session, not_found).await?,
+                        None => respond_reason(session, ctx, 404, ReasonCode::NoRoute).await?,
                     }
+                    return Ok(true); // 请求结束
+                }
+            },
+        };
+        let compiled = &config.routes[index];
+        let route = &compiled.route;
+        ctx.outcome.route = Some(config.route_name(index).to_string());
+        // 停用的路由：插件链之前直接返回 (默认 503 + Retry-After)，配置保留在快照里
+        if let Some(disabled) = &compiled.disabled {
+            ctx.outcome.reason = Some(ReasonCode::RouteDisabled);
+            respond_direct(session, disabled).await?;
+            return Ok(true);
+        }
+        // 末尾斜杠不符合路由的 trailing_slash 要求 (只看 GET / HEAD)：301 到规范写法，查询串原样保留
+        let canonical = redirect::canonical_trailing_slash(route.trailing_slash(), method, path);
+        if let Some(canonical) = canonical {
+            let location = match session.req_header().uri.query() {
+                Some(query) => format!("{}?{}", canonical, query),
+                None => canonical,
+            };
+            ctx.outcome.reason = Some(ReasonCode::RouteRedirect);
+            respond_redirect(session, 301, &location).await?;
+            return Ok(true);
+        }
+        // 请求体上限：声明了 Content-Length 的请求在这里直接拒绝，chunked 请求在 request_body_filter 里边收边数
+        if route.max_request_body_bytes > 0 {
+            if request_content_length(session.req_header())
+                .is_some_and(|len| len > route.max_request_body_bytes)
+            {
+                ctx.outcome.reason = Some(ReasonCode::RequestBodyTooLarge);
+                respond_reason(session, ctx, 413, ReasonCode::RequestBodyTooLarge).await?;
+                return Ok(true);
+            }
+            ctx.max_request_body_bytes = route.max_request_body_bytes;
+        }
+        ctx.trailer_policy = route.trailer_policy();
+        if route.hash_request_body {
+            ctx.body_hasher = Some(Sha256::new());
+        }
+        ctx.max_response_bytes = route.max_response_bytes;
+        ctx.deadline = match route.timeout_ms {
+            0 => self.default_timeout,
+            ms => Some(std::time::Duration::from_millis(ms)),
+        }
+        .map(|timeout| ctx.start + timeout);
+        // WebSocket：只有开启了 allow_websocket 的路由接受升级请求。升级之后是长连接上的双向字节流，
+        // 不受路由截止时间和响应大小上限约束，也不查缓存、不镜像 (见 upstream_peer 的空闲超时)
+        if is_websocket_upgrade(session.req_header()) {
+            if !route.allow_websocket {
+                ctx.outcome.reason = Some(ReasonCode::WebsocketNotAllowed);
+                respond_reason(session, ctx, 400, ReasonCode::WebsocketNotAllowed).await?;
+                return Ok(true);
+            }
+            ctx.websocket = true;
+            ctx.deadline = None;
+            ctx.max_response_bytes = 0;
+        }
+        // 3. 内置过滤器：写入请求属性。
+        // 【顺序约定】内置过滤器必须全部在插件链之前执行，插件看到的是冻结后的完整属性表。
+        ctx.attributes.set("client.ip", ctx.rollout_key.clone());
+        if let Some(host) = host {
+            ctx.attributes.set("request.host", host);
+        }
+        // 按权重分流：每个请求选一次，之后的阶段都用这个结果
+        ctx.split_cluster = compiled
+            .split
+            .as_ref()
+            .map(|split| split.pick(rand::random::<f64>()).to_string());
+        ctx.attributes.set("route.prefix", route.path_prefix.clone());
+        ctx.attributes.set_route_metadata(&route.metadata);
+        ctx.attributes.set(
+            "route.cluster",
+            ctx.split_cluster
+                .clone()
+                .unwrap_or_else(|| route.cluster_id.clone()),
+        );
 
-                    // 遍历执行该路由下的所有插件
-                    for plugin in &route.plugins {
-                        println!("Executing Plugin: {}", plugin.name);
-                        // 调用 Wasm 运行时的 run_plugin
-                        // 注意：这里 clone 了一份 headers 传给 Wasm
-                        match self.wasm.run_plugin(&plugin.wasm_path, headers.clone()).await {
-                            Ok(allow) => {
-                                if !allow {
-                                    // 插件拒绝 (如 Wasm 返回 1)
-                                    // 直接响应 403 Forbidden
-                                    let _ = session.respond_error(403).await;
-                                    return Ok(true); // True = 请求已处理，不再转发给 upstream_peer
-                                }
-                            }
-                            Err(e) => {
-                                // 插件执行出错 (如 Wasm 崩溃)
-                                // 安全起见返回 500
-                                eprintln!("Wasm Plugin Error [{}]: {}", plugin.name, e);
-                                let _ = session.respond_error(500).await;
-                                return Ok(true);
+        // 金丝雀名单：依赖内置过滤器写入的属性，所以放在它们之后
+        if let Some(canary) = &compiled.canary {
+            if canary.matches(&session.req_header().headers, &ctx.attributes) {
+                ctx.canary_cluster = Some(canary.cluster.clone());
+                ctx.outcome.canary_reason = Some("allowlist");
+            }
+        }
+
+        // 请求头改写：配置为 before_plugins 时在插件链之前执行，插件看到改写后的请求头
+        let transform = compiled.request_headers.as_ref();
+        if let Some(transform) = transform.filter(|t| t.before_plugins) {
+            transform.apply(session.req_header_mut())?;
+        }
+
+        // 4. 执行插件链 (Wasm Plugins)
+        // 探针 / 预检等豁免请求跳过插件链，但在访问日志里记下命中的规则
+        let exclusion = compiled
+            .exclusions
+            .iter()
+            .find(|e| e.matches(method, path));
+        if let Some(exclusion) = exclusion.filter(|_| compiled.has_plugins()) {
+            ctx.outcome.plugins_skipped = Some(exclusion.label.clone());
+        } else if let Some(decision) = self
+            .safe_mode
+            .decide(compiled)
+            .filter(|_| compiled.has_plugins())
+        {
+            // 安全模式：插件一律不执行，按路由声明的策略放行或拒绝
+            ctx.outcome.safe_mode = Some(decision);
+            if decision == "denied" {
+                ctx.outcome.reason = Some(ReasonCode::SafeMode);
+                respond_reason(session, ctx, 503, ReasonCode::SafeMode).await?;
+                return Ok(true);
+            }
+        } else if compiled.has_plugins() {
+            let attributes = ctx.attributes.freeze();
+            // 准备工作：把 Pingora 的 Header 转换成 Wasm 能懂的 HashMap
+            let mut headers = std::collections::HashMap::new();
+            for (name, value) in session.req_header().headers.iter() {
+                if let Ok(v_str) = value.to_str() {
+                    headers.insert(name.to_string(), v_str.to_string());
+                }
+            }
+
+            // 有插件导入了 agw_get_body 时，先把请求体读完再执行插件链
+            let body_bytes = if compiled
+                .plugins()
+                .any(|p| self.wasm.wants_body(&p.wasm_path))
+            {
+                read_body_for_plugins(session, self.plugin_max_body).await?
+            } else {
+                None
+            };
+
+            // 遍历执行插件链：全局插件在前，各自按 priority 排好顺序 (见 router.rs 的 plugin_chain)
+            for plugin in compiled.plugins() {
+                let limits = wasm::PluginLimits {
+                    timeout: match plugin.timeout_ms {
+                        0 => self.plugin_timeout,
+                        ms => std::time::Duration::from_millis(ms as u64),
+                    },
+                    fuel: match plugin.max_fuel {
+                        0 => self.plugin_max_fuel,
+                        fuel => fuel,
+                    },
+                };
+                // 调用 Wasm 运行时的 run_plugin
+                // 注意：这里 clone 了一份 headers 传给 Wasm
+                match self
+                    .wasm
+                    .run_plugin(
+                        &plugin.wasm_path,
+                        limits,
+                        headers.clone(),
+                        attributes.clone(),
+                        body_bytes.clone(),
+                    )
+                    .await
+                {
+                    Ok(PluginResult::Allow) => {
+                        ctx.outcome.record_plugin(&plugin.name, "allow");
+                    }
+                    Ok(PluginResult::Deny(response)) => {
+                        // 插件拒绝 (如 Wasm 返回 1)
+                        // 插件登记了响应 (agw_set_response) 时原样返回，否则直接响应 403 Forbidden
+                        ctx.outcome.record_plugin(&plugin.name, "deny");
+                        ctx.outcome.reason = Some(ReasonCode::PluginDeny);
+                        match response {
+                            Some(response) => respond_plugin(session, &response).await?,
+                            None => {
+                                respond_reason(session, ctx, 403, ReasonCode::PluginDeny).await?
                             }
                         }
+                        return Ok(true); // True = 请求已处理，不再转发给 upstream_peer
+                    }
+                    Err(e) => {
+                        // 插件执行出错 (如 Wasm 崩溃、执行超时、fuel 耗尽)
+                        // 安全起见返回 500
+                        let decision = wasm::error_decision(&e);
+                        if e.downcast_ref::<wasm::PluginOutOfFuel>().is_some() {
+                            metrics::record_fuel_exhausted(&plugin.name);
+                        }
+                        ctx.outcome.record_plugin(&plugin.name, decision);
+                        eprintln!("Wasm Plugin Error [{}]: {}", plugin.name, e);
+                        ctx.outcome.reason = Some(ReasonCode::PluginError);
+                        respond_reason(session, ctx, 500, ReasonCode::PluginError).await?;
+                        return Ok(true);
                     }
                 }
-                // 路由匹配成功 & 插件全通过 -> 进入下一阶段
-                // 返回 false 告诉 Pingora: "我没处理完，请继续交给 upstream_peer 处理"
-                return Ok(false); 
             }
         }
-
-        // 4. 没有匹配到任何路由 -> 404 Not Found
-        // 手动发送 404 响应
-        let _ = session.respond_error(404).await;
-        Ok(true) // 请求结束
+        // 默认的请求头改写时机：插件全部放行之后，转发 (和镜像) 之前
+        if let Some(transform) = transform.filter(|t| !t.before_plugins) {
+            transform.apply(session.req_header_mut())?;
+        }
+        // 重定向路由：插件全部放行后直接返回 3xx，不再做幂等、缓存和转发
+        if let Some(redirect) = &compiled.redirect {
+            let tls = session.digest().is_some_and(|d| d.ssl_digest.is_some());
+            let location = redirect.location(
+                tls,
+                host,
+                path,
+                session.req_header().uri.query(),
+                compiled.rewrite.as_ref(),
+            );
+            ctx.outcome.reason = Some(ReasonCode::RouteRedirect);
+            respond_redirect(session, redirect.status, &location).await?;
+            return Ok(true);
+        }
+        // 固定响应路由：同样在插件全部放行之后直接返回
+        if let Some(direct) = &compiled.direct_response {
+            ctx.outcome.reason = Some(ReasonCode::DirectResponse);
+            respond_direct(session, direct).await?;
+            return Ok(true);
+        }
+        // 5. 幂等键去重：同样在插件全部放行之后 (回放不能绕过鉴权)
+        if let Some(policy) = &compiled.idempotency {
+            let req = session.req_header();
+            if let Some(key) = policy.key(&req.method, path, &req.headers, &ctx.attributes) {
+                if idempotency_filter(&self.idempotency, session, ctx, policy, key).await? {
+                    return Ok(true);
+                }
+            }
+        }
+        // 6. 响应缓存：只缓存 GET / HEAD，插件全部放行之后才查缓存 (缓存不能绕过鉴权)
+        if let Some(policy) = route.cache.as_ref().filter(|_| !ctx.websocket) {
+            let req = session.req_header();
+            if req.method == http::Method::GET || req.method == http::Method::HEAD {
+                let key = ResponseCache::base_key(
+                    req.method.as_str(),
+                    request_host(req).unwrap_or(""),
+                    &req.uri.to_string(),
+                );
+                let request_headers = req
+                    .headers
+                    .iter()
+                    .filter_map(|(k, v)| {
+                        v.to_str().ok().map(|v| (k.as_str().to_string(), v.to_string()))
+                    })
+                    .collect();
+                if let Some(hit) = self.cache.lookup(&key, &request_headers) {
+                    ctx.outcome.cache = Some("hit");
+                    respond_cached(session, &hit).await?;
+                    return Ok(true);
+                }
+                ctx.outcome.cache = Some("miss");
+                ctx.cache_policy = Some(policy.clone());
+                ctx.cache_key = key;
+                ctx.cache_request_headers = request_headers;
+            }
+        }
+        // 7. 流量镜像：只镜像真正转发给上游的请求 (缓存命中、幂等回放在上面已经返回)，按 mirror_sample_rate 抽样
+        if !route.mirror_cluster.is_empty()
+            && !ctx.websocket
+            && mirror::sampled(route.mirror_sample_rate)
+            && self.watchdog.allow_optional()
+        {
+            let req = session.req_header();
+            let upstream_path = match &compiled.rewrite {
+                Some(rewrite) => rewrite.apply(req.uri.path()),
+                None => req.uri.path().to_string(),
+            };
+            let path_and_query = match req.uri.query() {
+                Some(query) => format!("{}?{}", upstream_path, query),
+                None => upstream_path,
+            };
+            ctx.mirror = Some(self.mirror.prepare(
+                &route.mirror_cluster,
+                &route.path_prefix,
+                req,
+                request_host(req),
+                &path_and_query,
+            ));
+        }
+        // 可能重试的请求需要保留请求体，换节点重试时重新发送
+        if compiled
+            .retry
+            .as_ref()
+            .is_some_and(|r| r.retries_method(&session.req_header().method))
+        {
+            session.as_mut().enable_retry_buffering();
+        }
+        // 路由匹配成功 & 插件全通过 -> 进入下一阶段
+        // 返回 false 告诉 Pingora: "我没处理完，请继续交给 upstream_peer 处理"
+        ctx.matched = Some(MatchedRoute {
+            config: config.clone(),
+            index,
+        });
+        Ok(false)
     }
 
     // 【阶段 2: 上游节点选择 (Upstream Peer Selection)】
     // 如果 request_filter 返回 Ok(false)，Pingora 就会调用这个函数。
     // 我们的任务是：决定把请求转发给哪个后端 IP:PORT。
-    async fn upstream_peer(
+    async fn upstream_peer_phase(
         &self,
         session: &mut Session,
-        _ctx: &mut Self::CTX,
+        ctx: &mut RequestCtx,
     ) -> pingora::Result<Box<pingora::upstreams::peer::HttpPeer>> {
-        let config = self.config.load();
-        let path = session.req_header().uri.path();
-
-        // 1. 重新匹配路由 (Route Lookup)
-        // TODO: 这里目前有些低效，因为在 request_filter 里已经匹配过一次了。
-        // 理想做法是在 request_filter 里把匹配到的 Cluster Name 存到 CTX 里传递过来。
-        let mut cluster_name = "";
-        for route in &config.routes {
-            if path.starts_with(&route.path_prefix) {
-                cluster_name = &route.cluster_id;
-                break;
-            }
-        }
-
-        if cluster_name.is_empty() {
+        // 1. 取出 request_filter 命中的路由 (以及当时的配置快照)
+        let Some(matched) = ctx.matched.as_ref() else {
             // 理论上不会发生，因为 request_filter 已经拦截了无效路由
             // 防御性编程：返回 502 Bad Gateway
+            ctx.outcome.reason = Some(ReasonCode::NoRoute);
             return Err(pingora::Error::create(
                 pingora::ErrorType::HTTPStatus(502),
                 pingora::ErrorSource::Upstream,
                 Some("no route match".into()),
                 None,
             ));
+        };
+        let config = matched.config.clone();
+        let compiled = &config.routes[matched.index];
+        let route = &compiled.route;
+        // 重试：先按退避等待，再重新选节点 (刚失败的节点已经记入被动健康检查)
+        if let Some(backoff) = ctx.retry_backoff.take() {
+            tokio::time::sleep(ctx.remaining().map_or(backoff, |r| r.min(backoff))).await;
+        }
+        // 插件链或前几次尝试已经用完了截止时间
+        if ctx.timed_out() {
+            ctx.outcome.reason = Some(ReasonCode::RouteTimeout);
+            return Err(pingora::Error::create(
+                pingora::ErrorType::HTTPStatus(504),
+                pingora::ErrorSource::Internal,
+                Some("route deadline exceeded".into()),
+                None,
+            ));
         }
+        // 集群选择表达式 (金丝雀名单优先)；结果不是已知集群时回退到分流选出的集群或路由的 cluster_id
+        let selected = match (&ctx.canary_cluster, &compiled.cluster_selector) {
+            (None, Some(selector)) => {
+                let name = selector
+                    .select(&session.req_header().headers, &ctx.attributes)
+                    .filter(|name| config.snapshot.clusters.iter().any(|c| &c.name == name));
+                ctx.outcome.cluster_selector =
+                    Some(if name.is_some() { "selected" } else { "fallback" });
+                name
+            }
+            _ => None,
+        };
+        let cluster_name = ctx
+            .canary_cluster
+            .as_deref()
+            .or(selected.as_deref())
+            .or(ctx.split_cluster.as_deref())
+            .unwrap_or(&route.cluster_id);
 
         // 2. 服务发现 (Service Discovery)
         // 根据 cluster_name 在配置中找到对应的 Cluster 定义
-        let cluster = config.clusters.iter().find(|c| c.name == cluster_name);
+        let cluster = config.snapshot.clusters.iter().find(|c| c.name == cluster_name);
         if let Some(c) = cluster {
             // 3. 负载均衡 (Load Balancing)
-            // MVP: 简单地选择第一个 Endpoint (First Available)
-            // 生产环境应在此实现 RoundRobin / Random / LeastReq 等算法，并结合健康检查。
-            if let Some(endpoint) = c.endpoints.first() {
-                let addr = (endpoint.address.as_str(), endpoint.port as u16);
+            // 候选节点经过子集过滤和健康过滤后，按集群的 lb_policy 选择 (见 lb.rs)
+            ctx.outcome.cluster = Some(c.name.clone());
+            // 熔断打开时直接 503；试探请求重试时不再重新申请
+            if !ctx.breaker_probe {
+                match self.breakers.admit(c) {
+                    Admission::Allowed => {}
+                    Admission::Probe => ctx.breaker_probe = true,
+                    Admission::Rejected => {
+                        ctx.outcome.reason = Some(ReasonCode::CircuitOpen);
+                        return Err(pingora::Error::create(
+                            pingora::ErrorType::HTTPStatus(503),
+                            pingora::ErrorSource::Internal,
+                            Some("circuit breaker open".into()),
+                            None,
+                        ));
+                    }
+                }
+            }
+            // 子集过滤在负载均衡之前进行
+            let candidates: Vec<_> = match &route.subset_selector {
+                Some(selector) => {
+                    let (subset, label) =
+                        upstream::select_subset(&c.endpoints, selector, &ctx.attributes);
+                    if subset.is_empty() && selector.fallback_to_all {
+                        ctx.outcome.subset = Some(format!("{} (fallback)", label));
+                        c.endpoints.iter().collect()
+                    } else {
+                        ctx.outcome.subset = Some(label);
+                        subset
+                    }
+                }
+                None => c.endpoints.iter().collect(),
+            };
+            // 去掉人工下线和被摘除的节点 (见 EndpointRegistry::usable)
+            let candidates = self.health.usable(&c.name, candidates);
+            if let Some(endpoint) = self.lb.pick(c, &candidates) {
+                let label = upstream::endpoint_label(endpoint);
+                // 重试时会再次进入这里，旧的计数随替换自动释放
+                ctx.in_flight = Some(self.lb.begin(c, &label));
+                ctx.outcome.endpoint = Some(label);
+                ctx.upstream_started = Some(Instant::now());
+                // 路由上的 host_rewrite 优先于集群上的
+                let host_rewrite = if route.host_rewrite.is_empty() {
+                    &c.host_rewrite
+                } else {
+                    &route.host_rewrite
+                };
+                ctx.upstream_host = upstream::rewritten_host(host_rewrite, endpoint);
                 
                 // 4. 构造 Upstream Peer
-                // 告诉 Pingora 转发的目标地址
-                let peer = Box::new(pingora::upstreams::peer::HttpPeer::new(
-                    addr,           // 目标 IP:PORT (如 10.244.1.5:8080)
-                    false,          // TLS: 是否使用 HTTPS 连接上游 (这里 MVP 暂不支持 upstream TLS)
-                    "".to_string(), // SNI: 如果是 HTTPS，这里填域名
-                ));
+                // 告诉 Pingora 转发的目标地址 (TCP 的 IP:PORT，或者本地 Unix Socket)
+                let mut peer = Box::new(upstream::build_peer(endpoint, c.protocol())?);
+                peer.options.read_timeout = Some(self.upstream_read_timeout);
+                if let Some(timeout) = compiled.retry.as_ref().and_then(|r| r.per_try_timeout) {
+                    peer.options.connection_timeout = Some(timeout);
+                    peer.options.read_timeout = Some(timeout);
+                }
+                // WebSocket 连接可以长时间没有消息，读写超时都换成空闲超时
+                if ctx.websocket {
+                    peer.options.read_timeout = self.websocket_idle_timeout;
+                    peer.options.write_timeout = self.websocket_idle_timeout;
+                }
+                // 截止时间：每一步的超时都不超过剩余时间。读超时是单次读的上限，
+                // 响应体陆续到达时由 response_body_filter 检查截止时间
+                if let Some(remaining) = ctx.remaining() {
+                    let cap = |t: Option<std::time::Duration>| {
+                        Some(t.map_or(remaining, |t| t.min(remaining)))
+                    };
+                    peer.options.connection_timeout = cap(peer.options.connection_timeout);
+                    peer.options.total_connection_timeout =
+                        cap(peer.options.total_connection_timeout);
+                    peer.options.read_timeout = cap(peer.options.read_timeout);
+                    peer.options.write_timeout = cap(peer.options.write_timeout);
+                }
                 return Ok(peer);
             }
         }
         
         // 找到了 Cluster 但没有可用 Endpoint (可能 Pod 还没 Ready)
         // 返回 503 Service Unavailable
+        ctx.outcome.reason = Some(ReasonCode::NoEndpoint);
         Err(pingora::Error::create(
             pingora::ErrorType::HTTPStatus(503),
             pingora::ErrorSource::Upstream,
@@ -170,70 +1067,465 @@ impl ProxyHttp for AgwProxy {
             None,
         ))
     }
+
+    // 【转发前改写请求头】
+    // 把 traceparent 换成网关自己的 span，上游服务的 span 挂在网关下面
+    async fn upstream_request_filter_phase(
+        &self,
+        _session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut RequestCtx,
+    ) -> pingora::Result<()> {
+        if let Some(trace) = &ctx.trace {
+            upstream_request.insert_header("traceparent", trace.traceparent())?;
+        }
+        if let Some(host) = &ctx.upstream_host {
+            upstream_request.insert_header(http::header::HOST, host.as_str())?;
+        }
+        // 路径改写：路由匹配已经用原始路径完成，这里只改转发给上游的 URI，查询串原样保留
+        let rewrite = ctx
+            .matched
+            .as_ref()
+            .and_then(|m| m.config.routes[m.index].rewrite.as_ref());
+        if let Some(rewrite) = rewrite {
+            let path = rewrite.apply(upstream_request.uri.path());
+            let path_and_query = match upstream_request.uri.query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path.clone(),
+            };
+            let mut parts = upstream_request.uri.clone().into_parts();
+            parts.path_and_query = Some(
+                path_and_query
+                    .parse()
+                    .or_err(pingora::ErrorType::InternalError, "invalid rewritten path")?,
+            );
+            let uri = http::Uri::from_parts(parts)
+                .or_err(pingora::ErrorType::InternalError, "invalid rewritten uri")?;
+            upstream_request.set_uri(uri);
+            ctx.outcome.upstream_path = Some(path);
+        }
+        Ok(())
+    }
+
+    // 【请求体哈希】
+    // 每个分片转发给上游之前顺手更新哈希，不缓冲、不改动分片，流式语义不变。
+    // 请求体完整结束时算出摘要：写进访问日志和请求属性 (request.body_sha256)。
+    async fn request_body_filter_phase(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut RequestCtx,
+    ) -> pingora::Result<()> {
+        // 没有 Content-Length (chunked) 的请求体超过路由的 max_request_body_bytes：返回错误，
+        // Pingora 回 413 (fail_to_proxy) 并丢弃已经发出一半请求的上游连接，不会复用
+        if ctx.max_request_body_bytes > 0 {
+            ctx.request_body_received += body.as_ref().map_or(0, |b| b.len() as u64);
+            if ctx.request_body_received > ctx.max_request_body_bytes {
+                ctx.outcome.reason = Some(ReasonCode::RequestBodyTooLarge);
+                ctx.mirror = None;
+                return Err(pingora::Error::create(
+                    pingora::ErrorType::HTTPStatus(413),
+                    pingora::ErrorSource::Downstream,
+                    Some("request body exceeds max_request_body_bytes".into()),
+                    None,
+                ));
+            }
+        }
+        if let (Some(pending), Some(chunk)) = (ctx.mirror.as_mut(), body.as_ref()) {
+            self.mirror.push_body(pending, chunk);
+        }
+        if end_of_stream {
+            self.dispatch_mirror(ctx);
+        }
+        let Some(hasher) = ctx.body_hasher.as_mut() else {
+            return Ok(());
+        };
+        if let Some(chunk) = body {
+            hasher.update(&chunk[..]);
+            ctx.outcome.request_body_bytes += chunk.len() as u64;
+        }
+        if end_of_stream {
+            finish_body_hash(ctx);
+        }
+        Ok(())
+    }
+
+    // 【响应头过滤】
+    // 在响应头发给客户端之前，先检查上游声明的 Content-Length 是否超过路由允许的大小。
+    // 此时客户端还什么都没收到，返回 Upstream 方向的错误，Pingora 会回 502 并丢弃这条上游连接。
+    async fn response_filter_phase(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut RequestCtx,
+    ) -> pingora::Result<()> {
+        // 状态码命中路由的 retry_on 且还能重试：丢弃这个响应，换一个节点再试 (见 retry.rs)
+        let status = upstream_response.status.as_u16();
+        let retryable = ctx
+            .matched
+            .as_ref()
+            .and_then(|m| m.config.routes[m.index].retry.as_ref())
+            .is_some_and(|r| r.retries_status(status));
+        if retryable {
+            let reason =
+                (status >= 500 && self.health.counts_5xx()).then_some(ReasonCode::Upstream5xx);
+            if self.take_retry(session, ctx, reason) {
+                let mut e = pingora::Error::create(
+                    retry::RETRYABLE_STATUS,
+                    pingora::ErrorSource::Upstream,
+                    Some(format!("upstream status {}", status).into()),
+                    None,
+                );
+                e.set_retry(true);
+                return Err(e);
+            }
+        }
+        // 响应头改写：在缓存、幂等记录之前，存下来的回放响应和这次的响应一致
+        if let Some(transform) = ctx
+            .matched
+            .as_ref()
+            .and_then(|m| m.config.routes[m.index].response_headers.as_ref())
+        {
+            transform.apply(upstream_response)?;
+        }
+        // 缓存未命中：根据上游的状态码和 Cache-Control / Expires / Vary 决定这次响应要不要存
+        // 资源紧张时不再写入新的缓存条目 (已有条目照常命中)
+        if let Some(policy) = ctx
+            .cache_policy
+            .take()
+            .filter(|_| self.watchdog.allow_optional())
+        {
+            ctx.cache_pending = self.cache.begin(
+                &policy,
+                std::mem::take(&mut ctx.cache_key),
+                std::mem::take(&mut ctx.cache_request_headers),
+                upstream_response.status.as_u16(),
+                &upstream_response.headers,
+            );
+            if ctx.cache_pending.is_none() {
+                ctx.outcome.cache = Some("bypass");
+            }
+        }
+        if let Some(started) = ctx.upstream_started.take() {
+            let elapsed = started.elapsed();
+            metrics::observe_upstream_latency(&ctx.outcome, elapsed);
+            // 同一份耗时喂给 PeakEwma 负载均衡
+            if let Some(in_flight) = &ctx.in_flight {
+                in_flight.observe(elapsed);
+            }
+        }
+        ctx.upstream_status = Some(upstream_response.status.as_u16());
+        if let Some(reservation) = &mut ctx.idempotency {
+            reservation.capture_head(upstream_response.status.as_u16(), &upstream_response.headers);
+        }
+        if ctx.max_response_bytes == 0 {
+            return Ok(());
+        }
+        ctx.truncate_oversize = ctx.matched.as_ref().is_some_and(|m| {
+            let types = &m.config.routes[m.index].route.truncate_content_types;
+            upstream::content_type_listed(&upstream_response.headers, types)
+        });
+        let declared = upstream_response
+            .headers
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok());
+        if let Some(len) = declared {
+            if len > ctx.max_response_bytes && ctx.truncate_oversize {
+                // 截断模式：声明的长度已经不成立，去掉 Content-Length 改为流式转发，到上限时截断
+                upstream_response.remove_header(&http::header::CONTENT_LENGTH);
+            } else if len > ctx.max_response_bytes {
+                ctx.outcome.oversize = Some("rejected");
+                metrics::record_oversize(&ctx.outcome, "rejected");
+                return Err(pingora::Error::create(
+                    outlier::RESPONSE_TOO_LARGE,
+                    pingora::ErrorSource::Upstream,
+                    Some(
+                        format!(
+                            "content-length {} exceeds limit {}",
+                            len, ctx.max_response_bytes
+                        )
+                        .into(),
+                    ),
+                    None,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // 【响应体过滤】
+    // chunked 或者谎报 Content-Length 的响应只能边转发边计数，一旦超限立刻中断。
+    // 此时响应头已经发出，无法再改成 502，只能断开连接 (上游连接同样不会被复用)。
+    // 截断模式 (truncate_content_types) 下先把上限以内的部分发给客户端，收到下一块数据时再断开。
+    fn response_body_filter_phase(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut RequestCtx,
+    ) -> pingora::Result<Option<std::time::Duration>> {
+        if ctx.outcome.oversize == Some("truncated") {
+            return Err(pingora::Error::create(
+                outlier::RESPONSE_TRUNCATED,
+                pingora::ErrorSource::Internal,
+                Some(format!("response truncated at {} bytes", ctx.max_response_bytes).into()),
+                None,
+            ));
+        }
+        // 响应头已经发出，超过截止时间时只能断开连接；不完整的响应不缓存、不作为幂等结果
+        if !end_of_stream && ctx.timed_out() {
+            ctx.outcome.reason = Some(ReasonCode::RouteTimeout);
+            ctx.cache_pending = None;
+            ctx.idempotency = None;
+            return Err(pingora::Error::create(
+                pingora::ErrorType::HTTPStatus(504),
+                pingora::ErrorSource::Internal,
+                Some("route deadline exceeded while streaming the response".into()),
+                None,
+            ));
+        }
+        if ctx.max_response_bytes > 0 && ctx.truncate_oversize {
+            if let Some(b) = body.as_mut() {
+                let remaining = ctx.max_response_bytes.saturating_sub(ctx.response_bytes);
+                if b.len() as u64 > remaining {
+                    b.truncate(remaining as usize);
+                    ctx.outcome.oversize = Some("truncated");
+                    ctx.outcome.reason = Some(ReasonCode::UpstreamResponseTruncated);
+                    metrics::record_oversize(&ctx.outcome, "truncated");
+                    // 截断的响应不完整，不能缓存或作为幂等结果回放
+                    ctx.cache_pending = None;
+                    ctx.idempotency = None;
+                }
+            }
+        }
+        if let Some(b) = body {
+            // 边转发边累积待缓存的响应体，超过缓存上限就放弃缓存 (不影响转发)
+            if let Some(pending) = &mut ctx.cache_pending {
+                if !pending.append(b) {
+                    ctx.cache_pending = None;
+                    ctx.outcome.cache = Some("bypass");
+                }
+            }
+            // 幂等键：响应体超过存储上限就放弃存储，之后的重试照常访问上游
+            if let Some(reservation) = &mut ctx.idempotency {
+                if !reservation.append(b) {
+                    ctx.idempotency = None;
+                    ctx.outcome.idempotency = Some("not_stored");
+                }
+            }
+            ctx.response_bytes += b.len() as u64;
+            ctx.outcome.response_bytes = ctx.response_bytes;
+            if ctx.max_response_bytes > 0 && ctx.response_bytes > ctx.max_response_bytes {
+                ctx.outcome.oversize = Some("aborted");
+                metrics::record_oversize(&ctx.outcome, "aborted");
+                return Err(pingora::Error::create(
+                    outlier::RESPONSE_TOO_LARGE,
+                    pingora::ErrorSource::Upstream,
+                    Some(
+                        format!(
+                            "response body exceeds limit {}",
+                            ctx.max_response_bytes
+                        )
+                        .into(),
+                    ),
+                    None,
+                ));
+            }
+        }
+        if end_of_stream {
+            if let Some(pending) = ctx.cache_pending.take() {
+                self.cache.store(pending);
+            }
+            if let Some(reservation) = ctx.idempotency.take() {
+                ctx.outcome.idempotency = Some(if reservation.complete() { "stored" } else { "not_stored" });
+            }
+        }
+        Ok(None)
+    }
+
+    // 【响应 Trailer 过滤】
+    // 流式后端 (gRPC 的 grpc-status、校验和等) 会在响应体之后发送 trailer。
+    // 策略：
+    // 1. 路由配置为 TRAILER_DROP -> 一律丢弃；
+    // 2. 客户端没有声明 "TE: trailers" -> 它不认识 trailer，丢弃；
+    // 3. 其他情况原样透传给客户端。
+    async fn response_trailer_filter_phase(
+        &self,
+        _session: &mut Session,
+        upstream_trailers: &mut http::HeaderMap,
+        ctx: &mut RequestCtx,
+    ) -> pingora::Result<Option<Bytes>> {
+        ctx.outcome.trailers = upstream_trailers.len();
+        // 带 trailer 的响应不缓存：缓存命中时无法还原 trailer
+        if !upstream_trailers.is_empty() && ctx.cache_pending.take().is_some() {
+            ctx.outcome.cache = Some("bypass");
+        }
+        let forward =
+            ctx.accepts_trailers && ctx.trailer_policy == TrailerPolicy::TrailerPropagate;
+        if !forward {
+            upstream_trailers.clear();
+        }
+        ctx.outcome.trailers_forwarded = forward && !upstream_trailers.is_empty();
+        Ok(None)
+    }
+
+
+    // 【阶段 3: 日志 (Logging)】
+    // 请求结束 (无论成功、失败还是被拦截) 后 Pingora 都会调用这里。
+    // 所有的访问日志都只从 ctx.outcome 渲染，不再各自拼字段。
+    async fn logging_phase(
+        &self,
+        session: &mut Session,
+        e: Option<&pingora::Error>,
+        ctx: &mut RequestCtx,
+    ) {
+        // 上游请求已经结束，先释放进行中计数，不等访问日志写完
+        ctx.in_flight = None;
+        // 没有请求体的请求 (如 GET) 可能不会经过 request_body_filter；
+        // 只有请求体确实完整收到时才记录摘要，客户端中途断开的不记录
+        if ctx.body_hasher.is_some() && session.is_body_done() {
+            finish_body_hash(ctx);
+        }
+        // 同理，没有请求体的请求在这里发出镜像；客户端中途断开的不镜像
+        if ctx.mirror.is_some() && session.is_body_done() {
+            self.dispatch_mirror(ctx);
+        }
+        let status = session
+            .response_written()
+            .map(|resp| resp.status.as_u16())
+            .unwrap_or(0);
+        ctx.outcome.finish(status, ctx.start, ctx.received_at);
+        ctx.outcome.retries = ctx.retry_count;
+        ctx.outcome.attributes = ctx.attributes.select(&self.log_attributes);
+        if let Some(e) = e {
+            // 上游协议错误：归类成稳定的原因码，并记到对应节点名下
+            let upstream_reason = outlier::classify_upstream_error(e);
+            if let (Some(reason), Some(endpoint)) = (upstream_reason, &ctx.outcome.endpoint) {
+                self.outliers.record_failure(endpoint, reason);
+                if let Some(cluster) = &ctx.outcome.cluster {
+                    self.health.record_failure(cluster, endpoint, reason);
+                }
+                self.record_breaker(ctx, true);
+            }
+            if ctx.outcome.reason.is_none() {
+                ctx.outcome.reason = Some(upstream_reason.unwrap_or_else(|| error_reason(e)));
+            }
+        } else if let (Some(cluster), Some(endpoint)) = (&ctx.outcome.cluster, &ctx.outcome.endpoint) {
+            // 上游返回 5xx 也算节点失败 (被动健康检查)，响应本身照常转发
+            if ctx.upstream_status.is_some_and(|s| s >= 500) && self.health.counts_5xx() {
+                self.outliers.record_failure(endpoint, ReasonCode::Upstream5xx);
+                self.health.record_failure(cluster, endpoint, ReasonCode::Upstream5xx);
+                self.record_breaker(ctx, true);
+            } else {
+                self.health.record_success(cluster, endpoint);
+                self.record_breaker(ctx, false);
+            }
+        }
+        if ctx.outcome.reason == Some(ReasonCode::RouteTimeout) {
+            metrics::record_route_timeout(&ctx.outcome);
+            eprintln!(
+                "Route {} timed out after {:?} (deadline {:?})",
+                ctx.outcome.route.as_deref().unwrap_or("-"),
+                ctx.start.elapsed(),
+                ctx.deadline.map(|d| d - ctx.start).unwrap_or_default(),
+            );
+        }
+        if ctx.outcome.reason == Some(ReasonCode::RouteDisabled) {
+            metrics::record_route_disabled(&ctx.outcome);
+        }
+        metrics::record_request(&ctx.outcome);
+        self.access_log.log(&ctx.outcome).await;
+        if self.watchdog.allow_optional() {
+            self.recent.record(&ctx.outcome);
+        }
+    }
 }
 
 fn main() {
     // 初始化日志系统 (env_logger)，允许通过 RUST_LOG 环境变量控制日志级别
     env_logger::init();
+    panics::install_hook();
     
+    // 线程布局：worker 线程数、后台线程数、CPU 绑核
+    let layout = RuntimeLayout::from_env();
+
+    // 命令行参数：
+    // --self-test:     只运行内置 Wasm 自检后退出 (0 = 通过, 1 = 失败)
+    // --validate-only: 校验运行环境 (目前即 Wasm 自检)，不连接 Control Plane、不监听端口
+    // replay --snapshot dump.pb --request req.json: 用 /config_dump 导出的快照离线复现路由结论 (见 replay.rs)
+    // plugin inspect plugin.wasm: 离线检查插件的导入 / 导出，给出和加载时相同的结论 (见 wasm.rs)
+    // --safe-mode:     以安全模式启动，不执行任何插件 (见 safemode.rs)
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|a| a == "replay") {
+        std::process::exit(replay::run(&args[2..]));
+    }
+    if args.get(1).is_some_and(|a| a == "plugin") && args.get(2).is_some_and(|a| a == "inspect") {
+        std::process::exit(wasm::inspect_cli(&args[3..]));
+    }
+    if args.iter().any(|a| a == "--self-test" || a == "--validate-only") {
+        let rt = layout.build_background_runtime().unwrap();
+        let report = rt.block_on(WasmRuntime::new(ExternalResources::default()).self_test());
+        print_self_test(&report);
+        std::process::exit(if report.passed { 0 } else { 1 });
+    }
+
+    // 启动时打印一行结构化的构建信息，方便确认每个环境跑的是哪个构建
+    println!(
+        "build_info {}",
+        serde_json::to_string(&BuildInfo::current()).unwrap_or_default()
+    );
+
     // 初始化 Pingora Server 实例
     // Server 是 Pingora 的核心，负责管理工作线程、信号处理和平滑重启
     let mut server = Server::new(Some(Opt::default())).unwrap();
+    if let Some(threads) = layout.worker_threads {
+        // 此时 configuration 还没有被任何 Service 引用，可以直接修改
+        if let Some(conf) = Arc::get_mut(&mut server.configuration) {
+            conf.threads = threads;
+        }
+    }
     server.bootstrap();
+    layout.log(server.configuration.threads);
 
-    // 创建一个独立的 Tokio Runtime
-    // Pingora 内部有自己的 Runtime，但在启动 Pingora 之前，我们需要先用一个 Runtime 
+    // 创建唯一的后台 Tokio Runtime
+    // Pingora 内部有自己的 Runtime，但在启动 Pingora 之前，我们需要先用一个 Runtime
     // 去连 Control Plane 拿配置。这也是 Data Plane 的 "Bootstrap" 过程。
-    let rt = tokio::runtime::Runtime::new().unwrap();
+    // 启动之后，配置同步等所有后台任务也都跑在这个 Runtime 上。
+    let rt = layout.build_background_runtime().unwrap();
+    // 后台 Runtime 的线程已经创建完毕，现在把主线程钉到 worker CPU 上，
+    // 之后由 Pingora 创建的 worker 线程会继承这个亲和性。
+    layout.pin_workers();
+
+    // 节点身份 (AGW_NODE_ID / AGW_NODE_REGION / AGW_NODE_ZONE / AGW_NODE_LABELS)
+    let node = NodeIdentity::from_env();
 
     // 1. 获取 Control Plane 地址 (环境变量优先，默认本地)
     let cp_url = std::env::var("AGW_CONTROL_PLANE_URL")
         .unwrap_or_else(|_| "http://localhost:18000".to_string());
-    println!(
-        "Connecting to Control Plane at {} to fetch initial config...",
-        cp_url
-    );
 
-    // 2.【同步阻塞】获取初始配置 (Initial Config Fetch)
-    // 我们的策略是：必须拿到第一份有效配置，才能启动网关服务。
-    // 如果连不上 Control Plane，或者拿到的是空配置，就死循环重试。
-    let initial_config = rt.block_on(async {
-        loop {
-            // 尝试建立 gRPC 连接
-            match AgwClient::connect(cp_url.clone(), "node-1".to_string()).await {
-                Ok(mut client) => {
-                    // 构造握手请求 (Node Identity)
-                    let request = tonic::Request::new(client::Node {
-                        id: "node-1".to_string(), // TODO: 应该动态生成或从配置读取
-                        region: "us-east-1".to_string(),
-                        version: "0.1.0".to_string(),
-                    });
-                    
-                    // 发起 StreamConfig 请求
-                    match client.client.stream_config(request).await {
-                        Ok(resp) => {
-                            // 获取从 Server 返回的流 (Stream)
-                            let mut stream = resp.into_inner();
-                            // 等待流里的第一条消息 (First Snapshot)
-                            if let Ok(Some(snapshot)) = stream.message().await {
-                                // 校验配置有效性：如果 Listener 为空，说明 Control Plane 可能还没准备好
-                                if snapshot.listeners.is_empty() {
-                                    eprintln!("Received config, but it has NO listeners (likely Control Plane is not ready). Retrying...");
-                                } else {
-                                    // 成功拿到有效配置！跳出循环，进入下一步
-                                    return snapshot;
-                                }
-                            }
-                        }
-                        Err(e) => eprintln!("Stream handshake failed: {}", e),
-                    }
-                }
-                Err(e) => eprintln!("Connection failed: {}", e),
-            }
-            // 失败重试，防止把 CPU 跑满
-            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-        }
-    });
+    // 【启动顺序】AGW_BIND_BEFORE_CONFIG=true 时，先绑定端口再等配置。
+    // 默认模式下必须先连上 Control Plane 才会监听端口，这会导致 CP 宕机期间
+    // K8s 的 liveness 探针失败、Pod 被反复重启。开启此模式后：
+    // - 立即按 AGW_BOOTSTRAP_LISTENERS (逗号分隔的 "ip:port" 列表) 绑定端口；
+    // - 在第一份有效配置到达之前，所有请求返回 503 (WARMING_UP)；
+    // - 管理端口上的 /healthz 始终返回 200，/readyz 在配置应用后才返回 200。
+    let bind_before_config = std::env::var("AGW_BIND_BEFORE_CONFIG")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    // 2. 获取初始配置 (Initial Config Fetch)
+    let initial_config = if bind_before_config {
+        println!("AGW_BIND_BEFORE_CONFIG enabled, binding bootstrap listeners before first config");
+        client::agw::v1::ConfigSnapshot::default()
+    } else {
+        println!(
+            "Connecting to Control Plane at {} to fetch initial config...",
+            cp_url
+        );
+        rt.block_on(fetch_initial_config(&cp_url, &node))
+    };
 
     println!(
         "Received initial config version: {}",
@@ -242,20 +1534,136 @@ fn main() {
 
     // 【Why Clone?】
     // 这里我们使用了 `initial_config.clone()`，因为我们实际上需要把这份配置用两次：
-    // 1. 第一次：放入 `config_store` (ArcSwap) 里，作为全局配置供 Proxy 处理请求使用。这一步会消耗掉数据的所有权。
-    // 2. 第二次：在下面的 for 循环中，再次遍历 `initial_config.listeners`，把证书写到磁盘上。
+    // 1. 第一次：编译后放入 `config_store` (ArcSwap) 里，作为全局配置供 Proxy 处理请求使用。这一步会消耗掉数据的所有权。
+    // 2. 第二次：在下面的 for 循环中，再次遍历 `initial_config.listeners`，注册监听器并从内存里的 PEM 加载证书。
     // 因此，我们需要克隆一份给 config_store。
-    let config_store = Arc::new(ArcSwap::from_pointee(initial_config.clone()));
+    // 初始配置如果编译失败 (如非法正则)，没有旧配置可以回退，只能以空路由表启动并等待下一份配置。
+    let active = ActiveConfig::compile(initial_config.clone()).unwrap_or_else(|errors| {
+        eprintln!(
+            "Initial config failed validation: {}. Starting with empty route table",
+            validate::format_errors(&errors)
+        );
+        ActiveConfig::empty()
+    });
+    let config_store = Arc::new(ArcSwap::from_pointee(active));
 
     let resources = {
         let _guard = rt.enter();
         init_resources(&initial_config)
     };
     let wasm_runtime = WasmRuntime::new(resources);
+    wasm_runtime.set_runtime_info(&RuntimeInfo {
+        config_version: initial_config.version_id.clone(),
+        environment: initial_config.environment.clone(),
+        ..RuntimeInfo::new(node.clone())
+    });
+
+    // 启动自检：wasmtime 升级或 Host ABI 改动导致的问题在这里就能暴露出来
+    let report = rt.block_on(wasm_runtime.self_test());
+    print_self_test(&report);
+    if !report.passed {
+        eprintln!("WARNING: Wasm self-test failed, plugins may not work correctly");
+    }
+    // 初始配置里写了 version 的插件先加载一次，记下已加载的版本，之后的快照按它判断是否需要热更新
+    for (path, version, result) in rt.block_on(wasm_runtime.reload_changed(&initial_config)) {
+        if let Err(e) = result {
+            eprintln!(
+                "WARNING: failed to load plugin {} (version {:?}): {}",
+                path, version, e
+            );
+        }
+    }
+    let ready = Arc::new(Readiness::new(bind_before_config));
+    let outliers = Arc::new(OutlierTracker::default());
+    let health = Arc::new(EndpointRegistry::from_env());
+    let watchdog = Arc::new(Watchdog::new(Budgets::from_env()));
+    let panic_tracker = Arc::new(PanicTracker::from_env());
+    let access_log = Arc::new(AccessLog::from_env(&rt, &node.id));
+    let recent = Arc::new(RecentRequests::from_env(server.configuration.threads));
+    let lb = Arc::new(LoadBalancer::default());
+    let breakers = Arc::new(CircuitBreakers::default());
+    let safe_mode = Arc::new(SafeMode::from_startup(&args));
+    let client_addrs = Arc::new(proxy_protocol::ClientAddrs::default());
     // 这个AgwProxy实现了一个trait ProxyHttp，Pingora会调用这个trait的
     let proxy_service = AgwProxy {
         config: config_store.clone(),
-        wasm: wasm_runtime,
+        wasm: wasm_runtime.clone(),
+        ready: ready.clone(),
+        outliers: outliers.clone(),
+        health: health.clone(),
+        upstream_read_timeout: std::time::Duration::from_secs(
+            std::env::var("AGW_UPSTREAM_READ_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+        ),
+        websocket_idle_timeout: match std::env::var("AGW_WEBSOCKET_IDLE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600)
+        {
+            0 => None,
+            secs => Some(std::time::Duration::from_secs(secs)),
+        },
+        default_timeout: std::env::var("AGW_DEFAULT_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&ms| ms > 0)
+            .map(std::time::Duration::from_millis),
+        cache: Arc::new(ResponseCache::new(
+            std::env::var("AGW_CACHE_MAX_ENTRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10_000),
+        )),
+        idempotency: Arc::new(IdempotencyStore::new(
+            std::env::var("AGW_IDEMPOTENCY_MAX_ENTRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10_000),
+        )),
+        recent: recent.clone(),
+        log_attributes: attributes::log_keys_from_env(),
+        watchdog: watchdog.clone(),
+        access_log: access_log.clone(),
+        lb: lb.clone(),
+        breakers: breakers.clone(),
+        panics: panic_tracker.clone(),
+        mirror: Arc::new(Mirror::new(
+            std::time::Duration::from_millis(
+                std::env::var("AGW_MIRROR_TIMEOUT_MS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(5000),
+            ),
+            std::env::var("AGW_MIRROR_MAX_BODY_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(64 * 1024),
+            std::env::var("AGW_MIRROR_MAX_IN_FLIGHT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(256),
+        )),
+        safe_mode: safe_mode.clone(),
+        client_addrs: client_addrs.clone(),
+        plugin_max_body: std::env::var("AGW_PLUGIN_MAX_BODY_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(PLUGIN_BODY_LIMIT)
+            .min(PLUGIN_BODY_LIMIT),
+        plugin_timeout: std::time::Duration::from_millis(
+            std::env::var("AGW_PLUGIN_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&ms| ms > 0)
+                .unwrap_or(1000),
+        ),
+        plugin_max_fuel: std::env::var("AGW_PLUGIN_MAX_FUEL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&fuel| fuel > 0)
+            .unwrap_or(1_000_000_000),
     };
 
     // 初始化 HTTP 代理服务
@@ -264,39 +1672,88 @@ fn main() {
     // 1. &server.configuration: 传入全局 server 配置（如线程数、PID 文件位置等）。
     // 2. proxy_service: 传入实现了 ProxyHttp Trait 的业务逻辑对象。
     let mut my_proxy = http_proxy_service(&server.configuration, proxy_service);
+    // 明文端口也接受 h2c (gRPC 客户端直接发 HTTP/2 前导)，没有前导的连接仍按 HTTP/1.1 处理
+    if let Some(app) = my_proxy.app_logic_mut() {
+        // HttpServerOptions 是 #[non_exhaustive]，只能先取默认值再改
+        let mut options = HttpServerOptions::default();
+        options.h2c = true;
+        app.server_options = Some(options);
+    }
 
     // 2. Setup Listeners (根据初始配置启动端口监听)
-    if initial_config.listeners.is_empty() {
+    if bind_before_config {
+        // 预热模式下还没有 Listener 配置，只能使用静态的 bootstrap 列表 (仅支持明文 HTTP)
+        let bootstrap = std::env::var("AGW_BOOTSTRAP_LISTENERS").ok();
+        for addr in warmup::bootstrap_listeners(bootstrap.as_deref()) {
+            println!("Adding bootstrap TCP Listener at {}", addr);
+            my_proxy.add_tcp(&addr);
+        }
+    } else if initial_config.listeners.is_empty() {
         // Fallback: 如果万一没有 Listener，至少开个 HTTP 端口防止服务起不来
         my_proxy.add_tcp("0.0.0.0:6188");
     }
 
 
+    // 每个 TLS Listener 当前的证书，配置更新时由后台任务替换
+    let mut cert_stores = std::collections::HashMap::new();
+    // PROXY protocol 中继的 Runtime 和占住内部端口的 socket，都要存活到进程退出
+    let relay_rt = if initial_config.listeners.iter().any(|l| l.proxy_protocol) {
+        Some(runtime::build_relay_runtime(server.configuration.threads).unwrap())
+    } else {
+        None
+    };
+    let mut reserved_ports = Vec::new();
     // 遍历初始配置里的监听器 definition
     for listener in &initial_config.listeners {
         // 构造监听地址字符串，例如 "0.0.0.0:6188"
         let addr = format!("{}:{}", listener.address, listener.port);
         
+        // 【PROXY protocol】公开端口交给中继，Pingora 改为监听中继转发的本机端口
+        let (addr, sock_opt) = match (&relay_rt, listener.proxy_protocol) {
+            (Some(relay_rt), true) => {
+                let reserved = match proxy_protocol::reserve_loopback_port() {
+                    Ok(reserved) => reserved,
+                    Err(e) => {
+                        eprintln!("Failed to reserve a port for {}: {}", listener.name, e);
+                        continue;
+                    }
+                };
+                let internal = match reserved.local_addr() {
+                    Ok(internal) => internal,
+                    Err(e) => {
+                        eprintln!("Failed to reserve a port for {}: {}", listener.name, e);
+                        continue;
+                    }
+                };
+                reserved_ports.push(reserved);
+                println!(
+                    "Adding PROXY protocol relay: {} at {} -> {}",
+                    listener.name, addr, internal
+                );
+                relay_rt.spawn(proxy_protocol::relay(addr, internal, client_addrs.clone()));
+                // 和占位的 socket 一样开 SO_REUSEPORT，才能绑定到同一个端口
+                let mut sock_opt = TcpSocketOptions::default();
+                sock_opt.so_reuseport = Some(true);
+                (internal.to_string(), Some(sock_opt))
+            }
+            _ => (addr, None),
+        };
+
         // 判断是否为 HTTPS/TLS 监听器
         if let Some(tls) = &listener.tls {
-            // 【TLS 证书处理：写文件策略】
-            // Pingora 的 `add_tls` 方法目前只支持传入证书文件的路径 (str)，
-            // 不支持直接传入内存中的证书内容 (bytes)。
-            // 而我们的证书是从 Control Plane 通过网络传过来的内存数据。
-            // 解决方案：先把证书内容写到本地临时目录 (/tmp/) 下，再把文件路径传给 Pingora。
-            let cert_path = format!("/tmp/{}_cert.pem", listener.name);
-            let key_path = format!("/tmp/{}_key.pem", listener.name);
-
-            // 1. 写证书文件 (public cert)
-            if let Err(e) = std::fs::write(&cert_path, &tls.cert_pem) {
-                eprintln!("Failed to write cert for {}: {}", listener.name, e);
-                continue; // 写失败则跳过该端口监听，不影响其他端口
-            }
-            // 2. 写私钥文件 (private key)
-            if let Err(e) = std::fs::write(&key_path, &tls.key_pem) {
-                eprintln!("Failed to write key for {}: {}", listener.name, e);
-                continue;
-            }
+            // 【TLS 证书处理：内存加载 + 热更新】
+            // 证书是从 Control Plane 通过网络传过来的内存数据，直接从 PEM 构造 TlsSettings，不落盘。
+            // 解析后的证书放在这个 Listener 的 CertStore 里，每次握手都从 CertStore 读取，
+            // 证书轮换时后台任务替换它即可，不需要重启 (见 tls.rs)。
+            let (settings, store) =
+                match tls::rotatable_tls_settings_from_pem(&tls.cert_pem, &tls.key_pem) {
+                    Ok(loaded) => loaded,
+                    Err(e) => {
+                        eprintln!("Failed to load TLS material for {}: {}", listener.name, e);
+                        continue; // 证书有问题则跳过该端口监听，不影响其他端口
+                    }
+                };
+            cert_stores.insert(listener.name.clone(), store);
 
             println!(
                 "Adding TLS Listener: {} at {}. Cert: {} bytes, Key: {} bytes",
@@ -306,72 +1763,683 @@ fn main() {
                 tls.key_pem.len()
             );
 
-            // 3. 注册 HTTPS 监听器
+            // 注册 HTTPS 监听器
             // 这一步告诉 Pingora: "在 addr 这个端口上监听 HTTPS 流量，用这组证书解密"。
-            if let Err(e) = my_proxy.add_tls(&addr, &cert_path, &key_path) {
-                eprintln!("Failed to add TLS listener {}: {}", listener.name, e);
-            }
+            my_proxy.add_tls_with_settings(&addr, sock_opt, settings);
         } else {
             // 【普通 TCP/HTTP 处理】
             println!("Adding TCP Listener: {} at {}", listener.name, addr);
             // 注册普通 TCP 监听器 (HTTP)
-            my_proxy.add_tcp(&addr);
+            match sock_opt {
+                Some(sock_opt) => my_proxy.add_tcp_with_settings(&addr, sock_opt),
+                None => my_proxy.add_tcp(&addr),
+            }
         }
     }
 
     // 3. 启动后台配置更新任务 (Spawn Background Update Task)
     // 我们的主线程 (main thread) 即将阻塞在 server.run_forever() 上，去处理 Pingora 的网络流量。
-    // 所以我们需要起一个独立的线程 (std::thread::spawn)，专门负责“监听配置变更”。
-    //
-    // 注意：这里为什么不复用原本的 rt (Tokio Runtime)？
-    // 因为 Pingora 启动后会接管所有的 Worker 线程，我们在外面起的线程需要自给自足，
-    // 所以我们在后台线程里“新开”了一个 Tokio Runtime。
-    let cp_url_bg = cp_url.clone();
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
-            loop {
-                // 长连接重连逻辑
-                match AgwClient::connect(cp_url_bg.clone(), "node-1".to_string()).await {
-                    Ok(mut client) => {
-                        let request = tonic::Request::new(client::Node {
-                            id: "node-1".to_string(),
-                            region: "us-east-1".to_string(),
-                            version: "0.1.0".to_string(),
-                        });
-                        
-                        // 建立 gRPC Stream
-                        match client.client.stream_config(request).await {
-                            Ok(resp) => {
-                                let mut stream = resp.into_inner();
-                                println!("Connected to CP stream (Background)...");
-                                
-                                // 【核心循环】：不断等待 Stream 里的新消息
-                                while let Ok(Some(snapshot)) = stream.message().await {
-                                    println!("Received Dynamic Config Update: Version {}", snapshot.version_id);
-                                    
-                                    // 【ArcSwap 写操作】
-                                    // 这一步是最关键的：我们收到了 Control Plane 推过来的新配置。
-                                    // 调用 store() 方法，"原子地" (Atomic) 替换掉全局指针。
-                                    // 这一瞬间，所有新进来的 HTTP 请求就会立刻读到这份新配置。
-                                    config_store.store(Arc::new(snapshot));
-                                    
-                                    // Note: Listeners update required restart in this MVP
-                                }
+    // 所以配置监听任务被 spawn 到后台 Runtime 上运行 (rt 会一直存活，直到进程退出)。
+    let config_status = Arc::new(ConfigStatus::default());
+    let admin_config = config_store.clone();
+    if !bind_before_config {
+        config_status.record_applied(&initial_config.version_id);
+    }
+    let updater = ConfigUpdater {
+        cp_url: cp_url.clone(),
+        node,
+        config_store,
+        ready: ready.clone(),
+        wasm: wasm_runtime.clone(),
+        status: config_status.clone(),
+        sanity_guard: SanityGuard::from_env(),
+        apply_timeout: std::time::Duration::from_secs(
+            std::env::var("AGW_CONFIG_APPLY_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10),
+        ),
+        lb,
+        breakers,
+        health: health.clone(),
+        outliers: outliers.clone(),
+        cert_stores,
+        panics: panic_tracker.clone(),
+    };
+    // 后台任务 panic 后按退避重启 (见 panics.rs)
+    let updater = Arc::new(updater);
+    rt.spawn(panics::supervise(
+        "config_stream",
+        panic_tracker.clone(),
+        move || updater.clone().run(),
+    ));
+    let sampled = watchdog.clone();
+    rt.spawn(panics::supervise(
+        "watchdog",
+        panic_tracker.clone(),
+        move || sampled.clone().run(Box::new(ProcSampler)),
+    ));
+    let checker = Arc::new(HealthChecker::new(admin_config.clone(), health.clone()));
+    rt.spawn(panics::supervise(
+        "health_check",
+        panic_tracker.clone(),
+        move || checker.clone().run(),
+    ));
+
+    // 4. 管理端口 (Admin API): /healthz, /readyz
+    let admin_addr =
+        std::env::var("AGW_ADMIN_ADDR").unwrap_or_else(|_| "0.0.0.0:9901".to_string());
+    let mut admin_service = Service::new(
+        "AGW Admin API".to_string(),
+        HttpServer::new_app(AdminApp {
+            ready: ready.clone(),
+            config_status: config_status.clone(),
+            wasm: wasm_runtime,
+            outliers,
+            recent,
+            config: admin_config,
+            health,
+            watchdog,
+            access_log,
+            panics: panic_tracker,
+            safe_mode,
+        }),
+    );
+    admin_service.add_tcp(&admin_addr);
+    println!("Admin API listening at {}", admin_addr);
+
+    // 5. Prometheus 指标: GET /metrics (见 metrics.rs)
+    let metrics_addr =
+        std::env::var("AGW_METRICS_ADDR").unwrap_or_else(|_| "0.0.0.0:9090".to_string());
+    let mut metrics_service = Service::prometheus_http_service();
+    metrics_service.add_tcp(&metrics_addr);
+    println!("Metrics listening at {}", metrics_addr);
+
+    server.add_service(my_proxy);
+    server.add_service(admin_service);
+    server.add_service(metrics_service);
+    server.run_forever();
+}
+
+// 【后台配置更新任务】
+// 与 Control Plane 保持长连接，不断接收新的配置快照并原子替换。
+//
+// 应用过程拆成一条流水线，保证读 gRPC 流的循环永远不会被重活卡住：
+// 1. 接收 (run): 只负责读流，把快照放进 watch 通道就立刻回去读下一条，
+//    不会因为应用慢而造成 gRPC 背压或错过 keepalive。
+// 2. 派生 (apply_loop/apply): 校验 -> 编译 (路由匹配器、正则等，放在 blocking 线程上) -> 原子替换。
+//    只有派生全部成功才会 store 到 ArcSwap，中途失败不影响正在服务的配置。
+// 3. 取消：派生过程中如果又来了更新的快照，当前这份直接作废，改为应用最新的 (watch 只保留最新值)。
+//    只有替换之前的派生可以取消 (见 derive.rs)；替换之后的收尾 (状态延续、证书、插件、ready) 总是做完。
+// 4. 超时：派生超过 AGW_CONFIG_APPLY_TIMEOUT_SECS (默认 10 秒) 视为拒绝，保留旧配置。
+struct ConfigUpdater {
+    cp_url: String,
+    node: NodeIdentity,
+    config_store: Arc<ArcSwap<ActiveConfig>>,
+    ready: Arc<Readiness>,
+    wasm: WasmRuntime,
+    status: Arc<ConfigStatus>,
+    sanity_guard: SanityGuard,
+    apply_timeout: std::time::Duration,
+    // 跨快照保留的运行时状态，应用新配置后只清理被删除的集群 / 节点
+    lb: Arc<LoadBalancer>,
+    breakers: Arc<CircuitBreakers>,
+    health: Arc<EndpointRegistry>,
+    outliers: Arc<OutlierTracker>,
+    // 启动时注册的 TLS Listener 的证书 (按 Listener 名)，新快照里证书变化时热替换
+    cert_stores: std::collections::HashMap<String, tls::CertStore>,
+    panics: Arc<PanicTracker>,
+}
+
+impl ConfigUpdater {
+    async fn run(self: Arc<Self>) {
+        let (tx, rx) = tokio::sync::watch::channel(None);
+        // 流重连后 (run 被 supervise 重启) 旧的派生任务随 tx 释放而退出，这里总是启动新的
+        let updater = self.clone();
+        tokio::spawn(panics::supervise(
+            "config_apply",
+            self.panics.clone(),
+            move || updater.clone().apply_loop(rx.clone()),
+        ));
+        loop {
+            // 长连接重连逻辑
+            // 错误先转成字符串：Box<dyn Error> 不是 Send，不能跨 await 留在 spawn 的 future 里
+            let connected = AgwClient::connect(self.cp_url.clone(), self.node.id.clone())
+                .await
+                .map_err(|e| e.to_string());
+            match connected {
+                Ok(mut client) => {
+                    let request = tonic::Request::new(self.node.to_proto());
+
+                    // 建立 gRPC Stream
+                    match client.client.stream_config(request).await {
+                        Ok(resp) => {
+                            let mut stream = resp.into_inner();
+                            println!("Connected to CP stream (Background)...");
+
+                            // 【核心循环】：不断等待 Stream 里的新消息，收到后只交给派生任务，不在这里做任何重活
+                            while let Ok(Some(snapshot)) = stream.message().await {
+                                println!(
+                                    "Received Dynamic Config Update: Version {}",
+                                    snapshot.version_id
+                                );
+                                tx.send_replace(Some(snapshot));
                             }
-                            Err(e) => eprintln!("Stream disconnected: {}", e),
                         }
+                        Err(e) => eprintln!("Stream disconnected: {}", e),
                     }
-                    Err(e) => eprintln!("Reconnect failed in background: {}", e),
                 }
-                // 断线重连等待 5 秒
-                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                Err(e) => eprintln!("Reconnect failed in background: {}", e),
             }
+            // 断线重连等待 5 秒
+            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+        }
+    }
+
+    // 派生任务：总是应用 watch 通道里最新的快照
+    async fn apply_loop(
+        self: Arc<Self>,
+        mut rx: tokio::sync::watch::Receiver<Option<client::agw::v1::ConfigSnapshot>>,
+    ) {
+        // 上一份派生被新快照打断时，不需要再等待通知，直接处理最新值
+        let mut superseded = false;
+        loop {
+            if !superseded && rx.changed().await.is_err() {
+                return;
+            }
+            superseded = false;
+            let Some(snapshot) = rx.borrow_and_update().clone() else {
+                continue;
+            };
+            superseded = self.apply(snapshot, &mut rx).await;
+        }
+    }
+
+    // 应用一份快照；派生期间被更新的快照取代时返回 true (见 derive.rs)，原子替换之后的步骤不会被打断
+    async fn apply(
+        &self,
+        snapshot: client::agw::v1::ConfigSnapshot,
+        newer: &mut tokio::sync::watch::Receiver<Option<client::agw::v1::ConfigSnapshot>>,
+    ) -> bool {
+        let started = Instant::now();
+
+        // 预热模式下的第一份配置：同样要求 Listener 非空才算"有效"
+        if !self.ready.accepts(&snapshot) {
+            eprintln!("Received config, but it has NO listeners (likely Control Plane is not ready). Still warming up...");
+            return false;
+        }
+        if !self.ready.is_ready() {
+            // 外部资源 (Redis/DB) 在启动时无法初始化，这里补上
+            self.wasm.set_resources(init_resources(&snapshot));
+        }
+
+        // 健全性检查：路由/集群数量骤降的快照直接拒绝，保留旧配置。
+        // 首次启动 (还没 ready) 时不做检查。
+        let current = self.config_store.load_full();
+        let current_ref = if self.ready.is_ready() {
+            Some(&current.snapshot)
+        } else {
+            None
+        };
+        if let Err(error) = self.sanity_guard.check(current_ref, &snapshot) {
+            eprintln!(
+                "!!! REJECTED config snapshot {} by sanity guard: {}. Keeping version {} (set allow_major_reduction to override) !!!",
+                snapshot.version_id, error.message, current.snapshot.version_id
+            );
+            self.status.record_rejected(&snapshot.version_id, vec![error]);
+            return false;
+        }
+        let version_id = snapshot.version_id.clone();
+        let environment = snapshot.environment.clone();
+        let validated = Instant::now();
+
+        // 编译 (路径匹配器、正则等)。这是最重的一步，放到 blocking 线程上，不占用后台 Runtime 的 worker。
+        // 编译失败说明快照本身有问题，整份拒绝，保留旧配置。
+        let active = match derive::derive(snapshot, self.apply_timeout, newer).await {
+            Derivation::Ready(active) => active,
+            Derivation::Rejected(errors) => {
+                eprintln!(
+                    "Rejected config snapshot {}: {}. Keeping version {}",
+                    version_id,
+                    validate::format_errors(&errors),
+                    current.snapshot.version_id
+                );
+                self.status.record_rejected(&version_id, errors);
+                return false;
+            }
+            Derivation::Superseded => {
+                println!(
+                    "Config apply of version {} superseded by a newer snapshot",
+                    version_id
+                );
+                self.status.record_superseded();
+                return true;
+            }
+        };
+        let derived = Instant::now();
+
+        // 【ArcSwap 写操作】
+        // 这一步是最关键的：我们收到了 Control Plane 推过来的新配置。
+        // 调用 store() 方法，"原子地" (Atomic) 替换掉全局指针。
+        // 这一瞬间，所有新进来的 HTTP 请求就会立刻读到这份新配置。
+        // 从这里开始到函数结束不再检查新快照：替换之后的收尾必须做完，新快照等这一份应用完再处理。
+        self.wasm.set_runtime_info(&RuntimeInfo {
+            config_version: version_id.clone(),
+            environment,
+            ..RuntimeInfo::new(self.node.clone())
+        });
+        let active = Arc::new(active);
+        self.config_store.store(active.clone());
+        self.status.record_applied(&version_id);
+        self.carry_over_state(&version_id, &current, &active);
+        self.reload_certificates(&current, &active);
+        self.reload_plugins(&version_id, &active).await;
+        self.status.record_apply_timing(ApplyTiming {
+            version: version_id.clone(),
+            validate_ms: (validated - started).as_millis() as u64,
+            derive_ms: (derived - validated).as_millis() as u64,
+            swap_ms: derived.elapsed().as_millis() as u64,
+            total_ms: started.elapsed().as_millis() as u64,
         });
-    });
 
-    server.add_service(my_proxy);
-    server.run_forever();
+        // 配置已就位，原子地打开路由开关 (readiness 同时变为 true)
+        if self.ready.mark_ready() {
+            println!("First config applied, gateway is ready");
+        }
+
+        // Note: Listeners update required restart in this MVP
+        false
+    }
+
+    // 证书轮换：证书或私钥有变化的 TLS Listener 替换 CertStore，之后的新握手使用新证书。
+    // 新证书无法解析时保留旧证书 (校验阶段已经拒绝了明显不是 PEM 的快照)。
+    // 启动之后才新增的 Listener 仍然需要重启才会监听。
+    fn reload_certificates(&self, previous: &ActiveConfig, active: &ActiveConfig) {
+        for listener in &active.snapshot.listeners {
+            let (Some(tls), Some(store)) = (&listener.tls, self.cert_stores.get(&listener.name)) else {
+                continue;
+            };
+            let unchanged = previous
+                .snapshot
+                .listeners
+                .iter()
+                .find(|l| l.name == listener.name)
+                .and_then(|l| l.tls.as_ref())
+                .is_some_and(|old| old.cert_pem == tls.cert_pem && old.key_pem == tls.key_pem);
+            if unchanged {
+                continue;
+            }
+            match tls::TlsCerts::from_pem(&tls.cert_pem, &tls.key_pem) {
+                Ok(certs) => {
+                    store.store(Arc::new(certs));
+                    println!("Reloaded TLS certificate for listener {}", listener.name);
+                }
+                Err(e) => eprintln!(
+                    "Failed to reload TLS certificate for listener {}: {}. Keeping the previous certificate",
+                    listener.name, e
+                ),
+            }
+        }
+    }
+
+    // 插件的 version 和已加载的版本不同时重新编译插件文件，替换缓存的模块 (见 WasmRuntime::reload_changed)。
+    // 没有写 version 的插件不会重新加载，更新文件后仍需重启。新文件编译失败时继续用旧模块，失败记到 /status。
+    async fn reload_plugins(&self, version_id: &str, active: &ActiveConfig) {
+        let reloads = self.wasm.reload_changed(&active.snapshot).await;
+        for (path, version, result) in &reloads {
+            match result {
+                Ok(()) => println!("Reloaded plugin {} (version {:?})", path, version),
+                Err(e) => eprintln!(
+                    "Failed to reload plugin {} (version {:?}): {}. Keeping the previously loaded module",
+                    path, version, e
+                ),
+            }
+        }
+        let live = wasm::plugin_versions(&active.snapshot);
+        self.status
+            .record_plugin_reloads(version_id, &reloads, |path| live.contains_key(path));
+    }
+
+    // 新配置生效后，按 (集群名, 节点地址) 延续运行时状态，只清理被删除的集群 / 节点
+    fn carry_over_state(&self, version_id: &str, previous: &ActiveConfig, active: &ActiveConfig) {
+        let before = upstream::endpoint_identities(&previous.snapshot);
+        let live = upstream::endpoint_identities(&active.snapshot);
+        let mut carryover = StateCarryover::diff(version_id, &before, &live);
+        carryover.state_resets = self.health.retain(&live)
+            + self.lb.retain(&live)
+            + self.breakers.retain(&live)
+            + self.outliers.retain(&live);
+        if carryover.clusters_removed + carryover.endpoints_removed + carryover.state_resets > 0 {
+            println!(
+                "Config {}: clusters +{}/-{}, endpoints +{}/-{}, runtime state reset for {} entries",
+                version_id,
+                carryover.clusters_added,
+                carryover.clusters_removed,
+                carryover.endpoints_added,
+                carryover.endpoints_removed,
+                carryover.state_resets
+            );
+        }
+        self.status.record_carryover(carryover);
+    }
+}
+
+// 【同步阻塞】获取初始配置
+// 我们的策略是：必须拿到第一份有效配置，才能启动网关服务。
+// 如果连不上 Control Plane，或者拿到的是空配置，就死循环重试。
+async fn fetch_initial_config(cp_url: &str, node: &NodeIdentity) -> client::agw::v1::ConfigSnapshot {
+    loop {
+        // 尝试建立 gRPC 连接
+        match AgwClient::connect(cp_url.to_string(), node.id.clone()).await {
+            Ok(mut client) => {
+                // 构造握手请求 (Node Identity)
+                let request = tonic::Request::new(node.to_proto());
+
+                // 发起 StreamConfig 请求
+                match client.client.stream_config(request).await {
+                    Ok(resp) => {
+                        // 获取从 Server 返回的流 (Stream)
+                        let mut stream = resp.into_inner();
+                        // 等待流里的第一条消息 (First Snapshot)
+                        if let Ok(Some(snapshot)) = stream.message().await {
+                            // 校验配置有效性：如果 Listener 为空，说明 Control Plane 可能还没准备好
+                            if snapshot.listeners.is_empty() {
+                                eprintln!("Received config, but it has NO listeners (likely Control Plane is not ready). Retrying...");
+                            } else {
+                                // 成功拿到有效配置！跳出循环，进入下一步
+                                return snapshot;
+                            }
+                        }
+                    }
+                    Err(e) => eprintln!("Stream handshake failed: {}", e),
+                }
+            }
+            Err(e) => eprintln!("Connection failed: {}", e),
+        }
+        // 失败重试，防止把 CPU 跑满
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+}
+
+fn print_self_test(report: &wasm::SelfTestReport) {
+    for check in &report.checks {
+        println!(
+            "Self-test [{}] {}: {}",
+            if check.passed { "PASS" } else { "FAIL" },
+            check.interface,
+            check.detail
+        );
+    }
+}
+
+// 直接向客户端返回带原因码的 JSON 错误响应 (不转发给 upstream)，或者快照里配置的错误响应模板
+async fn respond_reason(
+    session: &mut Session,
+    ctx: &RequestCtx,
+    status: u16,
+    reason: ReasonCode,
+) -> pingora::Result<()> {
+    // gRPC 客户端看不懂 JSON 错误体，回 Trailers-Only 响应 (grpc-status 见 grpc.rs)
+    if grpc::is_grpc(&session.req_header().headers) {
+        let header = grpc::trailers_only(status, reason)?;
+        return session.write_response_header(Box::new(header), true).await;
+    }
+    // 快照为这个状态码配置了 error_responses 模板时用模板 (见 direct.rs)
+    let page = ctx
+        .error_pages
+        .as_ref()
+        .and_then(|pages| pages.render(status, reason, ctx.outcome.trace_id.as_deref()));
+    if let Some(page) = page {
+        return respond_direct(session, &page).await;
+    }
+    let body = reason.error_body(status);
+    let mut header = ResponseHeader::build(status, None)?;
+    header.insert_header("Content-Type", "application/json")?;
+    header.insert_header("Content-Length", body.len().to_string())?;
+    session
+        .write_response_header(Box::new(header), false)
+        .await?;
+    session
+        .write_response_body(Some(Bytes::from(body)), true)
+        .await?;
+    Ok(())
+}
+
+// 幂等键：第一次请求占位后继续转发；重试回放已存储的响应，或在第一次请求未完成时等待 / 返回 409。
+// 返回 true 表示已经直接响应客户端。
+async fn idempotency_filter(
+    store: &Arc<IdempotencyStore>,
+    session: &mut Session,
+    ctx: &mut RequestCtx,
+    policy: &CompiledIdempotency,
+    key: String,
+) -> pingora::Result<bool> {
+    let deadline = policy.wait.map(|wait| tokio::time::Instant::now() + wait);
+    loop {
+        match store.claim(key.clone(), policy) {
+            Claim::Owner(reservation) => {
+                ctx.outcome.idempotency = Some("miss");
+                ctx.idempotency = Some(reservation);
+                return Ok(false);
+            }
+            Claim::Replay(stored) => {
+                ctx.outcome.idempotency = Some("replay");
+                respond_replayed(session, &stored).await?;
+                return Ok(true);
+            }
+            Claim::InFlight(mut done) => {
+                // 等第一次请求结束后重新查询：它存储成功则回放，没有存储则由本请求接手占位
+                if let Some(deadline) = deadline {
+                    if tokio::time::timeout_at(deadline, done.changed()).await.is_ok() {
+                        continue;
+                    }
+                }
+                ctx.outcome.idempotency = Some("conflict");
+                ctx.outcome.reason = Some(ReasonCode::IdempotencyConflict);
+                respond_reason(session, ctx, 409, ReasonCode::IdempotencyConflict).await?;
+                return Ok(true);
+            }
+            Claim::Unavailable => {
+                // 存储不可用时降级为透传：宁可让后端处理重复请求，也不能拒绝正常请求
+                eprintln!("WARNING: idempotency store is full (AGW_IDEMPOTENCY_MAX_ENTRIES), passing request through");
+                ctx.outcome.idempotency = Some("unavailable");
+                return Ok(false);
+            }
+        }
+    }
+}
+
+// 回放幂等键对应的响应，附带 Idempotent-Replayed: true
+async fn respond_replayed(
+    session: &mut Session,
+    stored: &idempotency::StoredResponse,
+) -> pingora::Result<()> {
+    let mut resp = ResponseHeader::build(stored.status, Some(stored.headers.len() + 2))?;
+    for (name, value) in &stored.headers {
+        resp.append_header(name.clone(), value.as_slice())?;
+    }
+    resp.insert_header("Content-Length", stored.body.len().to_string())?;
+    resp.insert_header("Idempotent-Replayed", "true")?;
+    session
+        .write_response_header(Box::new(resp), false)
+        .await?;
+    session
+        .write_response_body(Some(stored.body.clone()), true)
+        .await?;
+    Ok(())
+}
+
+// 405 带 Allow 头 (RFC 9110 要求)，响应体与其他网关错误一样是带原因码的 JSON
+async fn respond_method_not_allowed(session: &mut Session, allow: &str) -> pingora::Result<()> {
+    let body = ReasonCode::MethodNotAllowed.error_body(405);
+    let mut header = ResponseHeader::build(405, None)?;
+    header.insert_header("Allow", allow)?;
+    header.insert_header("Content-Type", "application/json")?;
+    header.insert_header("Content-Length", body.len().to_string())?;
+    session
+        .write_response_header(Box::new(header), false)
+        .await?;
+    session
+        .write_response_body(Some(Bytes::from(body)), true)
+        .await?;
+    Ok(())
+}
+
+async fn respond_redirect(
+    session: &mut Session,
+    status: u16,
+    location: &str,
+) -> pingora::Result<()> {
+    let mut header = ResponseHeader::build(status, None)?;
+    header.insert_header("Location", location)?;
+    header.insert_header("Content-Length", "0")?;
+    session
+        .write_response_header(Box::new(header), true)
+        .await?;
+    Ok(())
+}
+
+// WebSocket 握手：Upgrade: websocket 且 Connection 里带 upgrade (HTTP/1.1 的升级机制)
+fn is_websocket_upgrade(req: &RequestHeader) -> bool {
+    let has_token = |name: http::header::HeaderName, token: &str| {
+        req.headers.get_all(name).iter().any(|v| {
+            v.to_str()
+                .is_ok_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+        })
+    };
+    has_token(http::header::UPGRADE, "websocket") && has_token(http::header::CONNECTION, "upgrade")
+}
+
+// HEAD 请求只写响应头 (Content-Length 仍是响应体的长度)
+async fn respond_direct(
+    session: &mut Session,
+    direct: &direct::CompiledDirectResponse,
+) -> pingora::Result<()> {
+    let mut header = ResponseHeader::build(direct.status, Some(direct.headers.len() + 1))?;
+    for (name, value) in &direct.headers {
+        header.append_header(name.clone(), value.clone())?;
+    }
+    header.insert_header("Content-Length", direct.body.len().to_string())?;
+    let head_only = session.req_header().method == http::Method::HEAD || direct.body.is_empty();
+    session
+        .write_response_header(Box::new(header), head_only)
+        .await?;
+    if !head_only {
+        session
+            .write_response_body(Some(direct.body.clone()), true)
+            .await?;
+    }
+    Ok(())
+}
+
+// 插件用 agw_set_response 登记的拒绝响应
+async fn respond_plugin(
+    session: &mut Session,
+    response: &wasm::DenyResponse,
+) -> pingora::Result<()> {
+    let mut header = ResponseHeader::build(response.status, Some(response.headers.len() + 1))?;
+    for (name, value) in &response.headers {
+        header.append_header(name.clone(), value.clone())?;
+    }
+    header.insert_header("Content-Length", response.body.len().to_string())?;
+    let head_only = session.req_header().method == http::Method::HEAD || response.body.is_empty();
+    session
+        .write_response_header(Box::new(header), head_only)
+        .await?;
+    if !head_only {
+        session
+            .write_response_body(Some(response.body.clone()), true)
+            .await?;
+    }
+    Ok(())
+}
+
+// 插件能读取的请求体上限：提前读走的请求体靠 Pingora 的重试缓冲 (64KB) 转发给上游，不能超过它
+const PLUGIN_BODY_LIMIT: usize = 64 * 1024;
+
+// 在 request_filter 里把请求体读完交给插件 (agw_get_body)。
+// 读走的数据留在 Pingora 的重试缓冲里，转发上游时照常发出，request_body_filter 的哈希、镜像也照常看到它。
+// 只读 Content-Length 不超过上限的请求：chunked 或超过上限时返回 None (插件拿到 -4)，
+// 一旦读了就没法放回去，读到一半才发现超限的请求体无法再转发。
+async fn read_body_for_plugins(
+    session: &mut Session,
+    max_body: usize,
+) -> pingora::Result<Option<Bytes>> {
+    if session.is_body_empty() {
+        return Ok(Some(Bytes::new()));
+    }
+    let Some(len) = session
+        .req_header()
+        .headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+    else {
+        return Ok(None);
+    };
+    if len > max_body {
+        return Ok(None);
+    }
+    session.enable_retry_buffering();
+    let mut body = Vec::with_capacity(len);
+    while let Some(chunk) = session.read_request_body().await? {
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Some(Bytes::from(body)))
+}
+
+fn request_content_length(req: &pingora::http::RequestHeader) -> Option<u64> {
+    req.headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+}
+
+fn finish_body_hash(ctx: &mut RequestCtx) {
+    if let Some(hasher) = ctx.body_hasher.take() {
+        let digest = format!("{:x}", hasher.finalize());
+        ctx.attributes.set("request.body_sha256", digest.clone());
+        ctx.outcome.request_body_sha256 = Some(digest);
+    }
+}
+
+// 请求的目标域名 (虚拟主机)：请求行带了 authority 时以它为准，否则取 Host 头。
+// - h2 没有 Host 头，:authority 由 Pingora 放在 uri 里；
+// - absolute-form 的 HTTP/1.1 请求 ("GET http://a.example.com/path") 按 RFC 9112 忽略 Host 头；
+// - origin-form 的 HTTP/1.1 请求 ("GET /path") 的 uri 没有 authority，取 Host 头。
+fn request_host(req: &pingora::http::RequestHeader) -> Option<&str> {
+    req.uri.authority().map(|a| a.as_str()).or_else(|| {
+        req.headers
+            .get(http::header::HOST)
+            .and_then(|v| v.to_str().ok())
+    })
+}
+
+// 非上游方向的错误对应的原因码
+fn error_reason(e: &pingora::Error) -> ReasonCode {
+    match e.esource() {
+        pingora::ErrorSource::Downstream => ReasonCode::ClientError,
+        pingora::ErrorSource::Upstream => ReasonCode::UpstreamError,
+        pingora::ErrorSource::Internal | pingora::ErrorSource::Unset => ReasonCode::InternalError,
+    }
+}
+
+// 用缓存的响应直接回复客户端，附带 Age (RFC 9111) 和 X-Cache: HIT
+async fn respond_cached(session: &mut Session, hit: &cache::CachedResponse) -> pingora::Result<()> {
+    let mut resp = ResponseHeader::build(hit.status, Some(hit.headers.len() + 3))?;
+    for (name, value) in &hit.headers {
+        resp.append_header(name.clone(), value.as_slice())?;
+    }
+    resp.insert_header("Age", hit.age().as_secs().to_string())?;
+    resp.insert_header("Content-Length", hit.body.len().to_string())?;
+    resp.insert_header("X-Cache", "HIT")?;
+    session
+        .write_response_header(Box::new(resp), false)
+        .await?;
+    session
+        .write_response_body(Some(hit.body.clone()), true)
+        .await?;
+    Ok(())
 }
 
 fn init_resources(config: &client::agw::v1::ConfigSnapshot) -> ExternalResources {
@@ -427,6 +2495,9 @@ fn init_resources(config: &client::agw::v1::ConfigSnapshot) -> ExternalResources
                  eprintln!("Unsupported DB type: {}", db.r#type);
              }
          }
+
+         // 插件的出站 HTTP (agw_http_fetch)
+         resources.http = res_config.http_client.as_ref().map(wasm::HttpClient::new);
     }
     resources
 }
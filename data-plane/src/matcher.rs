@@ -0,0 +1,690 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::client::agw::config::v1::string_match::Pattern;
+use crate::client::agw::config::v1::{HeaderMatch, QueryParamMatch, StringMatch};
+
+// 【通用字符串匹配器 (StringMatcher)】
+// 路由路径、Header 规则、WAF 规则等所有 "按 精确/前缀/后缀/包含/正则 匹配字符串" 的场景都统一用它，
+// 避免各个功能各自实现、在大小写和锚定语义上出现细微差异。
+//
+// 统一语义：
+// - 在校验 (应用配置) 阶段编译一次，请求路径上只做匹配，不做任何编译。
+// - ignore_case 对所有匹配方式生效。
+// - 正则是【全匹配】：自动加上 ^(?:...)$ 锚定，"/api/.*" 不会匹配 "/v1/api/x"。
+// - 正则长度和编译后的大小都有上限，超限的规则在校验阶段直接拒绝。
+//   (regex crate 本身保证线性时间匹配，不存在回溯爆炸，这里限制的是内存占用)
+#[derive(Debug, Clone)]
+pub enum StringMatcher {
+    Exact(String),
+    Prefix(String),
+    // 路径前缀：只在路径段边界上命中 (见 path_prefix_matches)
+    PathPrefix(String),
+    Suffix(String),
+    Contains(String),
+    Regex(Regex),
+    // 未指定任何模式：匹配一切
+    Any,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledMatch {
+    matcher: StringMatcher,
+    ignore_case: bool,
+}
+
+// 正则源码最大长度
+const MAX_REGEX_LEN: usize = 1024;
+// 正则编译后的最大内存占用 (字节)
+const MAX_REGEX_SIZE: usize = 256 * 1024;
+
+impl CompiledMatch {
+    pub fn compile(m: &StringMatch) -> Result<Self, String> {
+        let ignore_case = m.ignore_case;
+        let fold = |s: &str| fold_case(s, ignore_case);
+        let matcher = match &m.pattern {
+            Some(Pattern::Exact(s)) => StringMatcher::Exact(fold(s)),
+            Some(Pattern::Prefix(s)) => StringMatcher::Prefix(fold(s)),
+            Some(Pattern::Suffix(s)) => StringMatcher::Suffix(fold(s)),
+            Some(Pattern::Contains(s)) => StringMatcher::Contains(fold(s)),
+            Some(Pattern::Regex(s)) => StringMatcher::Regex(compile_regex(s, ignore_case)?),
+            None => StringMatcher::Any,
+        };
+        Ok(Self {
+            matcher,
+            ignore_case,
+        })
+    }
+
+    // 大小写敏感的前缀匹配 (兼容旧的 path_prefix 字段)
+    pub fn prefix(prefix: &str) -> Self {
+        Self {
+            matcher: StringMatcher::Prefix(prefix.to_string()),
+            ignore_case: false,
+        }
+    }
+
+    // 路径前缀匹配 (路由的 path_prefix 字段)；ignore_case 对应路由的 case_insensitive_path
+    pub fn path_prefix(prefix: &str, ignore_case: bool) -> Self {
+        Self {
+            matcher: StringMatcher::PathPrefix(fold_case(prefix, ignore_case)),
+            ignore_case,
+        }
+    }
+
+    // 用于请求路径时，前缀匹配改为按路径段边界匹配 (路由的 path 字段)
+    pub fn for_path(self) -> Self {
+        match self.matcher {
+            StringMatcher::Prefix(s) => Self {
+                matcher: StringMatcher::PathPrefix(s),
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    // 精确匹配 (path_prefix + PATH_MATCH_EXACT)
+    pub fn exact(path: &str, ignore_case: bool) -> Self {
+        Self {
+            matcher: StringMatcher::Exact(fold_case(path, ignore_case)),
+            ignore_case,
+        }
+    }
+
+    // 正则全匹配 (path_prefix + PATH_MATCH_REGEX)；ignore_case 时相当于加上 (?i)
+    pub fn regex(pattern: &str, ignore_case: bool) -> Result<Self, String> {
+        Ok(Self {
+            matcher: StringMatcher::Regex(compile_regex(pattern, ignore_case)?),
+            ignore_case,
+        })
+    }
+
+    // 能放进精确匹配索引的匹配器。返回 (值, 是否大小写不敏感)；ignore_case 时值已经是小写。
+    pub fn indexable_exact(&self) -> Option<(&str, bool)> {
+        match &self.matcher {
+            StringMatcher::Exact(s) => Some((s.as_str(), self.ignore_case)),
+            _ => None,
+        }
+    }
+
+    // 能放进前缀树索引的匹配器：前缀匹配，以及等价于空前缀的 "匹配一切"。
+    // 返回 (前缀, 是否大小写不敏感)；ignore_case 时前缀已经是小写。
+    pub fn indexable_prefix(&self) -> Option<(&str, bool)> {
+        match &self.matcher {
+            StringMatcher::Prefix(s) | StringMatcher::PathPrefix(s) => {
+                Some((s.as_str(), self.ignore_case))
+            }
+            StringMatcher::Any => Some(("", false)),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, input: &str) -> bool {
+        // 只有需要时才分配小写副本，正则自带大小写不敏感标志，不需要折叠
+        let folded;
+        let input = if self.ignore_case && !matches!(self.matcher, StringMatcher::Regex(_)) {
+            folded = input.to_lowercase();
+            folded.as_str()
+        } else {
+            input
+        };
+        match &self.matcher {
+            StringMatcher::Exact(s) => input == s.as_str(),
+            StringMatcher::Prefix(s) => input.starts_with(s.as_str()),
+            StringMatcher::PathPrefix(s) => path_prefix_matches(s, input),
+            StringMatcher::Suffix(s) => input.ends_with(s.as_str()),
+            StringMatcher::Contains(s) => input.contains(s.as_str()),
+            StringMatcher::Regex(re) => re.is_match(input),
+            StringMatcher::Any => true,
+        }
+    }
+}
+
+// 【路径前缀匹配】
+// 只在路径段边界上命中，"/api" 不会把 "/apiv2/evil"、"/api-internal" 的流量带走：
+// - "/api" 命中 "/api"、"/api/"、"/api/users"，不命中 "/apix"
+// - "/api/" (带结尾斜杠) 只命中 "/api/" 及其下的路径，不命中 "/api"
+// - "/" 和空前缀命中所有路径
+// 路由的前缀树只按字节前缀取候选，是否真正命中由这里决定 (request_filter 里的 path.matches)。
+pub fn path_prefix_matches(prefix: &str, path: &str) -> bool {
+    let Some(rest) = path.strip_prefix(prefix) else {
+        return false;
+    };
+    prefix.is_empty() || prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/')
+}
+
+fn fold_case(s: &str, ignore_case: bool) -> String {
+    if ignore_case {
+        s.to_lowercase()
+    } else {
+        s.to_string()
+    }
+}
+
+fn compile_regex(pattern: &str, ignore_case: bool) -> Result<Regex, String> {
+    if pattern.len() > MAX_REGEX_LEN {
+        return Err(format!(
+            "regex too long ({} > {} bytes)",
+            pattern.len(),
+            MAX_REGEX_LEN
+        ));
+    }
+    RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .case_insensitive(ignore_case)
+        .size_limit(MAX_REGEX_SIZE)
+        .dfa_size_limit(MAX_REGEX_SIZE)
+        .build()
+        .map_err(|e| format!("invalid regex {:?}: {}", pattern, e))
+}
+
+// 【域名匹配 (HostMatcher)】
+// 路由的 hosts 列表 (大小写不敏感)：
+// - 精确域名 "api.example.com"；
+// - "*.example.com" 只通配一层子域名：匹配 acme.example.com，不匹配 a.b.example.com，也不匹配 example.com 本身；
+// - "**.example.com" 通配任意层子域名 (同样不匹配 example.com 本身)。
+// 列表为空表示任意域名。匹配前会去掉请求 Host 里的端口和 FQDN 结尾的 "." ("example.com." 等同 "example.com")。
+#[derive(Debug, Clone, Default)]
+pub struct HostMatcher {
+    exact: Vec<String>,
+    // "*.example.com" 存为 ".example.com"
+    wildcards: Vec<String>,
+    // "**.example.com" 存为 ".example.com"
+    deep_wildcards: Vec<String>,
+}
+
+// 请求域名是怎么匹配上的，越靠前越精确。router 据此让精确域名优先于通配 (见 ActiveConfig::resolve)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HostMatch {
+    Exact,
+    Wildcard,
+    DeepWildcard,
+    // 路由不限域名
+    Any,
+}
+
+impl HostMatch {
+    pub fn is_wildcard(self) -> bool {
+        matches!(self, HostMatch::Wildcard | HostMatch::DeepWildcard)
+    }
+}
+
+impl HostMatcher {
+    pub fn compile(hosts: &[String]) -> Result<Self, String> {
+        let mut matcher = Self::default();
+        for host in hosts {
+            let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+            if let Some(rest) = host.strip_prefix('*') {
+                let (suffix, list) = match rest.strip_prefix('*') {
+                    Some(suffix) => (suffix, &mut matcher.deep_wildcards),
+                    None => (rest, &mut matcher.wildcards),
+                };
+                if !suffix.starts_with('.') || suffix.len() < 2 || suffix[1..].contains('*') {
+                    return Err(format!("invalid wildcard host {:?}", host));
+                }
+                list.push(suffix.to_string());
+            } else if host.is_empty() || host.contains('*') {
+                return Err(format!("invalid host {:?}", host));
+            } else {
+                matcher.exact.push(host);
+            }
+        }
+        Ok(matcher)
+    }
+
+    fn is_any(&self) -> bool {
+        self.exact.is_empty() && self.wildcards.is_empty() && self.deep_wildcards.is_empty()
+    }
+
+    // host 为 None (请求没有 Host) 时只有 "任意域名" 的路由能匹配。
+    // 同一个列表里多种写法都能匹配时返回最精确的一种
+    pub fn matches(&self, host: Option<&str>) -> Option<HostMatch> {
+        if self.is_any() {
+            return Some(HostMatch::Any);
+        }
+        let host = strip_port(host?).trim_end_matches('.').to_ascii_lowercase();
+        // 通配部分 (去掉后缀后剩下的子域名标签)，不能为空：通配不匹配裸域名
+        let labels = |suffix: &String| {
+            host.strip_suffix(suffix.as_str())
+                .filter(|labels| !labels.is_empty())
+        };
+        if self.exact.iter().any(|h| *h == host) {
+            Some(HostMatch::Exact)
+        } else if self
+            .wildcards
+            .iter()
+            .any(|s| labels(s).is_some_and(|labels| !labels.contains('.')))
+        {
+            Some(HostMatch::Wildcard)
+        } else if self.deep_wildcards.iter().any(|s| labels(s).is_some()) {
+            Some(HostMatch::DeepWildcard)
+        } else {
+            None
+        }
+    }
+}
+
+// 【请求头匹配 (HeaderMatcher)】
+// 路由的 headers 列表，由调用方要求全部满足 (AND)：
+// - 只有 name: 请求带这个头即可 (exists)；
+// - name + absent: 请求不能带这个头 (A/B 分流里把 "没有打标记" 的流量显式留在稳定版)；
+// - name + value: 任意一个值满足 StringMatch 即可。同名头出现多次，或者一行里用逗号分隔多个值时逐个检查
+//   (整行也会检查一次，这样 "exact: a, b" 之类的写法仍然有效)。
+#[derive(Debug, Clone)]
+pub struct HeaderMatcher {
+    name: http::HeaderName,
+    value: Option<CompiledMatch>,
+    absent: bool,
+}
+
+impl HeaderMatcher {
+    pub fn compile(m: &HeaderMatch) -> Result<Self, String> {
+        let name = http::HeaderName::from_bytes(m.name.trim().as_bytes())
+            .map_err(|_| format!("invalid header name {:?}", m.name))?;
+        if m.absent && m.value.is_some() {
+            return Err("absent cannot be combined with value".to_string());
+        }
+        let value = m.value.as_ref().map(CompiledMatch::compile).transpose()?;
+        Ok(Self {
+            name,
+            value,
+            absent: m.absent,
+        })
+    }
+
+    pub fn matches(&self, headers: &http::HeaderMap) -> bool {
+        if self.absent {
+            return !headers.contains_key(&self.name);
+        }
+        let Some(m) = &self.value else {
+            return headers.contains_key(&self.name);
+        };
+        headers
+            .get_all(&self.name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|line| m.matches(line) || line.split(',').any(|v| m.matches(v.trim())))
+    }
+}
+
+// 【查询参数匹配 (QueryParamMatcher)】
+// 与 HeaderMatcher 相同的语义：只有 name 表示参数存在即可；带 value 时同名参数的任意一个值满足即可。
+// 参数名和值都先做 URL 解码 ("+" 视为空格)，请求的查询串每个请求最多解析一次 (见 parse_query)。
+#[derive(Debug, Clone)]
+pub struct QueryParamMatcher {
+    name: String,
+    value: Option<CompiledMatch>,
+}
+
+impl QueryParamMatcher {
+    pub fn compile(m: &QueryParamMatch) -> Result<Self, String> {
+        if m.name.is_empty() {
+            return Err("query parameter matcher without name".to_string());
+        }
+        let value = m.value.as_ref().map(CompiledMatch::compile).transpose()?;
+        Ok(Self {
+            name: m.name.clone(),
+            value,
+        })
+    }
+
+    pub fn matches(&self, params: &[(String, String)]) -> bool {
+        let mut values = params
+            .iter()
+            .filter(|(k, _)| *k == self.name)
+            .map(|(_, v)| v);
+        match &self.value {
+            None => values.next().is_some(),
+            Some(m) => values.any(|v| m.matches(v)),
+        }
+    }
+}
+
+// 解析并 URL 解码查询串，保留重复参数和顺序
+pub fn parse_query(query: Option<&str>) -> Vec<(String, String)> {
+    query
+        .map(|q| {
+            form_urlencoded::parse(q.as_bytes())
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// "example.com:8080" -> "example.com"；"[::1]:8080" -> "[::1]"
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        };
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(hosts: &[&str]) -> HostMatcher {
+        HostMatcher::compile(&hosts.iter().map(|h| h.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn header(name: &str, value: Option<Pattern>) -> HeaderMatcher {
+        HeaderMatcher::compile(&HeaderMatch {
+            name: name.to_string(),
+            value: value.map(|pattern| StringMatch {
+                pattern: Some(pattern),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn request_headers(pairs: &[(&'static str, &'static str)]) -> http::HeaderMap {
+        let mut headers = http::HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, http::HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn host_wildcards_cover_one_or_many_labels() {
+        let single = hosts(&["*.example.com"]);
+        assert_eq!(
+            single.matches(Some("acme.example.com")),
+            Some(HostMatch::Wildcard)
+        );
+        assert_eq!(single.matches(Some("a.b.example.com")), None);
+        assert_eq!(single.matches(Some("example.com")), None);
+
+        let deep = hosts(&["**.example.com"]);
+        assert_eq!(
+            deep.matches(Some("acme.example.com")),
+            Some(HostMatch::DeepWildcard)
+        );
+        assert_eq!(
+            deep.matches(Some("a.b.example.com")),
+            Some(HostMatch::DeepWildcard)
+        );
+        assert_eq!(deep.matches(Some("example.com")), None);
+    }
+
+    #[test]
+    fn host_match_reports_the_most_specific_form() {
+        let matcher = hosts(&["**.example.com", "*.example.com", "api.example.com"]);
+        assert_eq!(
+            matcher.matches(Some("api.example.com")),
+            Some(HostMatch::Exact)
+        );
+        assert_eq!(
+            matcher.matches(Some("web.example.com")),
+            Some(HostMatch::Wildcard)
+        );
+        assert_eq!(
+            matcher.matches(Some("a.web.example.com")),
+            Some(HostMatch::DeepWildcard)
+        );
+        assert!(HostMatch::Exact < HostMatch::Wildcard);
+        assert!(HostMatch::Wildcard < HostMatch::DeepWildcard);
+        assert!(HostMatch::DeepWildcard < HostMatch::Any);
+    }
+
+    #[test]
+    fn host_match_ignores_case_port_and_trailing_dot() {
+        let matcher = hosts(&["API.example.com."]);
+        assert_eq!(
+            matcher.matches(Some("api.EXAMPLE.com:8443")),
+            Some(HostMatch::Exact)
+        );
+        assert_eq!(
+            matcher.matches(Some("api.example.com.")),
+            Some(HostMatch::Exact)
+        );
+        assert_eq!(matcher.matches(None), None);
+        assert_eq!(hosts(&[]).matches(None), Some(HostMatch::Any));
+    }
+
+    #[test]
+    fn invalid_wildcard_hosts_are_rejected() {
+        for host in [
+            "*example.com",
+            "*.",
+            "*.*.example.com",
+            "api.*.com",
+            "***.example.com",
+        ] {
+            assert!(
+                HostMatcher::compile(&[host.to_string()]).is_err(),
+                "{}",
+                host
+            );
+        }
+    }
+
+    #[test]
+    fn header_values_are_split_on_commas() {
+        let matcher = header("x-tenant", Some(Pattern::Exact("beta".to_string())));
+        assert!(matcher.matches(&request_headers(&[("x-tenant", "alpha, beta")])));
+        assert!(matcher.matches(&request_headers(&[
+            ("x-tenant", "alpha"),
+            ("x-tenant", "beta")
+        ])));
+        assert!(!matcher.matches(&request_headers(&[("x-tenant", "alpha, betamax")])));
+
+        // 整行也检查一次，值里本来就带逗号的写法仍然能匹配
+        let whole = header("x-tenant", Some(Pattern::Exact("a, b".to_string())));
+        assert!(whole.matches(&request_headers(&[("x-tenant", "a, b")])));
+    }
+
+    #[test]
+    fn parse_query_decodes_and_keeps_repeats() {
+        assert_eq!(
+            parse_query(Some("env=staging&tag=a+b&tag=%2Fx&flag")),
+            [
+                ("env".to_string(), "staging".to_string()),
+                ("tag".to_string(), "a b".to_string()),
+                ("tag".to_string(), "/x".to_string()),
+                ("flag".to_string(), String::new()),
+            ]
+        );
+        assert!(parse_query(None).is_empty());
+        assert!(parse_query(Some("")).is_empty());
+    }
+
+    #[test]
+    fn exact_paths_do_not_match_longer_paths() {
+        let exact = CompiledMatch::exact("/api", false);
+        assert!(exact.matches("/api"));
+        assert!(!exact.matches("/api/v1"));
+        assert!(!exact.matches("/api/"));
+        assert!(!exact.matches("/apix"));
+        assert!(!exact.matches("/API"));
+
+        let folded = CompiledMatch::exact("/API", true);
+        assert!(folded.matches("/api"));
+        assert!(!folded.matches("/api/v1"));
+
+        // 同一个值的前缀匹配则会命中
+        assert!(CompiledMatch::path_prefix("/api", false).matches("/api/v1"));
+    }
+
+    // 【性质测试 (Property Tests)】
+    // 随机生成模式和输入，对照最朴素的参考实现；种子固定，失败时可以复现
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            // xorshift64
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn string(&mut self, alphabet: &[char], max_len: usize) -> String {
+            let len = self.below(max_len + 1);
+            (0..len)
+                .map(|_| alphabet[self.below(alphabet.len())])
+                .collect()
+        }
+    }
+
+    // 字母表故意很小，前缀 / 包含等关系才会经常成立；带一个非 ASCII 字母检查大小写折叠
+    const ALPHABET: &[char] = &['a', 'A', 'b', 'B', '/', '-', '.', 'é', 'É'];
+    const CASES: usize = 20_000;
+
+    fn string_match(pattern: Pattern, ignore_case: bool) -> CompiledMatch {
+        CompiledMatch::compile(&StringMatch {
+            pattern: Some(pattern),
+            ignore_case,
+        })
+        .unwrap()
+    }
+
+    fn fold(s: &str, ignore_case: bool) -> String {
+        if ignore_case {
+            s.chars().flat_map(char::to_lowercase).collect()
+        } else {
+            s.to_string()
+        }
+    }
+
+    #[test]
+    fn literal_matchers_agree_with_the_reference() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..CASES {
+            let pattern = rng.string(ALPHABET, 4);
+            let input = rng.string(ALPHABET, 8);
+            let ignore_case = rng.below(2) == 1;
+            let (p, i) = (fold(&pattern, ignore_case), fold(&input, ignore_case));
+            let cases = [
+                (Pattern::Exact(pattern.clone()), i == p),
+                (Pattern::Prefix(pattern.clone()), i.starts_with(&p)),
+                (Pattern::Suffix(pattern.clone()), i.ends_with(&p)),
+                (Pattern::Contains(pattern.clone()), i.contains(&p)),
+            ];
+            for (kind, expected) in cases {
+                let matcher = string_match(kind.clone(), ignore_case);
+                assert_eq!(
+                    matcher.matches(&input),
+                    expected,
+                    "{:?} ignore_case={} input={:?}",
+                    kind,
+                    ignore_case,
+                    input
+                );
+            }
+        }
+    }
+
+    // 正则是全匹配：转义后的字面量等价于 exact，加上 .* 分别等价于 prefix / suffix / contains
+    #[test]
+    fn regexes_are_fully_anchored() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..CASES / 10 {
+            let pattern = rng.string(ALPHABET, 4);
+            let input = rng.string(ALPHABET, 8);
+            let ignore_case = rng.below(2) == 1;
+            let literal = regex::escape(&pattern);
+            let pairs = [
+                (literal.clone(), Pattern::Exact(pattern.clone())),
+                (format!("{}.*", literal), Pattern::Prefix(pattern.clone())),
+                (format!(".*{}", literal), Pattern::Suffix(pattern.clone())),
+                (
+                    format!(".*{}.*", literal),
+                    Pattern::Contains(pattern.clone()),
+                ),
+            ];
+            for (regex, equivalent) in pairs {
+                assert_eq!(
+                    string_match(Pattern::Regex(regex.clone()), ignore_case).matches(&input),
+                    string_match(equivalent, ignore_case).matches(&input),
+                    "regex {:?} ignore_case={} input={:?}",
+                    regex,
+                    ignore_case,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn path_prefixes_agree_with_the_segment_definition() {
+        let mut rng = Rng(0xdead_beef_cafe_f00d);
+        for _ in 0..CASES {
+            let prefix = rng.string(&['a', 'b', '/'], 4);
+            let path = rng.string(&['a', 'b', '/'], 8);
+            let expected = if prefix.is_empty() || prefix.ends_with('/') {
+                path.starts_with(&prefix)
+            } else {
+                path == prefix || path.starts_with(&format!("{}/", prefix))
+            };
+            assert_eq!(
+                path_prefix_matches(&prefix, &path),
+                expected,
+                "prefix={:?} path={:?}",
+                prefix,
+                path
+            );
+        }
+    }
+
+    // 随机拼出来的正则：要么编译成功、匹配不 panic，要么在编译阶段被拒绝
+    #[test]
+    fn random_regexes_compile_or_are_rejected() {
+        let syntax = [
+            '(', ')', '[', ']', '{', '}', '*', '+', '?', '|', '\\', '^', '$', '.', 'a', '1', ',',
+        ];
+        let mut rng = Rng(0x0123_4567_89ab_cdef);
+        let (mut compiled, mut rejected) = (0, 0);
+        for _ in 0..CASES / 4 {
+            let pattern = rng.string(&syntax, 12);
+            match CompiledMatch::regex(&pattern, rng.below(2) == 1) {
+                Ok(matcher) => {
+                    compiled += 1;
+                    for _ in 0..4 {
+                        matcher.matches(&rng.string(ALPHABET, 16));
+                    }
+                }
+                Err(e) => {
+                    rejected += 1;
+                    assert!(e.starts_with("invalid regex"), "{}", e);
+                }
+            }
+        }
+        assert!(compiled > 0 && rejected > 0);
+    }
+
+    #[test]
+    fn oversized_regexes_are_rejected() {
+        let long = "a".repeat(MAX_REGEX_LEN + 1);
+        assert!(
+            CompiledMatch::regex(&long, false)
+                .unwrap_err()
+                .contains("too long")
+        );
+        assert!(CompiledMatch::regex(&"a".repeat(MAX_REGEX_LEN), false).is_ok());
+        // 源码很短，展开后的自动机超过 MAX_REGEX_SIZE
+        for pattern in [r"(\w{100}){100}", r"[a-z]{1000}{1000}"] {
+            assert!(CompiledMatch::regex(pattern, false).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn path_prefixes_match_on_segment_boundaries() {
+        assert!(path_prefix_matches("/api", "/api"));
+        assert!(path_prefix_matches("/api", "/api/users"));
+        assert!(!path_prefix_matches("/api", "/apix"));
+        assert!(path_prefix_matches("/api/", "/api/users"));
+        assert!(!path_prefix_matches("/api/", "/api"));
+        assert!(path_prefix_matches("/", "/anything"));
+    }
+}
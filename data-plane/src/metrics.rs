@@ -0,0 +1,172 @@
+use prometheus::{
+    HistogramVec, IntCounterVec, IntGauge, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::outcome::RequestOutcome;
+use crate::routestats::ROUTE_STATS;
+
+// 【Prometheus 指标】
+// 指标注册在 prometheus 的默认 Registry 上，由 Pingora 自带的 Prometheus 服务在
+// AGW_METRICS_ADDR (默认 0.0.0.0:9090) 的 GET /metrics 暴露，文本格式。
+//
+// - agw_requests_total{route, status}: 请求数，按命中的路由 (path_prefix，未命中为 "-") 和最终状态码。
+//   在 logging 阶段从 RequestOutcome 统计，网关自己生成的响应 (404 / 403 / 503 ...) 也包括在内。
+// - agw_upstream_latency_seconds{route, cluster}: 从选中上游节点到收到上游响应头的耗时。
+// - agw_active_requests: 正在处理中的请求数 (随请求的 CTX 创建和释放)。
+// - agw_oversize_responses_total{route, action}: 超过 max_response_bytes 的响应，
+//   action 为 rejected / aborted / truncated (见 RequestOutcome.oversize)。
+// - agw_route_timeouts_total{route}: 超过路由截止时间 (timeout_ms / AGW_DEFAULT_TIMEOUT_MS) 的请求。
+// - agw_route_disabled_total{route}: 命中停用 (disabled) 路由、由网关直接拒绝的请求 (见 direct.rs)。
+// - agw_mirror_requests_total{route, result}: 流量镜像的请求 (见 mirror.rs)，result 为
+//   success / failed / body_too_large / no_endpoint / overloaded。
+// - agw_plugin_fuel_exhausted_total{plugin}: 用完 fuel (指令数上限) 被中止的插件调用，plugin 为配置里的插件名。
+// - agw_panics_total{route, phase}: 被捕获的 panic (见 panics.rs)；后台任务的 route 为 "-"，phase 为任务名。
+//
+// 同一次 record_request 还会计入 routestats.rs 的按路由延迟分位数 (管理端口 /stats/routes)，
+// 不跑 Prometheus 时也能看 p50 / p99。
+//
+// label 只用配置里的路由 / 集群名，不用请求路径，避免 label 基数失控。
+static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "agw_requests_total",
+        "Requests handled by the gateway, by route and final status",
+        &["route", "status"]
+    )
+    .unwrap()
+});
+
+static UPSTREAM_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "agw_upstream_latency_seconds",
+        "Time from upstream selection to the upstream response header",
+        &["route", "cluster"]
+    )
+    .unwrap()
+});
+
+static OVERSIZE_RESPONSES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "agw_oversize_responses_total",
+        "Upstream responses exceeding the route's max_response_bytes, by route and action",
+        &["route", "action"]
+    )
+    .unwrap()
+});
+
+static ROUTE_TIMEOUTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "agw_route_timeouts_total",
+        "Requests that exceeded the route's deadline, by route",
+        &["route"]
+    )
+    .unwrap()
+});
+
+static ROUTE_DISABLED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "agw_route_disabled_total",
+        "Requests rejected because their route is disabled, by route",
+        &["route"]
+    )
+    .unwrap()
+});
+
+static MIRROR_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "agw_mirror_requests_total",
+        "Mirrored (shadow) requests, by route and result",
+        &["route", "result"]
+    )
+    .unwrap()
+});
+
+static PLUGIN_FUEL_EXHAUSTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "agw_plugin_fuel_exhausted_total",
+        "Plugin invocations stopped because they ran out of fuel, by plugin name",
+        &["plugin"]
+    )
+    .unwrap()
+});
+
+static PANICS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "agw_panics_total",
+        "Panics caught at a proxy phase or background task boundary",
+        &["route", "phase"]
+    )
+    .unwrap()
+});
+
+static ACTIVE_REQUESTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("agw_active_requests", "Requests currently being processed").unwrap()
+});
+
+// 一个处理中的请求；Drop 时 agw_active_requests 减一
+pub struct ActiveRequest(());
+
+impl ActiveRequest {
+    pub fn begin() -> Self {
+        ACTIVE_REQUESTS.inc();
+        Self(())
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        ACTIVE_REQUESTS.dec();
+    }
+}
+
+pub fn record_request(outcome: &RequestOutcome) {
+    REQUESTS
+        .with_label_values(&[route_label(outcome), &outcome.status.to_string()])
+        .inc();
+    ROUTE_STATS.record(outcome);
+}
+
+pub fn observe_upstream_latency(outcome: &RequestOutcome, elapsed: Duration) {
+    UPSTREAM_LATENCY
+        .with_label_values(&[
+            route_label(outcome),
+            outcome.cluster.as_deref().unwrap_or("-"),
+        ])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_oversize(outcome: &RequestOutcome, action: &'static str) {
+    OVERSIZE_RESPONSES
+        .with_label_values(&[route_label(outcome), action])
+        .inc();
+}
+
+pub fn record_route_timeout(outcome: &RequestOutcome) {
+    ROUTE_TIMEOUTS
+        .with_label_values(&[route_label(outcome)])
+        .inc();
+}
+
+pub fn record_route_disabled(outcome: &RequestOutcome) {
+    ROUTE_DISABLED
+        .with_label_values(&[route_label(outcome)])
+        .inc();
+}
+
+pub fn record_mirror(route: &str, result: &'static str) {
+    MIRROR_REQUESTS.with_label_values(&[route, result]).inc();
+}
+
+pub fn record_fuel_exhausted(plugin: &str) {
+    PLUGIN_FUEL_EXHAUSTED.with_label_values(&[plugin]).inc();
+}
+
+pub fn record_panic(route: &str, phase: &str) {
+    PANICS.with_label_values(&[route, phase]).inc();
+}
+
+fn route_label(outcome: &RequestOutcome) -> &str {
+    outcome.route.as_deref().unwrap_or("-")
+}
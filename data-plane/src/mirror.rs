@@ -0,0 +1,218 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Semaphore;
+
+use crate::client::agw::config::v1::Endpoint;
+use crate::metrics;
+
+// 【流量镜像 (Request Mirroring)】
+// 路由配置了 mirror_cluster 时，转发给主集群的请求另外复制一份 (请求头 + 请求体) 发给镜像集群的一个节点，
+// 用真实流量验证新版本服务，客户端拿到的始终是主集群的响应。
+//
+// - 镜像请求带上 X-Mirrored: true，影子服务据此区分 (例如不发邮件、不扣款)。
+// - mirror_sample_rate 按比例随机抽取要镜像的请求 (不设置表示全部镜像，0 表示不镜像)，影子集群容量比主集群小时用它限流。
+// - 请求体完整收到之后才发出 (后台任务，不占用请求处理流程)，镜像端的快慢和成败都不影响客户端延迟。
+//   请求体超过 AGW_MIRROR_MAX_BODY_BYTES 的请求不镜像 (截断的请求体等于一个不同的请求)。
+// - 镜像请求的结果只计入 agw_mirror_requests_total{route, result}，不写访问日志、不影响健康检查和熔断：
+//   success / failed (连不上、超时、5xx) / body_too_large / no_endpoint / overloaded。
+// - 同时进行中的镜像请求有上限 (AGW_MIRROR_MAX_IN_FLIGHT)，影子服务变慢时多出来的直接丢弃 (overloaded)；
+//   资源看门狗降级时同样不再镜像。
+//
+// 请求按 HTTP/1.1 + Connection: close 发送，只读响应头拿状态码，响应体直接丢弃。
+pub struct Mirror {
+    timeout: Duration,
+    max_body_bytes: usize,
+    permits: Arc<Semaphore>,
+}
+
+// 一个等待请求体收完的镜像请求
+pub struct PendingMirror {
+    pub cluster: String,
+    route: String,
+    // 请求行 + 请求头 (不含 Host / Content-Length，发送时补上)
+    head: String,
+    // 客户端请求的 Host (镜像集群配置了 host_rewrite 时被替换)
+    host: Option<String>,
+    body: Vec<u8>,
+    too_large: bool,
+}
+
+// 不复制的请求头：逐跳头，以及发送时重新计算的 Host / Content-Length
+const SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "transfer-encoding",
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "upgrade",
+    "expect",
+];
+
+const MAX_RESPONSE_HEAD: usize = 16 * 1024;
+
+// 这个请求要不要镜像：sample_rate 未设置时全部镜像，否则按比例随机抽取 (0 一个都不抽)
+pub fn sampled(sample_rate: Option<f32>) -> bool {
+    match sample_rate {
+        None => true,
+        Some(rate) => rand::random::<f32>() < rate,
+    }
+}
+
+impl Mirror {
+    pub fn new(timeout: Duration, max_body_bytes: usize, max_in_flight: usize) -> Self {
+        Self {
+            timeout,
+            max_body_bytes,
+            permits: Arc::new(Semaphore::new(max_in_flight)),
+        }
+    }
+
+    // request_filter 放行之后调用，path_and_query 为改写后发给上游的路径
+    pub fn prepare(
+        &self,
+        cluster: &str,
+        route: &str,
+        req: &pingora::http::RequestHeader,
+        host: Option<&str>,
+        path_and_query: &str,
+    ) -> PendingMirror {
+        let mut head = format!("{} {} HTTP/1.1\r\n", req.method, path_and_query);
+        for (name, value) in req.headers.iter() {
+            if SKIPPED_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            head.push_str(name.as_str());
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        head.push_str("X-Mirrored: true\r\nConnection: close\r\n");
+        PendingMirror {
+            cluster: cluster.to_string(),
+            route: route.to_string(),
+            head,
+            host: host.map(str::to_string),
+            body: Vec::new(),
+            too_large: false,
+        }
+    }
+
+    pub fn push_body(&self, pending: &mut PendingMirror, chunk: &Bytes) {
+        if pending.too_large {
+            return;
+        }
+        if pending.body.len() + chunk.len() > self.max_body_bytes {
+            pending.too_large = true;
+            pending.body = Vec::new();
+            return;
+        }
+        pending.body.extend_from_slice(chunk);
+    }
+
+    // 请求体收完之后调用；endpoint 为 None 表示镜像集群没有可用节点
+    pub fn dispatch(
+        &self,
+        pending: PendingMirror,
+        endpoint: Option<&Endpoint>,
+        host_rewrite: Option<String>,
+    ) {
+        if pending.too_large {
+            metrics::record_mirror(&pending.route, "body_too_large");
+            return;
+        }
+        let Some(endpoint) = endpoint else {
+            metrics::record_mirror(&pending.route, "no_endpoint");
+            return;
+        };
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            metrics::record_mirror(&pending.route, "overloaded");
+            return;
+        };
+        let endpoint = endpoint.clone();
+        let timeout = self.timeout;
+        tokio::spawn(async move {
+            let _permit = permit;
+            let host = host_rewrite.or(pending.host.clone());
+            let result = tokio::time::timeout(timeout, send(&pending, host, &endpoint)).await;
+            let outcome = match result {
+                Ok(Ok(status)) if status < 500 => "success",
+                _ => "failed",
+            };
+            metrics::record_mirror(&pending.route, outcome);
+        });
+    }
+}
+
+async fn send(
+    pending: &PendingMirror,
+    host: Option<String>,
+    endpoint: &Endpoint,
+) -> std::io::Result<u16> {
+    let mut request = pending.head.clone();
+    if let Some(host) = host {
+        request.push_str(&format!("Host: {}\r\n", host));
+    }
+    if !pending.body.is_empty() {
+        request.push_str(&format!("Content-Length: {}\r\n", pending.body.len()));
+    }
+    request.push_str("\r\n");
+    if endpoint.unix_path.is_empty() {
+        let stream =
+            tokio::net::TcpStream::connect((endpoint.address.as_str(), endpoint.port as u16))
+                .await?;
+        exchange(stream, request.as_bytes(), &pending.body).await
+    } else {
+        let stream = tokio::net::UnixStream::connect(&endpoint.unix_path).await?;
+        exchange(stream, request.as_bytes(), &pending.body).await
+    }
+}
+
+// 发送请求，读到响应头为止，返回状态码
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    head: &[u8],
+    body: &[u8],
+) -> std::io::Result<u16> {
+    stream.write_all(head).await?;
+    stream.write_all(body).await?;
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 || buf.len() + n > MAX_RESPONSE_HEAD {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut response = httparse::Response::new(&mut headers);
+        match response.parse(&buf) {
+            Ok(httparse::Status::Complete(_)) => return Ok(response.code.unwrap_or_default()),
+            Ok(httparse::Status::Partial) => continue,
+            Err(_) => return Err(std::io::ErrorKind::InvalidData.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_rate_zero_mirrors_nothing() {
+        assert!((0..1000).all(|_| !sampled(Some(0.0))));
+    }
+
+    #[test]
+    fn unset_or_full_sample_rate_mirrors_everything() {
+        assert!((0..1000).all(|_| sampled(None)));
+        assert!((0..1000).all(|_| sampled(Some(1.0))));
+    }
+}
@@ -0,0 +1,75 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::buildinfo::BuildInfo;
+use crate::client::Node;
+
+// 数据面的二进制版本号
+pub const DATA_PLANE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// 【节点身份 (Node Identity)】
+// 启动时从环境变量读取一次，之后只读：
+// - AGW_NODE_ID / AGW_NODE_REGION / AGW_NODE_ZONE
+// - AGW_NODE_LABELS: 逗号分隔的 "k=v" 列表，如 "tier=edge,pool=blue"
+// 既用于和 Control Plane 握手，也通过 runtime-info 接口暴露给插件。
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeIdentity {
+    pub id: String,
+    pub region: String,
+    pub zone: String,
+    pub labels: HashMap<String, String>,
+}
+
+impl NodeIdentity {
+    pub fn from_env() -> Self {
+        let labels = std::env::var("AGW_NODE_LABELS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|kv| kv.split_once('='))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .filter(|(k, _)| !k.is_empty())
+            .collect();
+        Self {
+            id: std::env::var("AGW_NODE_ID").unwrap_or_else(|_| "node-1".to_string()),
+            region: std::env::var("AGW_NODE_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            zone: std::env::var("AGW_NODE_ZONE").unwrap_or_default(),
+            labels,
+        }
+    }
+
+    // 构造与 Control Plane 握手用的 Node 消息
+    pub fn to_proto(&self) -> Node {
+        Node {
+            id: self.id.clone(),
+            region: self.region.clone(),
+            version: DATA_PLANE_VERSION.to_string(),
+            zone: self.zone.clone(),
+            labels: self.labels.clone(),
+            build: Some(BuildInfo::current().to_proto()),
+        }
+    }
+}
+
+// 【运行时信息 (runtime-info)】
+// 插件可读取的 "我在哪个环境、哪个节点" 的精选记录。
+// 刻意不提供任意环境变量的访问能力：插件保持可移植，也不会泄露宿主机上的密钥。
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeInfo {
+    pub node: NodeIdentity,
+    pub data_plane_version: &'static str,
+    // 当前已应用的配置版本
+    pub config_version: String,
+    // 来自配置快照的环境名 (如 "staging", "prod")
+    pub environment: String,
+}
+
+impl RuntimeInfo {
+    pub fn new(node: NodeIdentity) -> Self {
+        Self {
+            node,
+            data_plane_version: DATA_PLANE_VERSION,
+            config_version: String::new(),
+            environment: String::new(),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::reason::ReasonCode;
+
+// 【请求结果记录 (RequestOutcome)】
+// "这个请求到底发生了什么" 的唯一数据来源。
+// 每个处理阶段 (request_filter / upstream_peer / logging) 以及每个功能模块
+// 都只往这里填自己的字段，访问日志、Tracing 属性等输出统一从这里渲染，
+// 保证各个出口看到的值完全一致。新功能只需要新增字段并在对应阶段填充即可。
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RequestOutcome {
+    // 请求到达时间 (Unix 毫秒)
+    pub timestamp_ms: u64,
+    pub method: String,
+    pub path: String,
+    // W3C trace context：trace id、网关自己的 span id、客户端传入的父 span id (没有时网关是根 span)
+    pub trace_id: Option<String>,
+    pub span_id: Option<String>,
+    pub parent_span_id: Option<String>,
+    // 路由改写了路径时，实际转发给上游的路径 (不含查询串)
+    pub upstream_path: Option<String>,
+    // 命中的路由 (path_prefix)
+    pub route: Option<String>,
+    // 命中路由处于定时生效/灰度中时，当前的流量比例 (0.0 ~ 1.0)
+    pub rollout_fraction: Option<f64>,
+    // 选中的集群名
+    pub cluster: Option<String>,
+    // 路由配置了 cluster_selector 时："selected" (表达式选中了集群) / "fallback" (回退到 cluster_id)
+    pub cluster_selector: Option<&'static str>,
+    // 请求被金丝雀名单命中时为 "allowlist"
+    pub canary_reason: Option<&'static str>,
+    // 路由配置了 subset_selector 时选中的子集 (如 "tier=premium"，退回整个集群时带 " (fallback)")
+    pub subset: Option<String>,
+    // 选中的上游节点 "ip:port" 或 "unix:<path>"
+    pub endpoint: Option<String>,
+    // 路由重试策略触发的重试次数 (endpoint 是最后一次尝试的节点)
+    pub retries: u32,
+    // 每个插件的执行结果，按执行顺序排列
+    pub plugins: Vec<PluginDecision>,
+    // 是否有插件拒绝了请求 (由 plugins 汇总，方便日志检索)
+    pub plugin_denied: bool,
+    // 命中插件链豁免时的规则标签 (如 "GET /healthz")，此时 plugins 为空
+    pub plugins_skipped: Option<String>,
+    // 安全模式下插件没有执行："bypassed" (路由策略为 allow，直接放行) / "denied" (503)
+    pub safe_mode: Option<&'static str>,
+    // 转发给客户端的响应体字节数
+    pub response_bytes: u64,
+    // 响应超过路由的 max_response_bytes 时的处理："rejected" (Content-Length 超限，502) /
+    // "aborted" (边转发边超限，断开) / "truncated" (截断后断开)
+    pub oversize: Option<&'static str>,
+    // 网关自身生成响应 (拦截/短路/上游失败) 时的原因码，每个这样的响应有且只有一个
+    pub reason: Option<ReasonCode>,
+    // 上游返回的 trailer 数量，以及是否转发给了客户端
+    pub trailers: usize,
+    pub trailers_forwarded: bool,
+    // 路由开启 hash_request_body 且请求体完整收到时：请求体的 SHA-256 (小写 hex) 与字节数
+    pub request_body_sha256: Option<String>,
+    pub request_body_bytes: u64,
+    // 响应缓存结果："hit" / "miss" / "bypass" (路由未开启缓存时为 None)
+    pub cache: Option<&'static str>,
+    // 幂等键处理结果："miss" / "stored" / "not_stored" / "replay" / "conflict" / "unavailable"
+    // (路由未开启或请求没带幂等键时为 None)
+    pub idempotency: Option<&'static str>,
+    // AGW_LOG_ATTRIBUTES 选中的请求属性
+    pub attributes: BTreeMap<String, String>,
+    // 最终返回给客户端的状态码
+    pub status: u16,
+    pub duration_ms: u64,
+    pub duration_us: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginDecision {
+    pub name: String,
+    // "allow" / "deny" / "error" (离线回放不执行插件，记为 "not_run")
+    pub decision: &'static str,
+}
+
+impl RequestOutcome {
+    pub fn record_plugin(&mut self, name: &str, decision: &'static str) {
+        self.plugins.push(PluginDecision {
+            name: name.to_string(),
+            decision,
+        });
+    }
+
+    // 请求结束时补齐状态码、到达时间、耗时等汇总字段
+    pub fn finish(&mut self, status: u16, start: Instant, received_at: SystemTime) {
+        self.status = status;
+        self.timestamp_ms = received_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let elapsed = start.elapsed();
+        self.duration_ms = elapsed.as_millis() as u64;
+        self.duration_us = elapsed.as_micros() as u64;
+        self.plugin_denied = self.plugins.iter().any(|p| p.decision == "deny");
+    }
+}
+
+// 单行 key=value 格式，用于访问日志
+impl fmt::Display for RequestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plugins = self
+            .plugins
+            .iter()
+            .map(|p| format!("{}:{}", p.name, p.decision))
+            .collect::<Vec<_>>()
+            .join(",");
+        write!(
+            f,
+            "method={} path={} trace_id={} span_id={} parent_span_id={} route={} upstream_path={} rollout={} cluster={} selector={} canary={} subset={} endpoint={} retries={} plugins=[{}] plugins_skipped={} safe_mode={} reason={} response_bytes={} oversize={} trailers={} trailers_forwarded={} body_sha256={} body_bytes={} cache={} idempotency={} attrs={{{}}} status={} duration_ms={} duration_us={} ts={}",
+            self.method,
+            self.path,
+            self.trace_id.as_deref().unwrap_or("-"),
+            self.span_id.as_deref().unwrap_or("-"),
+            self.parent_span_id.as_deref().unwrap_or("-"),
+            self.route.as_deref().unwrap_or("-"),
+            self.upstream_path.as_deref().unwrap_or("-"),
+            self.rollout_fraction
+                .map(|f| format!("{:.4}", f))
+                .unwrap_or_else(|| "-".to_string()),
+            self.cluster.as_deref().unwrap_or("-"),
+            self.cluster_selector.unwrap_or("-"),
+            self.canary_reason.unwrap_or("-"),
+            self.subset.as_deref().unwrap_or("-"),
+            self.endpoint.as_deref().unwrap_or("-"),
+            self.retries,
+            plugins,
+            self.plugins_skipped.as_deref().unwrap_or("-"),
+            self.safe_mode.unwrap_or("-"),
+            self.reason.map(|r| r.as_str()).unwrap_or("-"),
+            self.response_bytes,
+            self.oversize.unwrap_or("-"),
+            self.trailers,
+            self.trailers_forwarded,
+            self.request_body_sha256.as_deref().unwrap_or("-"),
+            self.request_body_bytes,
+            self.cache.unwrap_or("-"),
+            self.idempotency.unwrap_or("-"),
+            self.attributes
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(","),
+            self.status,
+            self.duration_ms,
+            self.duration_us,
+            self.timestamp_ms
+        )
+    }
+}
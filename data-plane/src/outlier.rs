@@ -0,0 +1,144 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::reason::ReasonCode;
+
+// 【上游异常追踪 (Outlier Tracking)】
+// 记录每个上游节点 ("ip:port" 或 "unix:<path>") 出现的协议级错误，按错误类别计数。
+// 典型场景：后端声明的 Content-Length 比实际 body 大、chunked 编码损坏、响应超出路由允许的大小。
+// 这些连接一定不会被放回连接池 (Pingora 只复用完整读完响应的连接)，这里负责把 "是哪个节点、什么原因" 记下来，
+// 通过管理端口 /upstreams 暴露出来。
+#[derive(Default)]
+pub struct OutlierTracker {
+    endpoints: Mutex<HashMap<String, EndpointFailures>>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct EndpointFailures {
+    pub total: u64,
+    pub by_reason: HashMap<&'static str, u64>,
+    pub last_reason: Option<&'static str>,
+}
+
+// 自定义错误类型：响应超过路由配置的 max_response_bytes
+pub const RESPONSE_TOO_LARGE: pingora::ErrorType = pingora::ErrorType::new("UpstreamResponseTooLarge");
+// 截断模式下转发到上限后主动断开。这是路由策略而不是节点的问题，错误来源记为 Internal，不计入节点异常
+pub const RESPONSE_TRUNCATED: pingora::ErrorType = pingora::ErrorType::new("ResponseTruncated");
+
+impl OutlierTracker {
+    pub fn record_failure(&self, endpoint: &str, reason: ReasonCode) {
+        let reason = reason.as_str();
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let entry = endpoints.entry(endpoint.to_string()).or_default();
+        entry.total += 1;
+        *entry.by_reason.entry(reason).or_insert(0) += 1;
+        entry.last_reason = Some(reason);
+    }
+
+    // 配置更新后调用：清掉已经不在任何集群里的节点的统计，返回清掉的条数
+    pub fn retain(&self, live: &HashMap<String, HashSet<String>>) -> usize {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let before = endpoints.len();
+        endpoints.retain(|endpoint, _| live.values().any(|e| e.contains(endpoint)));
+        before - endpoints.len()
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, EndpointFailures> {
+        self.endpoints.lock().unwrap().clone()
+    }
+}
+
+// 把 Pingora 的上游错误归类成稳定的原因码，用于日志和计数。
+// 只关心上游 (Upstream) 方向的错误，下游客户端断开等不算节点的问题。
+pub fn classify_upstream_error(e: &pingora::Error) -> Option<ReasonCode> {
+    if e.esource() != &pingora::ErrorSource::Upstream {
+        return None;
+    }
+    let reason = match e.etype() {
+        pingora::ErrorType::ReadTimedout | pingora::ErrorType::WriteTimedout => {
+            ReasonCode::UpstreamTimeout
+        }
+        pingora::ErrorType::InvalidHTTPHeader => ReasonCode::UpstreamInvalidHeader,
+        pingora::ErrorType::ConnectionClosed => ReasonCode::UpstreamConnectionClosed,
+        pingora::ErrorType::H2Error | pingora::ErrorType::InvalidH2 => ReasonCode::UpstreamH2Error,
+        pingora::ErrorType::ConnectTimedout | pingora::ErrorType::ConnectRefused => {
+            ReasonCode::UpstreamConnectFailed
+        }
+        etype => match etype.as_str() {
+            // Content-Length 大于实际 body，连接提前关闭
+            "PrematureBodyEnd" => ReasonCode::UpstreamPrematureBodyEnd,
+            // chunked 编码损坏
+            "InvalidChunk" => ReasonCode::UpstreamBadFraming,
+            "UpstreamResponseTooLarge" => ReasonCode::UpstreamResponseTooLarge,
+            _ => ReasonCode::UpstreamError,
+        },
+    };
+    Some(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_are_counted_per_endpoint_and_reason() {
+        let tracker = OutlierTracker::default();
+        for _ in 0..3 {
+            tracker.record_failure("10.0.0.1:80", ReasonCode::Upstream5xx);
+        }
+        tracker.record_failure("10.0.0.1:80", ReasonCode::UpstreamTimeout);
+        tracker.record_failure("10.0.0.2:80", ReasonCode::Upstream5xx);
+
+        let snapshot = tracker.snapshot();
+        let first = &snapshot["10.0.0.1:80"];
+        assert_eq!(first.total, 4);
+        assert_eq!(first.by_reason["UPSTREAM_5XX"], 3);
+        assert_eq!(first.by_reason["UPSTREAM_TIMEOUT"], 1);
+        assert_eq!(first.last_reason, Some("UPSTREAM_TIMEOUT"));
+        assert_eq!(snapshot["10.0.0.2:80"].total, 1);
+    }
+
+    #[test]
+    fn retain_drops_endpoints_no_longer_in_any_cluster() {
+        let tracker = OutlierTracker::default();
+        tracker.record_failure("10.0.0.1:80", ReasonCode::Upstream5xx);
+        tracker.record_failure("10.0.0.2:80", ReasonCode::Upstream5xx);
+        let live = HashMap::from([(
+            "backend".to_string(),
+            HashSet::from(["10.0.0.2:80".to_string()]),
+        )]);
+        assert_eq!(tracker.retain(&live), 1);
+        assert_eq!(
+            tracker.snapshot().keys().collect::<Vec<_>>(),
+            ["10.0.0.2:80"]
+        );
+    }
+
+    #[test]
+    fn only_upstream_errors_are_classified() {
+        let upstream = |etype| classify_upstream_error(&pingora::Error::new_up(etype));
+        assert_eq!(
+            upstream(pingora::ErrorType::ConnectRefused),
+            Some(ReasonCode::UpstreamConnectFailed)
+        );
+        assert_eq!(
+            upstream(pingora::ErrorType::ReadTimedout),
+            Some(ReasonCode::UpstreamTimeout)
+        );
+        assert_eq!(
+            upstream(RESPONSE_TOO_LARGE),
+            Some(ReasonCode::UpstreamResponseTooLarge)
+        );
+        assert_eq!(
+            upstream(pingora::ErrorType::new("PrematureBodyEnd")),
+            Some(ReasonCode::UpstreamPrematureBodyEnd)
+        );
+        assert_eq!(
+            classify_upstream_error(&pingora::Error::new_down(
+                pingora::ErrorType::ConnectionClosed
+            )),
+            None
+        );
+    }
+}
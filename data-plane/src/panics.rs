@@ -0,0 +1,221 @@
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+use crate::outcome::RequestOutcome;
+
+// 【Panic 处理】
+// 过滤器里的一个 unwrap (比如遇到奇怪的请求头) 以前会直接打到 Pingora 的 worker 上：
+// 连接被莫名断开，日志里看不出是哪个路由、哪个阶段出的问题。
+//
+// - 请求阶段：ProxyHttp 的每个阶段在边界上用 catch / catch_sync 包住。panic 被转换成 500
+//   (原因码 INTERNAL_PANIC)，打一条带 backtrace、trace_id、路由和阶段的结构化日志，
+//   并计入 agw_panics_total{route, phase}。响应头已经发出的阶段只能断开连接。
+// - 后台任务：配置同步 / 应用、主动健康检查、资源看门狗由 supervise 运行，panic 后按退避间隔重启，
+//   不会悄无声息地停掉。
+// - 最近一分钟的 panic 数达到 AGW_PANIC_DEGRADED_PER_MINUTE 时，/healthz 报告 degraded。
+//
+// backtrace 由 panic hook 在 panic 发生的线程上采集 (此时栈还没有展开)，捕获处再取出来；
+// 被捕获的 panic 不再走默认 hook 打印，避免同一个 panic 出现两份输出。
+pub struct PanicReport {
+    pub message: String,
+    pub location: String,
+    pub backtrace: String,
+}
+
+thread_local! {
+    // 当前线程上正在捕获的层数；> 0 时 hook 只采集信息，由捕获处统一输出
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+    // 最近一次被捕获的 panic 的 (位置, backtrace)
+    static LAST: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+}
+
+// 启动时调用一次；没有被捕获的 panic (如 Pingora 自己的线程) 仍然交给原来的 hook
+pub fn install_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if CATCHING.get() > 0 {
+            let location = info.location().map(|l| l.to_string()).unwrap_or_default();
+            LAST.set(Some((location, Backtrace::force_capture().to_string())));
+        } else {
+            previous(info);
+        }
+    }));
+}
+
+fn run_caught<T>(f: impl FnOnce() -> T) -> Result<T, PanicReport> {
+    CATCHING.set(CATCHING.get() + 1);
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.set(CATCHING.get() - 1);
+    result.map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "non-string panic payload".to_string());
+        let (location, backtrace) = LAST.take().unwrap_or_default();
+        PanicReport {
+            message,
+            location,
+            backtrace,
+        }
+    })
+}
+
+// 同步阶段 (如 response_body_filter) 使用
+pub fn catch_sync<T>(f: impl FnOnce() -> T) -> Result<T, PanicReport> {
+    run_caught(f)
+}
+
+// 异步阶段使用：每次 poll 都在 catch_unwind 里执行，panic 时以 Err 结束
+pub fn catch<F: Future>(future: F) -> CatchUnwind<F> {
+    CatchUnwind { future }
+}
+
+pub struct CatchUnwind<F> {
+    future: F,
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, PanicReport>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: future 是结构性固定的字段，这里只把 Pin 投影过去，从不移动它
+        let future = unsafe { self.map_unchecked_mut(|s| &mut s.future) };
+        match run_caught(|| future.poll(cx)) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+// 最近一分钟的 panic 计数，以及输出日志 / 指标
+pub struct PanicTracker {
+    // 每分钟达到多少次 panic 视为 degraded (0 = 不判定)
+    threshold: usize,
+    recent: Mutex<VecDeque<Instant>>,
+    total: AtomicU64,
+}
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+impl PanicTracker {
+    pub fn from_env() -> Self {
+        Self {
+            threshold: std::env::var("AGW_PANIC_DEGRADED_PER_MINUTE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+            recent: Mutex::new(VecDeque::new()),
+            total: AtomicU64::new(0),
+        }
+    }
+
+    // 请求阶段的 panic
+    pub fn record_request(
+        &self,
+        report: &PanicReport,
+        phase: &'static str,
+        outcome: &RequestOutcome,
+    ) {
+        let route = outcome.route.as_deref().unwrap_or("-");
+        eprintln!(
+            "panic {}",
+            serde_json::json!({
+                "phase": phase,
+                "route": route,
+                "method": outcome.method,
+                "path": outcome.path,
+                "trace_id": outcome.trace_id,
+                "message": report.message,
+                "location": report.location,
+                "backtrace": report.backtrace,
+            })
+        );
+        metrics::record_panic(route, phase);
+        self.note();
+    }
+
+    // 后台任务的 panic
+    pub fn record_task(&self, report: &PanicReport, task: &'static str, restart_in: Duration) {
+        eprintln!(
+            "panic {}",
+            serde_json::json!({
+                "task": task,
+                "restart_in_ms": restart_in.as_millis() as u64,
+                "message": report.message,
+                "location": report.location,
+                "backtrace": report.backtrace,
+            })
+        );
+        metrics::record_panic("-", task);
+        self.note();
+    }
+
+    fn note(&self) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        recent.push_back(now);
+        prune(&mut recent, now);
+    }
+
+    pub fn last_minute(&self) -> usize {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        prune(&mut recent, now);
+        recent.len()
+    }
+
+    pub fn degraded(&self) -> bool {
+        self.threshold > 0 && self.last_minute() >= self.threshold
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+}
+
+fn prune(recent: &mut VecDeque<Instant>, now: Instant) {
+    while recent
+        .front()
+        .is_some_and(|t| now.duration_since(*t) > WINDOW)
+    {
+        recent.pop_front();
+    }
+}
+
+// 重启退避：1s 起，每次翻倍，最长 60s；任务稳定运行超过 60s 后再 panic 则从 1s 重新开始
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+// 运行一个后台任务：正常结束即返回，panic 时记录并按退避间隔重新创建任务
+pub async fn supervise<F, Fut>(task: &'static str, tracker: Arc<PanicTracker>, make: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut backoff = BACKOFF_MIN;
+    loop {
+        let started = Instant::now();
+        let Err(report) = catch(make()).await else {
+            return;
+        };
+        if started.elapsed() > BACKOFF_MAX {
+            backoff = BACKOFF_MIN;
+        }
+        tracker.record_task(&report, task, backoff);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(BACKOFF_MAX);
+    }
+}
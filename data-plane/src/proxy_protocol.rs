@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pingora::http::RequestHeader;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+// 【PROXY protocol (v1 / v2)】
+// 网关在 L4 负载均衡 (AWS NLB、HAProxy) 后面时，TCP 连接的源地址是负载均衡自己的，
+// 真实的客户端地址在连接开头的 PROXY protocol 头里。Listener 设置了 proxy_protocol 时：
+// - 公开端口由这里的中继 (relay) 监听：先读掉 PROXY 头 (v1 文本 / v2 二进制，自动识别)，
+//   再把剩下的字节原样转给同一进程里只监听 127.0.0.1 的 Pingora 端口 (TLS 也在那里终止)。
+//   中继跑在独立的 Runtime 上 (runtime::build_relay_runtime)，不占用后台 Runtime。
+// - 中继到内部端口的连接 (本地地址, 内部地址) -> 真实客户端地址登记在 ClientAddrs 里，连接结束时删除。
+//   按完整的连接四元组查表：本机其他进程从同一个本地端口连别的 Listener 也查不到。
+// - request_filter 总是先删掉客户端自己带的 X-Real-IP，查到真实地址时再用它作为 client.ip
+//   (灰度分桶、金丝雀名单等) 并写入 X-Real-IP 转发给上游 (见 forward_real_ip)。
+//
+// 没有 PROXY 头 (或格式错误、HEADER_TIMEOUT 内没有发完) 的连接直接断开：开启了 proxy_protocol 的端口
+// 只应该接负载均衡的流量，否则任何人都能伪造客户端地址。v1 的 UNKNOWN 和 v2 的 LOCAL 命令
+// (负载均衡自己的健康检查) 照常转发，没有真实地址。
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// v2 的 12 字节签名；前 6 字节和 v1 的 "PROXY " 不同，读 6 个字节就能区分两个版本
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+// v1 头最长 107 字节 (含 "PROXY " 和结尾的 CRLF)
+const V1_MAX_LEN: usize = 107;
+
+// 中继连接 (本地地址, 内部地址) -> PROXY 头里的客户端地址
+#[derive(Default)]
+pub struct ClientAddrs {
+    by_conn: Mutex<HashMap<(SocketAddr, SocketAddr), SocketAddr>>,
+}
+
+impl ClientAddrs {
+    // peer / server 是 Pingora 看到的连接两端；不是来自本机的连接 (普通 Listener) 不查表
+    pub fn lookup(&self, peer: SocketAddr, server: SocketAddr) -> Option<SocketAddr> {
+        if !peer.ip().is_loopback() {
+            return None;
+        }
+        self.by_conn.lock().unwrap().get(&(peer, server)).copied()
+    }
+
+    fn register(&self, conn: (SocketAddr, SocketAddr), client: SocketAddr) {
+        self.by_conn.lock().unwrap().insert(conn, client);
+    }
+
+    fn unregister(&self, conn: (SocketAddr, SocketAddr)) {
+        self.by_conn.lock().unwrap().remove(&conn);
+    }
+}
+
+// 客户端自己带的 X-Real-IP 不可信，一律删掉；只有 PROXY 头给出的地址才写回去
+pub fn forward_real_ip(
+    header: &mut RequestHeader,
+    real: Option<SocketAddr>,
+) -> pingora::Result<()> {
+    header.remove_header("X-Real-IP");
+    if let Some(real) = real {
+        header.insert_header("X-Real-IP", real.ip().to_string())?;
+    }
+    Ok(())
+}
+
+// 给 Pingora 的内部端口占一个本机空闲端口。返回的 socket 只绑定、不监听，调用方要一直持有它：
+// 它和 Pingora 的监听 socket 都开了 SO_REUSEPORT，端口在 Pingora 绑定之前不会被别的进程抢走
+// (其他用户的进程不能加入同一个 SO_REUSEPORT 组)，而连接只会分给正在监听的 Pingora。
+pub fn reserve_loopback_port() -> std::io::Result<TcpSocket> {
+    let socket = TcpSocket::new_v4()?;
+    socket.set_reuseport(true)?;
+    socket.bind((Ipv4Addr::LOCALHOST, 0).into())?;
+    Ok(socket)
+}
+
+// 在 public 上接受连接，读掉 PROXY 头后转发给 internal (Pingora 的内部端口)
+pub async fn relay(public: String, internal: SocketAddr, addrs: Arc<ClientAddrs>) {
+    let listener = match TcpListener::bind(&public).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("PROXY protocol listener {}: bind failed: {}", public, e);
+            return;
+        }
+    };
+    loop {
+        let (client, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // 文件描述符耗尽之类的错误：稍等再接，避免空转
+                eprintln!("PROXY protocol listener {}: accept failed: {}", public, e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let addrs = addrs.clone();
+        tokio::spawn(async move {
+            if let Err(e) = relay_connection(client, internal, &addrs).await {
+                eprintln!("PROXY protocol connection from {} rejected: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn relay_connection(
+    mut client: TcpStream,
+    internal: SocketAddr,
+    addrs: &ClientAddrs,
+) -> Result<(), String> {
+    let source = tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut client))
+        .await
+        .map_err(|_| "timed out waiting for the PROXY header".to_string())??;
+    let mut upstream = TcpStream::connect(internal)
+        .await
+        .map_err(|e| format!("connect {}: {}", internal, e))?;
+    let conn = (upstream.local_addr().map_err(|e| e.to_string())?, internal);
+    // 先登记再转发字节：Pingora 收到第一个请求时一定查得到
+    if let Some(source) = source {
+        addrs.register(conn, source);
+    }
+    // 任意一端关闭或出错时结束，连接重置之类的错误是正常现象，不打日志
+    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+    addrs.unregister(conn);
+    Ok(())
+}
+
+// 读掉连接开头的 PROXY 头，返回其中的客户端地址；None = 头里没有地址 (UNKNOWN / LOCAL)
+pub async fn read_header<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<Option<SocketAddr>, String> {
+    let mut start = [0u8; 6];
+    stream
+        .read_exact(&mut start)
+        .await
+        .map_err(|e| e.to_string())?;
+    if &start == b"PROXY " {
+        read_v1(stream).await
+    } else if start == V2_SIGNATURE[..6] {
+        read_v2(stream).await
+    } else {
+        Err("connection does not start with a PROXY protocol header".to_string())
+    }
+}
+
+async fn read_v1<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>, String> {
+    // 逐字节读到 CRLF：多读一个字节就吃掉了后面的 HTTP 请求
+    let mut line = Vec::with_capacity(V1_MAX_LEN);
+    while !line.ends_with(b"\r\n") {
+        if line.len() + 6 >= V1_MAX_LEN {
+            return Err("PROXY v1 header is too long".to_string());
+        }
+        line.push(stream.read_u8().await.map_err(|e| e.to_string())?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| "PROXY v1 header is not ASCII".to_string())?;
+    parse_v1(line)
+}
+
+// "TCP4 192.0.2.1 198.51.100.1 56324 443" (开头的 "PROXY " 已经读掉)
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>, String> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["UNKNOWN", ..] => Ok(None),
+        [proto @ ("TCP4" | "TCP6"), source, _, source_port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| format!("invalid source address {:?}", source))?;
+            if ip.is_ipv4() != (*proto == "TCP4") {
+                return Err(format!("{} header carries address {}", proto, ip));
+            }
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| format!("invalid source port {:?}", source_port))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(format!("malformed PROXY v1 header {:?}", line)),
+    }
+}
+
+async fn read_v2<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>, String> {
+    // 签名剩下的 6 字节 + 版本/命令 + 地址族/协议 + 2 字节的地址块长度
+    let mut head = [0u8; 10];
+    stream
+        .read_exact(&mut head)
+        .await
+        .map_err(|e| e.to_string())?;
+    if head[..6] != V2_SIGNATURE[6..] {
+        return Err("invalid PROXY v2 signature".to_string());
+    }
+    let version_command = head[6];
+    if version_command >> 4 != 2 {
+        return Err(format!(
+            "unsupported PROXY protocol version {}",
+            version_command >> 4
+        ));
+    }
+    let len = u16::from_be_bytes([head[8], head[9]]) as usize;
+    let mut block = vec![0u8; len];
+    stream
+        .read_exact(&mut block)
+        .await
+        .map_err(|e| e.to_string())?;
+    match version_command & 0x0f {
+        // LOCAL：负载均衡自己发起的连接 (健康检查)
+        0x0 => Ok(None),
+        0x1 => parse_v2_addresses(head[7], &block),
+        command => Err(format!("unsupported PROXY v2 command {:#x}", command)),
+    }
+}
+
+// 地址块：IPv4 为源 / 目的地址各 4 字节 + 源 / 目的端口各 2 字节，IPv6 为 16 + 16 + 2 + 2 字节，
+// 之后可能跟着 TLV 扩展 (忽略)。UNIX / UNSPEC 地址族没有可用的客户端地址
+fn parse_v2_addresses(family: u8, block: &[u8]) -> Result<Option<SocketAddr>, String> {
+    match family >> 4 {
+        0x1 if block.len() >= 12 => {
+            let ip = Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            let port = u16::from_be_bytes([block[8], block[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        0x2 if block.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&block[..16]);
+            let port = u16::from_be_bytes([block[32], block[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        0x1 | 0x2 => Err("PROXY v2 address block is too short".to_string()),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+
+    // 起一个中继，internal 端用普通的 TcpListener 代替 Pingora
+    async fn start_relay() -> (SocketAddr, TcpListener, Arc<ClientAddrs>) {
+        let internal = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let public = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let addrs = Arc::new(ClientAddrs::default());
+        tokio::spawn(relay(
+            public.to_string(),
+            internal.local_addr().unwrap(),
+            addrs.clone(),
+        ));
+        // 等中继开始监听 (探测连接没有 PROXY 头，中继直接断开，不会转到 internal)
+        for _ in 0..50 {
+            if TcpStream::connect(public).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        (public, internal, addrs)
+    }
+
+    // 把 internal 端收到的请求当成 Pingora 处理：按连接两端查表，再改写请求头
+    async fn forwarded_real_ip(
+        internal: &TcpListener,
+        addrs: &ClientAddrs,
+        spoofed: &str,
+    ) -> (Option<String>, Vec<u8>) {
+        let (mut conn, peer) = internal.accept().await.unwrap();
+        let server = conn.local_addr().unwrap();
+        let mut request = vec![0u8; REQUEST.len()];
+        conn.read_exact(&mut request).await.unwrap();
+
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        header.insert_header("X-Real-IP", spoofed).unwrap();
+        forward_real_ip(&mut header, addrs.lookup(peer, server)).unwrap();
+        let real_ip = header
+            .headers
+            .get("X-Real-IP")
+            .map(|v| v.to_str().unwrap().to_string());
+        (real_ip, request)
+    }
+
+    #[tokio::test]
+    async fn v1_header_sets_x_real_ip() {
+        let (public, internal, addrs) = start_relay().await;
+        let mut client = TcpStream::connect(public).await.unwrap();
+        client
+            .write_all(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n")
+            .await
+            .unwrap();
+        client.write_all(REQUEST).await.unwrap();
+
+        let (real_ip, request) = forwarded_real_ip(&internal, &addrs, "10.6.6.6").await;
+        assert_eq!(real_ip.as_deref(), Some("192.0.2.1"));
+        // PROXY 头被读掉，上游只看到 HTTP 请求
+        assert_eq!(request, REQUEST);
+    }
+
+    #[tokio::test]
+    async fn unknown_header_drops_client_x_real_ip() {
+        let (public, internal, addrs) = start_relay().await;
+        let mut client = TcpStream::connect(public).await.unwrap();
+        client
+            .write_all(b"PROXY UNKNOWN\r\n")
+            .await
+            .unwrap();
+        client.write_all(REQUEST).await.unwrap();
+
+        let (real_ip, _) = forwarded_real_ip(&internal, &addrs, "10.6.6.6").await;
+        assert_eq!(real_ip, None);
+    }
+
+    #[test]
+    fn lookup_requires_the_relay_connection() {
+        let addrs = ClientAddrs::default();
+        let relay_side: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let internal: SocketAddr = "127.0.0.1:40001".parse().unwrap();
+        let client: SocketAddr = "192.0.2.1:56324".parse().unwrap();
+        addrs.register((relay_side, internal), client);
+
+        assert_eq!(addrs.lookup(relay_side, internal), Some(client));
+        // 同一个本地端口连到别的 Listener (例如普通的 6188) 查不到
+        let other: SocketAddr = "127.0.0.1:6188".parse().unwrap();
+        assert_eq!(addrs.lookup(relay_side, other), None);
+
+        addrs.unregister((relay_side, internal));
+        assert_eq!(addrs.lookup(relay_side, internal), None);
+    }
+
+    #[test]
+    fn forward_real_ip_removes_spoofed_header() {
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        header.insert_header("X-Real-IP", "10.6.6.6").unwrap();
+        forward_real_ip(&mut header, None).unwrap();
+        assert!(header.headers.get("X-Real-IP").is_none());
+    }
+
+    #[test]
+    fn parses_v1_and_v2_headers() {
+        assert_eq!(
+            parse_v1("TCP6 2001:db8::1 2001:db8::2 1234 443").unwrap(),
+            Some("[2001:db8::1]:1234".parse().unwrap())
+        );
+        assert!(parse_v1("TCP4 2001:db8::1 192.0.2.2 1234 443").is_err());
+
+        let block = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        assert_eq!(
+            parse_v2_addresses(0x11, &block).unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        assert!(parse_v2_addresses(0x11, &block[..8]).is_err());
+    }
+}
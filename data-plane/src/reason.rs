@@ -0,0 +1,241 @@
+use serde::Serialize;
+use std::fmt;
+
+// 【原因码 (Reason Code)】
+// 网关自己生成的每一个响应 (拦截、短路、上游失败) 都必须带且只带一个原因码，
+// 这样复盘时不会再纠结 "这个 403 到底是插件拒绝的，还是别的什么规则"。
+// 原因码是一个封闭枚举：新增拦截逻辑时必须在这里新增一个变体，不能随手写字符串。
+//
+// 出现在：
+// - 网关生成的 JSON 错误体: {"error": {"status": 403, "reason": "PLUGIN_DENY"}}
+// - 访问日志 (RequestOutcome.reason) 与 /recent_requests
+// - /upstreams 的按原因计数 (上游相关的原因码)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReasonCode {
+    // 还没有应用第一份配置
+    WarmingUp,
+    // 没有匹配的路由
+    NoRoute,
+    // 路径命中了路由，但这些路由都不接受请求的方法 (405)
+    MethodNotAllowed,
+    // 路由指向的集群不存在或没有可用节点
+    NoEndpoint,
+    // 集群的熔断处于打开状态 (见 circuit_breaker.rs)
+    CircuitOpen,
+    // 路由配置了 redirect，网关直接返回 3xx
+    RouteRedirect,
+    // 路由配置了 direct_response，网关直接返回配置好的响应
+    DirectResponse,
+    // 插件返回 Deny
+    PluginDeny,
+    // 插件执行出错
+    PluginError,
+    // 安全模式下，路由的 plugin_bypass_policy 为 deny (插件是鉴权层，不能跳过)
+    SafeMode,
+    // 以下为上游方向的错误
+    UpstreamConnectFailed,
+    UpstreamTimeout,
+    // 超过路由的 timeout_ms (请求整体的截止时间)，网关返回 504
+    RouteTimeout,
+    UpstreamInvalidHeader,
+    UpstreamConnectionClosed,
+    UpstreamH2Error,
+    // Content-Length 大于实际 body，连接提前关闭
+    UpstreamPrematureBodyEnd,
+    // chunked 编码损坏
+    UpstreamBadFraming,
+    // 超过路由的 max_response_bytes
+    UpstreamResponseTooLarge,
+    // 超过路由的 max_response_bytes，按 truncate_content_types 截断后断开
+    UpstreamResponseTruncated,
+    // 上游返回 5xx：响应照常转发给客户端，只用于节点健康统计 (不会作为网关响应的原因码)
+    Upstream5xx,
+    UpstreamError,
+    // 客户端请求本身有问题 (无法解析等)
+    ClientError,
+    // 网关内部错误
+    InternalError,
+    // 处理请求的某个阶段 panic 了 (已捕获，见 panics.rs)
+    InternalPanic,
+    // 资源超过硬预算，正在拒绝新请求 (见 watchdog)
+    Overloaded,
+    // 同一个幂等键的请求还在处理中 (409)
+    IdempotencyConflict,
+    // WebSocket 升级请求命中了没有开启 allow_websocket 的路由 (400)
+    WebsocketNotAllowed,
+    // 路由被标记为 disabled (故障处理时临时停用)，返回 disabled_response (默认 503)
+    RouteDisabled,
+    // 请求体超过路由的 max_request_body_bytes (413)
+    RequestBodyTooLarge,
+}
+
+impl ReasonCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasonCode::WarmingUp => "WARMING_UP",
+            ReasonCode::NoRoute => "NO_ROUTE",
+            ReasonCode::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            ReasonCode::NoEndpoint => "NO_ENDPOINT",
+            ReasonCode::CircuitOpen => "CIRCUIT_OPEN",
+            ReasonCode::RouteRedirect => "ROUTE_REDIRECT",
+            ReasonCode::DirectResponse => "DIRECT_RESPONSE",
+            ReasonCode::PluginDeny => "PLUGIN_DENY",
+            ReasonCode::PluginError => "PLUGIN_ERROR",
+            ReasonCode::SafeMode => "SAFE_MODE",
+            ReasonCode::UpstreamConnectFailed => "UPSTREAM_CONNECT_FAILED",
+            ReasonCode::UpstreamTimeout => "UPSTREAM_TIMEOUT",
+            ReasonCode::RouteTimeout => "ROUTE_TIMEOUT",
+            ReasonCode::UpstreamInvalidHeader => "UPSTREAM_INVALID_HEADER",
+            ReasonCode::UpstreamConnectionClosed => "UPSTREAM_CONNECTION_CLOSED",
+            ReasonCode::UpstreamH2Error => "UPSTREAM_H2_ERROR",
+            ReasonCode::UpstreamPrematureBodyEnd => "UPSTREAM_PREMATURE_BODY_END",
+            ReasonCode::UpstreamBadFraming => "UPSTREAM_BAD_FRAMING",
+            ReasonCode::UpstreamResponseTooLarge => "UPSTREAM_RESPONSE_TOO_LARGE",
+            ReasonCode::UpstreamResponseTruncated => "UPSTREAM_RESPONSE_TRUNCATED",
+            ReasonCode::Upstream5xx => "UPSTREAM_5XX",
+            ReasonCode::UpstreamError => "UPSTREAM_ERROR",
+            ReasonCode::ClientError => "CLIENT_ERROR",
+            ReasonCode::InternalError => "INTERNAL_ERROR",
+            ReasonCode::InternalPanic => "INTERNAL_PANIC",
+            ReasonCode::Overloaded => "OVERLOADED",
+            ReasonCode::IdempotencyConflict => "IDEMPOTENCY_CONFLICT",
+            ReasonCode::WebsocketNotAllowed => "WEBSOCKET_NOT_ALLOWED",
+            ReasonCode::RouteDisabled => "ROUTE_DISABLED",
+            ReasonCode::RequestBodyTooLarge => "REQUEST_BODY_TOO_LARGE",
+        }
+    }
+
+    // 网关生成的 JSON 错误体
+    pub fn error_body(&self, status: u16) -> String {
+        serde_json::json!({
+            "error": {
+                "status": status,
+                "reason": self,
+            }
+        })
+        .to_string()
+    }
+}
+
+impl fmt::Display for ReasonCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 生产代码里会产生原因码的文件；新增产生原因码的模块时加到这里
+    const SOURCES: &[(&str, &str)] = &[
+        ("main.rs", include_str!("main.rs")),
+        ("direct.rs", include_str!("direct.rs")),
+        ("outlier.rs", include_str!("outlier.rs")),
+        ("replay.rs", include_str!("replay.rs")),
+    ];
+
+    // 按声明顺序遍历所有变体。match 是穷举的：新增变体不在这里接上就编译不过
+    fn after(code: Option<ReasonCode>) -> Option<ReasonCode> {
+        use ReasonCode::*;
+        match code {
+            None => Some(WarmingUp),
+            Some(WarmingUp) => Some(NoRoute),
+            Some(NoRoute) => Some(MethodNotAllowed),
+            Some(MethodNotAllowed) => Some(NoEndpoint),
+            Some(NoEndpoint) => Some(CircuitOpen),
+            Some(CircuitOpen) => Some(RouteRedirect),
+            Some(RouteRedirect) => Some(DirectResponse),
+            Some(DirectResponse) => Some(PluginDeny),
+            Some(PluginDeny) => Some(PluginError),
+            Some(PluginError) => Some(SafeMode),
+            Some(SafeMode) => Some(UpstreamConnectFailed),
+            Some(UpstreamConnectFailed) => Some(UpstreamTimeout),
+            Some(UpstreamTimeout) => Some(RouteTimeout),
+            Some(RouteTimeout) => Some(UpstreamInvalidHeader),
+            Some(UpstreamInvalidHeader) => Some(UpstreamConnectionClosed),
+            Some(UpstreamConnectionClosed) => Some(UpstreamH2Error),
+            Some(UpstreamH2Error) => Some(UpstreamPrematureBodyEnd),
+            Some(UpstreamPrematureBodyEnd) => Some(UpstreamBadFraming),
+            Some(UpstreamBadFraming) => Some(UpstreamResponseTooLarge),
+            Some(UpstreamResponseTooLarge) => Some(UpstreamResponseTruncated),
+            Some(UpstreamResponseTruncated) => Some(Upstream5xx),
+            Some(Upstream5xx) => Some(UpstreamError),
+            Some(UpstreamError) => Some(ClientError),
+            Some(ClientError) => Some(InternalError),
+            Some(InternalError) => Some(InternalPanic),
+            Some(InternalPanic) => Some(Overloaded),
+            Some(Overloaded) => Some(IdempotencyConflict),
+            Some(IdempotencyConflict) => Some(WebsocketNotAllowed),
+            Some(WebsocketNotAllowed) => Some(RouteDisabled),
+            Some(RouteDisabled) => Some(RequestBodyTooLarge),
+            Some(RequestBodyTooLarge) => None,
+        }
+    }
+
+    fn all() -> Vec<ReasonCode> {
+        std::iter::successors(after(None), |code| after(Some(*code))).collect()
+    }
+
+    // 去掉文件末尾的测试模块，只看生产代码
+    fn production(source: &str) -> &str {
+        source
+            .find("\n#[cfg(test)]\nmod tests {")
+            .map_or(source, |end| &source[..end])
+    }
+
+    // source 里是否有 ReasonCode::{name} (后面不能紧跟标识符字符，Upstream 不算 Upstream5xx)
+    fn produces(source: &str, name: &str) -> bool {
+        let needle = format!("ReasonCode::{}", name);
+        source.match_indices(&needle).any(|(at, _)| {
+            !source[at + needle.len()..]
+                .starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        })
+    }
+
+    #[test]
+    fn every_reason_code_has_a_producing_path() {
+        let codes = all();
+        assert_eq!(codes.len(), 30);
+        let missing: Vec<String> = codes
+            .iter()
+            .map(|code| format!("{:?}", code))
+            .filter(|name| {
+                !SOURCES
+                    .iter()
+                    .any(|(_, source)| produces(production(source), name))
+            })
+            .collect();
+        assert!(
+            missing.is_empty(),
+            "reason codes nothing produces: {:?}",
+            missing
+        );
+    }
+
+    #[test]
+    fn wire_names_are_unique_and_match_serde() {
+        let codes = all();
+        let mut names: Vec<&str> = codes.iter().map(|code| code.as_str()).collect();
+        for code in &codes {
+            assert_eq!(
+                serde_json::to_value(code).unwrap(),
+                serde_json::Value::from(code.as_str())
+            );
+        }
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), codes.len());
+    }
+
+    #[test]
+    fn error_body_carries_status_and_reason() {
+        let body: serde_json::Value =
+            serde_json::from_str(&ReasonCode::PluginDeny.error_body(403)).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"error": {"status": 403, "reason": "PLUGIN_DENY"}})
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use serde::Serialize;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::outcome::RequestOutcome;
+
+// 【最近请求环形缓冲 (Recent Requests)】
+// Grafana 只能看到错误率，看不到 "最近 50 个失败的请求具体是什么"。
+// 这里在内存里保留最近 N 条 RequestOutcome (只有摘要，不含请求/响应体和 Header，路径不含 query string)，
+// 通过管理端口 GET /recent_requests?route=X&status=5xx&limit=50 查询。
+//
+// 写入路径尽量轻：按线程分片，每个 worker 线程固定写自己的分片，几乎不会产生锁竞争；
+// 读取时把所有分片合并，按写入顺序倒序返回。
+// AGW_RECENT_REQUESTS_SIZE=0 时完全关闭 (隐私敏感的部署)，logging 阶段不做任何记录。
+pub struct RecentRequests {
+    shards: Vec<Mutex<VecDeque<RecentRequest>>>,
+    per_shard: usize,
+    seq: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentRequest {
+    // 全局递增序号，用于合并分片后排序
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub outcome: RequestOutcome,
+}
+
+// 查询条件
+#[derive(Debug, Default)]
+pub struct RecentQuery {
+    pub route: Option<String>,
+    // "5xx" 这样的状态码段，或者 "503" 这样的精确状态码
+    pub status: Option<String>,
+    pub limit: usize,
+}
+
+const DEFAULT_LIMIT: usize = 50;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // 每个线程第一次写入时分配一个分片号
+    static SHARD: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+impl RecentRequests {
+    // capacity: 总条数上限 (0 = 关闭)；shards: 分片数，一般等于 worker 线程数
+    pub fn new(capacity: usize, shards: usize) -> Self {
+        if capacity == 0 {
+            return Self {
+                shards: Vec::new(),
+                per_shard: 0,
+                seq: AtomicU64::new(0),
+            };
+        }
+        let shards = shards.clamp(1, capacity);
+        Self {
+            shards: (0..shards).map(|_| Mutex::new(VecDeque::new())).collect(),
+            per_shard: capacity.div_ceil(shards),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    pub fn from_env(shards: usize) -> Self {
+        let capacity = std::env::var("AGW_RECENT_REQUESTS_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1024);
+        Self::new(capacity, shards)
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.shards.is_empty()
+    }
+
+    pub fn record(&self, outcome: &RequestOutcome) {
+        if !self.enabled() {
+            return;
+        }
+        let shard = SHARD.with(|s| {
+            let idx = s
+                .get()
+                .unwrap_or_else(|| NEXT_SHARD.fetch_add(1, Ordering::Relaxed));
+            s.set(Some(idx));
+            idx % self.shards.len()
+        });
+        let entry = RecentRequest {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            outcome: outcome.clone(),
+        };
+        let mut buf = self.shards[shard].lock().unwrap();
+        if buf.len() >= self.per_shard {
+            buf.pop_front();
+        }
+        buf.push_back(entry);
+    }
+
+    // 合并所有分片，按时间倒序 (最新的在前) 返回满足条件的记录
+    pub fn query(&self, q: &RecentQuery) -> Vec<RecentRequest> {
+        let mut all: Vec<RecentRequest> = Vec::new();
+        for shard in &self.shards {
+            let buf = shard.lock().unwrap();
+            all.extend(buf.iter().filter(|r| q.matches(&r.outcome)).cloned());
+        }
+        all.sort_by(|a, b| b.seq.cmp(&a.seq));
+        all.truncate(if q.limit == 0 { DEFAULT_LIMIT } else { q.limit });
+        all
+    }
+}
+
+impl RecentQuery {
+    // 解析 "route=/api&status=5xx&limit=20"
+    pub fn parse(query: Option<&str>) -> Self {
+        let mut q = Self::default();
+        for pair in query.unwrap_or("").split('&') {
+            let Some((k, v)) = pair.split_once('=') else { continue };
+            match k {
+                "route" => q.route = Some(v.to_string()),
+                "status" => q.status = Some(v.to_ascii_lowercase()),
+                "limit" => q.limit = v.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+        q
+    }
+
+    fn matches(&self, outcome: &RequestOutcome) -> bool {
+        if let Some(route) = &self.route {
+            if outcome.route.as_deref() != Some(route.as_str()) {
+                return false;
+            }
+        }
+        if let Some(status) = &self.status {
+            let actual = outcome.status.to_string();
+            let ok = match status.strip_suffix("xx") {
+                Some(class) => actual.starts_with(class),
+                None => &actual == status,
+            };
+            if !ok {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,115 @@
+use crate::client::agw::config::v1::{RedirectAction, TrailingSlashPolicy};
+use crate::rewrite::PathRewrite;
+
+// 【路由重定向 (Redirect)】
+// 路由配置了 redirect 时，网关在 request_filter 里直接返回 3xx + Location，不经过任何上游。
+// 典型场景：http://old.example.com/foo -> https://new.example.com/foo。
+//
+// 路由的插件链先执行 (鉴权插件可以拦截重定向)，之后不再做幂等、缓存和转发。
+// Location 的各部分没有配置时沿用请求自己的：scheme 按请求是否走 TLS 判断，host 取请求的 Host，
+// path 为请求路径 (路由配置了 strip_prefix / rewrite_prefix 时先按它们改写)；
+// 查询串只在 preserve_query 时保留。
+#[derive(Debug)]
+pub struct CompiledRedirect {
+    pub status: u16,
+    scheme: Option<String>,
+    host: Option<String>,
+    path: Option<String>,
+    preserve_query: bool,
+}
+
+impl CompiledRedirect {
+    pub fn compile(action: &RedirectAction) -> Result<Self, String> {
+        let status = match action.status_code {
+            0 => 301,
+            code @ (301 | 302 | 303 | 307 | 308) => code as u16,
+            code => return Err(format!("status_code {} is not a redirect status", code)),
+        };
+        let scheme = match action.scheme.to_ascii_lowercase().as_str() {
+            "" => None,
+            scheme @ ("http" | "https") => Some(scheme.to_string()),
+            _ => return Err(format!("scheme {:?} must be http or https", action.scheme)),
+        };
+        if !action.host.is_empty()
+            && (action.host.contains('@') || action.host.parse::<http::uri::Authority>().is_err())
+        {
+            return Err(format!("invalid host {:?}", action.host));
+        }
+        if !action.path.is_empty()
+            && (!action.path.starts_with('/')
+                || action.path.contains(['?', '#'])
+                || http::uri::PathAndQuery::try_from(action.path.as_str()).is_err())
+        {
+            return Err(format!(
+                "path {:?} must be a path starting with \"/\"",
+                action.path
+            ));
+        }
+        Ok(Self {
+            status,
+            scheme,
+            host: Some(action.host.clone()).filter(|h| !h.is_empty()),
+            path: Some(action.path.clone()).filter(|p| !p.is_empty()),
+            preserve_query: action.preserve_query,
+        })
+    }
+
+    // 请求不知道自己的 Host 时 (且配置里也没有) 只能给出相对地址 (只有 path 和 query)
+    pub fn location(
+        &self,
+        tls: bool,
+        host: Option<&str>,
+        path: &str,
+        query: Option<&str>,
+        rewrite: Option<&PathRewrite>,
+    ) -> String {
+        let mut location = String::new();
+        if let Some(host) = self.host.as_deref().or(host) {
+            let scheme = self
+                .scheme
+                .as_deref()
+                .unwrap_or(if tls { "https" } else { "http" });
+            location.push_str(scheme);
+            location.push_str("://");
+            location.push_str(host);
+        }
+        match (&self.path, rewrite) {
+            (Some(path), _) => location.push_str(path),
+            (None, Some(rewrite)) => location.push_str(&rewrite.apply(path)),
+            (None, None) => location.push_str(path),
+        }
+        if let Some(query) = query.filter(|q| self.preserve_query && !q.is_empty()) {
+            location.push('?');
+            location.push_str(query);
+        }
+        location
+    }
+}
+
+// 【末尾斜杠规范化 (trailing_slash)】
+// 上游把 "/docs" 和 "/docs/" 当成两个不同的资源时，路由可以要求统一成一种写法，不符合的请求直接 301：
+// - ADD: "/docs" -> "/docs/"
+// - REMOVE: "/docs/" -> "/docs"
+// - IGNORE (默认): 原样转发
+// 只对 GET / HEAD 生效，其余方法原样转发 (客户端跟随 301 时会把 POST 改成 GET、丢掉请求体)；
+// 根路径 "/" 从不重定向。在插件链之前返回，插件只会看到规范写法的请求。
+// 返回规范写法的路径，调用方拼上原始查询串作为 Location；已经是规范写法时返回 None。
+pub fn canonical_trailing_slash(
+    policy: TrailingSlashPolicy,
+    method: &str,
+    path: &str,
+) -> Option<String> {
+    if path == "/" || !(method == "GET" || method == "HEAD") {
+        return None;
+    }
+    match policy {
+        TrailingSlashPolicy::TrailingSlashAdd if !path.ends_with('/') => Some(format!("{}/", path)),
+        TrailingSlashPolicy::TrailingSlashRemove if path.ends_with('/') => {
+            match path.trim_end_matches('/') {
+                "" => Some("/".to_string()),
+                trimmed => Some(trimmed.to_string()),
+            }
+        }
+        _ => None,
+    }
+}
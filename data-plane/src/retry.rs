@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use crate::client::agw::config::v1::RetryPolicy;
+
+// 【重试 (Retry Policy)】
+// 上游连接失败、超时、连接被重置，或者返回 retry_on 里的状态码时，换一个节点再试，最多 max_retries 次。
+// 重试本身由 Pingora 驱动：错误带上 retry 标记后 Pingora 会重新调用 upstream_peer，
+// 负载均衡因此自然选到另一个节点 (被动健康检查同时把失败的节点记下来)。
+//
+// - 只重试 GET / HEAD，路由设置 retry_non_idempotent 后才重试 POST 等方法；
+//   带请求体的请求由 Pingora 的重试缓冲保存请求体，超过缓冲上限的不再重试。
+// - 响应头已经发给客户端之后不再重试。
+// - 每次重试之前按指数退避等待：base * 2^(n-1)，上限 max，实际等待取 [一半, 全部] 之间的随机值，
+//   避免大量请求在同一时刻一起打到剩下的节点上。
+#[derive(Debug)]
+pub struct CompiledRetry {
+    max_retries: u32,
+    retry_on: Vec<u16>,
+    pub per_try_timeout: Option<Duration>,
+    non_idempotent: bool,
+    base: Duration,
+    max: Duration,
+}
+
+// 状态码命中 retry_on 时 response_filter 返回的错误 (带 retry 标记，Pingora 据此重新选节点)
+pub const RETRYABLE_STATUS: pingora::ErrorType = pingora::ErrorType::new("UpstreamRetryableStatus");
+
+// 单个请求的重试次数上限 (Pingora 自身最多尝试 16 次)
+const MAX_RETRIES: u32 = 10;
+
+impl CompiledRetry {
+    // max_retries 为 0 时返回 None
+    pub fn compile(policy: &RetryPolicy) -> Result<Option<Self>, String> {
+        if policy.max_retries == 0 {
+            return Ok(None);
+        }
+        if policy.max_retries > MAX_RETRIES {
+            return Err(format!(
+                "max_retries {} exceeds the limit of {}",
+                policy.max_retries, MAX_RETRIES
+            ));
+        }
+        let mut retry_on = Vec::with_capacity(policy.retry_on.len());
+        for &status in &policy.retry_on {
+            if !(400..=599).contains(&status) {
+                return Err(format!(
+                    "retry_on status {} is not a 4xx / 5xx code",
+                    status
+                ));
+            }
+            retry_on.push(status as u16);
+        }
+        let base = Duration::from_millis(match policy.base_interval_ms {
+            0 => 25,
+            ms => ms,
+        });
+        let max = Duration::from_millis(match policy.max_interval_ms {
+            0 => 250,
+            ms => ms,
+        });
+        Ok(Some(Self {
+            max_retries: policy.max_retries,
+            retry_on,
+            per_try_timeout: match policy.per_try_timeout_ms {
+                0 => None,
+                ms => Some(Duration::from_millis(ms)),
+            },
+            non_idempotent: policy.retry_non_idempotent,
+            base,
+            max: max.max(base),
+        }))
+    }
+
+    // 这个请求还能不能再试一次 (retries = 已经重试过的次数)
+    pub fn allows(&self, method: &http::Method, retries: u32) -> bool {
+        retries < self.max_retries && self.retries_method(method)
+    }
+
+    pub fn retries_method(&self, method: &http::Method) -> bool {
+        self.non_idempotent || *method == http::Method::GET || *method == http::Method::HEAD
+    }
+
+    pub fn retries_status(&self, status: u16) -> bool {
+        self.retry_on.contains(&status)
+    }
+
+    // 第 n 次重试 (从 1 开始) 之前的等待时间
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .base
+            .saturating_mul(1u32 << retry.saturating_sub(1).min(16))
+            .min(self.max);
+        let half = exp / 2;
+        half + half.mul_f64(rand::random::<f64>())
+    }
+}
@@ -0,0 +1,65 @@
+use crate::client::agw::config::v1::Route;
+use crate::matcher::CompiledMatch;
+
+// 【路径改写 (Path Rewrite)】
+// 上游服务自己的路径往往不带网关上的路由前缀：客户端访问 "/backend-a/v1/users"，服务只认 "/v1/users"。
+// 路由上可以配置：
+// - strip_prefix: 去掉命中的路由前缀 (只对前缀路由有效)
+// - rewrite_prefix: 在 (去掉前缀后的) 路径前面加上新的前缀
+// 两者可以一起用："/backend-a" + strip + rewrite "/internal"，"/backend-a/v1/users" -> "/internal/v1/users"。
+//
+// 路由匹配、访问日志的 path 始终用客户端的原始路径；改写只作用于转发给上游的请求 (upstream_request_filter)，
+// 改写后的路径记在访问日志的 upstream_path。查询串原样保留。
+#[derive(Debug)]
+pub struct PathRewrite {
+    // 去掉的前缀字节数 (0 = 不去掉)
+    strip: usize,
+    // 去掉结尾 "/" 的新前缀，拼接时由剩余路径提供 "/"
+    prefix: String,
+}
+
+impl PathRewrite {
+    // 路由没有配置改写时返回 None
+    pub fn compile(route: &Route, path: &CompiledMatch) -> Result<Option<Self>, String> {
+        if !route.strip_prefix && route.rewrite_prefix.is_empty() {
+            return Ok(None);
+        }
+        let strip = if route.strip_prefix {
+            match path.indexable_prefix() {
+                Some((prefix, _)) => prefix.len(),
+                None => return Err("strip_prefix requires a prefix path match".to_string()),
+            }
+        } else {
+            0
+        };
+        if !route.rewrite_prefix.is_empty()
+            && (!route.rewrite_prefix.starts_with('/')
+                || route.rewrite_prefix.contains(['?', '#'])
+                || http::uri::PathAndQuery::try_from(route.rewrite_prefix.as_str()).is_err())
+        {
+            return Err(format!(
+                "rewrite_prefix {:?} must be a path starting with \"/\"",
+                route.rewrite_prefix
+            ));
+        }
+        Ok(Some(Self {
+            strip,
+            prefix: route.rewrite_prefix.trim_end_matches('/').to_string(),
+        }))
+    }
+
+    // "/backend-a/v1/users" -> "/v1/users"；去掉前缀后为空时得到 "/" (或者新前缀本身)
+    pub fn apply(&self, path: &str) -> String {
+        let rest = path.get(self.strip..).unwrap_or(path);
+        let mut rewritten = self.prefix.clone();
+        if !rest.is_empty() && !rest.starts_with('/') {
+            // 前缀带结尾 "/" 时 ("/api/" 命中 "/api/x")，剩下的 "x" 需要补回分隔符
+            rewritten.push('/');
+        }
+        rewritten.push_str(rest);
+        if rewritten.is_empty() {
+            rewritten.push('/');
+        }
+        rewritten
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::{Duration, SystemTime};
+
+use crate::client::agw::config::v1::Route;
+
+// 【路由灰度发布调度 (Rollout Scheduling)】
+// 路由可以声明两种 "按时间生效" 的方式，都由数据面对照本地时钟计算，切换时刻不需要 CP 推送：
+// 1. effective_at: 在该时刻之前，这条路由完全不参与匹配 (请求会落到后面的路由，即 "旧行为" 或兜底路由)。
+// 2. ramp { start, duration }: 从 start 开始，在 duration 内把流量比例从 0% 线性提升到 100%。
+//    一个请求是否落在新路由上，由客户端地址的哈希值决定 (同一个客户端在同一时刻结果稳定)。
+//
+// 关于时钟与重启：
+// - 比例只取决于 "当前时间" 和 "请求哈希"，是一个纯函数。网关在灰度中途重启后会直接回到同一个比例，
+//   不需要持久化任何状态。
+// - 各网关之间的时钟偏差会让切换时刻相差同样的时间 (NTP 同步下通常是毫秒级)，
+//   在 ramp 过程中体现为比例上的微小差异，不影响正确性。
+const BUCKETS: u64 = 10_000;
+
+// 计算路由在 now 时刻应承接的流量比例 (0.0 ~ 1.0)
+pub fn effective_fraction(route: &Route, now: SystemTime) -> f64 {
+    if let Some(at) = route.effective_at.as_ref().and_then(to_system_time) {
+        if now < at {
+            return 0.0;
+        }
+    }
+    let Some(ramp) = &route.ramp else {
+        return 1.0;
+    };
+    let Some(start) = ramp.start.as_ref().and_then(to_system_time) else {
+        return 1.0;
+    };
+    let duration = ramp
+        .duration
+        .clone()
+        .and_then(|d| Duration::try_from(d).ok())
+        .unwrap_or_default();
+    match now.duration_since(start) {
+        // 还没到 ramp 开始时间
+        Err(_) => 0.0,
+        Ok(elapsed) if duration.is_zero() || elapsed >= duration => 1.0,
+        Ok(elapsed) => elapsed.as_secs_f64() / duration.as_secs_f64(),
+    }
+}
+
+// 路由是否有任何时间相关的调度配置 (用于决定要不要在日志里输出比例)
+pub fn is_scheduled(route: &Route) -> bool {
+    route.effective_at.is_some() || route.ramp.is_some()
+}
+
+// 判断某个请求 (由 key 标识) 在当前比例下是否应该命中这条路由
+pub fn captures(fraction: f64, key: &[u8]) -> bool {
+    if fraction >= 1.0 {
+        return true;
+    }
+    if fraction <= 0.0 {
+        return false;
+    }
+    fnv1a(key) % BUCKETS < (fraction * BUCKETS as f64) as u64
+}
+
+fn to_system_time(ts: &prost_types::Timestamp) -> Option<SystemTime> {
+    SystemTime::try_from(ts.clone()).ok()
+}
+
+// FNV-1a：实现简单且跨版本稳定，保证所有网关对同一个 key 算出相同的桶
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in data {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 一万个不同的客户端地址
+    fn keys() -> Vec<String> {
+        (0..10_000)
+            .map(|i| format!("10.{}.{}.{}", i / 65_536, i / 256 % 256, i % 256))
+            .collect()
+    }
+
+    fn captured(fraction: f64) -> Vec<bool> {
+        keys()
+            .iter()
+            .map(|key| captures(fraction, key.as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn captured_share_follows_the_fraction() {
+        for fraction in [0.05, 0.3, 0.5, 0.9] {
+            let share = captured(fraction).iter().filter(|&&c| c).count() as f64 / 10_000.0;
+            assert!(
+                (share - fraction).abs() <= 0.02,
+                "fraction {}: share {}",
+                fraction,
+                share
+            );
+        }
+        assert!(captured(0.0).iter().all(|&c| !c));
+        assert!(captured(1.0).iter().all(|&c| c));
+    }
+
+    // 比例上升时已经切过去的客户端不会再切回来
+    #[test]
+    fn raising_the_fraction_keeps_captured_clients() {
+        let before = captured(0.3);
+        let after = captured(0.6);
+        assert!(before.iter().zip(&after).all(|(&b, &a)| !b || a));
+    }
+}
@@ -0,0 +1,1643 @@
+use crate::canary::CompiledCanary;
+use crate::client::agw::config::v1::{
+    PathMatchType, Plugin, PluginBypassPolicy, Protocol, Route, StringMatch,
+};
+use crate::client::agw::v1::{ConfigError, ConfigErrorCode, ConfigSnapshot};
+use crate::direct::{CompiledDirectResponse, ErrorPages};
+use crate::exclusion::CompiledExclusion;
+use crate::grpc;
+use crate::idempotency::CompiledIdempotency;
+use crate::matcher::{
+    self, CompiledMatch, HeaderMatcher, HostMatch, HostMatcher, QueryParamMatcher,
+};
+use crate::redirect::CompiledRedirect;
+use crate::retry::CompiledRetry;
+use crate::rewrite::PathRewrite;
+use crate::rollout;
+use crate::selector::ClusterSelector;
+use crate::split::CompiledSplit;
+use crate::transform::CompiledHeaderTransform;
+use crate::upstream;
+use crate::validate::config_error;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+// 【已编译的配置 (ActiveConfig)】
+// Control Plane 推过来的是原始的 proto 快照；在应用之前，我们先把其中需要 "编译" 的部分
+// (路径匹配器、正则等) 一次性处理好。编译失败 = 快照校验失败，整份快照被拒绝。
+// 校验不会在第一个错误处停下，而是收集全部错误 (ConfigError) 一起返回。
+// 请求路径上只读这份已编译的结构，不做任何解析或编译。
+pub struct ActiveConfig {
+    pub snapshot: ConfigSnapshot,
+    pub routes: Vec<CompiledRoute>,
+    // 兜底路由 (default_route) 在 routes 里的下标；它排在最后，不进路由索引，只在没有路由命中时使用
+    pub default_route: Option<usize>,
+    // 没有兜底路由时 404 的响应 (not_found_response)；None = JSON 错误体
+    pub not_found: Option<CompiledDirectResponse>,
+    // 网关生成的错误响应模板 (error_responses)；请求开始时拿一份引用放进 CTX
+    pub error_pages: Arc<ErrorPages>,
+    // 路由索引，和 routes 一起编译、一起替换，不会出现索引和路由表来自不同快照的情况
+    index: RouteIndex,
+}
+
+pub struct CompiledRoute {
+    pub route: Route,
+    // 大写的方法名；为空表示任意方法
+    pub methods: Vec<String>,
+    pub path: CompiledMatch,
+    pub hosts: HostMatcher,
+    // 请求头条件，全部满足才算命中
+    pub headers: Vec<HeaderMatcher>,
+    // 查询参数条件，全部满足才算命中
+    pub query_params: Vec<QueryParamMatcher>,
+    pub exclusions: Vec<CompiledExclusion>,
+    pub canary: Option<CompiledCanary>,
+    pub idempotency: Option<CompiledIdempotency>,
+    // 转发给上游前的路径改写 (strip_prefix / rewrite_prefix)
+    pub rewrite: Option<PathRewrite>,
+    // 按请求计算集群名的表达式
+    pub cluster_selector: Option<ClusterSelector>,
+    // 上游失败时的重试策略
+    pub retry: Option<CompiledRetry>,
+    // 设置后直接返回重定向，不转发给上游
+    pub redirect: Option<CompiledRedirect>,
+    // 设置后直接返回配置好的响应，不转发给上游
+    pub direct_response: Option<CompiledDirectResponse>,
+    // 路由被停用 (disabled) 时返回的响应，见 direct.rs
+    pub disabled: Option<CompiledDirectResponse>,
+    // 设置后按权重在多个集群之间分流，代替 cluster_id
+    pub split: Option<CompiledSplit>,
+    // 转发前的请求头改写 (request_headers)
+    pub request_headers: Option<CompiledHeaderTransform>,
+    // 上游响应头改写 (response_headers)
+    pub response_headers: Option<CompiledHeaderTransform>,
+    // 实际执行的插件链：全局插件 (去掉 skip_global_plugins 里的) 在前，路由自己的插件在后
+    plugin_chain: Vec<Plugin>,
+}
+
+impl ActiveConfig {
+    pub fn compile(snapshot: ConfigSnapshot) -> Result<Self, Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        let cluster_exists = |name: &str| snapshot.clusters.iter().any(|c| c.name == name);
+
+        for (i, listener) in snapshot.listeners.iter().enumerate() {
+            if let Some(tls) = &listener.tls {
+                if !is_pem(&tls.cert_pem) || !is_pem(&tls.key_pem) {
+                    errors.push(config_error(
+                        ConfigErrorCode::BadTlsMaterial,
+                        format!("listeners[{}].tls", i),
+                        format!("listener {:?}: certificate or key is missing or not PEM", listener.name),
+                    ));
+                }
+            }
+        }
+        for (i, cluster) in snapshot.clusters.iter().enumerate() {
+            if !upstream::valid_host_rewrite(&cluster.host_rewrite) {
+                errors.push(config_error(
+                    ConfigErrorCode::InvalidHostRewrite,
+                    format!("clusters[{}].host_rewrite", i),
+                    format!("invalid host {:?}", cluster.host_rewrite),
+                ));
+            }
+        }
+
+        let global_plugins = sorted_plugins(snapshot.global_plugins.iter());
+        // 兜底路由和普通路由走同一套编译和校验，错误路径写作 "default_route.xxx"
+        let mut routes = Vec::with_capacity(snapshot.routes.len() + 1);
+        let default_at = snapshot
+            .default_route
+            .is_some()
+            .then_some(snapshot.routes.len());
+        let all_routes = snapshot.routes.iter().chain(&snapshot.default_route);
+        for (i, route) in all_routes.enumerate() {
+            let at = if Some(i) == default_at {
+                DEFAULT_ROUTE_NAME.to_string()
+            } else {
+                format!("routes[{}]", i)
+            };
+            if Some(i) == default_at && has_match_conditions(route) {
+                errors.push(config_error(
+                    ConfigErrorCode::InvalidDefaultRoute,
+                    "default_route",
+                    "default_route matches every request left over; path, hosts, methods, headers and query_params must be empty",
+                ));
+                continue;
+            }
+            // 重定向 / 固定响应路由不转发，不需要集群；分流路由的集群在下面逐个检查
+            let answered = route.redirect.is_some()
+                || route.direct_response.is_some()
+                || !route.weighted_clusters.is_empty();
+            if !answered && !cluster_exists(&route.cluster_id) {
+                errors.push(config_error(
+                    ConfigErrorCode::UnknownClusterRef,
+                    format!("{}.cluster_id", at),
+                    format!("unknown cluster {:?}", route.cluster_id),
+                ));
+            }
+            // 路由可以用通用的 StringMatch 描述路径；没有配置时退回旧的 path_prefix 前缀匹配。
+            // 两种写法的前缀匹配都按路径段边界 (见 matcher::path_prefix_matches)
+            // case_insensitive_path 对两种写法都生效 (等价于 StringMatch 的 ignore_case)，只影响匹配，
+            // 转发给上游的仍然是客户端的原始路径 (strip_prefix 按字节数去掉前缀，URI 路径只含 ASCII)
+            let ignore_case = route.case_insensitive_path;
+            let (path, field) = match &route.path {
+                Some(m) => {
+                    let m = StringMatch {
+                        ignore_case: m.ignore_case || ignore_case,
+                        ..m.clone()
+                    };
+                    (CompiledMatch::compile(&m).map(CompiledMatch::for_path), "path")
+                }
+                None => {
+                    let path = match route.match_type() {
+                        PathMatchType::PathMatchExact => {
+                            Ok(CompiledMatch::exact(&route.path_prefix, ignore_case))
+                        }
+                        PathMatchType::PathMatchPrefix => {
+                            Ok(CompiledMatch::path_prefix(&route.path_prefix, ignore_case))
+                        }
+                        PathMatchType::PathMatchRegex => {
+                            CompiledMatch::regex(&route.path_prefix, ignore_case)
+                        }
+                    };
+                    (path, "path_prefix")
+                }
+            };
+            let path = match path {
+                Ok(path) => path,
+                Err(e) => {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidRegex,
+                        format!("{}.{}", at, field),
+                        e,
+                    ));
+                    continue;
+                }
+            };
+            let hosts = match HostMatcher::compile(&route.hosts) {
+                Ok(hosts) => hosts,
+                Err(e) => {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidHost,
+                        format!("{}.hosts", at),
+                        e,
+                    ));
+                    continue;
+                }
+            };
+            let mut headers = Vec::with_capacity(route.headers.len());
+            for (j, header) in route.headers.iter().enumerate() {
+                match HeaderMatcher::compile(header) {
+                    Ok(header) => headers.push(header),
+                    Err(e) => errors.push(config_error(
+                        ConfigErrorCode::InvalidHeaderMatch,
+                        format!("{}.headers[{}]", at, j),
+                        e,
+                    )),
+                }
+            }
+            let mut query_params = Vec::with_capacity(route.query_params.len());
+            for (j, param) in route.query_params.iter().enumerate() {
+                match QueryParamMatcher::compile(param) {
+                    Ok(param) => query_params.push(param),
+                    Err(e) => errors.push(config_error(
+                        ConfigErrorCode::InvalidQueryMatch,
+                        format!("{}.query_params[{}]", at, j),
+                        e,
+                    )),
+                }
+            }
+            let grpc_upstream = snapshot
+                .clusters
+                .iter()
+                .find(|c| c.name == route.cluster_id)
+                .map(|c| c.protocol() == Protocol::Grpc);
+            if route.protocol() == Protocol::Grpc && grpc_upstream == Some(false) {
+                eprintln!(
+                    "WARNING: gRPC route {:?} forwards to cluster {:?}, which is not protocol GRPC; the upstream is reached over HTTP/1.1",
+                    route.path_prefix, route.cluster_id
+                );
+            }
+            if let Some(selector) = &route.subset_selector {
+                let endpoints = snapshot
+                    .clusters
+                    .iter()
+                    .find(|c| c.name == route.cluster_id)
+                    .map(|c| c.endpoints.as_slice())
+                    .unwrap_or_default();
+                if upstream::subset_never_matches(endpoints, selector) {
+                    eprintln!(
+                        "WARNING: route {:?}: subset selector {:?} matches no endpoint in cluster {:?}",
+                        route.path_prefix, selector.metadata, route.cluster_id
+                    );
+                }
+            }
+            let canary = match route.canary.as_ref().map(CompiledCanary::compile).transpose() {
+                Ok(canary) => canary,
+                Err(e) => {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidCanary,
+                        format!("{}.canary", at),
+                        e,
+                    ));
+                    continue;
+                }
+            };
+            let mut exclusions = Vec::with_capacity(route.plugin_exclusions.len());
+            for (j, exclusion) in route.plugin_exclusions.iter().enumerate() {
+                match CompiledExclusion::compile(exclusion) {
+                    Ok(exclusion) => {
+                        if exclusion.looks_suspicious() {
+                            eprintln!(
+                                "WARNING: route {:?}: plugin exclusion {:?} does not look like a health check or preflight; matching requests bypass all plugins",
+                                route.path_prefix, exclusion.label
+                            );
+                        }
+                        exclusions.push(exclusion);
+                    }
+                    Err(e) => errors.push(config_error(
+                        ConfigErrorCode::InvalidRegex,
+                        format!("{}.plugin_exclusions[{}].path", at, j),
+                        e,
+                    )),
+                }
+            }
+            let rewrite = match PathRewrite::compile(route, &path) {
+                Ok(rewrite) => rewrite,
+                Err(e) => {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidRewrite,
+                        format!("{}.rewrite_prefix", at),
+                        e,
+                    ));
+                    continue;
+                }
+            };
+            if !upstream::valid_host_rewrite(&route.host_rewrite) {
+                errors.push(config_error(
+                    ConfigErrorCode::InvalidHostRewrite,
+                    format!("{}.host_rewrite", at),
+                    format!("invalid host {:?}", route.host_rewrite),
+                ));
+            }
+            let cluster_selector = if route.cluster_selector.is_empty() {
+                None
+            } else {
+                match ClusterSelector::compile(&route.cluster_selector) {
+                    Ok(selector) => {
+                        // 能静态枚举出全部结果时，每个结果都必须是已知集群
+                        for name in selector.outcomes().unwrap_or_default() {
+                            if !cluster_exists(&name) {
+                                errors.push(config_error(
+                                    ConfigErrorCode::UnknownClusterRef,
+                                    format!("{}.cluster_selector", at),
+                                    format!("selector can produce unknown cluster {:?}", name),
+                                ));
+                            }
+                        }
+                        Some(selector)
+                    }
+                    Err(e) => {
+                        errors.push(config_error(
+                            ConfigErrorCode::InvalidClusterSelector,
+                            format!("{}.cluster_selector", at),
+                            e,
+                        ));
+                        continue;
+                    }
+                }
+            };
+            let redirect = match route.redirect.as_ref().map(CompiledRedirect::compile) {
+                None => None,
+                Some(Ok(redirect)) => Some(redirect),
+                Some(Err(e)) => {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidRedirect,
+                        format!("{}.redirect", at),
+                        e,
+                    ));
+                    continue;
+                }
+            };
+            let direct_response = match &route.direct_response {
+                None => None,
+                Some(_) if !route.cluster_id.is_empty() || route.redirect.is_some() => {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidDirectResponse,
+                        format!("{}.direct_response", at),
+                        "direct_response cannot be combined with cluster_id or redirect",
+                    ));
+                    continue;
+                }
+                Some(action) => match CompiledDirectResponse::compile(action) {
+                    Ok(direct) => Some(direct),
+                    Err(e) => {
+                        errors.push(config_error(
+                            ConfigErrorCode::InvalidDirectResponse,
+                            format!("{}.direct_response", at),
+                            e,
+                        ));
+                        continue;
+                    }
+                },
+            };
+            let disabled = if route.disabled {
+                match CompiledDirectResponse::disabled(route.disabled_response.as_ref()) {
+                    Ok(response) => Some(response),
+                    Err(e) => {
+                        errors.push(config_error(
+                            ConfigErrorCode::InvalidDirectResponse,
+                            format!("{}.disabled_response", at),
+                            e,
+                        ));
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+            let split = if route.weighted_clusters.is_empty() {
+                None
+            } else if !route.cluster_id.is_empty()
+                || route.redirect.is_some()
+                || route.direct_response.is_some()
+            {
+                errors.push(config_error(
+                    ConfigErrorCode::InvalidWeightedClusters,
+                    format!("{}.weighted_clusters", at),
+                    "weighted_clusters cannot be combined with cluster_id, redirect or direct_response",
+                ));
+                continue;
+            } else {
+                match CompiledSplit::compile(&route.weighted_clusters) {
+                    Ok(split) => {
+                        for name in split.cluster_names().filter(|name| !cluster_exists(name)) {
+                            errors.push(config_error(
+                                ConfigErrorCode::UnknownClusterRef,
+                                format!("{}.weighted_clusters", at),
+                                format!("unknown cluster {:?}", name),
+                            ));
+                        }
+                        Some(split)
+                    }
+                    Err(e) => {
+                        errors.push(config_error(
+                            ConfigErrorCode::InvalidWeightedClusters,
+                            format!("{}.weighted_clusters", at),
+                            e,
+                        ));
+                        continue;
+                    }
+                }
+            };
+            let retry = match route.retry.as_ref().map(CompiledRetry::compile) {
+                None => None,
+                Some(Ok(retry)) => retry,
+                Some(Err(e)) => {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidRetryPolicy,
+                        format!("{}.retry", at),
+                        e,
+                    ));
+                    continue;
+                }
+            };
+            for name in &route.skip_global_plugins {
+                if !global_plugins.iter().any(|p| &p.name == name) {
+                    eprintln!(
+                        "WARNING: route {:?}: skip_global_plugins names {:?}, which is not a global plugin",
+                        route.path_prefix, name
+                    );
+                }
+            }
+            let plugin_chain = plugin_chain(&global_plugins, route);
+            // 安全模式下不执行插件：带插件 (包括全局插件) 的路由必须明确声明放行还是拒绝，不能默认跳过鉴权
+            if !plugin_chain.is_empty()
+                && route.plugin_bypass_policy() == PluginBypassPolicy::PluginBypassUnspecified
+            {
+                errors.push(config_error(
+                    ConfigErrorCode::MissingPluginBypassPolicy,
+                    format!("{}.plugin_bypass_policy", at),
+                    "routes with plugins (including global plugins) must declare plugin_bypass_policy (allow or deny) for safe mode",
+                ));
+            }
+            let request_headers = match &route.request_headers {
+                None => None,
+                Some(transform) => match CompiledHeaderTransform::compile(transform) {
+                    Ok(compiled) => Some(compiled),
+                    Err(e) => {
+                        errors.push(config_error(
+                            ConfigErrorCode::InvalidHeaderTransform,
+                            format!("{}.request_headers", at),
+                            e,
+                        ));
+                        continue;
+                    }
+                },
+            };
+            let response_headers = match &route.response_headers {
+                None => None,
+                Some(transform) => match CompiledHeaderTransform::compile(transform) {
+                    Ok(compiled) => Some(compiled),
+                    Err(e) => {
+                        errors.push(config_error(
+                            ConfigErrorCode::InvalidHeaderTransform,
+                            format!("{}.response_headers", at),
+                            e,
+                        ));
+                        continue;
+                    }
+                },
+            };
+            if !route.mirror_cluster.is_empty() && !cluster_exists(&route.mirror_cluster) {
+                errors.push(config_error(
+                    ConfigErrorCode::UnknownClusterRef,
+                    format!("{}.mirror_cluster", at),
+                    format!("unknown cluster {:?}", route.mirror_cluster),
+                ));
+            }
+            // 未设置采样率 = 镜像全部请求；设置了就必须在 [0, 1] 内 (NaN 也不行)
+            if let Some(sample_rate) = route.mirror_sample_rate {
+                if !(0.0..=1.0).contains(&sample_rate) {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidMirror,
+                        format!("{}.mirror_sample_rate", at),
+                        format!("sample rate {} must be between 0 and 1", sample_rate),
+                    ));
+                } else if sample_rate > 0.0 && route.mirror_cluster.is_empty() {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidMirror,
+                        format!("{}.mirror_sample_rate", at),
+                        "mirror_sample_rate requires mirror_cluster",
+                    ));
+                }
+            }
+            if let Some(canary) = &canary {
+                if !cluster_exists(&canary.cluster) {
+                    errors.push(config_error(
+                        ConfigErrorCode::UnknownClusterRef,
+                        format!("{}.canary.cluster_id", at),
+                        format!("unknown cluster {:?}", canary.cluster),
+                    ));
+                }
+            }
+            routes.push(CompiledRoute {
+                route: route.clone(),
+                methods: route
+                    .methods
+                    .iter()
+                    .map(|m| m.trim().to_ascii_uppercase())
+                    .filter(|m| !m.is_empty())
+                    .collect(),
+                path,
+                hosts,
+                headers,
+                query_params,
+                exclusions,
+                canary,
+                idempotency: route
+                    .idempotency
+                    .as_ref()
+                    .map(|policy| CompiledIdempotency::compile(route, policy)),
+                rewrite,
+                cluster_selector,
+                retry,
+                redirect,
+                direct_response,
+                disabled,
+                split,
+                request_headers,
+                response_headers,
+                plugin_chain,
+            });
+        }
+        // 没有兜底路由时 404 的响应体 / 响应头可以定制，状态码固定是 404
+        let not_found = match &snapshot.not_found_response {
+            None => None,
+            Some(action) if action.status != 0 && action.status != 404 => {
+                errors.push(config_error(
+                    ConfigErrorCode::InvalidDirectResponse,
+                    "not_found_response.status",
+                    format!("status {} must be 0 or 404", action.status),
+                ));
+                None
+            }
+            Some(action) => match CompiledDirectResponse::compile(action) {
+                Ok(direct) => Some(CompiledDirectResponse {
+                    status: 404,
+                    ..direct
+                }),
+                Err(e) => {
+                    errors.push(config_error(
+                        ConfigErrorCode::InvalidDirectResponse,
+                        "not_found_response",
+                        e,
+                    ));
+                    None
+                }
+            },
+        };
+        let error_pages = match ErrorPages::compile(&snapshot.error_responses) {
+            Ok(pages) => pages,
+            Err(page_errors) => {
+                errors.extend(page_errors.into_iter().map(|(i, e)| {
+                    config_error(
+                        ConfigErrorCode::InvalidErrorResponse,
+                        format!("error_responses[{}]", i),
+                        e,
+                    )
+                }));
+                ErrorPages::default()
+            }
+        };
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let index = RouteIndex::build(&routes[..snapshot.routes.len()]);
+        Ok(Self {
+            snapshot,
+            routes,
+            default_route: default_at,
+            not_found,
+            error_pages: Arc::new(error_pages),
+            index,
+        })
+    }
+
+    // 按优先级返回路径可能命中的路由下标 (见 RouteIndex)。
+    // 调用方仍需检查域名、灰度等其余条件，不满足时继续尝试下一个候选。
+    pub fn candidates(&self, path: &str) -> Vec<usize> {
+        self.index.candidates(path)
+    }
+
+    // 按优先级找出请求命中的第一条路由。
+    // 候选路由还要依次满足域名、路径、请求头、查询参数、方法和定时生效 / 灰度条件，不满足时继续尝试下一个。
+    // 精确域名优先于通配：通过 "*.example.com" 命中的路由先记下来，继续看后面有没有写明这个域名的路由
+    // (或者更精确的通配)，有就用后者；不限域名的路由不参与这个比较，仍按原来的顺序。
+    // 代理的 request_filter 和离线回放 (replay.rs) 都走这里，回放的结论因此和线上一致。
+    pub fn resolve(&self, request: &RouteQuery) -> Resolution {
+        // 路径命中、但方法不被接受的路由声明的方法 (用于 405 的 Allow 头)
+        let mut allowed: Vec<&str> = Vec::new();
+        // 查询参数只在有路由需要时解析，且每个请求最多解析一次
+        let query = std::cell::OnceCell::new();
+        // 通过通配域名命中、等着被更精确的域名顶替的路由
+        let mut wildcard: Option<(HostMatch, Resolution)> = None;
+        for index in self.candidates(request.path) {
+            let compiled = &self.routes[index];
+            let route = &compiled.route;
+            // 域名 + 路径都匹配才算命中 (路径默认前缀匹配，也可以是 StringMatch 描述的精确/正则等)
+            let Some(host_match) = compiled.hosts.matches(request.host) else {
+                continue;
+            };
+            if !compiled.path.matches(request.path) {
+                continue;
+            }
+            // 请求头条件 (如 X-Canary: true) 不满足：这条路由不适用，继续尝试后面的路由
+            if !compiled.matches_headers(request.headers) {
+                continue;
+            }
+            // gRPC 路由只接 gRPC 请求 (Content-Type: application/grpc)，其余请求继续尝试后面的路由
+            if route.protocol() == Protocol::Grpc && !grpc::is_grpc(request.headers) {
+                continue;
+            }
+            if !compiled.query_params.is_empty() {
+                let params = query.get_or_init(|| matcher::parse_query(request.query));
+                if !compiled.matches_query(params) {
+                    continue;
+                }
+            }
+            // 方法不匹配：继续尝试后面的路由 (如 GET 和 POST 分别配置了不同的集群)
+            if !compiled.allows_method(request.method) {
+                allowed.extend(compiled.methods.iter().map(String::as_str));
+                continue;
+            }
+            // 定时生效 / 灰度：未生效或未被选中的请求跳过这条路由，继续匹配后面的 (旧) 路由
+            let mut rollout_fraction = None;
+            if rollout::is_scheduled(route) {
+                let fraction = rollout::effective_fraction(route, request.at);
+                if !rollout::captures(fraction, request.rollout_key) {
+                    continue;
+                }
+                rollout_fraction = Some(fraction);
+            }
+            let matched = Resolution::Matched {
+                index,
+                rollout_fraction,
+            };
+            match wildcard.as_ref().map(|(best, _)| *best) {
+                None if host_match.is_wildcard() => wildcard = Some((host_match, matched)),
+                None => return matched,
+                Some(_) if host_match == HostMatch::Exact => return matched,
+                Some(best) if host_match < best => wildcard = Some((host_match, matched)),
+                Some(_) => {}
+            }
+        }
+        if let Some((_, matched)) = wildcard {
+            return matched;
+        }
+        if allowed.is_empty() {
+            return Resolution::NoRoute;
+        }
+        allowed.sort_unstable();
+        allowed.dedup();
+        Resolution::MethodNotAllowed(allowed.join(", "))
+    }
+
+    // 访问日志 / 指标里的路由名：普通路由用 path_prefix，兜底路由固定为 "default_route"
+    pub fn route_name(&self, index: usize) -> &str {
+        if Some(index) == self.default_route {
+            DEFAULT_ROUTE_NAME
+        } else {
+            &self.routes[index].route.path_prefix
+        }
+    }
+
+    // 空配置 (AGW_BIND_BEFORE_CONFIG 模式下第一份配置到达之前使用)
+    pub fn empty() -> Self {
+        Self {
+            snapshot: ConfigSnapshot::default(),
+            routes: Vec::new(),
+            default_route: None,
+            not_found: None,
+            error_pages: Arc::default(),
+            index: RouteIndex::default(),
+        }
+    }
+}
+
+const DEFAULT_ROUTE_NAME: &str = "default_route";
+
+// 兜底路由不参与匹配，写了匹配条件说明配置者以为它会被匹配
+fn has_match_conditions(route: &Route) -> bool {
+    route.path.is_some()
+        || !route.path_prefix.is_empty()
+        || !route.hosts.is_empty()
+        || !route.methods.is_empty()
+        || !route.headers.is_empty()
+        || !route.query_params.is_empty()
+}
+
+// 路由匹配用到的请求信息
+pub struct RouteQuery<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub host: Option<&'a str>,
+    pub headers: &'a http::HeaderMap,
+    pub query: Option<&'a str>,
+    // 灰度哈希使用的 key (客户端 IP) 和请求到达的时刻
+    pub rollout_key: &'a [u8],
+    pub at: SystemTime,
+}
+
+pub enum Resolution {
+    Matched {
+        index: usize,
+        // 命中的路由处于灰度中时的放量比例
+        rollout_fraction: Option<f64>,
+    },
+    // 路径命中了路由，但没有路由接受这个方法；带上这些路由声明的方法 (Allow 头)
+    MethodNotAllowed(String),
+    NoRoute,
+}
+
+impl CompiledRoute {
+    pub fn matches_headers(&self, headers: &http::HeaderMap) -> bool {
+        self.headers.iter().all(|h| h.matches(headers))
+    }
+
+    // 查询串在调用方按需解析一次 (没有查询参数条件的路由不需要解析)
+    pub fn matches_query(&self, params: &[(String, String)]) -> bool {
+        self.query_params.iter().all(|q| q.matches(params))
+    }
+
+    pub fn allows_method(&self, method: &str) -> bool {
+        self.methods.is_empty() || self.methods.iter().any(|m| m == method)
+    }
+
+    // 按执行顺序排列的插件链 (见 plugin_chain)
+    pub fn plugins(&self) -> impl Iterator<Item = &Plugin> {
+        self.plugin_chain.iter()
+    }
+
+    pub fn has_plugins(&self) -> bool {
+        !self.plugin_chain.is_empty()
+    }
+
+    // 安全模式下是否不执行插件直接放行；只有声明了 allow 的路由放行
+    pub fn bypasses_plugins_in_safe_mode(&self) -> bool {
+        self.route.plugin_bypass_policy() == PluginBypassPolicy::PluginBypassAllow
+    }
+}
+
+// 【插件执行顺序 (priority) 与全局插件】
+// 插件按 priority 从小到大执行 (默认 0)：鉴权这类安全插件写 -100，保证排在业务插件前面，
+// 不依赖控制面拼接插件列表的顺序。priority 相同的插件保持配置顺序 (稳定排序)。
+// 快照的 global_plugins 对每条路由 (包括兜底路由) 生效，整体排在路由自己的插件之前；
+// 全局插件拒绝时路由的插件不再执行。路由可以用 skip_global_plugins 按名称跳过个别全局插件。
+// 插件链在编译快照时算好，请求路径上不再排序。
+fn plugin_chain(global: &[Plugin], route: &Route) -> Vec<Plugin> {
+    global
+        .iter()
+        .filter(|p| !route.skip_global_plugins.contains(&p.name))
+        .cloned()
+        .chain(sorted_plugins(route.plugins.iter()))
+        .collect()
+}
+
+fn sorted_plugins<'a>(plugins: impl Iterator<Item = &'a Plugin>) -> Vec<Plugin> {
+    let mut sorted: Vec<Plugin> = plugins.cloned().collect();
+    sorted.sort_by_key(|p| p.priority);
+    sorted
+}
+
+// 【路由索引 (RouteIndex)】
+// 路由很多时，每个请求线性扫描整个路由表会体现在 p99 上。这里按匹配方式分开索引：
+// - 精确路由放进 HashMap (大小写不敏感的单独一张表，用小写后的路径查)。
+// - 前缀路由 (path_prefix、StringMatch 的 prefix、以及等价于空前缀的 "匹配一切") 放进前缀树 (radix tree)，
+//   查找只和路径长度有关；大小写不敏感的前缀单独放一棵树，用小写后的路径查。
+// - 正则 / 后缀 / 包含这类无法建索引的路由放在 scan 列表里，按配置顺序逐个尝试。
+//
+// 候选顺序 (也就是路由优先级)：
+// 1. 精确路由 ("/healthz" 精确路由先于 "/" 前缀路由)。
+// 2. scan 列表中的路由，按配置顺序 (这类规则通常是刻意写的特例，应先于宽泛的前缀)。
+// 3. 前缀路由，最长前缀优先。
+// 路径相同的精确路由 / 一样长的前缀路由之间，query_params 条件多的优先 (更具体：带 ?version=2 条件的路由
+// 不会被写在前面、没有条件的同路径路由挡住)，条件一样多时按配置顺序。
+#[derive(Default)]
+struct RouteIndex {
+    exact: HashMap<String, Vec<usize>>,
+    exact_ignore_case: HashMap<String, Vec<usize>>,
+    prefixes: PrefixTree,
+    prefixes_ignore_case: PrefixTree,
+    scan: Vec<usize>,
+    // 每条路由的 query_params 条件数，同路径路由之间排序用
+    query_conditions: Vec<usize>,
+}
+
+impl RouteIndex {
+    fn build(routes: &[CompiledRoute]) -> Self {
+        let mut index = Self {
+            query_conditions: routes.iter().map(|r| r.query_params.len()).collect(),
+            ..Self::default()
+        };
+        for (i, route) in routes.iter().enumerate() {
+            if let Some((path, ignore_case)) = route.path.indexable_exact() {
+                let table = if ignore_case {
+                    &mut index.exact_ignore_case
+                } else {
+                    &mut index.exact
+                };
+                table.entry(path.to_string()).or_default().push(i);
+                continue;
+            }
+            match route.path.indexable_prefix() {
+                Some((prefix, false)) => index.prefixes.insert(prefix, i),
+                Some((prefix, true)) => index.prefixes_ignore_case.insert(prefix, i),
+                None => index.scan.push(i),
+            }
+        }
+        index
+    }
+
+    fn candidates(&self, path: &str) -> Vec<usize> {
+        let folded = if self.exact_ignore_case.is_empty() && self.prefixes_ignore_case.is_empty() {
+            None
+        } else {
+            Some(path.to_lowercase())
+        };
+
+        let specificity = |i: usize| std::cmp::Reverse(self.query_conditions[i]);
+        let mut exact: Vec<usize> = self.exact.get(path).cloned().unwrap_or_default();
+        if let Some(routes) = folded.as_ref().and_then(|p| self.exact_ignore_case.get(p)) {
+            exact.extend(routes);
+        }
+        exact.sort_unstable_by_key(|&i| (specificity(i), i));
+
+        let mut prefixed = Vec::new();
+        self.prefixes.collect(path, &mut prefixed);
+        if let Some(folded) = &folded {
+            self.prefixes_ignore_case.collect(folded, &mut prefixed);
+        }
+        // 最长前缀优先，一样长时 query_params 条件多的优先，再按配置顺序
+        prefixed.sort_by_key(|&(len, i)| (std::cmp::Reverse(len), specificity(i), i));
+
+        let mut candidates = exact;
+        candidates.extend(&self.scan);
+        candidates.extend(prefixed.into_iter().map(|(_, i)| i));
+        candidates
+    }
+}
+
+// 按字节的压缩前缀树。每个节点保存以 "根到该节点的完整前缀" 为 path_prefix 的路由下标。
+#[derive(Default)]
+struct PrefixTree {
+    root: PrefixNode,
+}
+
+#[derive(Default)]
+struct PrefixNode {
+    // 相对父节点的那一段
+    label: Vec<u8>,
+    routes: Vec<usize>,
+    // 子节点的首字节互不相同
+    children: Vec<PrefixNode>,
+}
+
+impl PrefixTree {
+    fn insert(&mut self, prefix: &str, route: usize) {
+        self.root.insert(prefix.as_bytes(), route);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.root.routes.is_empty() && self.root.children.is_empty()
+    }
+
+    // 收集前缀命中 path 的所有路由，写入 (前缀长度, 路由下标)
+    fn collect(&self, path: &str, out: &mut Vec<(usize, usize)>) {
+        let mut node = &self.root;
+        let mut rest = path.as_bytes();
+        let mut depth = 0;
+        loop {
+            out.extend(node.routes.iter().map(|&i| (depth, i)));
+            let Some(child) = node.children.iter().find(|c| rest.starts_with(&c.label)) else {
+                break;
+            };
+            rest = &rest[child.label.len()..];
+            depth += child.label.len();
+            node = child;
+        }
+    }
+}
+
+impl PrefixNode {
+    fn insert(&mut self, key: &[u8], route: usize) {
+        if key.is_empty() {
+            self.routes.push(route);
+            return;
+        }
+        for child in &mut self.children {
+            let common = child
+                .label
+                .iter()
+                .zip(key)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if common == 0 {
+                continue;
+            }
+            // 只共享了一部分：把子节点从分叉处拆成两层
+            if common < child.label.len() {
+                let tail = PrefixNode {
+                    label: child.label.split_off(common),
+                    routes: std::mem::take(&mut child.routes),
+                    children: std::mem::take(&mut child.children),
+                };
+                child.children.push(tail);
+            }
+            child.insert(&key[common..], route);
+            return;
+        }
+        self.children.push(PrefixNode {
+            label: key.to_vec(),
+            routes: vec![route],
+            children: Vec::new(),
+        });
+    }
+}
+
+fn is_pem(data: &[u8]) -> bool {
+    data.windows(b"-----BEGIN ".len()).any(|w| w == b"-----BEGIN ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::agw::config::v1::string_match::Pattern;
+    use crate::client::agw::config::v1::{Cluster, HeaderMatch, QueryParamMatch};
+
+    // 路由引用的集群 (以及 "backend") 都放进快照
+    fn snapshot(routes: Vec<Route>) -> ConfigSnapshot {
+        let mut names = vec!["backend".to_string()];
+        for route in &routes {
+            if !route.cluster_id.is_empty() && !names.contains(&route.cluster_id) {
+                names.push(route.cluster_id.clone());
+            }
+        }
+        ConfigSnapshot {
+            clusters: names
+                .into_iter()
+                .map(|name| Cluster {
+                    name,
+                    ..Default::default()
+                })
+                .collect(),
+            routes,
+            ..Default::default()
+        }
+    }
+
+    // 默认声明 deny：测试里的路由加了插件 (或全局插件) 也能通过校验
+    fn route(path_prefix: &str) -> Route {
+        Route {
+            path_prefix: path_prefix.to_string(),
+            cluster_id: "backend".to_string(),
+            plugin_bypass_policy: PluginBypassPolicy::PluginBypassDeny as i32,
+            ..Default::default()
+        }
+    }
+
+    fn routed_to(path_prefix: &str, cluster: &str) -> Route {
+        Route {
+            cluster_id: cluster.to_string(),
+            ..route(path_prefix)
+        }
+    }
+
+    fn resolved(
+        config: &ActiveConfig,
+        method: &str,
+        uri: &str,
+        host: Option<&str>,
+        headers: &http::HeaderMap,
+    ) -> Resolution {
+        let (path, query) = match uri.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (uri, None),
+        };
+        config.resolve(&RouteQuery {
+            method,
+            path,
+            host,
+            headers,
+            query,
+            rollout_key: b"",
+            at: SystemTime::now(),
+        })
+    }
+
+    fn index(resolution: Resolution) -> Option<usize> {
+        match resolution {
+            Resolution::Matched { index, .. } => Some(index),
+            Resolution::MethodNotAllowed(_) | Resolution::NoRoute => None,
+        }
+    }
+
+    // 按请求行解析路由 (没有 Host、请求头)，返回命中的路由下标
+    fn matched(config: &ActiveConfig, method: &str, uri: &str) -> Option<usize> {
+        index(resolved(config, method, uri, None, &http::HeaderMap::new()))
+    }
+
+    fn matched_cluster<'a>(config: &'a ActiveConfig, uri: &str) -> Option<&'a str> {
+        matched(config, "GET", uri).map(|i| config.routes[i].route.cluster_id.as_str())
+    }
+
+    fn with_plugin(mut route: Route) -> Route {
+        route.plugins.push(Plugin {
+            name: "auth".to_string(),
+            ..Default::default()
+        });
+        route
+    }
+
+    fn unspecified(route: Route) -> Route {
+        Route {
+            plugin_bypass_policy: PluginBypassPolicy::PluginBypassUnspecified as i32,
+            ..route
+        }
+    }
+
+    #[test]
+    fn plugins_without_bypass_policy_reject_the_snapshot() {
+        let errors = match ActiveConfig::compile(snapshot(vec![
+            route("/health"),
+            unspecified(with_plugin(route("/api"))),
+        ])) {
+            Ok(_) => panic!("expected the snapshot to be rejected"),
+            Err(errors) => errors,
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), ConfigErrorCode::MissingPluginBypassPolicy);
+        assert_eq!(errors[0].path, "routes[1].plugin_bypass_policy");
+
+        // 路由自己没有插件也一样：全局插件在安全模式下同样不执行
+        let mut global = snapshot(vec![unspecified(route("/api"))]);
+        global.global_plugins = with_plugin(route("/")).plugins;
+        assert_eq!(
+            error_codes(global),
+            [ConfigErrorCode::MissingPluginBypassPolicy]
+        );
+
+        // 没有任何插件的路由不需要声明
+        assert!(error_codes(snapshot(vec![unspecified(route("/api"))])).is_empty());
+    }
+
+    #[test]
+    fn plugins_with_bypass_policy_compile() {
+        let mut allow = with_plugin(route("/tags"));
+        allow.set_plugin_bypass_policy(PluginBypassPolicy::PluginBypassAllow);
+        let mut deny = with_plugin(route("/api"));
+        deny.set_plugin_bypass_policy(PluginBypassPolicy::PluginBypassDeny);
+
+        let config = ActiveConfig::compile(snapshot(vec![allow, deny])).unwrap();
+        assert!(config.routes[0].bypasses_plugins_in_safe_mode());
+        assert!(!config.routes[1].bypasses_plugins_in_safe_mode());
+    }
+
+    fn mirrored(sample_rate: Option<f32>) -> Route {
+        Route {
+            mirror_cluster: "backend".to_string(),
+            mirror_sample_rate: sample_rate,
+            ..route("/api")
+        }
+    }
+
+    fn error_codes(snapshot: ConfigSnapshot) -> Vec<ConfigErrorCode> {
+        match ActiveConfig::compile(snapshot) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors.iter().map(|e| e.code()).collect(),
+        }
+    }
+
+    #[test]
+    fn mirror_sample_rate_must_be_a_fraction() {
+        for rate in [None, Some(0.0), Some(0.25), Some(1.0)] {
+            assert!(error_codes(snapshot(vec![mirrored(rate)])).is_empty());
+        }
+        for rate in [-0.1, 1.5, f32::NAN] {
+            assert_eq!(
+                error_codes(snapshot(vec![mirrored(Some(rate))])),
+                [ConfigErrorCode::InvalidMirror]
+            );
+        }
+    }
+
+    // n 条不相干的路由，一半排在目标路由前面、一半排在后面
+    fn padded(n: usize) -> ConfigSnapshot {
+        let mut routes: Vec<Route> = (0..n).map(|i| route(&format!("/svc-{}/v1", i))).collect();
+        routes.insert(n / 2, routed_to("/api/orders", "orders"));
+        routes.insert(0, route("/api"));
+        snapshot(routes)
+    }
+
+    #[test]
+    fn selected_cluster_does_not_depend_on_route_count() {
+        for n in [0, 10, 1_000, 10_000] {
+            let config = ActiveConfig::compile(padded(n)).unwrap();
+            assert_eq!(config.routes.len(), n + 2);
+            assert_eq!(
+                matched_cluster(&config, "/api/orders/42"),
+                Some("orders"),
+                "{} routes",
+                n
+            );
+            assert_eq!(
+                matched_cluster(&config, "/api/orders"),
+                Some("orders"),
+                "{} routes",
+                n
+            );
+            assert_eq!(
+                matched_cluster(&config, "/api/users"),
+                Some("backend"),
+                "{} routes",
+                n
+            );
+            assert_eq!(matched_cluster(&config, "/other"), None, "{} routes", n);
+        }
+    }
+
+    fn compiled(routes: Vec<Route>) -> ActiveConfig {
+        ActiveConfig::compile(snapshot(routes)).unwrap()
+    }
+
+    fn exact(path: &str) -> Route {
+        let mut route = route(path);
+        route.set_match_type(PathMatchType::PathMatchExact);
+        route
+    }
+
+    fn suffix(suffix: &str) -> Route {
+        Route {
+            path: Some(StringMatch {
+                pattern: Some(Pattern::Suffix(suffix.to_string())),
+                ..Default::default()
+            }),
+            ..route("")
+        }
+    }
+
+    fn with_query(mut route: Route, name: &str, value: &str) -> Route {
+        route.query_params.push(QueryParamMatch {
+            name: name.to_string(),
+            value: Some(StringMatch {
+                pattern: Some(Pattern::Exact(value.to_string())),
+                ..Default::default()
+            }),
+        });
+        route
+    }
+
+    #[test]
+    fn index_orders_prefixes_longest_first() {
+        let config = compiled(vec![
+            route("/"),
+            route("/api"),
+            route("/api/v2"),
+            route("/app"),
+        ]);
+        assert_eq!(config.candidates("/api/v2/users"), [2, 1, 0]);
+        assert_eq!(config.candidates("/api"), [1, 0]);
+        assert_eq!(config.candidates("/app/x"), [3, 0]);
+        assert_eq!(config.candidates("/other"), [0]);
+    }
+
+    #[test]
+    fn index_puts_exact_then_scanned_routes_before_prefixes() {
+        let config = compiled(vec![route("/api"), suffix(".json"), exact("/api/health")]);
+        assert_eq!(config.candidates("/api/health"), [2, 1, 0]);
+        assert_eq!(config.candidates("/api/users"), [1, 0]);
+        assert_eq!(matched(&config, "GET", "/api/health"), Some(2));
+        assert_eq!(matched(&config, "GET", "/api/users.json"), Some(1));
+        assert_eq!(matched(&config, "GET", "/api/users"), Some(0));
+    }
+
+    #[test]
+    fn index_prefers_more_query_conditions_on_the_same_path() {
+        let config = compiled(vec![
+            route("/api"),
+            with_query(route("/api"), "version", "2"),
+            with_query(with_query(route("/api"), "version", "2"), "env", "staging"),
+        ]);
+        assert_eq!(config.candidates("/api/users"), [2, 1, 0]);
+        assert_eq!(
+            matched(&config, "GET", "/api/users?env=staging&version=2"),
+            Some(2)
+        );
+        assert_eq!(matched(&config, "GET", "/api/users?version=2"), Some(1));
+        assert_eq!(matched(&config, "GET", "/api/users?version=1"), Some(0));
+        assert_eq!(matched(&config, "GET", "/api/users"), Some(0));
+    }
+
+    #[test]
+    fn index_looks_up_case_insensitive_paths_folded() {
+        let mut insensitive = route("/Admin");
+        insensitive.case_insensitive_path = true;
+        let config = compiled(vec![route("/admin/static"), insensitive]);
+        assert_eq!(config.candidates("/ADMIN/static"), [1]);
+        assert_eq!(config.candidates("/admin/static/app.js"), [0, 1]);
+    }
+
+    #[test]
+    fn prefix_tree_splits_shared_labels() {
+        let mut tree = PrefixTree::default();
+        for (i, prefix) in ["/api/users", "/api/orders", "/app", "/api"]
+            .iter()
+            .enumerate()
+        {
+            tree.insert(prefix, i);
+        }
+        let collect = |path: &str| {
+            let mut out = Vec::new();
+            tree.collect(path, &mut out);
+            out
+        };
+        assert_eq!(collect("/api/users/42"), [(4, 3), (10, 0)]);
+        assert_eq!(collect("/api/orders"), [(4, 3), (11, 1)]);
+        assert_eq!(collect("/apps"), [(4, 2)]);
+        assert!(collect("/a").is_empty());
+        assert!(!tree.is_empty());
+    }
+
+    #[test]
+    fn longest_prefix_wins_in_any_declaration_order() {
+        let orders = [
+            ["/", "/api", "/api/v2/users"],
+            ["/api/v2/users", "/api", "/"],
+            ["/api", "/", "/api/v2/users"],
+        ];
+        for order in orders {
+            let config = compiled(order.iter().map(|p| route(p)).collect());
+            let landed = |uri: &str| matched(&config, "GET", uri).map(|i| config.route_name(i));
+            assert_eq!(landed("/"), Some("/"), "{:?}", order);
+            assert_eq!(landed("/index.html"), Some("/"), "{:?}", order);
+            assert_eq!(landed("/apix"), Some("/"), "{:?}", order);
+            assert_eq!(landed("/api"), Some("/api"), "{:?}", order);
+            assert_eq!(landed("/api/v2"), Some("/api"), "{:?}", order);
+            assert_eq!(
+                landed("/api/v2/users"),
+                Some("/api/v2/users"),
+                "{:?}",
+                order
+            );
+            assert_eq!(
+                landed("/api/v2/users/42?page=2"),
+                Some("/api/v2/users"),
+                "{:?}",
+                order
+            );
+            assert_eq!(landed("/api/v2/usersx"), Some("/api"), "{:?}", order);
+        }
+    }
+
+    fn plugin(name: &str, priority: i32) -> Plugin {
+        Plugin {
+            name: name.to_string(),
+            wasm_path: format!("/plugins/{}.wasm", name),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    // 插件链的顺序就是执行顺序，也是访问日志里 plugins 的顺序
+    fn chain(config: &ActiveConfig, index: usize) -> Vec<&str> {
+        config.routes[index]
+            .plugins()
+            .map(|p| p.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn plugin_chain_runs_lowest_priority_first() {
+        let declared = [
+            plugin("business", 10),
+            plugin("auth", -100),
+            plugin("audit", 0),
+        ];
+        let mut forward = route("/api");
+        forward.plugins = declared.to_vec();
+        let mut reversed = route("/admin");
+        reversed.plugins = declared.iter().rev().cloned().collect();
+
+        let config = compiled(vec![forward, reversed]);
+        assert_eq!(chain(&config, 0), ["auth", "audit", "business"]);
+        assert_eq!(chain(&config, 1), ["auth", "audit", "business"]);
+    }
+
+    #[test]
+    fn equal_priorities_keep_declaration_order() {
+        let mut route = route("/api");
+        route.plugins = vec![
+            plugin("metrics", 0),
+            plugin("auth", -100),
+            plugin("audit", 0),
+        ];
+        assert_eq!(
+            chain(&compiled(vec![route]), 0),
+            ["auth", "metrics", "audit"]
+        );
+    }
+
+    #[test]
+    fn global_plugins_run_before_route_plugins() {
+        let mut with_own = route("/api");
+        with_own.plugins = vec![plugin("early", -1000)];
+        let mut skipping = route("/public");
+        skipping.skip_global_plugins = vec!["auth".to_string()];
+
+        let mut snapshot = snapshot(vec![route("/health"), with_own, skipping]);
+        snapshot.global_plugins = vec![plugin("logging", 0), plugin("auth", -100)];
+        let config = ActiveConfig::compile(snapshot).unwrap();
+        assert_eq!(chain(&config, 0), ["auth", "logging"]);
+        assert_eq!(chain(&config, 1), ["auth", "logging", "early"]);
+        assert_eq!(chain(&config, 2), ["logging"]);
+    }
+
+    // 按 request_filter 的方式执行插件链：依次执行，第一个拒绝的插件之后不再执行
+    async fn run_chain(config: &ActiveConfig, index: usize) -> (Vec<String>, bool) {
+        let wasm = crate::wasm::WasmRuntime::with_pool_size(Default::default(), 0);
+        let limits = crate::wasm::PluginLimits {
+            timeout: std::time::Duration::from_secs(5),
+            fuel: 1_000_000,
+        };
+        let mut ran = Vec::new();
+        for plugin in config.routes[index].plugins() {
+            ran.push(plugin.name.clone());
+            let result = wasm
+                .run_plugin(
+                    &plugin.wasm_path,
+                    limits,
+                    HashMap::new(),
+                    Arc::default(),
+                    None,
+                )
+                .await
+                .unwrap();
+            if matches!(result, crate::wasm::PluginResult::Deny(_)) {
+                return (ran, false);
+            }
+        }
+        (ran, true)
+    }
+
+    fn wat_plugin(name: &str, on_request: i32) -> Plugin {
+        let path =
+            std::env::temp_dir().join(format!("agw-router-{}-{}.wat", name, std::process::id()));
+        let wat = format!(
+            r#"(module (func (export "on_request") (result i32) (i32.const {})))"#,
+            on_request
+        );
+        std::fs::write(&path, wat).unwrap();
+        Plugin {
+            name: name.to_string(),
+            wasm_path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn global_auth_plugin_denies_routes_without_plugins() {
+        let mut guarded = route("/api");
+        guarded.plugins = vec![wat_plugin("route-allow", 0)];
+        let mut public = route("/public");
+        public.skip_global_plugins = vec!["auth".to_string()];
+
+        let mut snapshot = snapshot(vec![route("/health"), guarded, public]);
+        snapshot.global_plugins = vec![wat_plugin("auth", 1)];
+        let config = ActiveConfig::compile(snapshot).unwrap();
+
+        assert_eq!(
+            run_chain(&config, 0).await,
+            (vec!["auth".to_string()], false)
+        );
+        assert_eq!(
+            run_chain(&config, 1).await,
+            (vec!["auth".to_string()], false)
+        );
+        assert_eq!(run_chain(&config, 2).await, (Vec::new(), true));
+    }
+
+    fn hosted(path_prefix: &str, cluster: &str, hosts: &[&str]) -> Route {
+        Route {
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            ..routed_to(path_prefix, cluster)
+        }
+    }
+
+    fn host_cluster<'a>(
+        config: &'a ActiveConfig,
+        host: Option<&str>,
+        uri: &str,
+    ) -> Option<&'a str> {
+        index(resolved(config, "GET", uri, host, &http::HeaderMap::new()))
+            .map(|i| config.routes[i].route.cluster_id.as_str())
+    }
+
+    #[test]
+    fn same_path_resolves_per_virtual_host() {
+        let config = compiled(vec![
+            hosted("/api", "shop", &["shop.example.com"]),
+            hosted("/api", "blog", &["blog.example.com"]),
+            hosted("/api", "tenants", &["*.example.com"]),
+            routed_to("/api", "fallback"),
+        ]);
+        assert_eq!(
+            host_cluster(&config, Some("shop.example.com"), "/api/x"),
+            Some("shop")
+        );
+        assert_eq!(
+            host_cluster(&config, Some("BLOG.example.com:8443"), "/api/x"),
+            Some("blog")
+        );
+        assert_eq!(
+            host_cluster(&config, Some("blog.example.com."), "/api/x"),
+            Some("blog")
+        );
+        assert_eq!(
+            host_cluster(&config, Some("acme.example.com"), "/api/x"),
+            Some("tenants")
+        );
+        assert_eq!(
+            host_cluster(&config, Some("other.test"), "/api/x"),
+            Some("fallback")
+        );
+        assert_eq!(host_cluster(&config, None, "/api/x"), Some("fallback"));
+    }
+
+    #[test]
+    fn exact_host_beats_an_earlier_wildcard() {
+        let config = compiled(vec![
+            hosted("/", "deep", &["**.example.com"]),
+            hosted("/", "wildcard", &["*.example.com"]),
+            hosted("/", "exact", &["api.example.com"]),
+        ]);
+        assert_eq!(
+            host_cluster(&config, Some("api.example.com"), "/"),
+            Some("exact")
+        );
+        assert_eq!(
+            host_cluster(&config, Some("web.example.com"), "/"),
+            Some("wildcard")
+        );
+        assert_eq!(
+            host_cluster(&config, Some("a.web.example.com"), "/"),
+            Some("deep")
+        );
+        assert_eq!(host_cluster(&config, Some("example.com"), "/"), None);
+    }
+
+    fn with_methods(path_prefix: &str, cluster: &str, methods: &[&str]) -> Route {
+        Route {
+            methods: methods.iter().map(|m| m.to_string()).collect(),
+            ..routed_to(path_prefix, cluster)
+        }
+    }
+
+    fn by_method(config: &ActiveConfig, method: &str, uri: &str) -> Result<&str, Option<String>> {
+        match resolved(config, method, uri, None, &http::HeaderMap::new()) {
+            Resolution::Matched { index, .. } => Ok(config.routes[index].route.cluster_id.as_str()),
+            Resolution::MethodNotAllowed(allow) => Err(Some(allow)),
+            Resolution::NoRoute => Err(None),
+        }
+    }
+
+    #[test]
+    fn routes_filter_by_method() {
+        let config = compiled(vec![
+            with_methods("/admin", "reader", &["GET"]),
+            with_methods("/admin", "writer", &["post"]),
+            with_methods("/items", "items", &["GET", "HEAD", "PUT"]),
+        ]);
+        assert_eq!(by_method(&config, "GET", "/admin/users"), Ok("reader"));
+        assert_eq!(by_method(&config, "POST", "/admin/users"), Ok("writer"));
+        assert_eq!(by_method(&config, "HEAD", "/items/1"), Ok("items"));
+        assert_eq!(by_method(&config, "PUT", "/items/1"), Ok("items"));
+    }
+
+    #[test]
+    fn unaccepted_method_is_405_with_the_allowed_methods() {
+        let config = compiled(vec![
+            with_methods("/admin", "reader", &["GET", "HEAD"]),
+            with_methods("/admin", "writer", &["POST", "GET"]),
+            with_methods("/items", "items", &["GET"]),
+        ]);
+        assert_eq!(
+            by_method(&config, "DELETE", "/admin/users"),
+            Err(Some("GET, HEAD, POST".to_string()))
+        );
+        assert_eq!(by_method(&config, "DELETE", "/other"), Err(None));
+    }
+
+    #[test]
+    fn a_broader_route_accepting_the_method_wins_over_405() {
+        let config = compiled(vec![
+            with_methods("/admin", "writer", &["POST"]),
+            routed_to("/", "catch-all"),
+        ]);
+        assert_eq!(by_method(&config, "GET", "/admin"), Ok("catch-all"));
+        assert_eq!(by_method(&config, "POST", "/admin"), Ok("writer"));
+    }
+
+    fn with_header(mut route: Route, name: &str, value: Option<Pattern>, absent: bool) -> Route {
+        route.headers.push(HeaderMatch {
+            name: name.to_string(),
+            value: value.map(|pattern| StringMatch {
+                pattern: Some(pattern),
+                ..Default::default()
+            }),
+            absent,
+        });
+        route
+    }
+
+    fn header_cluster<'a>(
+        config: &'a ActiveConfig,
+        headers: &[(&'static str, &'static str)],
+    ) -> Option<&'a str> {
+        let mut map = http::HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, http::HeaderValue::from_static(value));
+        }
+        index(resolved(config, "GET", "/app", None, &map))
+            .map(|i| config.routes[i].route.cluster_id.as_str())
+    }
+
+    #[test]
+    fn beta_header_routes_to_the_canary_cluster() {
+        // 和 examples 里的 A/B 配置一样：X-Beta: true 去 canary，其余流量留在 primary
+        let config = compiled(vec![
+            with_header(
+                routed_to("/app", "canary"),
+                "X-Beta",
+                Some(Pattern::Exact("true".to_string())),
+                false,
+            ),
+            routed_to("/app", "primary"),
+        ]);
+        assert_eq!(
+            header_cluster(&config, &[("x-beta", "true")]),
+            Some("canary")
+        );
+        assert_eq!(
+            header_cluster(&config, &[("x-beta", "false")]),
+            Some("primary")
+        );
+        assert_eq!(header_cluster(&config, &[]), Some("primary"));
+    }
+
+    #[test]
+    fn header_predicates_match_regex_presence_and_absence() {
+        let config = compiled(vec![
+            with_header(
+                routed_to("/app", "mobile"),
+                "user-agent",
+                Some(Pattern::Regex(r".*(Android|iPhone).*".to_string())),
+                false,
+            ),
+            with_header(routed_to("/app", "debug"), "x-debug", None, false),
+            with_header(routed_to("/app", "anonymous"), "authorization", None, true),
+            routed_to("/app", "signed-in"),
+        ]);
+        assert_eq!(
+            header_cluster(
+                &config,
+                &[
+                    ("user-agent", "Mozilla/5.0 (iPhone)"),
+                    ("authorization", "x")
+                ]
+            ),
+            Some("mobile")
+        );
+        assert_eq!(
+            header_cluster(&config, &[("x-debug", ""), ("authorization", "x")]),
+            Some("debug")
+        );
+        assert_eq!(
+            header_cluster(&config, &[("user-agent", "curl/8.0")]),
+            Some("anonymous")
+        );
+        assert_eq!(
+            header_cluster(&config, &[("authorization", "Bearer t")]),
+            Some("signed-in")
+        );
+    }
+
+    #[test]
+    fn all_header_predicates_of_a_route_must_match() {
+        let route = with_header(
+            with_header(
+                routed_to("/app", "canary"),
+                "x-beta",
+                Some(Pattern::Exact("true".to_string())),
+                false,
+            ),
+            "x-region",
+            Some(Pattern::Prefix("eu-".to_string())),
+            false,
+        );
+        let config = compiled(vec![route, routed_to("/app", "primary")]);
+        assert_eq!(
+            header_cluster(&config, &[("x-beta", "true"), ("x-region", "eu-west-1")]),
+            Some("canary")
+        );
+        assert_eq!(
+            header_cluster(&config, &[("x-beta", "true"), ("x-region", "us-east-1")]),
+            Some("primary")
+        );
+    }
+
+    #[test]
+    fn staging_query_parameter_routes_to_staging() {
+        // 没有条件的生产路由写在前面也不会挡住带 ?env=staging 条件的路由
+        let config = compiled(vec![
+            routed_to("/api", "production"),
+            with_query(routed_to("/api", "staging"), "env", "staging"),
+        ]);
+        assert_eq!(
+            matched_cluster(&config, "/api/orders?env=staging"),
+            Some("staging")
+        );
+        assert_eq!(
+            matched_cluster(&config, "/api/orders?page=2&env=staging"),
+            Some("staging")
+        );
+        assert_eq!(
+            matched_cluster(&config, "/api/orders?env=prod"),
+            Some("production")
+        );
+        assert_eq!(
+            matched_cluster(&config, "/api/orders?environment=staging"),
+            Some("production")
+        );
+        assert_eq!(matched_cluster(&config, "/api/orders"), Some("production"));
+    }
+
+    #[test]
+    fn query_parameter_values_are_url_decoded() {
+        let config = compiled(vec![
+            with_query(routed_to("/search", "beta"), "channel", "beta users"),
+            routed_to("/search", "stable"),
+        ]);
+        assert_eq!(
+            matched_cluster(&config, "/search?channel=beta+users"),
+            Some("beta")
+        );
+        assert_eq!(
+            matched_cluster(&config, "/search?channel=beta%20users"),
+            Some("beta")
+        );
+        assert_eq!(
+            matched_cluster(&config, "/search?channel=beta"),
+            Some("stable")
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::outcome::RequestOutcome;
+
+// 【按路由的延迟分位数 (Route Stats)】
+// 不跑 Prometheus 的小规模部署也要能回答 "/checkout 现在的 p95 是多少"：
+// 管理端口 GET /stats/routes[?route=/checkout] 返回每个路由最近 1m / 5m / 15m 的
+// 请求数、请求速率、错误率 (5xx 以及没有发出响应的请求) 和 p50 / p90 / p99 / p999 (毫秒)。
+//
+// 数据来自 metrics::record_request，和 Prometheus 指标是同一次记录，不会重复统计。
+// 每个路由保存 15 个一分钟的槽 (环形复用)，每个槽是一个对数分桶的直方图：
+// 每个 2 的幂区间再等分 8 份，分位数取桶的中点，相对误差不超过 1/16；
+// 范围 1us ~ 71 分钟 (更长的计入最后一个桶)。每个路由固定约 15KB 内存。
+// 超过 15 分钟没有请求的路由 (例如已经从配置里删掉的) 在查询时清理掉。
+pub static ROUTE_STATS: LazyLock<RouteStats> = LazyLock::new(RouteStats::default);
+
+const SLOTS: usize = 15;
+const SUB_BUCKETS: u64 = 8;
+const BUCKETS: usize = 240;
+const WINDOWS: [(&str, u64); 3] = [("1m", 1), ("5m", 5), ("15m", 15)];
+
+#[derive(Default)]
+pub struct RouteStats {
+    routes: RwLock<HashMap<String, Mutex<RouteWindow>>>,
+}
+
+struct RouteWindow {
+    slots: Vec<Slot>,
+}
+
+#[derive(Clone)]
+struct Slot {
+    // unix 分钟数，用来判断这个槽是不是已经过期 (环形复用)
+    minute: u64,
+    requests: u64,
+    errors: u64,
+    buckets: Box<[u32; BUCKETS]>,
+}
+
+impl Slot {
+    fn empty() -> Self {
+        Self {
+            minute: 0,
+            requests: 0,
+            errors: 0,
+            buckets: Box::new([0; BUCKETS]),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WindowSummary {
+    pub requests: u64,
+    pub rps: f64,
+    pub error_rate: f64,
+    // 没有请求时为 null
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub p999_ms: Option<f64>,
+}
+
+// 延迟 (微秒) -> 桶下标
+fn bucket_index(us: u64) -> usize {
+    let us = us.min(u32::MAX as u64);
+    if us < SUB_BUCKETS {
+        return us as usize;
+    }
+    let power = 63 - us.leading_zeros() as u64;
+    let sub = (us >> (power - 3)) & (SUB_BUCKETS - 1);
+    ((power - 2) * SUB_BUCKETS + sub) as usize
+}
+
+// 桶下标 -> 桶的中点 (微秒)
+fn bucket_value(index: usize) -> f64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index as f64;
+    }
+    let power = index / SUB_BUCKETS + 2;
+    let sub = index % SUB_BUCKETS;
+    let width = 1u64 << (power - 3);
+    ((SUB_BUCKETS + sub) * width) as f64 + width as f64 / 2.0
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 60)
+        .unwrap_or(0)
+}
+
+impl RouteStats {
+    pub fn record(&self, outcome: &RequestOutcome) {
+        let route = outcome.route.as_deref().unwrap_or("-");
+        let failed = outcome.status == 0 || outcome.status >= 500;
+        let minute = current_minute();
+        if let Some(window) = self.routes.read().unwrap().get(route) {
+            window
+                .lock()
+                .unwrap()
+                .record(minute, outcome.duration_us, failed);
+            return;
+        }
+        self.routes
+            .write()
+            .unwrap()
+            .entry(route.to_string())
+            .or_insert_with(|| {
+                Mutex::new(RouteWindow {
+                    slots: vec![Slot::empty(); SLOTS],
+                })
+            })
+            .get_mut()
+            .unwrap()
+            .record(minute, outcome.duration_us, failed);
+    }
+
+    // route 为 None 时返回全部路由
+    pub fn summary(
+        &self,
+        route: Option<&str>,
+    ) -> BTreeMap<String, BTreeMap<&'static str, WindowSummary>> {
+        let minute = current_minute();
+        let mut routes = self.routes.write().unwrap();
+        routes.retain(|_, window| {
+            window
+                .get_mut()
+                .unwrap()
+                .slots
+                .iter()
+                .any(|s| s.requests > 0 && minute.saturating_sub(s.minute) < SLOTS as u64)
+        });
+        routes
+            .iter()
+            .filter(|(name, _)| route.is_none_or(|r| r == name.as_str()))
+            .map(|(name, window)| {
+                let window = window.lock().unwrap();
+                let windows = WINDOWS
+                    .iter()
+                    .map(|&(label, minutes)| (label, window.summary(minute, minutes)))
+                    .collect();
+                (name.clone(), windows)
+            })
+            .collect()
+    }
+}
+
+impl RouteWindow {
+    fn record(&mut self, minute: u64, duration_us: u64, failed: bool) {
+        let slot = &mut self.slots[minute as usize % SLOTS];
+        if slot.minute != minute {
+            *slot = Slot {
+                minute,
+                ..Slot::empty()
+            };
+        }
+        slot.requests += 1;
+        if failed {
+            slot.errors += 1;
+        }
+        slot.buckets[bucket_index(duration_us)] += 1;
+    }
+
+    // 最近 minutes 分钟 (包括当前这一分钟) 的汇总
+    fn summary(&self, now: u64, minutes: u64) -> WindowSummary {
+        let mut requests = 0;
+        let mut errors = 0;
+        let mut buckets = [0u64; BUCKETS];
+        for slot in self
+            .slots
+            .iter()
+            .filter(|s| now.saturating_sub(s.minute) < minutes)
+        {
+            requests += slot.requests;
+            errors += slot.errors;
+            for (total, &count) in buckets.iter_mut().zip(slot.buckets.iter()) {
+                *total += count as u64;
+            }
+        }
+        let percentile = |q: f64| {
+            if requests == 0 {
+                return None;
+            }
+            // 第 rank 个请求 (从 1 开始) 所在的桶
+            let rank = ((q * requests as f64).ceil() as u64).max(1);
+            let mut seen = 0;
+            let index = buckets.iter().position(|&count| {
+                seen += count;
+                seen >= rank
+            })?;
+            Some(bucket_value(index) / 1000.0)
+        };
+        WindowSummary {
+            requests,
+            rps: requests as f64 / (minutes * 60) as f64,
+            error_rate: if requests == 0 {
+                0.0
+            } else {
+                errors as f64 / requests as f64
+            },
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            p999_ms: percentile(0.999),
+        }
+    }
+}
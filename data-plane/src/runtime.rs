@@ -0,0 +1,145 @@
+// 【线程布局 (Runtime Layout)】
+// 数据面有两类线程：
+// 1. Pingora Worker 线程：处理业务流量，数量由 AGW_WORKER_THREADS 控制 (默认取 Pingora 的默认值)。
+// 2. 后台线程：配置同步、健康检查等辅助任务，统一跑在一个独立的 Tokio Runtime 上，
+//    数量由 AGW_BACKGROUND_THREADS 控制 (默认 2)。
+// 3. PROXY protocol 中继线程：只有配置了 proxy_protocol 的 Listener 时才创建，数量和 worker 线程相同。
+//
+// 在大核数机器上，可以通过 AGW_WORKER_CPUS / AGW_BACKGROUND_CPUS (如 "0-31" 或 "32,33")
+// 把两类线程钉在不同的 CPU 核上，避免后台任务抢占业务线程，改善尾延迟。
+pub struct RuntimeLayout {
+    pub worker_threads: Option<usize>,
+    pub background_threads: usize,
+    pub worker_cpus: Option<Vec<usize>>,
+    pub background_cpus: Option<Vec<usize>>,
+}
+
+impl RuntimeLayout {
+    pub fn from_env() -> Self {
+        Self {
+            worker_threads: env_usize("AGW_WORKER_THREADS"),
+            background_threads: env_usize("AGW_BACKGROUND_THREADS").unwrap_or(2).max(1),
+            worker_cpus: env_cpus("AGW_WORKER_CPUS"),
+            background_cpus: env_cpus("AGW_BACKGROUND_CPUS"),
+        }
+    }
+
+    // 启动时打印生效的线程布局
+    pub fn log(&self, effective_worker_threads: usize) {
+        println!(
+            "Runtime layout: worker_threads={} worker_cpus={} background_threads={} background_cpus={}",
+            effective_worker_threads,
+            fmt_cpus(&self.worker_cpus),
+            self.background_threads,
+            fmt_cpus(&self.background_cpus),
+        );
+    }
+
+    // 把当前 (主) 线程钉到 worker CPU 集合上。
+    // 必须在 Pingora 创建 worker 线程之前调用：Linux 上新线程会继承创建者的亲和性掩码。
+    pub fn pin_workers(&self) {
+        if let Some(cpus) = &self.worker_cpus {
+            if let Err(e) = set_current_thread_affinity(cpus) {
+                eprintln!("Failed to pin worker threads to {:?}: {}", cpus, e);
+            }
+        }
+    }
+
+    // 构建唯一的后台 Runtime。所有后台任务 (配置同步、健康检查、流量镜像等) 都应该 spawn 到这里，
+    // 而不是各自 Runtime::new() 或者 std::thread::spawn。
+    pub fn build_background_runtime(&self) -> std::io::Result<tokio::runtime::Runtime> {
+        let cpus = self.background_cpus.clone();
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(self.background_threads)
+            .thread_name("agw-bg")
+            .enable_all()
+            .on_thread_start(move || {
+                if let Some(cpus) = &cpus {
+                    if let Err(e) = set_current_thread_affinity(cpus) {
+                        eprintln!("Failed to pin background thread to {:?}: {}", cpus, e);
+                    }
+                }
+            })
+            .build()
+    }
+}
+
+// PROXY protocol 中继 (见 proxy_protocol.rs) 专用的 Runtime：中继转发的是业务流量，
+// 不能和配置同步挤在后台 Runtime 上。在 pin_workers() 之后构建，线程继承 worker 的 CPU 亲和性。
+pub fn build_relay_runtime(threads: usize) -> std::io::Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(threads.max(1))
+        .thread_name("agw-proxy-relay")
+        .enable_all()
+        .build()
+}
+
+fn env_usize(key: &str) -> Option<usize> {
+    std::env::var(key).ok().and_then(|v| v.trim().parse().ok())
+}
+
+fn env_cpus(key: &str) -> Option<Vec<usize>> {
+    let raw = std::env::var(key).ok()?;
+    match parse_cpu_list(&raw) {
+        Ok(cpus) if !cpus.is_empty() => Some(cpus),
+        Ok(_) => None,
+        Err(e) => {
+            eprintln!("Ignoring invalid {}={:?}: {}", key, raw, e);
+            None
+        }
+    }
+}
+
+// 解析 Linux 风格的 CPU 列表，例如 "0-3,8,10-11"
+fn parse_cpu_list(raw: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = Vec::new();
+    for part in raw.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        if let Some((start, end)) = part.split_once('-') {
+            let start: usize = start.trim().parse().map_err(|_| format!("bad cpu '{}'", part))?;
+            let end: usize = end.trim().parse().map_err(|_| format!("bad cpu '{}'", part))?;
+            if start > end {
+                return Err(format!("bad range '{}'", part));
+            }
+            cpus.extend(start..=end);
+        } else {
+            cpus.push(part.parse().map_err(|_| format!("bad cpu '{}'", part))?);
+        }
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+fn fmt_cpus(cpus: &Option<Vec<usize>>) -> String {
+    match cpus {
+        Some(cpus) => cpus
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+        None => "any".to_string(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_current_thread_affinity(cpus: &[usize]) -> std::io::Result<()> {
+    // SAFETY: cpu_set_t 是纯数据结构，全零即为空集合；sched_setaffinity(0, ..) 只作用于当前线程。
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_current_thread_affinity(_cpus: &[usize]) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "cpu pinning is only supported on linux",
+    ))
+}
@@ -0,0 +1,203 @@
+use crate::router::CompiledRoute;
+use serde::Serialize;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 【安全模式 (Safe Mode)】
+// 怀疑是插件导致的故障时的总开关：打开后所有 Wasm 插件都不再执行，
+// 带插件的路由按各自的 plugin_bypass_policy 处理：
+// - allow: 插件只是锦上添花 (打标签、统计)，不执行插件直接放行；
+// - deny:  插件就是鉴权层，直接返回 503 (SAFE_MODE)，绝不在没有鉴权的情况下放行。
+// 快照校验要求每个带插件的路由都声明策略，安全模式不会悄悄关掉鉴权。
+//
+// 开关不依赖配置下发：启动时用 --safe-mode 或 AGW_SAFE_MODE=1 打开，
+// 运行中通过管理端口 PUT /safe_mode {"enabled": true, "reason": "..."} 切换 (进程重启后回到启动参数)。
+// 当前状态出现在 /healthz、/status，受影响请求的访问日志带 safe_mode=bypassed / denied。
+#[derive(Default)]
+pub struct SafeMode {
+    // 请求路径上只读这一个原子变量
+    enabled: AtomicBool,
+    status: RwLock<SafeModeStatus>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SafeModeStatus {
+    pub enabled: bool,
+    // 最近一次切换的来源："flag" / "env" / "admin"
+    pub source: Option<&'static str>,
+    pub reason: Option<String>,
+    // 最近一次切换的时间 (Unix 毫秒)
+    pub changed_at_ms: Option<u64>,
+}
+
+impl SafeMode {
+    // 启动参数：命令行 --safe-mode 优先于 AGW_SAFE_MODE
+    pub fn from_startup(args: &[String]) -> Self {
+        let safe_mode = Self::default();
+        if args.iter().any(|a| a == "--safe-mode") {
+            safe_mode.set(true, "flag", None);
+        } else if std::env::var("AGW_SAFE_MODE").is_ok_and(|v| v == "1" || v == "true") {
+            safe_mode.set(true, "env", None);
+        }
+        safe_mode
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool, source: &'static str, reason: Option<String>) {
+        let mut status = self.status.write().unwrap();
+        *status = SafeModeStatus {
+            enabled,
+            source: Some(source),
+            reason,
+            changed_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_millis() as u64),
+        };
+        self.enabled.store(enabled, Ordering::Relaxed);
+        println!(
+            "safe mode {} ({}{})",
+            if enabled { "enabled" } else { "disabled" },
+            source,
+            status
+                .reason
+                .as_deref()
+                .map(|r| format!(": {}", r))
+                .unwrap_or_default()
+        );
+    }
+
+    pub fn status(&self) -> SafeModeStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    // 带插件的路由在当前开关下怎么处理：None = 安全模式关闭，照常执行插件；
+    // 否则返回访问日志里 safe_mode 的取值 "bypassed" (不执行插件直接放行) 或 "denied" (返回 503)
+    pub fn decide(&self, route: &CompiledRoute) -> Option<&'static str> {
+        if !self.enabled() {
+            return None;
+        }
+        Some(if route.bypasses_plugins_in_safe_mode() {
+            "bypassed"
+        } else {
+            "denied"
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::agw::config::v1::{Cluster, Plugin, PluginBypassPolicy, Route};
+    use crate::client::agw::v1::ConfigSnapshot;
+    use crate::router::ActiveConfig;
+    use std::sync::atomic::AtomicUsize;
+
+    // /tags 声明 allow，/api 声明 deny，/health 没有插件
+    fn config() -> ActiveConfig {
+        let route = |path: &str, policy: PluginBypassPolicy, plugins: bool| {
+            let mut route = Route {
+                path_prefix: path.to_string(),
+                cluster_id: "backend".to_string(),
+                ..Default::default()
+            };
+            route.set_plugin_bypass_policy(policy);
+            if plugins {
+                route.plugins.push(Plugin {
+                    name: "auth".to_string(),
+                    ..Default::default()
+                });
+            }
+            route
+        };
+        ActiveConfig::compile(ConfigSnapshot {
+            clusters: vec![Cluster {
+                name: "backend".to_string(),
+                ..Default::default()
+            }],
+            routes: vec![
+                route("/tags", PluginBypassPolicy::PluginBypassAllow, true),
+                route("/api", PluginBypassPolicy::PluginBypassDeny, true),
+                route(
+                    "/health",
+                    PluginBypassPolicy::PluginBypassUnspecified,
+                    false,
+                ),
+            ],
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn each_policy_decides_while_enabled() {
+        let config = config();
+        let (allow, deny) = (&config.routes[0], &config.routes[1]);
+        let safe_mode = SafeMode::default();
+        assert_eq!(
+            (safe_mode.decide(allow), safe_mode.decide(deny)),
+            (None, None)
+        );
+
+        safe_mode.set(true, "admin", Some("plugin crash".to_string()));
+        assert_eq!(safe_mode.decide(allow), Some("bypassed"));
+        assert_eq!(safe_mode.decide(deny), Some("denied"));
+        let status = safe_mode.status();
+        assert!(status.enabled);
+        assert_eq!(status.source, Some("admin"));
+        assert_eq!(status.reason.as_deref(), Some("plugin crash"));
+
+        safe_mode.set(false, "admin", None);
+        assert_eq!(
+            (safe_mode.decide(allow), safe_mode.decide(deny)),
+            (None, None)
+        );
+        assert!(!config.routes[2].has_plugins());
+    }
+
+    // 请求线程一直在读开关，管理端口反复切换：deny 路由任何时候都不会被放行，allow 路由不会被拒绝，
+    // 关闭之后的请求不再受安全模式影响
+    #[test]
+    fn toggling_under_load_never_bypasses_a_deny_route() {
+        let config = config();
+        let safe_mode = SafeMode::default();
+        let stop = AtomicBool::new(false);
+        let (bypassed, denied) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while !stop.load(Ordering::Relaxed) {
+                        match safe_mode.decide(&config.routes[0]) {
+                            None => {}
+                            Some("bypassed") => {
+                                bypassed.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Some(other) => panic!("allow route decided {:?}", other),
+                        }
+                        match safe_mode.decide(&config.routes[1]) {
+                            None => {}
+                            Some("denied") => {
+                                denied.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Some(other) => panic!("deny route decided {:?}", other),
+                        }
+                    }
+                });
+            }
+            for i in 0..200 {
+                safe_mode.set(i % 2 == 0, "admin", None);
+                std::thread::sleep(std::time::Duration::from_micros(200));
+            }
+            safe_mode.set(false, "admin", None);
+            assert_eq!(safe_mode.decide(&config.routes[1]), None);
+            stop.store(true, Ordering::Relaxed);
+        });
+        assert!(bypassed.load(Ordering::Relaxed) > 0);
+        assert!(denied.load(Ordering::Relaxed) > 0);
+        assert!(!safe_mode.status().enabled);
+    }
+}
@@ -0,0 +1,404 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+
+use crate::attributes::{RequestAttributes, ValueSource};
+
+// 【集群选择表达式 (Cluster Selector)】
+// "cluster = shard- + (user_id mod 8)" 这类规则，写 Wasm 插件或者拆成 8 条路由都太笨重。
+// 路由可以配置一个小表达式，请求时用请求头 / 请求属性算出集群名：
+//
+//   "shard-" + hash(header("x-user-id")) % 8
+//   attr("tenant.tier") == "premium" ? "orders-premium" : "orders"
+//
+// 语法 (优先级从低到高)：
+// - cond ? a : b          条件 (cond 必须是比较的结果)
+// - a == b / a != b       按字符串比较
+// - a + b                 字符串拼接 (整数按十进制)
+// - a % b                 取模 (字符串按十进制解析为整数)
+// - "text" / 42 / (expr)
+// - attr("jwt.sub")       请求属性；header("x-user-id") 请求头；hash(expr) 稳定的 64 位哈希 (FNV-1a)
+//
+// 表达式在校验阶段编译，语法错误拒绝整份快照；能静态枚举出全部结果的表达式 (如 "shard-" + n % 8)
+// 还会检查每个结果都是快照里存在的集群。请求时求值只在拼接处分配一次字符串；
+// 属性 / 请求头不存在、取模失败、结果不是已知集群时，回退到路由的 cluster_id。
+#[derive(Debug)]
+pub struct ClusterSelector {
+    expr: Expr,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Str(String),
+    Int(u64),
+    Lookup(ValueSource),
+    Hash(Box<Expr>),
+    Concat(Vec<Expr>),
+    Mod(Box<Expr>, Box<Expr>),
+    // (左, 右, 是否为 !=)
+    Compare(Box<Expr>, Box<Expr>, bool),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+enum Value<'a> {
+    Str(Cow<'a, str>),
+    Int(u64),
+    Bool(bool),
+}
+
+impl Value<'_> {
+    fn as_str(&self) -> Option<Cow<'_, str>> {
+        match self {
+            Value::Str(s) => Some(Cow::Borrowed(s)),
+            Value::Int(n) => Some(Cow::Owned(n.to_string())),
+            Value::Bool(_) => None,
+        }
+    }
+
+    fn as_int(&self) -> Option<u64> {
+        match self {
+            Value::Str(s) => s.trim().parse().ok(),
+            Value::Int(n) => Some(*n),
+            Value::Bool(_) => None,
+        }
+    }
+}
+
+// 表达式源码最大长度 / 最大嵌套深度
+const MAX_LEN: usize = 1024;
+const MAX_DEPTH: usize = 32;
+// 静态枚举结果的上限，超过则不做校验 (只在运行时回退)
+const MAX_OUTCOMES: usize = 256;
+
+impl ClusterSelector {
+    pub fn compile(source: &str) -> Result<Self, String> {
+        if source.len() > MAX_LEN {
+            return Err(format!(
+                "cluster selector too long ({} > {} bytes)",
+                source.len(),
+                MAX_LEN
+            ));
+        }
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.expr()?;
+        if let Some((offset, token)) = tokens.get(parser.pos) {
+            return Err(format!("unexpected {:?} at offset {}", token, offset));
+        }
+        Ok(Self { expr })
+    }
+
+    // 求值得到集群名；任何一步取不到值时返回 None (调用方回退到路由的 cluster_id)
+    pub fn select(
+        &self,
+        headers: &http::HeaderMap,
+        attributes: &RequestAttributes,
+    ) -> Option<String> {
+        let value = self.expr.eval(headers, attributes)?;
+        value.as_str().map(Cow::into_owned)
+    }
+
+    // 静态可枚举时返回全部可能的结果，用于校验集群是否存在
+    pub fn outcomes(&self) -> Option<BTreeSet<String>> {
+        self.expr.outcomes()
+    }
+}
+
+impl Expr {
+    fn eval<'a>(
+        &'a self,
+        headers: &'a http::HeaderMap,
+        attributes: &'a RequestAttributes,
+    ) -> Option<Value<'a>> {
+        match self {
+            Expr::Str(s) => Some(Value::Str(Cow::Borrowed(s))),
+            Expr::Int(n) => Some(Value::Int(*n)),
+            Expr::Lookup(source) => source
+                .get(headers, attributes)
+                .map(|v| Value::Str(Cow::Borrowed(v))),
+            Expr::Hash(inner) => {
+                let value = inner.eval(headers, attributes)?;
+                Some(Value::Int(fnv1a(value.as_str()?.as_bytes())))
+            }
+            Expr::Concat(parts) => {
+                let mut out = String::new();
+                for part in parts {
+                    out.push_str(&part.eval(headers, attributes)?.as_str()?);
+                }
+                Some(Value::Str(Cow::Owned(out)))
+            }
+            Expr::Mod(a, b) => {
+                let a = a.eval(headers, attributes)?.as_int()?;
+                let b = b.eval(headers, attributes)?.as_int()?;
+                a.checked_rem(b).map(Value::Int)
+            }
+            Expr::Compare(a, b, negate) => {
+                let a = a.eval(headers, attributes)?;
+                let b = b.eval(headers, attributes)?;
+                Some(Value::Bool((a.as_str()? == b.as_str()?) != *negate))
+            }
+            Expr::Cond(cond, then, otherwise) => match cond.eval(headers, attributes)? {
+                Value::Bool(true) => then.eval(headers, attributes),
+                Value::Bool(false) => otherwise.eval(headers, attributes),
+                _ => None,
+            },
+        }
+    }
+
+    fn outcomes(&self) -> Option<BTreeSet<String>> {
+        match self {
+            Expr::Str(s) => Some(BTreeSet::from([s.clone()])),
+            Expr::Int(n) => Some(BTreeSet::from([n.to_string()])),
+            Expr::Mod(_, divisor) => match **divisor {
+                Expr::Int(n) if n > 0 && n as usize <= MAX_OUTCOMES => {
+                    Some((0..n).map(|i| i.to_string()).collect())
+                }
+                _ => None,
+            },
+            Expr::Concat(parts) => {
+                let mut out = BTreeSet::from([String::new()]);
+                for part in parts {
+                    let suffixes = part.outcomes()?;
+                    if out.len() * suffixes.len() > MAX_OUTCOMES {
+                        return None;
+                    }
+                    out = out
+                        .iter()
+                        .flat_map(|prefix| suffixes.iter().map(move |s| format!("{}{}", prefix, s)))
+                        .collect();
+                }
+                Some(out)
+            }
+            Expr::Cond(_, then, otherwise) => {
+                let mut out = then.outcomes()?;
+                out.extend(otherwise.outcomes()?);
+                (out.len() <= MAX_OUTCOMES).then_some(out)
+            }
+            Expr::Lookup(_) | Expr::Hash(_) | Expr::Compare(..) => None,
+        }
+    }
+}
+
+// FNV-1a 64：跨进程、跨版本稳定 (不能用 std 的 DefaultHasher，它的结果不保证稳定)
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Str(String),
+    Int(u64),
+    Ident(String),
+    LParen,
+    RParen,
+    Plus,
+    Percent,
+    Eq,
+    NotEq,
+    Question,
+    Colon,
+}
+
+// 返回 (源码偏移, token)，偏移用于错误信息
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '+' => Token::Plus,
+            '%' => Token::Percent,
+            '?' => Token::Question,
+            ':' => Token::Colon,
+            '=' | '!' => {
+                if chars.next_if(|(_, c)| *c == '=').is_none() {
+                    return Err(format!("expected \"{}=\" at offset {}", c, offset));
+                }
+                if c == '=' { Token::Eq } else { Token::NotEq }
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => s.push(c),
+                            _ => return Err(format!("bad escape in string at offset {}", offset)),
+                        },
+                        Some((_, c)) => s.push(c),
+                        None => return Err(format!("unterminated string at offset {}", offset)),
+                    }
+                }
+                Token::Str(s)
+            }
+            c if c.is_ascii_digit() => {
+                let mut n = c.to_digit(10).unwrap() as u64;
+                while let Some((_, d)) = chars.next_if(|(_, d)| d.is_ascii_digit()) {
+                    n = n
+                        .checked_mul(10)
+                        .and_then(|n| n.checked_add(d.to_digit(10).unwrap() as u64))
+                        .ok_or_else(|| format!("integer too large at offset {}", offset))?;
+                }
+                Token::Int(n)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    ident.push(c);
+                }
+                Token::Ident(ident)
+            }
+            c => return Err(format!("unexpected character {:?} at offset {}", c, offset)),
+        };
+        tokens.push((offset, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .map(|(_, t)| t.clone())
+            .ok_or_else(|| "unexpected end of expression".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, want: Token) -> Result<(), String> {
+        let offset = self.tokens.get(self.pos).map(|(o, _)| *o);
+        match self.next()? {
+            token if token == want => Ok(()),
+            token => Err(format!(
+                "expected {:?}, found {:?} at offset {}",
+                want,
+                token,
+                offset.unwrap_or_default()
+            )),
+        }
+    }
+
+    // cond ? a : b
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("cluster selector nested too deeply".to_string());
+        }
+        let cond = self.compare()?;
+        let expr = if self.peek() == Some(&Token::Question) {
+            self.pos += 1;
+            let then = self.expr()?;
+            self.expect(Token::Colon)?;
+            let otherwise = self.expr()?;
+            Expr::Cond(Box::new(cond), Box::new(then), Box::new(otherwise))
+        } else {
+            cond
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let left = self.concat()?;
+        let negate = match self.peek() {
+            Some(Token::Eq) => false,
+            Some(Token::NotEq) => true,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.concat()?;
+        Ok(Expr::Compare(Box::new(left), Box::new(right), negate))
+    }
+
+    fn concat(&mut self) -> Result<Expr, String> {
+        let mut parts = vec![self.modulo()?];
+        while self.peek() == Some(&Token::Plus) {
+            self.pos += 1;
+            parts.push(self.modulo()?);
+        }
+        Ok(if parts.len() == 1 {
+            parts.pop().unwrap()
+        } else {
+            Expr::Concat(parts)
+        })
+    }
+
+    fn modulo(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+        while self.peek() == Some(&Token::Percent) {
+            self.pos += 1;
+            let divisor = self.primary()?;
+            if matches!(divisor, Expr::Int(0)) {
+                return Err("modulo by zero".to_string());
+            }
+            expr = Expr::Mod(Box::new(expr), Box::new(divisor));
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let offset = self
+            .tokens
+            .get(self.pos)
+            .map(|(o, _)| *o)
+            .unwrap_or_default();
+        match self.next()? {
+            Token::Str(s) => Ok(Expr::Str(s)),
+            Token::Int(n) => Ok(Expr::Int(n)),
+            Token::LParen => {
+                let expr = self.expr()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Token::Ident(name) => {
+                self.expect(Token::LParen)?;
+                let expr = match name.as_str() {
+                    "attr" | "header" => {
+                        let Token::Str(key) = self.next()? else {
+                            return Err(format!(
+                                "{}() takes a string literal at offset {}",
+                                name, offset
+                            ));
+                        };
+                        if key.is_empty() {
+                            return Err(format!("{}() with empty name at offset {}", name, offset));
+                        }
+                        Expr::Lookup(if name == "header" {
+                            ValueSource::Header(key.to_ascii_lowercase())
+                        } else {
+                            ValueSource::Attribute(key)
+                        })
+                    }
+                    "hash" => Expr::Hash(Box::new(self.expr()?)),
+                    _ => return Err(format!("unknown function {:?} at offset {}", name, offset)),
+                };
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            token => Err(format!("unexpected {:?} at offset {}", token, offset)),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+
+use crate::client::agw::config::v1::WeightedCluster;
+
+// 【按权重分流到多个集群 (Weighted Clusters)】
+// 路由用 weighted_clusters 代替单个 cluster_id，例如 users-v1 权重 95、users-v2 权重 5：
+// request_filter 里按权重随机选一个集群记进 CTX，upstream_peer (包括重试) 都用这一个，
+// 同一个请求不会在两个版本之间来回切换。
+//
+// - 权重可以为 0，表示暂时不再分流量过去 (drain)，但仍保留在配置里；权重之和必须大于 0。
+// - 金丝雀名单优先于分流；集群选择表达式的结果不是已知集群时，回退到分流选出的集群。
+// - 选择是逐请求独立的随机数，不保证同一个客户端落在同一个集群；需要粘性时用金丝雀名单。
+#[derive(Debug)]
+pub struct CompiledSplit {
+    // (集群名, 累计权重)，按配置顺序；累计权重严格递增的项才可能被选中
+    clusters: Vec<(String, u64)>,
+    total: u64,
+}
+
+// 上限：分流是给几个版本之间切流量用的，不是负载均衡
+const MAX_CLUSTERS: usize = 16;
+
+impl CompiledSplit {
+    pub fn compile(clusters: &[WeightedCluster]) -> Result<Self, String> {
+        if clusters.len() > MAX_CLUSTERS {
+            return Err(format!(
+                "{} weighted clusters, the limit is {}",
+                clusters.len(),
+                MAX_CLUSTERS
+            ));
+        }
+        let mut seen = HashSet::new();
+        let mut cumulative = Vec::with_capacity(clusters.len());
+        let mut total = 0u64;
+        for weighted in clusters {
+            if weighted.cluster_id.is_empty() {
+                return Err("weighted cluster without cluster_id".to_string());
+            }
+            if !seen.insert(weighted.cluster_id.as_str()) {
+                return Err(format!("cluster {:?} listed twice", weighted.cluster_id));
+            }
+            total += weighted.weight as u64;
+            cumulative.push((weighted.cluster_id.clone(), total));
+        }
+        if total == 0 {
+            return Err("the weights add up to 0, no cluster can receive traffic".to_string());
+        }
+        Ok(Self {
+            clusters: cumulative,
+            total,
+        })
+    }
+
+    pub fn cluster_names(&self) -> impl Iterator<Item = &str> {
+        self.clusters.iter().map(|(name, _)| name.as_str())
+    }
+
+    // r 为 [0, 1) 的随机数
+    pub fn pick(&self, r: f64) -> &str {
+        let target = ((r * self.total as f64) as u64).min(self.total - 1);
+        let index = self
+            .clusters
+            .partition_point(|&(_, cumulative)| cumulative <= target);
+        &self.clusters[index].0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const PICKS: usize = 10_000;
+
+    fn split(weights: &[(&str, u32)]) -> CompiledSplit {
+        let clusters: Vec<WeightedCluster> = weights
+            .iter()
+            .map(|&(name, weight)| WeightedCluster {
+                cluster_id: name.to_string(),
+                weight,
+            })
+            .collect();
+        CompiledSplit::compile(&clusters).unwrap()
+    }
+
+    // 用固定种子选 PICKS 次，返回每个集群被选中的比例 (按配置顺序)
+    fn shares(split: &CompiledSplit) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let names: Vec<&str> = split.cluster_names().collect();
+        let mut counts = vec![0usize; names.len()];
+        for _ in 0..PICKS {
+            let picked = split.pick(rng.r#gen::<f64>());
+            counts[names.iter().position(|&name| name == picked).unwrap()] += 1;
+        }
+        counts.iter().map(|&c| c as f64 / PICKS as f64).collect()
+    }
+
+    // 每个集群的实际比例和期望比例相差不超过 1 个百分点
+    fn assert_distribution(actual: &[f64], expected: &[f64]) {
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (a - e).abs() <= 0.01,
+                "cluster {}: share {} expected {}",
+                i,
+                a,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn picks_follow_the_weights() {
+        let actual = shares(&split(&[("users-v1", 95), ("users-v2", 5)]));
+        assert_distribution(&actual, &[0.95, 0.05]);
+
+        let actual = shares(&split(&[("a", 1), ("b", 2), ("c", 7)]));
+        assert_distribution(&actual, &[0.1, 0.2, 0.7]);
+    }
+
+    #[test]
+    fn drained_clusters_receive_nothing() {
+        let drained = split(&[
+            ("users-v1", 0),
+            ("users-v2", 3),
+            ("users-v3", 0),
+            ("users-v4", 1),
+        ]);
+        let actual = shares(&drained);
+        assert_eq!((actual[0], actual[2]), (0.0, 0.0));
+        assert_distribution(&actual, &[0.0, 0.75, 0.0, 0.25]);
+
+        // 区间的两端也不会落在权重为 0 的集群上
+        assert_eq!(drained.pick(0.0), "users-v2");
+        assert_eq!(drained.pick(0.75), "users-v4");
+        assert_eq!(drained.pick(1.0 - f64::EPSILON), "users-v4");
+        assert_eq!(drained.pick(1.0), "users-v4");
+    }
+
+    #[test]
+    fn invalid_splits_are_rejected() {
+        let compile = |weights: &[(&str, u32)]| {
+            let clusters: Vec<WeightedCluster> = weights
+                .iter()
+                .map(|&(name, weight)| WeightedCluster {
+                    cluster_id: name.to_string(),
+                    weight,
+                })
+                .collect();
+            CompiledSplit::compile(&clusters).err()
+        };
+        assert_eq!(
+            compile(&[("a", 0), ("b", 0)]).as_deref(),
+            Some("the weights add up to 0, no cluster can receive traffic")
+        );
+        assert_eq!(
+            compile(&[("a", 1), ("a", 2)]).as_deref(),
+            Some("cluster \"a\" listed twice")
+        );
+        assert_eq!(
+            compile(&[("", 1)]).as_deref(),
+            Some("weighted cluster without cluster_id")
+        );
+        let names: Vec<String> = (0..=MAX_CLUSTERS).map(|i| format!("c{}", i)).collect();
+        let too_many: Vec<(&str, u32)> = names.iter().map(|name| (name.as_str(), 1)).collect();
+        assert!(compile(&too_many).is_some());
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt::Write;
+
+// 【分布式追踪上下文 (W3C Trace Context)】
+// 网关在链路中也是一跳：入口请求带 traceparent 时，网关作为它的子 span；没有时自己作为根 span。
+// 转发上游前把 traceparent 换成网关自己的 span id，上游服务的 span 因此挂在网关下面，
+// 而不是越过网关直接挂到客户端上。tracestate 原样透传。
+//
+// trace_id / span_id / parent_span_id 写进 RequestOutcome，访问日志里按 trace_id 就能和
+// 各服务的 trace 关联起来。OTLP 导出 (把网关 span 连同路由命中、插件决策、上游选择等事件发往
+// Collector) 还没有实现，见 specs/ROADMAP.md。
+//
+// 格式: "00-<32 hex trace-id>-<16 hex parent-id>-<2 hex flags>"
+// 非法的 traceparent (版本 ff、长度不对、全 0 的 id、大写 hex) 按规范视为没有，重新开始一条 trace。
+#[derive(Debug, Clone, Copy)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    // 网关自己的 span
+    pub span_id: [u8; 8],
+    // 客户端传入的父 span (没有时网关是根 span)
+    pub parent_span_id: Option<[u8; 8]>,
+    pub flags: u8,
+}
+
+// 新开始的 trace 默认采样
+const FLAG_SAMPLED: u8 = 0x01;
+
+impl TraceContext {
+    pub fn from_request(headers: &http::HeaderMap) -> Self {
+        let parent = headers
+            .get("traceparent")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_traceparent);
+        match parent {
+            Some((trace_id, parent_span_id, flags)) => Self {
+                trace_id,
+                span_id: new_span_id(),
+                parent_span_id: Some(parent_span_id),
+                flags,
+            },
+            None => Self {
+                trace_id: new_trace_id(),
+                span_id: new_span_id(),
+                parent_span_id: None,
+                flags: FLAG_SAMPLED,
+            },
+        }
+    }
+
+    // 转发给上游的 traceparent (父 span 为网关)
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.flags
+        )
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
+
+fn parse_traceparent(value: &str) -> Option<([u8; 16], [u8; 8], u8)> {
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+    // 版本 00 不允许有多余字段；未来版本可以在后面追加字段，只解析前四个
+    if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    decode::<1>(version)?;
+    let trace_id = decode::<16>(trace_id)?;
+    let parent_id = decode::<8>(parent_id)?;
+    let [flags] = decode::<1>(flags)?;
+    if trace_id == [0; 16] || parent_id == [0; 8] {
+        return None;
+    }
+    Some((trace_id, parent_id, flags))
+}
+
+// 定长小写 hex 解码
+fn decode<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 || !s.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let mut out = [0u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+fn new_trace_id() -> [u8; 16] {
+    loop {
+        let id: [u8; 16] = rand::random();
+        if id != [0; 16] {
+            return id;
+        }
+    }
+}
+
+fn new_span_id() -> [u8; 8] {
+    loop {
+        let id: [u8; 8] = rand::random();
+        if id != [0; 8] {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    fn context(traceparent: Option<&str>) -> TraceContext {
+        let mut headers = http::HeaderMap::new();
+        if let Some(value) = traceparent {
+            headers.insert("traceparent", value.parse().unwrap());
+        }
+        TraceContext::from_request(&headers)
+    }
+
+    #[test]
+    fn incoming_traceparent_makes_the_gateway_a_child_span() {
+        let trace = context(Some(&format!("00-{}-{}-00", TRACE_ID, PARENT_ID)));
+        assert_eq!(hex(&trace.trace_id), TRACE_ID);
+        assert_eq!(
+            trace.parent_span_id.map(|id| hex(&id)).as_deref(),
+            Some(PARENT_ID)
+        );
+        assert_ne!(hex(&trace.span_id), PARENT_ID);
+        // 客户端的采样决定原样保留
+        assert_eq!(trace.flags, 0x00);
+    }
+
+    #[test]
+    fn forwarded_traceparent_names_the_gateway_span() {
+        let trace = context(Some(&format!("00-{}-{}-01", TRACE_ID, PARENT_ID)));
+        let forwarded = trace.traceparent();
+        assert_eq!(
+            forwarded,
+            format!("00-{}-{}-01", TRACE_ID, hex(&trace.span_id))
+        );
+        // 上游再解析出来：同一条 trace，父 span 是网关
+        let upstream = context(Some(&forwarded));
+        assert_eq!(upstream.trace_id, trace.trace_id);
+        assert_eq!(upstream.parent_span_id, Some(trace.span_id));
+    }
+
+    #[test]
+    fn missing_or_invalid_traceparent_starts_a_new_trace() {
+        let invalid = [
+            None,
+            Some(format!("ff-{}-{}-01", TRACE_ID, PARENT_ID)),
+            Some(format!("00-{}-{}-01-extra", TRACE_ID, PARENT_ID)),
+            Some(format!("00-{}-{}-01", TRACE_ID.to_uppercase(), PARENT_ID)),
+            Some(format!("00-{}-{}-01", &TRACE_ID[1..], PARENT_ID)),
+            Some(format!("00-{}-{}-01", "0".repeat(32), PARENT_ID)),
+            Some(format!("00-{}-{}-01", TRACE_ID, "0".repeat(16))),
+            Some("garbage".to_string()),
+        ];
+        for value in invalid {
+            let trace = context(value.as_deref());
+            assert_eq!(trace.parent_span_id, None, "{:?}", value);
+            assert_ne!(hex(&trace.trace_id), TRACE_ID, "{:?}", value);
+            assert_ne!(trace.trace_id, [0; 16]);
+            assert_eq!(trace.flags, FLAG_SAMPLED);
+        }
+    }
+
+    // 未来版本可以在后面追加字段，只看前四个
+    #[test]
+    fn future_versions_may_append_fields() {
+        let trace = context(Some(&format!("01-{}-{}-01-what-ever", TRACE_ID, PARENT_ID)));
+        assert_eq!(hex(&trace.trace_id), TRACE_ID);
+        assert_eq!(
+            trace.parent_span_id.map(|id| hex(&id)).as_deref(),
+            Some(PARENT_ID)
+        );
+    }
+}
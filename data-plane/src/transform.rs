@@ -0,0 +1,199 @@
+use pingora::http::{RequestHeader, ResponseHeader};
+
+use crate::client::agw::config::v1::HeaderTransform;
+
+// 【请求头 / 响应头改写 (Header Transform)】
+// 路由上的 request_headers 在请求转发 (以及镜像) 之前改动请求头，例如：
+// - set:           X-Gateway-Route: /checkout (覆盖客户端自己带的值)
+// - set_if_absent: X-Request-Priority: normal (客户端没带时补一个默认值)
+// - add:           追加一个值，保留已有的值
+// - remove:        去掉客户端带来的内部头，如 "x-debug-*" (大小写无关，"*" 匹配任意字符)
+// 顺序固定为 remove -> set -> set_if_absent -> add，所以 remove 掉的头可以用 set 重新写入。
+// 一个头有多个值 (多行同名头) 时，remove 和 set 都作用于全部的值。
+//
+// 请求头默认在插件链之后执行，插件看到的是客户端的原始请求头；before_plugins 时在插件链之前执行，插件看到改写后的结果。
+// response_headers 用同一套规则改写上游的响应头 (去掉 Server、X-Powered-By，加 Cache-Control)，
+// 在 response_filter 里执行：网关缓存、幂等回放存下的都是改写后的响应头。
+// Host、Content-Length、Transfer-Encoding、Connection 等由网关管理的头 (请求边界和逐跳头) 不能改，快照校验时拒绝。
+#[derive(Debug)]
+pub struct CompiledHeaderTransform {
+    remove: Vec<RemoveRule>,
+    // (配置里写的头名称, 值)；按名称排序，map 的遍历顺序不固定
+    set: Vec<(String, http::HeaderValue)>,
+    set_if_absent: Vec<(String, http::HeaderValue)>,
+    add: Vec<(String, http::HeaderValue)>,
+    // 只对 request_headers 有意义
+    pub before_plugins: bool,
+}
+
+#[derive(Debug)]
+enum RemoveRule {
+    Exact(http::HeaderName),
+    // 小写的模式按 "*" 切开的各段
+    Glob(Vec<String>),
+}
+
+// 改了会破坏转发的头：请求边界、逐跳头 (连接管理)，以及有专门配置项的 Host
+const PROTECTED: &[&str] = &[
+    "host",
+    "content-length",
+    "transfer-encoding",
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "upgrade",
+    "te",
+];
+
+impl CompiledHeaderTransform {
+    pub fn compile(transform: &HeaderTransform) -> Result<Self, String> {
+        let mut remove = Vec::with_capacity(transform.remove.len());
+        for pattern in &transform.remove {
+            let lower = pattern.to_ascii_lowercase();
+            // "*" 换成任意合法字符后必须是合法的头名称
+            let name = http::HeaderName::from_bytes(lower.replace('*', "x").as_bytes())
+                .map_err(|_| format!("invalid header pattern {:?} in remove", pattern))?;
+            let rule = if lower.contains('*') {
+                RemoveRule::Glob(lower.split('*').map(str::to_string).collect())
+            } else {
+                RemoveRule::Exact(name)
+            };
+            if let Some(name) = PROTECTED.iter().find(|name| rule.matches(name)) {
+                return Err(format!(
+                    "remove pattern {:?} would remove {}, which the gateway manages",
+                    pattern, name
+                ));
+            }
+            remove.push(rule);
+        }
+        Ok(Self {
+            remove,
+            set: compile_values("set", &transform.set)?,
+            set_if_absent: compile_values("set_if_absent", &transform.set_if_absent)?,
+            add: compile_values("add", &transform.add)?,
+            before_plugins: transform.before_plugins,
+        })
+    }
+
+    pub fn apply<H: HeaderEdit>(&self, target: &mut H) -> pingora::Result<()> {
+        for rule in &self.remove {
+            match rule {
+                RemoveRule::Exact(name) => target.remove(name),
+                RemoveRule::Glob(_) => {
+                    let names: Vec<http::HeaderName> = target
+                        .headers()
+                        .keys()
+                        .filter(|name| rule.matches(name.as_str()))
+                        .cloned()
+                        .collect();
+                    for name in &names {
+                        target.remove(name);
+                    }
+                }
+            }
+        }
+        for (name, value) in &self.set {
+            target.insert(name, value)?;
+        }
+        for (name, value) in &self.set_if_absent {
+            if !target.headers().contains_key(name.as_str()) {
+                target.insert(name, value)?;
+            }
+        }
+        for (name, value) in &self.add {
+            target.append(name, value)?;
+        }
+        Ok(())
+    }
+}
+
+// Pingora 的请求头和响应头各自维护原始大小写，改动要走它们自己的方法
+pub trait HeaderEdit {
+    fn headers(&self) -> &http::HeaderMap;
+    fn remove(&mut self, name: &http::HeaderName);
+    fn insert(&mut self, name: &str, value: &http::HeaderValue) -> pingora::Result<()>;
+    fn append(&mut self, name: &str, value: &http::HeaderValue) -> pingora::Result<()>;
+}
+
+impl HeaderEdit for RequestHeader {
+    fn headers(&self) -> &http::HeaderMap {
+        &self.headers
+    }
+    fn remove(&mut self, name: &http::HeaderName) {
+        self.remove_header(name);
+    }
+    fn insert(&mut self, name: &str, value: &http::HeaderValue) -> pingora::Result<()> {
+        self.insert_header(name.to_string(), value.clone())
+    }
+    fn append(&mut self, name: &str, value: &http::HeaderValue) -> pingora::Result<()> {
+        self.append_header(name.to_string(), value.clone())
+            .map(|_| ())
+    }
+}
+
+impl HeaderEdit for ResponseHeader {
+    fn headers(&self) -> &http::HeaderMap {
+        &self.headers
+    }
+    fn remove(&mut self, name: &http::HeaderName) {
+        self.remove_header(name);
+    }
+    fn insert(&mut self, name: &str, value: &http::HeaderValue) -> pingora::Result<()> {
+        self.insert_header(name.to_string(), value.clone())
+    }
+    fn append(&mut self, name: &str, value: &http::HeaderValue) -> pingora::Result<()> {
+        self.append_header(name.to_string(), value.clone())
+            .map(|_| ())
+    }
+}
+
+impl RemoveRule {
+    // name 为小写的头名称
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            RemoveRule::Exact(exact) => exact.as_str() == name,
+            RemoveRule::Glob(parts) => glob_match(parts, name),
+        }
+    }
+}
+
+// parts 为模式按 "*" 切开的各段 (至少两段)：首段是前缀，末段是后缀，中间各段依次出现
+fn glob_match(parts: &[String], name: &str) -> bool {
+    let (Some(first), Some(last)) = (parts.first(), parts.last()) else {
+        return false;
+    };
+    let Some(mut rest) = name.strip_prefix(first.as_str()) else {
+        return false;
+    };
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part.as_str()) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last.as_str())
+}
+
+fn compile_values(
+    field: &str,
+    values: &std::collections::HashMap<String, String>,
+) -> Result<Vec<(String, http::HeaderValue)>, String> {
+    let mut sorted: Vec<_> = values.iter().collect();
+    sorted.sort();
+    sorted
+        .into_iter()
+        .map(|(name, value)| {
+            let parsed = http::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name {:?} in {}", name, field))?;
+            if PROTECTED.contains(&parsed.as_str()) {
+                return Err(format!(
+                    "{} cannot change {}, which the gateway manages",
+                    field, parsed
+                ));
+            }
+            let value = http::HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value for header {} in {}", name, field))?;
+            Ok((name.clone(), value))
+        })
+        .collect()
+}
@@ -0,0 +1,138 @@
+use pingora::upstreams::peer::HttpPeer;
+use std::collections::{HashMap, HashSet};
+
+use crate::attributes::RequestAttributes;
+use crate::client::agw::config::v1::{Endpoint, Protocol, SubsetSelector};
+use crate::client::agw::v1::ConfigSnapshot;
+
+// 【上游节点 (Upstream Endpoint)】
+// Endpoint 有两种形态，同一个 Cluster 里可以混用：
+// - TCP: address + port，标签为 "ip:port"
+// - UDS: unix_path (本地 sidecar)，标签为 "unix:<path>"，省掉 TCP loopback 这一跳
+// 标签用于访问日志、/upstreams 统计等所有 "按节点" 区分的地方。
+// 连接池由 Pingora 按 peer 的地址区分，UDS 和 TCP 节点各自独立复用连接。
+pub fn endpoint_label(endpoint: &Endpoint) -> String {
+    if endpoint.unix_path.is_empty() {
+        format!("{}:{}", endpoint.address, endpoint.port)
+    } else {
+        format!("unix:{}", endpoint.unix_path)
+    }
+}
+
+// 快照里所有节点的稳定标识：集群名 -> 节点标签集合。
+// 运行时状态 (健康、负载均衡计数、异常统计) 都按这个标识保存，跨快照时标识不变就原样保留。
+pub fn endpoint_identities(snapshot: &ConfigSnapshot) -> HashMap<String, HashSet<String>> {
+    snapshot
+        .clusters
+        .iter()
+        .map(|c| (c.name.clone(), c.endpoints.iter().map(endpoint_label).collect()))
+        .collect()
+}
+
+// 【Host 改写 (host_rewrite)】
+// 虚拟主机方式部署的上游只认自己的域名。路由 (优先) 或集群可以指定转发时的 Host：
+// - 固定值，如 "api.internal.example.com"
+// - "$endpoint"：选中节点的地址 (端口不是 80 时带上 ":port")；UDS 节点没有地址，保留客户端的 Host
+// 只改转发给上游的请求 (upstream_request_filter)，客户端看到的请求和访问日志不受影响。
+// (以后支持上游 TLS 时，SNI 也应该用这个值)
+pub const HOST_REWRITE_ENDPOINT: &str = "$endpoint";
+
+pub fn rewritten_host(rewrite: &str, endpoint: &Endpoint) -> Option<String> {
+    match rewrite {
+        "" => None,
+        HOST_REWRITE_ENDPOINT if !endpoint.unix_path.is_empty() => None,
+        HOST_REWRITE_ENDPOINT if endpoint.port == 80 => Some(endpoint.address.clone()),
+        HOST_REWRITE_ENDPOINT => Some(format!("{}:{}", endpoint.address, endpoint.port)),
+        host => Some(host.to_string()),
+    }
+}
+
+// 校验阶段使用：固定值必须是合法的 Host 头 (空 = 不改写)
+pub fn valid_host_rewrite(rewrite: &str) -> bool {
+    rewrite.is_empty()
+        || rewrite == HOST_REWRITE_ENDPOINT
+        || http::uri::Authority::try_from(rewrite).is_ok()
+}
+
+// 构造转发用的 HttpPeer (MVP 暂不支持 upstream TLS)
+// gRPC 集群只用 HTTP/2：没有 TLS 时按 h2c prior knowledge 直接发 HTTP/2 前导 (见 grpc.rs)
+pub fn build_peer(endpoint: &Endpoint, protocol: Protocol) -> pingora::Result<HttpPeer> {
+    let mut peer = if endpoint.unix_path.is_empty() {
+        HttpPeer::new(
+            (endpoint.address.as_str(), endpoint.port as u16), // 目标 IP:PORT (如 10.244.1.5:8080)
+            false,          // TLS: 是否使用 HTTPS 连接上游
+            "".to_string(), // SNI: 如果是 HTTPS，这里填域名
+        )
+    } else {
+        HttpPeer::new_uds(&endpoint.unix_path, false, "".to_string())?
+    };
+    if protocol == Protocol::Grpc {
+        peer.options.set_http_version(2, 2);
+    }
+    Ok(peer)
+}
+
+// 【子集负载均衡 (Subset Load Balancing)】
+// 路由的 subset_selector 在负载均衡之前先过滤候选节点：
+// - metadata: 静态条件，如 tier=premium
+// - from_attributes: 条件的值来自请求属性，如 tier <- tenant.tier (属性不存在则不匹配)
+// 返回匹配的节点以及子集标签 (如 "tier=premium")，标签用于日志里区分各个子集。
+pub fn select_subset<'a>(
+    endpoints: &'a [Endpoint],
+    selector: &SubsetSelector,
+    attributes: &RequestAttributes,
+) -> (Vec<&'a Endpoint>, String) {
+    let mut wanted: Vec<(&str, Option<&str>)> = selector
+        .metadata
+        .iter()
+        .map(|(k, v)| (k.as_str(), Some(v.as_str())))
+        .chain(
+            selector
+                .from_attributes
+                .iter()
+                .map(|(k, attr)| (k.as_str(), attributes.get(attr))),
+        )
+        .collect();
+    wanted.sort();
+    let label = wanted
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v.unwrap_or("?")))
+        .collect::<Vec<_>>()
+        .join(",");
+    let subset = endpoints
+        .iter()
+        .filter(|e| {
+            wanted.iter().all(|(k, v)| match v {
+                Some(v) => e.metadata.get(*k).map(String::as_str) == Some(*v),
+                None => false,
+            })
+        })
+        .collect();
+    (subset, label)
+}
+
+// 校验：静态条件在集群里一个节点都匹配不上时给出警告 (不拒绝配置，节点可能稍后才注册)
+pub fn subset_never_matches(endpoints: &[Endpoint], selector: &SubsetSelector) -> bool {
+    !selector.metadata.is_empty()
+        && !endpoints.iter().any(|e| {
+            selector
+                .metadata
+                .iter()
+                .all(|(k, v)| e.metadata.get(k) == Some(v))
+        })
+}
+
+// 上游响应的媒体类型 (去掉 "; charset=..." 等参数) 是否在列表里，大小写不敏感
+pub fn content_type_listed(headers: &http::HeaderMap, types: &[String]) -> bool {
+    if types.is_empty() {
+        return false;
+    }
+    let Some(media_type) = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or("").trim())
+    else {
+        return false;
+    };
+    types.iter().any(|t| t.trim().eq_ignore_ascii_case(media_type))
+}
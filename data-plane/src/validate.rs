@@ -0,0 +1,637 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::client::agw::v1::{ConfigError, ConfigErrorCode, ConfigSnapshot};
+
+// 【配置健全性守卫 (Sanity Guard)】
+// 曾经出现过 Control Plane 的 bug 推送了一份 "路由为空、但 Listener 正常" 的快照，
+// 所有数据面瞬间开始 404 —— 技术上 "正确"，但在运维上是灾难。
+// 因此：如果新快照的路由数或集群数相对当前已应用的配置骤降超过阈值 (默认 90%)，
+// 就拒绝这份快照、保留旧配置，并大声报警。
+// 控制面可以通过 `allow_major_reduction: true` 显式声明 "这是有意为之"。
+pub struct SanityGuard {
+    // 允许的最大下降百分比 (0-100)
+    max_reduction_pct: u32,
+}
+
+impl SanityGuard {
+    // 从环境变量 AGW_MAX_CONFIG_REDUCTION_PCT 读取阈值
+    pub fn from_env() -> Self {
+        let max_reduction_pct = std::env::var("AGW_MAX_CONFIG_REDUCTION_PCT")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .map(|v| v.min(100))
+            .unwrap_or(90);
+        Self { max_reduction_pct }
+    }
+
+    // 检查新快照相对当前配置是否 "骤降"。
+    // current 为 None 表示首次启动 (还没有应用过任何配置)，此时不做检查。
+    pub fn check(
+        &self,
+        current: Option<&ConfigSnapshot>,
+        next: &ConfigSnapshot,
+    ) -> Result<(), ConfigError> {
+        let Some(current) = current else {
+            return Ok(());
+        };
+        if next.allow_major_reduction {
+            return Ok(());
+        }
+        self.check_count("routes", current.routes.len(), next.routes.len())?;
+        self.check_count("clusters", current.clusters.len(), next.clusters.len())?;
+        Ok(())
+    }
+
+    fn check_count(&self, kind: &str, before: usize, after: usize) -> Result<(), ConfigError> {
+        if before == 0 || after >= before {
+            return Ok(());
+        }
+        let dropped_pct = (before - after) * 100 / before;
+        if dropped_pct > self.max_reduction_pct as usize {
+            return Err(config_error(
+                ConfigErrorCode::SanityReduction,
+                kind,
+                format!(
+                    "{} count dropped from {} to {} ({}% > allowed {}%)",
+                    kind, before, after, dropped_pct, self.max_reduction_pct
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// 配置应用状态，供管理端口 /status 查询。
+// 被健全性守卫拒绝的快照必须在这里 "非常显眼"。
+#[derive(Default)]
+pub struct ConfigStatus {
+    pub applied_version: RwLock<String>,
+    pub applied_total: AtomicU64,
+    pub rejected_total: AtomicU64,
+    // 最近一次被拒绝的快照版本和错误列表
+    pub last_rejection: RwLock<Option<(String, Vec<ConfigError>)>>,
+    // 派生过程中被更新的快照取代而放弃的次数
+    pub superseded_total: AtomicU64,
+    // 最近一次成功应用的分阶段耗时
+    pub last_apply: RwLock<Option<ApplyTiming>>,
+    // 累计清掉的运行时状态条数，以及最近一次应用的明细
+    pub state_resets_total: AtomicU64,
+    pub last_carryover: RwLock<Option<StateCarryover>>,
+    // 热更新失败、仍在用旧模块的插件 (按 wasm_path)；同一路径之后重新加载成功或从配置中删除时清掉
+    pub plugin_reload_failures: RwLock<HashMap<String, PluginReloadFailure>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginReloadFailure {
+    // 插件声明的新 version 和引入它的配置版本
+    pub version: String,
+    pub config_version: String,
+    pub error: String,
+}
+
+// 一次配置应用的分阶段耗时 (毫秒)
+#[derive(Debug, Clone, Serialize)]
+pub struct ApplyTiming {
+    pub version: String,
+    // 预热检查 + 健全性检查
+    pub validate_ms: u64,
+    // 编译路由匹配器等派生数据
+    pub derive_ms: u64,
+    // 原子替换 + runtime-info 更新
+    pub swap_ms: u64,
+    pub total_ms: u64,
+}
+
+// 【运行时状态延续 (State Carry-over)】
+// 集群按名字、节点按 "ip:port" / "unix:<path>" 识别。新快照里标识没变的集群和节点，
+// 健康状态、负载均衡计数、异常统计全部原样保留 (连接池由 Pingora 按地址复用，本来就不受快照影响)；
+// 只有被删除的集群 / 节点的状态会被清掉。
+// state_resets 是每次应用清掉的状态条数：一次 "只改了一条路由" 的推送如果这里不是 0，说明有问题。
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StateCarryover {
+    pub version: String,
+    pub clusters_added: usize,
+    pub clusters_removed: usize,
+    pub endpoints_added: usize,
+    pub endpoints_removed: usize,
+    pub state_resets: usize,
+}
+
+impl StateCarryover {
+    pub fn diff(
+        version: &str,
+        before: &HashMap<String, HashSet<String>>,
+        after: &HashMap<String, HashSet<String>>,
+    ) -> Self {
+        let mut carryover = Self {
+            version: version.to_string(),
+            ..Self::default()
+        };
+        let empty = HashSet::new();
+        for (cluster, endpoints) in after {
+            let old = before.get(cluster);
+            if old.is_none() {
+                carryover.clusters_added += 1;
+            }
+            carryover.endpoints_added += endpoints.difference(old.unwrap_or(&empty)).count();
+        }
+        for (cluster, endpoints) in before {
+            let new = after.get(cluster);
+            if new.is_none() {
+                carryover.clusters_removed += 1;
+            }
+            carryover.endpoints_removed += endpoints.difference(new.unwrap_or(&empty)).count();
+        }
+        carryover
+    }
+}
+
+impl ConfigStatus {
+    pub fn record_applied(&self, version: &str) {
+        *self.applied_version.write().unwrap() = version.to_string();
+        self.applied_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rejected(&self, version: &str, errors: Vec<ConfigError>) {
+        self.rejected_total.fetch_add(1, Ordering::Relaxed);
+        *self.last_rejection.write().unwrap() = Some((version.to_string(), errors));
+    }
+
+    pub fn record_superseded(&self) {
+        self.superseded_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_apply_timing(&self, timing: ApplyTiming) {
+        *self.last_apply.write().unwrap() = Some(timing);
+    }
+
+    pub fn record_carryover(&self, carryover: StateCarryover) {
+        self.state_resets_total
+            .fetch_add(carryover.state_resets as u64, Ordering::Relaxed);
+        *self.last_carryover.write().unwrap() = Some(carryover);
+    }
+
+    pub fn record_plugin_reloads(
+        &self,
+        config_version: &str,
+        reloads: &[(String, String, wasmtime::Result<()>)],
+        live: impl Fn(&str) -> bool,
+    ) {
+        let mut failures = self.plugin_reload_failures.write().unwrap();
+        for (path, version, result) in reloads {
+            match result {
+                Ok(()) => {
+                    failures.remove(path);
+                }
+                Err(e) => {
+                    failures.insert(
+                        path.clone(),
+                        PluginReloadFailure {
+                            version: version.clone(),
+                            config_version: config_version.to_string(),
+                            error: e.to_string(),
+                        },
+                    );
+                }
+            }
+        }
+        failures.retain(|path, _| live(path));
+    }
+}
+
+// 【结构化配置错误 (ConfigError)】
+// 校验层产生的所有错误都带稳定的错误码 + 对象路径 + 说明，而不是一段自由文本，
+// 控制面的自动化、/status、日志都基于同一份结构化数据。
+pub fn config_error(code: ConfigErrorCode, path: impl Into<String>, message: impl Into<String>) -> ConfigError {
+    ConfigError {
+        code: code as i32,
+        path: path.into(),
+        message: message.into(),
+    }
+}
+
+// 单行渲染，用于日志："INVALID_REGEX routes[3].path: invalid regex ..."
+pub fn format_errors(errors: &[ConfigError]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("{} {}: {}", e.code().as_str_name(), e.path, e.message))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// JSON 渲染，用于管理端口
+pub fn errors_json(errors: &[ConfigError]) -> serde_json::Value {
+    errors
+        .iter()
+        .map(|e| {
+            serde_json::json!({
+                "code": e.code().as_str_name(),
+                "path": e.path,
+                "message": e.message,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::agw::config::v1::{
+        CanaryOverride, Cluster, DirectResponse, HeaderMatch, HeaderTransform, Listener,
+        PathMatchType, Plugin, QueryParamMatch, RedirectAction, RetryPolicy, Route, TlsConfig,
+        WeightedCluster,
+    };
+    use crate::router::ActiveConfig;
+
+    fn route() -> Route {
+        Route {
+            path_prefix: "/api".to_string(),
+            cluster_id: "backend".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn snapshot(routes: Vec<Route>) -> ConfigSnapshot {
+        ConfigSnapshot {
+            version_id: "v1".to_string(),
+            clusters: vec![Cluster {
+                name: "backend".to_string(),
+                ..Default::default()
+            }],
+            routes,
+            ..Default::default()
+        }
+    }
+
+    // 快照被拒绝时的 (错误码, 路径) 列表
+    fn rejected(snapshot: ConfigSnapshot) -> Vec<(&'static str, String)> {
+        match ActiveConfig::compile(snapshot) {
+            Ok(_) => panic!("expected the snapshot to be rejected"),
+            Err(errors) => errors
+                .iter()
+                .map(|e| (e.code().as_str_name(), e.path.clone()))
+                .collect(),
+        }
+    }
+
+    fn rejected_route(route: Route) -> Vec<(&'static str, String)> {
+        rejected(snapshot(vec![route]))
+    }
+
+    fn one(code: &'static str, path: &str) -> Vec<(&'static str, String)> {
+        vec![(code, path.to_string())]
+    }
+
+    #[test]
+    fn valid_snapshot_compiles() {
+        assert!(ActiveConfig::compile(snapshot(vec![route()])).is_ok());
+    }
+
+    // 控制面的自动化按这些字符串分支，改名就是破坏兼容
+    #[test]
+    fn listener_and_cluster_errors() {
+        let mut tls = snapshot(vec![route()]);
+        tls.listeners.push(Listener {
+            name: "https".to_string(),
+            tls: Some(TlsConfig {
+                cert_pem: b"not a certificate".to_vec(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert_eq!(rejected(tls), one("BAD_TLS_MATERIAL", "listeners[0].tls"));
+
+        let mut host = snapshot(vec![route()]);
+        host.clusters[0].host_rewrite = "bad host".to_string();
+        assert_eq!(
+            rejected(host),
+            one("INVALID_HOST_REWRITE", "clusters[0].host_rewrite")
+        );
+    }
+
+    #[test]
+    fn route_match_errors() {
+        let unknown = Route {
+            cluster_id: "missing".to_string(),
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(unknown),
+            one("UNKNOWN_CLUSTER_REF", "routes[0].cluster_id")
+        );
+
+        let mut regex = Route {
+            path_prefix: "/api/(".to_string(),
+            ..route()
+        };
+        regex.set_match_type(PathMatchType::PathMatchRegex);
+        assert_eq!(
+            rejected_route(regex),
+            one("INVALID_REGEX", "routes[0].path_prefix")
+        );
+
+        let hosts = Route {
+            hosts: vec!["*".to_string()],
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(hosts),
+            one("INVALID_HOST", "routes[0].hosts")
+        );
+
+        let headers = Route {
+            headers: vec![HeaderMatch {
+                name: "bad header".to_string(),
+                ..Default::default()
+            }],
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(headers),
+            one("INVALID_HEADER_MATCH", "routes[0].headers[0]")
+        );
+
+        let query = Route {
+            query_params: vec![QueryParamMatch::default()],
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(query),
+            one("INVALID_QUERY_MATCH", "routes[0].query_params[0]")
+        );
+    }
+
+    #[test]
+    fn route_action_errors() {
+        let mut rewrite = Route {
+            strip_prefix: true,
+            ..route()
+        };
+        rewrite.set_match_type(PathMatchType::PathMatchExact);
+        assert_eq!(
+            rejected_route(rewrite),
+            one("INVALID_REWRITE", "routes[0].rewrite_prefix")
+        );
+
+        let canary = Route {
+            canary: Some(CanaryOverride::default()),
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(canary),
+            one("INVALID_CANARY", "routes[0].canary")
+        );
+
+        let selector = Route {
+            cluster_selector: "(".to_string(),
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(selector),
+            one("INVALID_CLUSTER_SELECTOR", "routes[0].cluster_selector")
+        );
+
+        let redirect = Route {
+            cluster_id: String::new(),
+            redirect: Some(RedirectAction {
+                status_code: 200,
+                ..Default::default()
+            }),
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(redirect),
+            one("INVALID_REDIRECT", "routes[0].redirect")
+        );
+
+        let direct = Route {
+            direct_response: Some(DirectResponse {
+                status: 200,
+                ..Default::default()
+            }),
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(direct),
+            one("INVALID_DIRECT_RESPONSE", "routes[0].direct_response")
+        );
+
+        let weighted = Route {
+            weighted_clusters: vec![WeightedCluster {
+                cluster_id: "backend".to_string(),
+                weight: 1,
+            }],
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(weighted),
+            one("INVALID_WEIGHTED_CLUSTERS", "routes[0].weighted_clusters")
+        );
+
+        let retry = Route {
+            retry: Some(RetryPolicy {
+                max_retries: 11,
+                ..Default::default()
+            }),
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(retry),
+            one("INVALID_RETRY_POLICY", "routes[0].retry")
+        );
+    }
+
+    #[test]
+    fn route_plugin_header_and_mirror_errors() {
+        let plugins = Route {
+            plugins: vec![Plugin {
+                name: "auth".to_string(),
+                wasm_path: "auth.wasm".to_string(),
+                ..Default::default()
+            }],
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(plugins),
+            one(
+                "MISSING_PLUGIN_BYPASS_POLICY",
+                "routes[0].plugin_bypass_policy"
+            )
+        );
+
+        let transform = Route {
+            request_headers: Some(HeaderTransform {
+                remove: vec!["host".to_string()],
+                ..Default::default()
+            }),
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(transform),
+            one("INVALID_HEADER_TRANSFORM", "routes[0].request_headers")
+        );
+
+        let mirror = Route {
+            mirror_cluster: "missing".to_string(),
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(mirror),
+            one("UNKNOWN_CLUSTER_REF", "routes[0].mirror_cluster")
+        );
+
+        let sample = Route {
+            mirror_cluster: "backend".to_string(),
+            mirror_sample_rate: Some(2.0),
+            ..route()
+        };
+        assert_eq!(
+            rejected_route(sample),
+            one("INVALID_MIRROR", "routes[0].mirror_sample_rate")
+        );
+    }
+
+    #[test]
+    fn snapshot_level_errors() {
+        let mut default_route = snapshot(vec![route()]);
+        default_route.default_route = Some(route());
+        assert_eq!(
+            rejected(default_route),
+            one("INVALID_DEFAULT_ROUTE", "default_route")
+        );
+
+        let mut not_found = snapshot(vec![route()]);
+        not_found.not_found_response = Some(DirectResponse {
+            status: 500,
+            ..Default::default()
+        });
+        assert_eq!(
+            rejected(not_found),
+            one("INVALID_DIRECT_RESPONSE", "not_found_response.status")
+        );
+
+        let mut error_pages = snapshot(vec![route()]);
+        error_pages.error_responses = vec![
+            DirectResponse {
+                status: 503,
+                ..Default::default()
+            },
+            DirectResponse {
+                status: 200,
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            rejected(error_pages),
+            one("INVALID_ERROR_RESPONSE", "error_responses[1]")
+        );
+    }
+
+    // 错误不会在第一个就停下：每条有问题的路由都带自己的路径
+    #[test]
+    fn errors_accumulate_across_routes() {
+        let missing = Route {
+            cluster_id: "missing".to_string(),
+            ..route()
+        };
+        let hosts = Route {
+            hosts: vec!["".to_string()],
+            ..route()
+        };
+        assert_eq!(
+            rejected(snapshot(vec![route(), missing, hosts])),
+            [
+                ("UNKNOWN_CLUSTER_REF", "routes[1].cluster_id".to_string()),
+                ("INVALID_HOST", "routes[2].hosts".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_render_with_stable_codes() {
+        let errors = vec![
+            config_error(
+                ConfigErrorCode::InvalidRegex,
+                "routes[3].path",
+                "unclosed group",
+            ),
+            config_error(
+                ConfigErrorCode::ApplyTimeout,
+                "",
+                "apply timed out after 5s",
+            ),
+        ];
+        assert_eq!(
+            format_errors(&errors),
+            "INVALID_REGEX routes[3].path: unclosed group; APPLY_TIMEOUT : apply timed out after 5s"
+        );
+        assert_eq!(
+            errors_json(&errors),
+            serde_json::json!([
+                {"code": "INVALID_REGEX", "path": "routes[3].path", "message": "unclosed group"},
+                {"code": "APPLY_TIMEOUT", "path": "", "message": "apply timed out after 5s"},
+            ])
+        );
+    }
+
+    // n 条路由、m 个集群
+    fn sized(routes: usize, clusters: usize) -> ConfigSnapshot {
+        ConfigSnapshot {
+            routes: vec![route(); routes],
+            clusters: (0..clusters)
+                .map(|i| Cluster {
+                    name: format!("cluster-{}", i),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    const GUARD: SanityGuard = SanityGuard {
+        max_reduction_pct: 90,
+    };
+
+    #[test]
+    fn sharp_drop_is_rejected() {
+        let current = sized(100, 10);
+        let error = GUARD.check(Some(&current), &sized(0, 10)).unwrap_err();
+        assert_eq!(error.code(), ConfigErrorCode::SanityReduction);
+        assert_eq!(error.path, "routes");
+        assert_eq!(
+            error.message,
+            "routes count dropped from 100 to 0 (100% > allowed 90%)"
+        );
+
+        let error = GUARD.check(Some(&current), &sized(100, 0)).unwrap_err();
+        assert_eq!(error.code(), ConfigErrorCode::SanityReduction);
+        assert_eq!(error.path, "clusters");
+
+        // 91% 超过阈值，90% 正好在阈值上
+        assert!(GUARD.check(Some(&current), &sized(9, 10)).is_err());
+        assert!(GUARD.check(Some(&current), &sized(10, 1)).is_ok());
+    }
+
+    #[test]
+    fn ordinary_changes_pass() {
+        let current = sized(100, 10);
+        for next in [sized(100, 10), sized(50, 5), sized(200, 20)] {
+            assert!(GUARD.check(Some(&current), &next).is_ok());
+        }
+        // 首次启动，以及之前就是空配置：没有可比较的基线
+        assert!(GUARD.check(None, &sized(0, 0)).is_ok());
+        assert!(GUARD.check(Some(&sized(0, 0)), &sized(0, 0)).is_ok());
+    }
+
+    #[test]
+    fn allow_major_reduction_overrides_the_guard() {
+        let next = ConfigSnapshot {
+            allow_major_reduction: true,
+            ..sized(0, 0)
+        };
+        assert!(GUARD.check(Some(&sized(100, 10)), &next).is_ok());
+    }
+}
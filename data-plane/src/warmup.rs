@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::client::agw::v1::ConfigSnapshot;
+
+// 【预热启动 (Bind Before Config)】
+// 默认必须先从 Control Plane 拿到第一份有效配置才会监听端口，CP 宕机期间 K8s 的 liveness 探针失败、
+// Pod 被反复重启，反而让恢复更慢。AGW_BIND_BEFORE_CONFIG=true 时先按 AGW_BOOTSTRAP_LISTENERS 绑定端口：
+// - 第一份有效配置 (Listener 非空) 应用之前，业务请求一律返回 503 (WARMING_UP)，/readyz 返回 503；
+// - /healthz 始终返回 200，进程不会因为 CP 不可用被杀掉；
+// - 第一份配置应用完成后原子地打开开关 (readiness 同时变为 true)，之后的请求正常路由。
+// 开关只会从 "预热" 变成 "就绪"，不会回退：之后的快照有问题时保留旧配置继续服务。
+pub struct Readiness {
+    ready: AtomicBool,
+}
+
+// 没有设置 AGW_BOOTSTRAP_LISTENERS 时监听的地址
+const DEFAULT_BOOTSTRAP_LISTENER: &str = "0.0.0.0:6188";
+
+impl Readiness {
+    // 默认模式下启动时已经拿到了第一份配置，一开始就是就绪的
+    pub fn new(bind_before_config: bool) -> Self {
+        Self {
+            ready: AtomicBool::new(!bind_before_config),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    // 快照能否应用：预热期间只接受带 Listener 的快照 (Listener 为空说明 CP 可能还没准备好)
+    pub fn accepts(&self, snapshot: &ConfigSnapshot) -> bool {
+        self.is_ready() || !snapshot.listeners.is_empty()
+    }
+
+    // 配置应用完成后调用；从预热变为就绪的那一次返回 true
+    pub fn mark_ready(&self) -> bool {
+        !self.ready.swap(true, Ordering::AcqRel)
+    }
+}
+
+// AGW_BOOTSTRAP_LISTENERS: 逗号分隔的 "ip:port" 列表 (仅支持明文 HTTP)
+pub fn bootstrap_listeners(spec: Option<&str>) -> Vec<String> {
+    spec.unwrap_or(DEFAULT_BOOTSTRAP_LISTENER)
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::agw::config::v1::Listener;
+
+    fn with_listener() -> ConfigSnapshot {
+        ConfigSnapshot {
+            version_id: "v1".to_string(),
+            listeners: vec![Listener {
+                name: "http".to_string(),
+                address: "0.0.0.0".to_string(),
+                port: 6188,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    // CP 在启动时不可用：一直预热，CP 恢复后第一份有效配置打开开关
+    #[test]
+    fn warms_up_until_the_first_valid_config() {
+        let readiness = Readiness::new(true);
+        assert!(!readiness.is_ready());
+
+        // CP 回来了但还没准备好，推了一份没有 Listener 的快照：继续预热
+        assert!(!readiness.accepts(&ConfigSnapshot::default()));
+        assert!(!readiness.is_ready());
+
+        assert!(readiness.accepts(&with_listener()));
+        assert!(readiness.mark_ready());
+        assert!(readiness.is_ready());
+
+        // 就绪之后不再回到预热，也不再要求 Listener 非空 (由健全性守卫和校验把关)
+        assert!(!readiness.mark_ready());
+        assert!(readiness.accepts(&ConfigSnapshot::default()));
+        assert!(readiness.is_ready());
+    }
+
+    #[test]
+    fn default_mode_starts_ready() {
+        let readiness = Readiness::new(false);
+        assert!(readiness.is_ready());
+        assert!(!readiness.mark_ready());
+    }
+
+    #[test]
+    fn bootstrap_listener_list() {
+        assert_eq!(bootstrap_listeners(None), ["0.0.0.0:6188"]);
+        assert_eq!(
+            bootstrap_listeners(Some("0.0.0.0:80, 0.0.0.0:8080,,")),
+            ["0.0.0.0:80", "0.0.0.0:8080"]
+        );
+        assert!(bootstrap_listeners(Some("")).is_empty());
+    }
+}
@@ -1,8 +1,26 @@
 use std::collections::HashMap;
 use std::path::Path;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use wasmtime::*;
 
+use arc_swap::ArcSwap;
+use bytes::Bytes;
+use serde::Serialize;
+
+mod fetch;
+mod pool;
+mod ratelimit;
+mod response;
+mod selftest;
+pub use fetch::HttpClient;
+use pool::{InstancePool, PooledInstance};
+use ratelimit::RateLimiter;
+pub use response::{DenyResponse, PluginResult};
+pub use selftest::SelfTestReport;
+
+use crate::client::agw::v1::ConfigSnapshot;
+use crate::node::RuntimeInfo;
 use redis::Client as RedisClient;
 use sqlx::{MySql, Pool, Postgres};
 
@@ -12,11 +30,222 @@ pub struct ExternalResources {
     // For now support Postgres and MySQL. In real world, use AnyPool or enum
     pub postgres: HashMap<String, Pool<Postgres>>,
     pub mysql: HashMap<String, Pool<MySql>>,
+    // agw_http_fetch 使用的出站 HTTP 客户端；None = 插件不能发 HTTP 请求
+    pub http: Option<HttpClient>,
+    // agw_ratelimit 的进程内令牌桶，所有请求共享
+    pub ratelimit: Arc<RateLimiter>,
 }
 
 pub struct WasmContext {
     pub headers: HashMap<String, String>,
     pub resources: ExternalResources,
+    // runtime-info 的 JSON 序列化结果 (节点身份、版本、环境名)，只读
+    pub runtime_info: Arc<Vec<u8>>,
+    // 内置过滤器写入的请求属性 (jwt.sub、client.ip 等)，插件只读
+    pub attributes: Arc<HashMap<String, String>>,
+    // agw_get_body 读取的请求体；None = 没有提前读 (没有插件导入 agw_get_body、chunked 或超过上限)
+    pub body_bytes: Option<Bytes>,
+    // agw_set_response 登记的拒绝响应，on_request 返回拒绝时使用
+    pub deny_response: Option<DenyResponse>,
+}
+
+// 【插件沙箱 (Sandbox)】
+// 插件能做的事只有下面这些 env.agw_* 宿主函数。除此之外的导入 (WASI 的文件、网络、环境变量、时钟等，
+// 以及任何其它模块名) 在加载时一律拒绝，错误信息里写明是哪个导入，而不是等实例化时报一条看不懂的链接错误。
+// 网关不提供 WASI：插件没有预打开的目录、没有环境变量、没有 socket，按 wasm32-unknown-unknown 编译即可。
+// data-plane plugin inspect <file.wasm> 离线给出同样的结论。
+const HOST_MODULE: &str = "env";
+const HOST_FUNCTIONS: &[&str] = &[
+    "agw_get_header",
+    "agw_runtime_info",
+    "agw_get_attribute",
+    "agw_get_body",
+    "agw_redis_command",
+    "agw_db_query",
+    "agw_http_fetch",
+    "agw_ratelimit",
+    "agw_set_response",
+];
+const ENTRY_POINT: &str = "on_request";
+
+// 一个插件导入项的检查结果
+#[derive(Debug, Serialize)]
+pub struct PluginImport {
+    pub module: String,
+    pub name: String,
+    pub allowed: bool,
+}
+
+// plugin inspect 的输出
+#[derive(Debug, Serialize)]
+pub struct PluginInspection {
+    pub path: String,
+    pub imports: Vec<PluginImport>,
+    pub exports: Vec<String>,
+    // None = 可以加载
+    pub rejected: Option<String>,
+}
+
+fn inspect_module(module: &Module) -> (Vec<PluginImport>, Option<String>) {
+    let imports: Vec<PluginImport> = module
+        .imports()
+        .map(|import| PluginImport {
+            module: import.module().to_string(),
+            name: import.name().to_string(),
+            allowed: import.module() == HOST_MODULE
+                && HOST_FUNCTIONS.contains(&import.name())
+                && matches!(import.ty(), ExternType::Func(_)),
+        })
+        .collect();
+    let verdict = match imports.iter().find(|i| !i.allowed) {
+        Some(denied) if denied.module.starts_with("wasi") => Some(format!(
+            "imports {}.{}: WASI is not available to plugins (no filesystem, network or environment access); \
+             build the plugin for wasm32-unknown-unknown",
+            denied.module, denied.name
+        )),
+        Some(denied) => Some(format!(
+            "imports {}.{}, which the gateway does not provide (allowed: {}.{{{}}})",
+            denied.module,
+            denied.name,
+            HOST_MODULE,
+            HOST_FUNCTIONS.join(", ")
+        )),
+        None if module.get_export(ENTRY_POINT).is_none() => {
+            Some(format!("does not export {}() -> i32", ENTRY_POINT))
+        }
+        None => None,
+    };
+    (imports, verdict)
+}
+
+// 离线检查一个插件文件 (不需要 Control Plane 和外部资源)
+pub fn inspect_file(path: &str) -> Result<PluginInspection> {
+    let mut config = Config::new();
+    config.async_support(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::from_file(&engine, path)?;
+    let (imports, rejected) = inspect_module(&module);
+    Ok(PluginInspection {
+        path: path.to_string(),
+        imports,
+        exports: module.exports().map(|e| e.name().to_string()).collect(),
+        rejected,
+    })
+}
+
+// data-plane plugin inspect 的入口，打印 PluginInspection (JSON)。
+// 退出码：0 = 可以加载，1 = 被拒绝或无法编译，2 = 参数错误
+pub fn inspect_cli(args: &[String]) -> i32 {
+    let [path] = args else {
+        eprintln!("usage: data-plane plugin inspect <plugin.wasm>");
+        return 2;
+    };
+    match inspect_file(path) {
+        Ok(inspection) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&inspection).unwrap_or_default()
+            );
+            if inspection.rejected.is_some() { 1 } else { 0 }
+        }
+        Err(e) => {
+            eprintln!("cannot compile {}: {}", path, e);
+            1
+        }
+    }
+}
+
+// 【插件执行超时 (Epoch Interruption)】
+// 死循环或者极慢的插件会一直占着 worker，拖垮同一线程上的其它请求。
+// 引擎开启 epoch 中断，后台线程每毫秒把 epoch 加一；每次执行插件前把 Store 的截止 epoch 设为
+// "超时毫秒数" 个 tick 之后，Wasm 代码在函数入口和循环回边检查 epoch，过了截止点就以 Trap::Interrupt 中止。
+// 计时是墙钟时间，包括等待宿主函数 (Redis、DB、HTTP) 的时间，但只有回到 Wasm 代码时才会被打断：
+// 宿主函数本身的等待由各自的超时负责。
+// 超时的插件返回 PluginTimeout 错误，请求按插件出错处理 (500 PLUGIN_ERROR)，访问日志记为 timeout。
+const EPOCH_TICK: Duration = Duration::from_millis(1);
+
+fn spawn_epoch_ticker(engine: Engine) {
+    std::thread::Builder::new()
+        .name("agw-wasm-epoch".to_string())
+        .spawn(move || {
+            loop {
+                std::thread::sleep(EPOCH_TICK);
+                engine.increment_epoch();
+            }
+        })
+        .expect("failed to spawn the wasm epoch thread");
+}
+
+fn epoch_ticks(timeout: Duration) -> u64 {
+    (timeout.as_nanos() / EPOCH_TICK.as_nanos()).max(1) as u64
+}
+
+// 插件执行超过 timeout 被中断
+#[derive(Debug)]
+pub struct PluginTimeout {
+    pub timeout: Duration,
+    pub elapsed: Duration,
+}
+
+impl std::fmt::Display for PluginTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "interrupted after {:?} (limit {:?})",
+            self.elapsed, self.timeout
+        )
+    }
+}
+
+impl std::error::Error for PluginTimeout {}
+
+// 【插件 CPU 上限 (Fuel Metering)】
+// 超时按墙钟计算，插件在宿主函数里等 Redis 的时间也算进去；fuel 限制的是插件自己消耗的 CPU。
+// 引擎开启 fuel 计量，Wasm 代码大致每执行一条指令消耗 1 个 fuel，每次调用 on_request 前把 Store 的 fuel
+// 加满到上限 (插件的 max_fuel，默认 AGW_PLUGIN_MAX_FUEL = 10 亿)，耗尽时以 Trap::OutOfFuel 中止。
+// 宿主函数不消耗 fuel。耗尽的插件返回 PluginOutOfFuel 错误，请求按插件出错处理 (500 PLUGIN_ERROR)，
+// 访问日志记为 out_of_fuel，并计入 agw_plugin_fuel_exhausted_total。
+#[derive(Debug, Clone, Copy)]
+pub struct PluginLimits {
+    pub timeout: Duration,
+    pub fuel: u64,
+}
+
+// 插件执行耗尽 fuel 被中止
+#[derive(Debug)]
+pub struct PluginOutOfFuel {
+    pub fuel: u64,
+}
+
+impl std::fmt::Display for PluginOutOfFuel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ran out of fuel after about {} instructions", self.fuel)
+    }
+}
+
+impl std::error::Error for PluginOutOfFuel {}
+
+// 快照中每个插件文件的 version (包括全局插件和兜底路由上的插件)；同一个文件出现多次时以最后一个为准
+pub fn plugin_versions(snapshot: &ConfigSnapshot) -> HashMap<&str, &str> {
+    snapshot
+        .routes
+        .iter()
+        .chain(&snapshot.default_route)
+        .flat_map(|route| &route.plugins)
+        .chain(&snapshot.global_plugins)
+        .map(|plugin| (plugin.wasm_path.as_str(), plugin.version.as_str()))
+        .collect()
+}
+
+// run_plugin 出错时访问日志里记的插件结论；不管哪一种，请求都按 500 PLUGIN_ERROR 处理
+pub fn error_decision(e: &Error) -> &'static str {
+    if e.downcast_ref::<PluginTimeout>().is_some() {
+        "timeout"
+    } else if e.downcast_ref::<PluginOutOfFuel>().is_some() {
+        "out_of_fuel"
+    } else {
+        "error"
+    }
 }
 
 #[derive(Clone)]
@@ -25,15 +254,35 @@ pub struct WasmRuntime {
     // Cache compiled modules: Path -> Module
     // wasmtime::Module is cheap to clone (internal ref counting)
     modules: Arc<RwLock<HashMap<String, Module>>>,
+    // 每个路径最近一次重新加载成功的 version (见 reload_changed)
+    loaded_versions: Arc<RwLock<HashMap<String, String>>>,
     linker: Linker<WasmContext>,
-    resources: ExternalResources,
+    // 外部资源 (Redis/DB) 可能在第一份配置到达后才初始化 (见 AGW_BIND_BEFORE_CONFIG)，
+    // 所以这里同样用 ArcSwap 包一层，允许后台线程原子替换。
+    resources: Arc<ArcSwap<ExternalResources>>,
+    // 预先序列化好的 runtime-info，每次应用新配置时替换
+    runtime_info: Arc<ArcSwap<Vec<u8>>>,
+    // 执行完可以复用的插件实例 (见 wasm/pool.rs)
+    pool: Arc<InstancePool>,
 }
 
 impl WasmRuntime {
     pub fn new(resources: ExternalResources) -> Self {
+        Self::with_pool(resources, InstancePool::from_env())
+    }
+
+    // 指定实例池大小 (0 = 不复用)，不读 AGW_PLUGIN_POOL_SIZE；基准测试用它对比复用前后的开销
+    pub fn with_pool_size(resources: ExternalResources, pool_size: usize) -> Self {
+        Self::with_pool(resources, InstancePool::new(pool_size))
+    }
+
+    fn with_pool(resources: ExternalResources, pool: InstancePool) -> Self {
         let mut config = Config::new();
         config.async_support(true);
+        config.epoch_interruption(true);
+        config.consume_fuel(true);
         let engine = Engine::new(&config).unwrap();
+        spawn_epoch_ticker(engine.clone());
         let mut linker = Linker::new(&engine);
 
         // Define Host Function: agw_get_header
@@ -106,6 +355,104 @@ impl WasmRuntime {
             )
             .unwrap();
 
+        // Host Function: agw_runtime_info
+        // (out_ptr, out_max) -> i32
+        // 把 runtime-info (JSON) 写入插件提供的 Buffer，返回写入的字节数。
+        // 插件据此区分 staging / prod、所在 region 等，而不需要访问任意环境变量。
+        linker
+            .func_wrap(
+                "env",
+                "agw_runtime_info",
+                |mut caller: Caller<'_, WasmContext>, out_ptr: i32, out_max: i32| -> i32 {
+                    let memory = match caller.get_export("memory") {
+                        Some(Extern::Memory(mem)) => mem,
+                        _ => return -1,
+                    };
+                    let info = caller.data().runtime_info.clone();
+                    if info.len() > out_max as usize {
+                        return -6; // 缓冲区太小
+                    }
+                    if memory.write(&mut caller, out_ptr as usize, &info).is_err() {
+                        return -7;
+                    }
+                    info.len() as i32
+                },
+            )
+            .unwrap();
+
+        // Host Function: agw_get_attribute
+        // (key_ptr, key_len, out_ptr, out_max) -> i32
+        // 读取内置过滤器写入的请求属性 (如 "jwt.sub")。
+        // 返回写入的字节数；属性不存在返回 -4 (与空字符串区分开)，Buffer 太小返回 -6。
+        linker
+            .func_wrap(
+                "env",
+                "agw_get_attribute",
+                |mut caller: Caller<'_, WasmContext>,
+                 key_ptr: i32,
+                 key_len: i32,
+                 out_ptr: i32,
+                 out_max: i32|
+                 -> i32 {
+                    let memory = match caller.get_export("memory") {
+                        Some(Extern::Memory(mem)) => mem,
+                        _ => return -1,
+                    };
+                    let key = {
+                        let mut buf = vec![0u8; key_len as usize];
+                        if memory.read(&caller, key_ptr as usize, &mut buf).is_err() {
+                            return -1;
+                        }
+                        match String::from_utf8(buf) {
+                            Ok(k) => k,
+                            Err(_) => return -1,
+                        }
+                    };
+                    let value = match caller.data().attributes.get(&key) {
+                        Some(v) => v.clone(),
+                        None => return -4,
+                    };
+                    if value.len() > out_max as usize {
+                        return -6;
+                    }
+                    if memory
+                        .write(&mut caller, out_ptr as usize, value.as_bytes())
+                        .is_err()
+                    {
+                        return -7;
+                    }
+                    value.len() as i32
+                },
+            )
+            .unwrap();
+
+        // Host Function: agw_get_body
+        // (out_ptr, out_max) -> i32
+        // 读取请求体 (只有导入了这个函数的插件才会让网关提前读请求体，见 main.rs read_body_for_plugins)。
+        // 返回写入的字节数；请求体不可用 (chunked、超过 AGW_PLUGIN_MAX_BODY_BYTES) 返回 -4，Buffer 太小返回 -6。
+        linker
+            .func_wrap(
+                "env",
+                "agw_get_body",
+                |mut caller: Caller<'_, WasmContext>, out_ptr: i32, out_max: i32| -> i32 {
+                    let memory = match caller.get_export("memory") {
+                        Some(Extern::Memory(mem)) => mem,
+                        _ => return -1,
+                    };
+                    let Some(body) = caller.data().body_bytes.clone() else {
+                        return -4;
+                    };
+                    if body.len() > out_max as usize {
+                        return -6;
+                    }
+                    if memory.write(&mut caller, out_ptr as usize, &body).is_err() {
+                        return -7;
+                    }
+                    body.len() as i32
+                },
+            )
+            .unwrap();
+
         // Host Function: agw_redis_command
         // (name_ptr, name_len, cmd_ptr, cmd_len, out_ptr, out_max) -> i32
         linker
@@ -340,14 +687,170 @@ impl WasmRuntime {
             )
             .unwrap();
 
+        // Host Function: agw_http_fetch
+        // (req_ptr, req_len, out_ptr, out_max) -> i32
+        // 请求和结果都是 JSON (见 wasm/fetch.rs)。请求本身失败 (超时、连不上、主机不在白名单) 时
+        // 仍然返回写入的字节数，结果为 {"error": "..."}；负数只表示调用方式的问题。
+        linker
+            .func_wrap4_async(
+                "env",
+                "agw_http_fetch",
+                |mut caller: Caller<'_, WasmContext>,
+                 req_ptr: i32,
+                 req_len: i32,
+                 out_ptr: i32,
+                 out_max: i32| {
+                    Box::new(async move {
+                        let mem = match caller.get_export("memory") {
+                            Some(Extern::Memory(mem)) => mem,
+                            _ => return Ok(-1),
+                        };
+                        let request: fetch::FetchRequest = {
+                            let mut buf = vec![0u8; req_len as usize];
+                            if mem.read(&caller, req_ptr as usize, &mut buf).is_err() {
+                                return Ok(-1);
+                            }
+                            match serde_json::from_slice(&buf) {
+                                Ok(request) => request,
+                                Err(_) => return Ok(-3),
+                            }
+                        };
+                        let Some(client) = caller.data().resources.http.clone() else {
+                            return Ok(-4);
+                        };
+                        let result = client.fetch(&request).await;
+                        let resp_bytes = serde_json::to_vec(&result).unwrap_or_default();
+                        if resp_bytes.len() > out_max as usize {
+                            return Ok(-6);
+                        }
+                        if mem
+                            .write(&mut caller, out_ptr as usize, &resp_bytes)
+                            .is_err()
+                        {
+                            return Ok(-7);
+                        }
+                        Ok(resp_bytes.len() as i32)
+                    })
+                },
+            )
+            .unwrap();
+
+        // Host Function: agw_ratelimit
+        // (bucket_ptr, bucket_len, capacity: i64, refill_per_sec: i64, tokens: i64) -> i32
+        // 进程内令牌桶 (见 wasm/ratelimit.rs)：返回 1 = 令牌已扣除，放行；0 = 令牌不足，应当拒绝。
+        // 参数非法 (桶名为空或过长、capacity 为 0、tokens 超过 capacity、负数) 或桶数量到达上限时返回 -3。
+        linker
+            .func_wrap(
+                "env",
+                "agw_ratelimit",
+                |mut caller: Caller<'_, WasmContext>,
+                 bucket_ptr: i32,
+                 bucket_len: i32,
+                 capacity: i64,
+                 refill_per_sec: i64,
+                 tokens: i64|
+                 -> i32 {
+                    let memory = match caller.get_export("memory") {
+                        Some(Extern::Memory(mem)) => mem,
+                        _ => return -1,
+                    };
+                    let bucket = {
+                        let mut buf = vec![0u8; bucket_len as usize];
+                        if memory.read(&caller, bucket_ptr as usize, &mut buf).is_err() {
+                            return -1;
+                        }
+                        match String::from_utf8(buf) {
+                            Ok(b) => b,
+                            Err(_) => return -1,
+                        }
+                    };
+                    let (Ok(capacity), Ok(refill_per_sec), Ok(tokens)) = (
+                        u64::try_from(capacity),
+                        u64::try_from(refill_per_sec),
+                        u64::try_from(tokens),
+                    ) else {
+                        return -3;
+                    };
+                    match caller.data().resources.ratelimit.check_and_consume(
+                        &bucket,
+                        capacity,
+                        refill_per_sec,
+                        tokens,
+                    ) {
+                        Ok(allowed) => allowed as i32,
+                        Err(e) => {
+                            eprintln!("agw_ratelimit({:?}): {}", bucket, e);
+                            -3
+                        }
+                    }
+                },
+            )
+            .unwrap();
+
+        // Host Function: agw_set_response
+        // (status, headers_ptr, headers_len, body_ptr, body_len) -> i32
+        // 登记 on_request 返回拒绝时的响应 (见 wasm/response.rs)，响应头为 JSON 数组 [["name", "value"]]。
+        // 返回 0 = 已登记；参数非法 (状态码、响应头、响应体过大) 返回 -3。
+        linker
+            .func_wrap(
+                "env",
+                "agw_set_response",
+                |mut caller: Caller<'_, WasmContext>,
+                 status: i32,
+                 headers_ptr: i32,
+                 headers_len: i32,
+                 body_ptr: i32,
+                 body_len: i32|
+                 -> i32 {
+                    let memory = match caller.get_export("memory") {
+                        Some(Extern::Memory(mem)) => mem,
+                        _ => return -1,
+                    };
+                    let mut headers = vec![0u8; headers_len as usize];
+                    let mut body = vec![0u8; body_len as usize];
+                    if memory
+                        .read(&caller, headers_ptr as usize, &mut headers)
+                        .is_err()
+                        || memory.read(&caller, body_ptr as usize, &mut body).is_err()
+                    {
+                        return -1;
+                    }
+                    match DenyResponse::parse(status, &headers, body) {
+                        Ok(response) => {
+                            caller.data_mut().deny_response = Some(response);
+                            0
+                        }
+                        Err(e) => {
+                            eprintln!("agw_set_response: {}", e);
+                            -3
+                        }
+                    }
+                },
+            )
+            .unwrap();
+
         Self {
             engine,
             modules: Arc::new(RwLock::new(HashMap::new())),
+            loaded_versions: Arc::default(),
             linker,
-            resources,
+            resources: Arc::new(ArcSwap::from_pointee(resources)),
+            runtime_info: Arc::new(ArcSwap::from_pointee(b"{}".to_vec())),
+            pool: Arc::new(pool),
         }
     }
 
+    // 更新插件可见的 runtime-info (启动时以及每次应用新配置后调用)
+    pub fn set_runtime_info(&self, info: &RuntimeInfo) {
+        let json = serde_json::to_vec(info).unwrap_or_else(|_| b"{}".to_vec());
+        self.runtime_info.store(Arc::new(json));
+    }
+
+    // 替换插件可见的外部资源 (Redis/DB 连接池)
+    pub fn set_resources(&self, resources: ExternalResources) {
+        self.resources.store(Arc::new(resources));
+    }
+
     // Get or load a module from path
     pub fn get_module(&self, path: &str) -> Result<Module> {
         // Read lock first
@@ -358,13 +861,7 @@ impl WasmRuntime {
             }
         }
 
-        // Load from disk
-        // Note: verify path security in real world!
-        if !Path::new(path).exists() {
-            return Err(Error::msg(format!("Wasm file not found: {}", path)));
-        }
-
-        let module = Module::from_file(&self.engine, path)?;
+        let module = self.compile_module(path)?;
 
         // Write lock to cache
         {
@@ -375,25 +872,167 @@ impl WasmRuntime {
         Ok(module)
     }
 
-    // Execute the plugin. Returns true if request should continue (Allow), false if Deny.
+    // 【插件热更新 (Hot Reload)】
+    // 模块按路径缓存，同一路径的文件换了内容网关也看不到。配置里插件的 version 变化时 (见 main.rs 的 reload_plugins)
+    // 调用这里：先在调用方的 blocking 线程上编译新文件 (预热)，成功后替换缓存里的模块，再让实例池丢弃旧模块的实例。
+    // 替换之前的请求继续用旧模块执行完。编译失败时保留旧模块继续服务 (和证书轮换失败时保留旧证书一样)，
+    // 错误返回给调用方，记到 /status 的 plugin_reload_failures 里，直到同一路径重新加载成功。
+    pub fn reload_module(&self, path: &str, version: &str) -> Result<()> {
+        let module = self.compile_module(path)?;
+        self.modules
+            .write()
+            .unwrap()
+            .insert(path.to_string(), module);
+        self.pool.evict(path);
+        self.loaded_versions
+            .write()
+            .unwrap()
+            .insert(path.to_string(), version.to_string());
+        Ok(())
+    }
+
+    // 快照里 version 和已加载版本不同的插件逐个重新加载，编译放在 blocking 线程上，不占用 worker。
+    // 比较的对象是运行时里实际加载成功的 version，而不是上一份快照：上一次应用没做完或者编译失败时，
+    // 下一份快照仍然会重新加载，版本更新不会因此被永久跳过。
+    // 返回每个插件的 (路径, 新 version, 结果)。没有写 version 的插件不会重新加载。
+    pub async fn reload_changed(
+        &self,
+        active: &ConfigSnapshot,
+    ) -> Vec<(String, String, Result<()>)> {
+        let mut reloads = Vec::new();
+        for (path, version) in plugin_versions(active) {
+            let loaded = self.loaded_versions.read().unwrap().get(path).cloned();
+            if loaded.as_deref().unwrap_or_default() == version {
+                continue;
+            }
+            let wasm = self.clone();
+            let (owned_path, owned_version) = (path.to_string(), version.to_string());
+            let result = tokio::task::spawn_blocking(move || {
+                wasm.reload_module(&owned_path, &owned_version)
+            })
+            .await
+            .unwrap_or_else(|e| Err(Error::msg(format!("reload task failed: {}", e))));
+            reloads.push((path.to_string(), version.to_string(), result));
+        }
+        reloads
+    }
+
+    // Load and compile a module from disk (not cached)
+    fn compile_module(&self, path: &str) -> Result<Module> {
+        // Note: verify path security in real world!
+        if !Path::new(path).exists() {
+            return Err(Error::msg(format!("Wasm file not found: {}", path)));
+        }
+
+        let module = Module::from_file(&self.engine, path)?;
+        if let (_, Some(reason)) = inspect_module(&module) {
+            return Err(Error::msg(format!("plugin {} rejected: {}", path, reason)));
+        }
+        Ok(module)
+    }
+
+    // 插件是否导入了 agw_get_body (需要网关在执行插件前读完请求体)；加载失败时返回 false，错误留给 run_plugin 报告
+    pub fn wants_body(&self, path: &str) -> bool {
+        self.get_module(path).is_ok_and(|module| {
+            module
+                .imports()
+                .any(|i| i.module() == HOST_MODULE && i.name() == "agw_get_body")
+        })
+    }
+
+    // Execute the plugin.
     // MVP ABI: on_request() -> i32 (0=Allow, 1=Deny)
     // 执行 Wasm 插件的主逻辑
     // 返回值:
-    // - Ok(true):  Allow, 请求继续
-    // - Ok(false): Deny,  请求被拦截
-    // - Err(...):  Error, 插件执行出错
-    pub async fn run_plugin(&self, path: &str, headers: HashMap<String, String>) -> Result<bool> {
-        let module = self.get_module(path)?;
-
+    // - Ok(Allow):     请求继续
+    // - Ok(Deny(..)):  请求被拦截，带上插件用 agw_set_response 登记的响应 (如果有)
+    // - Err(...):      插件执行出错
+    pub async fn run_plugin(
+        &self,
+        path: &str,
+        limits: PluginLimits,
+        headers: HashMap<String, String>,
+        attributes: Arc<HashMap<String, String>>,
+        body_bytes: Option<Bytes>,
+    ) -> Result<PluginResult> {
         let ctx = WasmContext {
             headers,
-            resources: self.resources.clone(),
+            resources: self.resources.load().as_ref().clone(),
+            runtime_info: self.runtime_info.load_full(),
+            attributes,
+            body_bytes,
+            deny_response: None,
+        };
+        // 截止 epoch 和 fuel 从这里开始算，实例化 (start 函数) 也在限额之内
+        let started = Instant::now();
+
+        // 池里有空闲实例时直接复用，换上这个请求的上下文
+        let PooledInstance {
+            mut store,
+            on_request,
+            generation,
+        } = match self.pool.take(path) {
+            Some(mut instance) => {
+                *instance.store.data_mut() = ctx;
+                instance
+                    .store
+                    .set_epoch_deadline(epoch_ticks(limits.timeout));
+                instance.store.set_fuel(limits.fuel)?;
+                instance
+            }
+            None => self.instantiate(path, ctx, limits).await?,
         };
 
+        // 6. 真正执行 Wasm 代码
+        // call_async() 会非阻塞地执行，允许 Wasm 在调用 Host Function 时 yield
+        let result = match on_request.call_async(&mut store, ()).await {
+            Ok(result) => result,
+            Err(e) if e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) => {
+                return Err(Error::new(PluginTimeout {
+                    timeout: limits.timeout,
+                    elapsed: started.elapsed(),
+                }));
+            }
+            Err(e) if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) => {
+                return Err(Error::new(PluginOutOfFuel { fuel: limits.fuel }));
+            }
+            Err(e) => return Err(e),
+        };
+
+        // 约定：返回 0 表示放行 (Allow)，非 0 表示拦截 (Deny)
+        let outcome = if result == 0 {
+            PluginResult::Allow
+        } else {
+            PluginResult::Deny(store.data_mut().deny_response.take())
+        };
+        self.pool.put(
+            path,
+            PooledInstance {
+                store,
+                on_request,
+                generation,
+            },
+        );
+        Ok(outcome)
+    }
+
+    // 新建一个插件实例 (池里没有空闲实例时)
+    async fn instantiate(
+        &self,
+        path: &str,
+        ctx: WasmContext,
+        limits: PluginLimits,
+    ) -> Result<PooledInstance> {
+        // 代数在读模块之前取：期间模块被热更新替换时，这个实例用完后不会放回池里
+        let generation = self.pool.generation(path);
+        let module = self.get_module(path)?;
+
         // 3. 创建 Store (Wasm 实例的独立“宇宙”)
         // Store 包含了实例的所有运行时状态（内存、全局变量、Table 等），以及我们塞进去的 ctx。
-        // 注意：每个请求的执行都需要一个新的临时 Store，用完即毁。
+        // 注意：Store 和实例绑定在一起，执行成功后整体放回实例池，供后面的请求复用。
         let mut store = Store::new(&self.engine, ctx);
+        store.set_epoch_deadline(epoch_ticks(limits.timeout));
+        store.set_fuel(limits.fuel)?;
 
         // 4. 实例化 (Instantiation)
         // 把“蓝图” (Module) 变成“房子” (Instance)。
@@ -414,12 +1053,240 @@ impl WasmRuntime {
         // 这一步类似于“强类型转换”。如果 Wasm 里确实有这个函数，但它实际上需要传参数，
         // 或者返回的不是 i32，这里就会直接报错，防止后面调用时出现内存错误。
         let on_request = instance.get_typed_func::<(), i32>(&mut store, "on_request")?;
+        Ok(PooledInstance {
+            store,
+            on_request,
+            generation,
+        })
+    }
+}
 
-        // 6. 真正执行 Wasm 代码
-        // call_async() 会非阻塞地执行，允许 Wasm 在调用 Host Function 时 yield
-        let result = on_request.call_async(&mut store, ()).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::agw::config::v1::Plugin;
 
-        // 约定：返回 0 表示放行 (Allow)，非 0 表示拦截 (Deny)
-        Ok(result == 0)
+    const ALLOW_WAT: &str = r#"(module (func (export "on_request") (result i32) (i32.const 0)))"#;
+    const DENY_WAT: &str = r#"(module (func (export "on_request") (result i32) (i32.const 1)))"#;
+
+    // 入口死循环的插件：只能靠超时或 fuel 中止
+    const LOOP_WAT: &str = r#"(module
+        (func (export "on_request") (result i32)
+            (loop $spin (br $spin))
+            (i32.const 0)))"#;
+
+    fn write_plugin(name: &str, wat: &str) -> String {
+        let path = std::env::temp_dir().join(format!("agw-{}-{}.wat", name, std::process::id()));
+        std::fs::write(&path, wat).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    async fn run(path: &str, limits: PluginLimits) -> Result<PluginResult> {
+        WasmRuntime::with_pool_size(ExternalResources::default(), 0)
+            .run_plugin(path, limits, HashMap::new(), Arc::default(), None)
+            .await
+    }
+
+    async fn allowed(wasm: &WasmRuntime, path: &str) -> bool {
+        let limits = PluginLimits {
+            timeout: Duration::from_secs(5),
+            fuel: 1_000_000,
+        };
+        match wasm
+            .run_plugin(path, limits, HashMap::new(), Arc::default(), None)
+            .await
+            .unwrap()
+        {
+            PluginResult::Allow => true,
+            PluginResult::Deny(_) => false,
+        }
+    }
+
+    fn plugin_snapshot(path: &str, version: &str) -> ConfigSnapshot {
+        ConfigSnapshot {
+            global_plugins: vec![Plugin {
+                name: "reloaded".to_string(),
+                wasm_path: path.to_string(),
+                version: version.to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn looping_plugin_is_interrupted_as_timeout() {
+        let path = write_plugin("loop-timeout", LOOP_WAT);
+        let limits = PluginLimits {
+            timeout: Duration::from_millis(20),
+            fuel: 1_000_000_000_000,
+        };
+        let started = Instant::now();
+        let err = run(&path, limits).await.unwrap_err();
+
+        let timeout = err.downcast_ref::<PluginTimeout>().expect("PluginTimeout");
+        assert_eq!(timeout.timeout, limits.timeout);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(error_decision(&err), "timeout");
+    }
+
+    #[tokio::test]
+    async fn looping_plugin_runs_out_of_fuel() {
+        let path = write_plugin("loop-fuel", LOOP_WAT);
+        let limits = PluginLimits {
+            timeout: Duration::from_secs(30),
+            fuel: 10_000,
+        };
+        let err = run(&path, limits).await.unwrap_err();
+
+        let out_of_fuel = err
+            .downcast_ref::<PluginOutOfFuel>()
+            .expect("PluginOutOfFuel");
+        assert_eq!(out_of_fuel.fuel, 10_000);
+        assert_eq!(error_decision(&err), "out_of_fuel");
+    }
+
+    #[tokio::test]
+    async fn version_bump_reloads_the_plugin_and_a_bad_file_keeps_the_old_one() {
+        let path = write_plugin("reload", ALLOW_WAT);
+        let wasm = WasmRuntime::with_pool_size(ExternalResources::default(), 4);
+        let v1 = plugin_snapshot(&path, "v1");
+        assert_eq!(wasm.reload_changed(&v1).await.len(), 1);
+        assert!(allowed(&wasm, &path).await);
+
+        // 文件换了但 version 没变：不重新加载，继续用缓存 (和池里) 的旧模块
+        std::fs::write(&path, DENY_WAT).unwrap();
+        assert!(wasm.reload_changed(&v1).await.is_empty());
+        assert!(allowed(&wasm, &path).await);
+
+        let v2 = plugin_snapshot(&path, "v2");
+        let reloads = wasm.reload_changed(&v2).await;
+        assert_eq!(reloads.len(), 1);
+        assert_eq!(
+            (reloads[0].0.as_str(), reloads[0].1.as_str()),
+            (path.as_str(), "v2")
+        );
+        assert!(reloads[0].2.is_ok());
+        assert!(!allowed(&wasm, &path).await);
+        assert!(wasm.reload_changed(&v2).await.is_empty());
+
+        // 新文件编译不过：报错，但 v2 的模块继续服务
+        std::fs::write(&path, "(module (func").unwrap();
+        let v3 = plugin_snapshot(&path, "v3");
+        let reloads = wasm.reload_changed(&v3).await;
+        assert_eq!(reloads.len(), 1);
+        assert!(reloads[0].2.is_err());
+        assert!(!allowed(&wasm, &path).await);
+
+        // 失败的版本没有记为已加载：下一份快照 (version 仍是 v3) 会再试一次，文件修好后生效
+        std::fs::write(&path, ALLOW_WAT).unwrap();
+        let reloads = wasm.reload_changed(&v3).await;
+        assert_eq!(reloads.len(), 1);
+        assert!(reloads[0].2.is_ok());
+        assert!(allowed(&wasm, &path).await);
+    }
+
+    #[tokio::test]
+    async fn a_skipped_snapshot_does_not_hide_a_version_bump() {
+        // v2 的应用没有走到插件热更新 (被取代或失败)，直接来了 v2 之后的下一份快照：比较的是已加载的 v1
+        let path = write_plugin("skipped", ALLOW_WAT);
+        let wasm = WasmRuntime::with_pool_size(ExternalResources::default(), 0);
+        wasm.reload_changed(&plugin_snapshot(&path, "v1")).await;
+        assert!(allowed(&wasm, &path).await);
+
+        std::fs::write(&path, DENY_WAT).unwrap();
+        let next = plugin_snapshot(&path, "v2");
+        assert_eq!(wasm.reload_changed(&next).await.len(), 1);
+        assert!(!allowed(&wasm, &path).await);
+    }
+
+    #[test]
+    fn other_plugin_errors_are_reported_as_error() {
+        assert_eq!(error_decision(&Error::msg("boom")), "error");
+    }
+
+    // 先用 agw_set_response 登记响应，再返回 verdict (0 = 放行，1 = 拒绝)
+    fn set_response_wat(status: u16, headers: &str, body: &str, verdict: i32) -> String {
+        let escape = |s: &str| s.replace('"', "\\\"");
+        format!(
+            r#"(module
+                (import "env" "agw_set_response"
+                    (func $set (param i32 i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (data (i32.const 1024) "{}")
+                (func (export "on_request") (result i32)
+                    (drop (call $set (i32.const {}) (i32.const 0) (i32.const {})
+                                     (i32.const 1024) (i32.const {})))
+                    (i32.const {})))"#,
+            escape(headers),
+            escape(body),
+            status,
+            headers.len(),
+            body.len(),
+            verdict
+        )
+    }
+
+    async fn run_to_result(name: &str, wat: &str) -> PluginResult {
+        let path = write_plugin(name, wat);
+        let limits = PluginLimits {
+            timeout: Duration::from_secs(5),
+            fuel: 1_000_000,
+        };
+        run(&path, limits).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn denied_plugin_answers_with_a_custom_429() {
+        let wat = set_response_wat(
+            429,
+            r#"[["Retry-After","1"],["X-RateLimit-Remaining","0"]]"#,
+            r#"{"error":"slow down"}"#,
+            1,
+        );
+        match run_to_result("custom-429", &wat).await {
+            PluginResult::Deny(Some(response)) => {
+                assert_eq!(response.status, 429);
+                assert_eq!(
+                    response.headers,
+                    [
+                        (
+                            http::header::RETRY_AFTER,
+                            http::HeaderValue::from_static("1")
+                        ),
+                        (
+                            http::HeaderName::from_static("x-ratelimit-remaining"),
+                            http::HeaderValue::from_static("0")
+                        ),
+                    ]
+                );
+                assert_eq!(response.body, r#"{"error":"slow down"}"#);
+            }
+            other => panic!("expected a custom deny response, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_response_is_dropped_when_the_plugin_allows() {
+        let wat = set_response_wat(429, r#"[["Retry-After","1"]]"#, "slow down", 0);
+        assert!(matches!(
+            run_to_result("custom-allow", &wat).await,
+            PluginResult::Allow
+        ));
+    }
+
+    // 参数非法时不登记，拒绝退回默认的 403
+    #[tokio::test]
+    async fn invalid_response_falls_back_to_the_default_deny() {
+        let wat = set_response_wat(429, r#"[["Content-Length","5"]]"#, "", 1);
+        assert!(matches!(
+            run_to_result("custom-invalid", &wat).await,
+            PluginResult::Deny(None)
+        ));
+        assert!(matches!(
+            run_to_result("plain-deny", DENY_WAT).await,
+            PluginResult::Deny(None)
+        ));
     }
 }
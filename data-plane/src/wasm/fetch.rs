@@ -0,0 +1,202 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::client::agw::config::v1::HttpClientConfig;
+
+// 【插件的出站 HTTP 请求 (agw_http_fetch)】
+// 插件可以调用外部接口 (如鉴权服务)，但只能通过这个宿主函数，并且受 resources.http_client 约束：
+// - 没有配置 http_client 时不可用 (宿主函数返回 -4)。
+// - allowed_hosts 非空时只能访问其中的主机 (写 "auth.internal" 或 "auth.internal:8080")。
+// - 整个请求 (建连 + 发送 + 读完响应) 受 timeout_ms 限制，响应超过 max_response_bytes 视为失败。
+//
+// 只支持 http://。请求按 HTTP/1.0 发送 (Connection: close)，上游因此不会用 chunked 编码，
+// 响应体读到连接关闭 (或 Content-Length) 为止，不需要完整的 HTTP 客户端。
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    timeout: Duration,
+    allowed_hosts: Vec<String>,
+    max_response_bytes: usize,
+}
+
+// 插件传入的请求 (JSON)：{"url": "...", "method": "POST", "headers": [["k", "v"]], "body": "..."}
+#[derive(Debug, Deserialize)]
+pub struct FetchRequest {
+    url: String,
+    #[serde(default = "default_method")]
+    method: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+// 写回插件的结果 (JSON)：成功为 {"status": 200, "headers": [...], "body": "..."}，失败为 {"error": "..."}
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum FetchResult {
+    Response {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
+    Error {
+        error: String,
+    },
+}
+
+impl HttpClient {
+    pub fn new(config: &HttpClientConfig) -> Self {
+        Self {
+            timeout: Duration::from_millis(match config.timeout_ms {
+                0 => 2000,
+                ms => ms as u64,
+            }),
+            allowed_hosts: config
+                .allowed_hosts
+                .iter()
+                .map(|h| h.to_ascii_lowercase())
+                .collect(),
+            max_response_bytes: match config.max_response_bytes {
+                0 => 1024 * 1024,
+                n => n as usize,
+            },
+        }
+    }
+
+    pub async fn fetch(&self, request: &FetchRequest) -> FetchResult {
+        let result = match tokio::time::timeout(self.timeout, self.exchange(request)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("timed out after {}ms", self.timeout.as_millis())),
+        };
+        match result {
+            Ok(response) => response,
+            Err(error) => FetchResult::Error { error },
+        }
+    }
+
+    async fn exchange(&self, request: &FetchRequest) -> Result<FetchResult, String> {
+        let uri: http::Uri = request
+            .url
+            .parse()
+            .map_err(|e| format!("invalid url: {}", e))?;
+        if uri.scheme_str() != Some("http") {
+            return Err("only http:// urls are supported".to_string());
+        }
+        let (Some(host), Some(authority)) = (uri.host(), uri.authority()) else {
+            return Err("url has no host".to_string());
+        };
+        let port = uri.port_u16().unwrap_or(80);
+        let authority = authority.as_str().to_ascii_lowercase();
+        if authority.contains('@') {
+            return Err("credentials in the url are not supported".to_string());
+        }
+        if !self.allowed_hosts.is_empty()
+            && !self
+                .allowed_hosts
+                .iter()
+                .any(|h| *h == authority || h.eq_ignore_ascii_case(host))
+        {
+            return Err(format!("host {} is not in allowed_hosts", authority));
+        }
+        let method: http::Method = request
+            .method
+            .to_ascii_uppercase()
+            .parse()
+            .map_err(|_| format!("invalid method {:?}", request.method))?;
+        let path = uri.path_and_query().map_or("/", |p| p.as_str());
+
+        let mut head = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, authority);
+        for (name, value) in &request.headers {
+            let name: http::HeaderName = name
+                .parse()
+                .map_err(|_| format!("invalid header name {:?}", name))?;
+            if name == http::header::HOST
+                || name == http::header::CONTENT_LENGTH
+                || name == http::header::TRANSFER_ENCODING
+                || name == http::header::CONNECTION
+            {
+                return Err(format!("header {} is set by the gateway", name));
+            }
+            let value = http::HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value for header {}", name))?;
+            head.push_str(name.as_str());
+            head.push_str(": ");
+            head.push_str(value.to_str().unwrap_or_default());
+            head.push_str("\r\n");
+        }
+        let body = request.body.as_deref().unwrap_or_default();
+        if request.body.is_some() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut stream = TcpStream::connect((host.trim_matches(['[', ']']), port))
+            .await
+            .map_err(|e| format!("connect {}: {}", authority, e))?;
+        stream
+            .write_all(head.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        stream
+            .write_all(body.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut raw = Vec::new();
+        (&mut stream)
+            .take(self.max_response_bytes as u64 + 1)
+            .read_to_end(&mut raw)
+            .await
+            .map_err(|e| e.to_string())?;
+        if raw.len() > self.max_response_bytes {
+            return Err(format!(
+                "response exceeds {} bytes",
+                self.max_response_bytes
+            ));
+        }
+        parse_response(&raw)
+    }
+}
+
+fn parse_response(raw: &[u8]) -> Result<FetchResult, String> {
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut response = httparse::Response::new(&mut headers);
+    let header_len = match response.parse(raw) {
+        Ok(httparse::Status::Complete(len)) => len,
+        Ok(httparse::Status::Partial) => return Err("incomplete response".to_string()),
+        Err(e) => return Err(format!("malformed response: {}", e)),
+    };
+    let headers: Vec<(String, String)> = response
+        .headers
+        .iter()
+        .map(|h| {
+            (
+                h.name.to_ascii_lowercase(),
+                String::from_utf8_lossy(h.value).into_owned(),
+            )
+        })
+        .collect();
+    let mut body = &raw[header_len..];
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok());
+    if let Some(len) = content_length {
+        if body.len() < len {
+            return Err("connection closed before the end of the body".to_string());
+        }
+        body = &body[..len];
+    }
+    Ok(FetchResult::Response {
+        status: response.code.unwrap_or_default(),
+        headers,
+        body: String::from_utf8_lossy(body).into_owned(),
+    })
+}
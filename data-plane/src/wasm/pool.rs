@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use wasmtime::{Store, TypedFunc};
+
+use super::WasmContext;
+
+// 【插件实例池 (Instance Pool)】
+// 每个请求都重新实例化插件 (分配线性内存、初始化数据段、链接宿主函数) 的开销比插件本身的逻辑还大。
+// 这里按插件路径缓存执行完的实例：下一个请求取出来，换上新的 WasmContext (请求头、请求体、属性) 直接调用 on_request。
+//
+// - 每个插件最多缓存 AGW_PLUGIN_POOL_SIZE 个空闲实例 (默认 16，0 = 不复用，每次都实例化)；
+//   池是空的就新建实例，用完时池已满就直接丢弃，并发高峰过去之后池不会无限增长。
+// - 只有正常返回的实例才放回池里；trap、超时中断的实例状态不可信，直接丢弃。
+// - 复用意味着插件的全局变量和线性内存会留到下一个请求：插件不能假设每次都是全新的内存，
+//   也不能把某个请求的数据留在全局变量里 (下一个请求可能属于另一个用户)。
+// - 插件热更新 (配置里的 version 变化) 替换模块时调用 evict：清空空闲实例并把代数 (generation) 加一。
+//   实例记着创建时的代数，执行中的旧实例用完后代数对不上，直接丢弃，不会混进池里。
+pub struct InstancePool {
+    max_per_plugin: usize,
+    idle: Mutex<HashMap<String, Slot>>,
+}
+
+#[derive(Default)]
+struct Slot {
+    generation: u64,
+    instances: Vec<PooledInstance>,
+}
+
+pub struct PooledInstance {
+    pub store: Store<WasmContext>,
+    pub on_request: TypedFunc<(), i32>,
+    // 创建实例时插件的代数
+    pub generation: u64,
+}
+
+impl InstancePool {
+    pub fn new(max_per_plugin: usize) -> Self {
+        Self {
+            max_per_plugin,
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("AGW_PLUGIN_POOL_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(16),
+        )
+    }
+
+    pub fn take(&self, path: &str) -> Option<PooledInstance> {
+        self.idle.lock().unwrap().get_mut(path)?.instances.pop()
+    }
+
+    // 新建实例前取当前代数 (要在读取模块缓存之前取，见 evict)
+    pub fn generation(&self, path: &str) -> u64 {
+        self.idle
+            .lock()
+            .unwrap()
+            .get(path)
+            .map_or(0, |slot| slot.generation)
+    }
+
+    // 模块已经替换：丢弃空闲实例，之后放回的旧实例也会被丢弃
+    pub fn evict(&self, path: &str) {
+        let mut idle = self.idle.lock().unwrap();
+        let slot = idle.entry(path.to_string()).or_default();
+        slot.generation += 1;
+        slot.instances.clear();
+    }
+
+    // 放回一个执行成功的实例；池已满时丢弃
+    pub fn put(&self, path: &str, mut instance: PooledInstance) {
+        if self.max_per_plugin == 0 {
+            return;
+        }
+        // 空闲实例不持有上一个请求的请求头、请求体和属性 (jwt.sub、client.ip 等)
+        let data = instance.store.data_mut();
+        data.headers = HashMap::new();
+        data.attributes = Arc::default();
+        data.body_bytes = None;
+        data.deny_response = None;
+        let mut idle = self.idle.lock().unwrap();
+        let slot = idle.entry(path.to_string()).or_default();
+        if slot.generation == instance.generation && slot.instances.len() < self.max_per_plugin {
+            slot.instances.push(instance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm::ExternalResources;
+    use bytes::Bytes;
+    use wasmtime::{Engine, Instance, Module};
+
+    const ALLOW_WAT: &str = r#"(module (func (export "on_request") (result i32) (i32.const 0)))"#;
+
+    // 一个带着上一个请求数据的实例
+    fn instance(engine: &Engine, generation: u64) -> PooledInstance {
+        let module = Module::new(engine, ALLOW_WAT).unwrap();
+        let ctx = WasmContext {
+            headers: HashMap::from([("authorization".to_string(), "Bearer t".to_string())]),
+            resources: ExternalResources::default(),
+            runtime_info: Arc::new(Vec::new()),
+            attributes: Arc::new(HashMap::from([(
+                "jwt.sub".to_string(),
+                "alice".to_string(),
+            )])),
+            body_bytes: Some(Bytes::from_static(b"{}")),
+            deny_response: None,
+        };
+        let mut store = Store::new(engine, ctx);
+        let instance = Instance::new(&mut store, &module, &[]).unwrap();
+        let on_request = instance.get_typed_func(&mut store, "on_request").unwrap();
+        PooledInstance {
+            store,
+            on_request,
+            generation,
+        }
+    }
+
+    #[test]
+    fn put_clears_request_state() {
+        let engine = Engine::default();
+        let pool = InstancePool::new(4);
+        pool.put("a.wasm", instance(&engine, 0));
+
+        let mut reused = pool.take("a.wasm").unwrap();
+        let data = reused.store.data();
+        assert!(data.headers.is_empty());
+        assert!(data.attributes.is_empty());
+        assert!(data.body_bytes.is_none());
+        assert_eq!(reused.on_request.call(&mut reused.store, ()).unwrap(), 0);
+        assert!(pool.take("a.wasm").is_none());
+    }
+
+    #[test]
+    fn put_drops_instances_beyond_max_size() {
+        let engine = Engine::default();
+        let pool = InstancePool::new(2);
+        for _ in 0..3 {
+            pool.put("a.wasm", instance(&engine, 0));
+        }
+        assert!(pool.take("a.wasm").is_some());
+        assert!(pool.take("a.wasm").is_some());
+        assert!(pool.take("a.wasm").is_none());
+
+        let disabled = InstancePool::new(0);
+        disabled.put("a.wasm", instance(&engine, 0));
+        assert!(disabled.take("a.wasm").is_none());
+    }
+
+    #[test]
+    fn evict_drops_idle_and_stale_instances() {
+        let engine = Engine::default();
+        let pool = InstancePool::new(4);
+        pool.put("a.wasm", instance(&engine, 0));
+        pool.put("b.wasm", instance(&engine, 0));
+
+        pool.evict("a.wasm");
+        assert_eq!(pool.generation("a.wasm"), 1);
+        assert!(pool.take("a.wasm").is_none());
+        // 其它插件不受影响
+        assert!(pool.take("b.wasm").is_some());
+
+        // 热更新之前创建、之后才执行完的实例不能混进池里
+        pool.put("a.wasm", instance(&engine, 0));
+        assert!(pool.take("a.wasm").is_none());
+        pool.put("a.wasm", instance(&engine, 1));
+        assert!(pool.take("a.wasm").is_some());
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// 【插件的进程内限流 (agw_ratelimit)】
+// redis-demo 用 Redis INCR 做限流，必须先有一个 Redis。多数场景只需要 "每个网关实例各自限流"，
+// 这里提供一个进程内的令牌桶：插件按桶名 (如 "user:123") 调用，
+// 桶容量 capacity、每秒补充 refill_per_sec 个令牌，本次消耗 tokens 个，够就扣掉并放行。
+//
+// - 桶在第一次使用时创建 (装满)，状态只在本数据面内存里，多副本部署时每个副本各算各的。
+// - 同名桶的 capacity / refill_per_sec 以本次调用的参数为准 (插件改了限额不需要重启)。
+// - 桶的数量有上限：满了之后先清掉已经补满的桶 (和新建的桶等价)，仍然满时调用失败。
+//   用请求里的任意值 (如未校验的 Header) 做桶名时，攻击者最多只能让限流失效，撑不爆内存。
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    capacity: u64,
+    refill_per_sec: u64,
+}
+
+const MAX_BUCKETS: usize = 100_000;
+const MAX_BUCKET_NAME: usize = 256;
+
+impl TokenBucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.refill_per_sec as f64).min(self.capacity as f64);
+        self.last_refill = now;
+    }
+}
+
+impl RateLimiter {
+    // 令牌足够时扣除并返回 true，不够时不扣除、返回 false
+    pub fn check_and_consume(
+        &self,
+        bucket: &str,
+        capacity: u64,
+        refill_per_sec: u64,
+        tokens: u64,
+    ) -> Result<bool, String> {
+        if bucket.is_empty() || bucket.len() > MAX_BUCKET_NAME {
+            return Err(format!(
+                "bucket name must be 1 to {} bytes",
+                MAX_BUCKET_NAME
+            ));
+        }
+        if capacity == 0 || tokens > capacity {
+            return Err(format!(
+                "tokens ({}) must not exceed a positive capacity ({})",
+                tokens, capacity
+            ));
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(bucket) && buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, b| {
+                b.refill(now);
+                b.tokens < b.capacity as f64
+            });
+            if buckets.len() >= MAX_BUCKETS {
+                return Err(format!("too many buckets (limit {})", MAX_BUCKETS));
+            }
+        }
+        let entry = buckets
+            .entry(bucket.to_string())
+            .or_insert_with(|| TokenBucket {
+                tokens: capacity as f64,
+                last_refill: now,
+                capacity,
+                refill_per_sec,
+            });
+        entry.capacity = capacity;
+        entry.refill_per_sec = refill_per_sec;
+        entry.refill(now);
+        if entry.tokens < tokens as f64 {
+            return Ok(false);
+        }
+        entry.tokens -= tokens as f64;
+        Ok(true)
+    }
+}
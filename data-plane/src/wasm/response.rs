@@ -0,0 +1,100 @@
+use bytes::Bytes;
+
+// 【插件自定义拒绝响应 (agw_set_response)】
+// on_request 只能返回放行 / 拒绝，拒绝时网关固定回 403 (PLUGIN_DENY)。限流插件想回 429 + Retry-After、
+// 鉴权插件想回 401 + WWW-Authenticate 时，先调用 agw_set_response 登记响应，再让 on_request 返回非 0：
+// - 状态码 200 ~ 599；响应头是 JSON 数组 [["Retry-After", "1"]] (长度 0 表示没有响应头)；响应体最大 64KB。
+// - Content-Length / Transfer-Encoding 由网关计算，插件不能设置。
+// - 多次调用以最后一次为准；on_request 返回 0 (放行) 时登记的响应被丢弃。
+// 链上后面的插件不再执行，访问日志的原因码仍然是 PLUGIN_DENY，状态码为插件给出的状态码。
+#[derive(Debug, Clone)]
+pub struct DenyResponse {
+    pub status: u16,
+    pub headers: Vec<(http::HeaderName, http::HeaderValue)>,
+    pub body: Bytes,
+}
+
+// 插件执行结果
+#[derive(Debug)]
+pub enum PluginResult {
+    Allow,
+    // None = 插件没有登记响应，按默认的 403 处理
+    Deny(Option<DenyResponse>),
+}
+
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+impl DenyResponse {
+    // headers_json 为空表示没有响应头
+    pub fn parse(status: i32, headers_json: &[u8], body: Vec<u8>) -> Result<Self, String> {
+        let status = match u16::try_from(status) {
+            Ok(code @ 200..=599) => code,
+            _ => return Err(format!("status {} must be between 200 and 599", status)),
+        };
+        if body.len() > MAX_BODY_BYTES {
+            return Err(format!(
+                "body is {} bytes, the limit is {}",
+                body.len(),
+                MAX_BODY_BYTES
+            ));
+        }
+        let pairs: Vec<(String, String)> = if headers_json.is_empty() {
+            Vec::new()
+        } else {
+            serde_json::from_slice(headers_json)
+                .map_err(|e| format!("headers must be a JSON array of [name, value]: {}", e))?
+        };
+        let mut headers = Vec::with_capacity(pairs.len());
+        for (name, value) in pairs {
+            let name: http::HeaderName = name
+                .parse()
+                .map_err(|_| format!("invalid header name {:?}", name))?;
+            if name == http::header::CONTENT_LENGTH || name == http::header::TRANSFER_ENCODING {
+                return Err(format!("header {} is set by the gateway", name));
+            }
+            let value = http::HeaderValue::from_str(&value)
+                .map_err(|_| format!("invalid value for header {}", name))?;
+            headers.push((name, value));
+        }
+        Ok(Self {
+            status,
+            headers,
+            body: Bytes::from(body),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(status: i32, headers: &str, body: &[u8]) -> Result<DenyResponse, String> {
+        DenyResponse::parse(status, headers.as_bytes(), body.to_vec())
+    }
+
+    #[test]
+    fn headers_and_body_are_kept() {
+        let response = parse(429, r#"[["Retry-After", "30"]]"#, b"slow down").unwrap();
+        assert_eq!(response.status, 429);
+        assert_eq!(response.headers.len(), 1);
+        assert_eq!(response.headers[0].0, http::header::RETRY_AFTER);
+        assert_eq!(response.headers[0].1, "30");
+        assert_eq!(response.body, "slow down");
+        assert!(parse(401, "", b"").unwrap().headers.is_empty());
+    }
+
+    #[test]
+    fn invalid_responses_are_rejected() {
+        assert!(parse(199, "", b"").is_err());
+        assert!(parse(600, "", b"").is_err());
+        assert!(parse(429, r#"{"Retry-After": "1"}"#, b"").is_err());
+        assert!(parse(429, r#"[["Bad Header", "1"]]"#, b"").is_err());
+        assert!(parse(429, r#"[["Retry-After", "a\nb"]]"#, b"").is_err());
+        assert_eq!(
+            parse(429, r#"[["Transfer-Encoding", "chunked"]]"#, b"").unwrap_err(),
+            "header transfer-encoding is set by the gateway"
+        );
+        assert!(parse(429, "", &vec![b'x'; MAX_BODY_BYTES + 1]).is_err());
+        assert!(parse(429, "", &vec![b'x'; MAX_BODY_BYTES]).is_ok());
+    }
+}
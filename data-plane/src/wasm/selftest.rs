@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use wasmtime::*;
+
+use super::{ExternalResources, WasmContext, WasmRuntime, epoch_ticks};
+
+// 【内置自检插件 (Self-Test)】
+// 一个直接编译进二进制的小型 WAT 模块，不依赖磁盘上任何 .wasm 文件。
+// 它对每个宿主函数做一次 "安全的" 调用 (不会访问网络或真实数据库)，并返回已知的结果：
+// - agw_get_header:    读取自检专用 Header，期望返回其长度 (2, 即 "ok")
+// - agw_redis_command: 使用一个不存在的实例名，期望返回 -4 (not found)，不会建立连接
+// - agw_db_query:      同上，期望返回 -4
+// - agw_runtime_info:  期望返回正数 (写入的 JSON 长度)
+// - agw_get_attribute: 读取自检专用属性，期望返回其长度 (2, 即 "ok")
+// - agw_get_body:      自检没有请求体，期望返回 -4 (not available)
+// - agw_http_fetch:    传入一个不是请求对象的 JSON，期望返回 -3 (参数错误)，不会发出请求
+// - agw_ratelimit:     capacity 为 0，期望返回 -3 (参数错误)，不会创建令牌桶
+// - agw_set_response:  状态码 0，期望返回 -3 (参数错误)，不会登记响应
+// - on_request:        期望返回 0 (Allow)
+//
+// 这样在 wasmtime 升级或 Host ABI 改动出错时，启动时就能立刻发现，而不是等到第一个真实请求。
+const SELF_TEST_WAT: &str = r#"
+(module
+  (import "env" "agw_get_header" (func $get_header (param i32 i32 i32 i32) (result i32)))
+  (import "env" "agw_redis_command" (func $redis (param i32 i32 i32 i32 i32 i32) (result i32)))
+  (import "env" "agw_db_query" (func $db (param i32 i32 i32 i32 i32 i32) (result i32)))
+  (import "env" "agw_runtime_info" (func $runtime_info (param i32 i32) (result i32)))
+  (import "env" "agw_get_attribute" (func $get_attribute (param i32 i32 i32 i32) (result i32)))
+  (import "env" "agw_get_body" (func $get_body (param i32 i32) (result i32)))
+  (import "env" "agw_http_fetch" (func $http_fetch (param i32 i32 i32 i32) (result i32)))
+  (import "env" "agw_ratelimit" (func $ratelimit (param i32 i32 i64 i64 i64) (result i32)))
+  (import "env" "agw_set_response" (func $set_response (param i32 i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "x-agw-selftest")
+  (data (i32.const 32) "__agw_selftest__")
+  (data (i32.const 64) "[\"PING\"]")
+  (data (i32.const 96) "SELECT 1")
+  (data (i32.const 128) "selftest.attr")
+  (func (export "check_header") (result i32)
+    (call $get_header (i32.const 0) (i32.const 14) (i32.const 256) (i32.const 64)))
+  (func (export "check_redis") (result i32)
+    (call $redis (i32.const 32) (i32.const 16) (i32.const 64) (i32.const 8) (i32.const 256) (i32.const 64)))
+  (func (export "check_db") (result i32)
+    (call $db (i32.const 32) (i32.const 16) (i32.const 96) (i32.const 8) (i32.const 256) (i32.const 64)))
+  (func (export "check_runtime_info") (result i32)
+    (call $runtime_info (i32.const 1024) (i32.const 4096)))
+  (func (export "check_attribute") (result i32)
+    (call $get_attribute (i32.const 128) (i32.const 13) (i32.const 256) (i32.const 64)))
+  (func (export "check_body") (result i32)
+    (call $get_body (i32.const 256) (i32.const 64)))
+  (func (export "check_http_fetch") (result i32)
+    (call $http_fetch (i32.const 64) (i32.const 8) (i32.const 256) (i32.const 64)))
+  (func (export "check_ratelimit") (result i32)
+    (call $ratelimit (i32.const 32) (i32.const 16) (i64.const 0) (i64.const 1) (i64.const 1)))
+  (func (export "check_set_response") (result i32)
+    (call $set_response (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)))
+  (func (export "on_request") (result i32)
+    (i32.const 0))
+)
+"#;
+
+// 自检调用都是立即返回的，给足余量，只防止 wasmtime 出问题时挂住启动
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(5);
+const SELF_TEST_FUEL: u64 = 1_000_000_000;
+
+const SELF_TEST_HEADER: &str = "x-agw-selftest";
+const SELF_TEST_ATTRIBUTE: &str = "selftest.attr";
+
+enum Expect {
+    Eq(i32),
+    Positive,
+}
+
+// (导出函数名, 对应的宿主接口, 期望返回值)
+const CHECKS: &[(&str, &str, Expect)] = &[
+    ("check_header", "agw_get_header", Expect::Eq(2)),
+    ("check_redis", "agw_redis_command", Expect::Eq(-4)),
+    ("check_db", "agw_db_query", Expect::Eq(-4)),
+    ("check_runtime_info", "agw_runtime_info", Expect::Positive),
+    ("check_attribute", "agw_get_attribute", Expect::Eq(2)),
+    ("check_body", "agw_get_body", Expect::Eq(-4)),
+    ("check_http_fetch", "agw_http_fetch", Expect::Eq(-3)),
+    ("check_ratelimit", "agw_ratelimit", Expect::Eq(-3)),
+    ("check_set_response", "agw_set_response", Expect::Eq(-3)),
+    ("on_request", "on_request", Expect::Eq(0)),
+];
+
+#[derive(Debug, Serialize)]
+pub struct SelfTestCheck {
+    pub interface: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl WasmRuntime {
+    // 运行内置自检插件，逐个接口报告 pass/fail
+    pub async fn self_test(&self) -> SelfTestReport {
+        let mut checks = Vec::new();
+
+        let module = match Module::new(&self.engine, SELF_TEST_WAT) {
+            Ok(m) => m,
+            Err(e) => {
+                checks.push(SelfTestCheck {
+                    interface: "compile",
+                    passed: false,
+                    detail: e.to_string(),
+                });
+                return SelfTestReport {
+                    passed: false,
+                    checks,
+                };
+            }
+        };
+
+        for (export, interface, expected) in CHECKS {
+            let detail = match self.call_self_test_export(&module, export).await {
+                Ok(got) => match expected {
+                    Expect::Eq(want) if got == *want => Ok(format!("returned {}", got)),
+                    Expect::Eq(want) => Err(format!("expected {}, got {}", want, got)),
+                    Expect::Positive if got > 0 => Ok(format!("returned {}", got)),
+                    Expect::Positive => Err(format!("expected > 0, got {}", got)),
+                },
+                Err(e) => Err(e.to_string()),
+            };
+            checks.push(SelfTestCheck {
+                interface: *interface,
+                passed: detail.is_ok(),
+                detail: detail.unwrap_or_else(|e| e),
+            });
+        }
+
+        SelfTestReport {
+            passed: checks.iter().all(|c| c.passed),
+            checks,
+        }
+    }
+
+    // 每个检查都使用全新的 Store，且外部资源为空 (保证不会触碰真实的 Redis/DB)
+    async fn call_self_test_export(&self, module: &Module, export: &str) -> Result<i32> {
+        let mut headers = HashMap::new();
+        headers.insert(SELF_TEST_HEADER.to_string(), "ok".to_string());
+        let mut attributes = HashMap::new();
+        attributes.insert(SELF_TEST_ATTRIBUTE.to_string(), "ok".to_string());
+        let ctx = WasmContext {
+            headers,
+            resources: ExternalResources::default(),
+            runtime_info: self.runtime_info.load_full(),
+            attributes: Arc::new(attributes),
+            body_bytes: None,
+            deny_response: None,
+        };
+        let mut store = Store::new(&self.engine, ctx);
+        store.set_epoch_deadline(epoch_ticks(SELF_TEST_TIMEOUT));
+        store.set_fuel(SELF_TEST_FUEL)?;
+        let instance = self.linker.instantiate_async(&mut store, module).await?;
+        let func = instance.get_typed_func::<(), i32>(&mut store, export)?;
+        func.call_async(&mut store, ()).await
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+// 【资源看门狗 (Resource Watchdog)】
+// 与其在请求处理中途被 OOM Killer 杀掉，不如在接近资源上限时主动降级自保。
+// 后台定期采样进程的 RSS 和打开的文件描述符数量，与软/硬两档预算比较：
+// - 超过软预算 (Soft): 关闭可选的内存消耗者 —— 响应缓存不再写入新条目、最近请求环形缓冲停止记录；
+// - 超过硬预算 (Hard): 额外开始拒绝新请求 (503 OVERLOADED)，直到回落到软预算以下才恢复 (滞回，避免抖动)。
+// 当前生效的降级项可以在管理端口 /healthz 的响应体里看到。
+//
+// 预算通过环境变量配置，未配置 (0) 的维度不参与判断；全部未配置时看门狗不启动。
+pub struct Watchdog {
+    budgets: Budgets,
+    pressure: AtomicU8,
+    // 最近一次进入当前状态的原因 (哪个维度超了预算)
+    reason: RwLock<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Budgets {
+    pub rss_soft_bytes: u64,
+    pub rss_hard_bytes: u64,
+    pub fds_soft: u64,
+    pub fds_hard: u64,
+    pub interval: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pressure {
+    Normal = 0,
+    Soft = 1,
+    Hard = 2,
+}
+
+// 一次采样结果；拿不到的维度为 None
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ResourceUsage {
+    pub rss_bytes: Option<u64>,
+    pub open_fds: Option<u64>,
+}
+
+// 采样器可替换，方便模拟各种资源水位
+pub trait ResourceSampler: Send + Sync {
+    fn sample(&self) -> ResourceUsage;
+}
+
+// 从 /proc/self 读取 (仅 Linux，其他平台返回空采样)
+pub struct ProcSampler;
+
+impl ResourceSampler for ProcSampler {
+    fn sample(&self) -> ResourceUsage {
+        let rss_bytes = std::fs::read_to_string("/proc/self/statm")
+            .ok()
+            .and_then(|s| s.split_whitespace().nth(1)?.parse::<u64>().ok())
+            .map(|pages| pages * page_size());
+        let open_fds = std::fs::read_dir("/proc/self/fd")
+            .ok()
+            .map(|dir| dir.count() as u64);
+        ResourceUsage {
+            rss_bytes,
+            open_fds,
+        }
+    }
+}
+
+impl Budgets {
+    pub fn from_env() -> Self {
+        let mb = |key: &str| env_u64(key) * 1024 * 1024;
+        Self {
+            rss_soft_bytes: mb("AGW_MEMORY_SOFT_MB"),
+            rss_hard_bytes: mb("AGW_MEMORY_HARD_MB"),
+            fds_soft: env_u64("AGW_FD_SOFT"),
+            fds_hard: env_u64("AGW_FD_HARD"),
+            interval: Duration::from_secs(match env_u64("AGW_WATCHDOG_INTERVAL_SECS") {
+                0 => 5,
+                secs => secs,
+            }),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.rss_soft_bytes > 0 || self.rss_hard_bytes > 0 || self.fds_soft > 0 || self.fds_hard > 0
+    }
+}
+
+impl Watchdog {
+    pub fn new(budgets: Budgets) -> Self {
+        Self {
+            budgets,
+            pressure: AtomicU8::new(Pressure::Normal as u8),
+            reason: RwLock::new(String::new()),
+        }
+    }
+
+    pub fn pressure(&self) -> Pressure {
+        match self.pressure.load(Ordering::Relaxed) {
+            2 => Pressure::Hard,
+            1 => Pressure::Soft,
+            _ => Pressure::Normal,
+        }
+    }
+
+    // 可选的内存消耗者 (缓存写入、最近请求记录) 是否允许工作
+    pub fn allow_optional(&self) -> bool {
+        self.pressure() == Pressure::Normal
+    }
+
+    // 是否正在拒绝新请求
+    pub fn shedding(&self) -> bool {
+        self.pressure() == Pressure::Hard
+    }
+
+    // 当前生效的降级项 (按生效顺序)
+    pub fn degradations(&self) -> Vec<&'static str> {
+        let mut active = Vec::new();
+        if self.pressure() >= Pressure::Soft {
+            active.push("response_cache_fill_disabled");
+            active.push("recent_requests_disabled");
+        }
+        if self.pressure() >= Pressure::Hard {
+            active.push("shedding_new_requests");
+        }
+        active
+    }
+
+    pub fn reason(&self) -> String {
+        self.reason.read().unwrap().clone()
+    }
+
+    // 根据一次采样推进状态机
+    pub fn observe(&self, usage: ResourceUsage) {
+        let b = &self.budgets;
+        let over = |value: Option<u64>, budget: u64| budget > 0 && value.is_some_and(|v| v >= budget);
+        let hard = if over(usage.rss_bytes, b.rss_hard_bytes) {
+            Some(format!("rss {} >= hard budget {}", usage.rss_bytes.unwrap_or(0), b.rss_hard_bytes))
+        } else if over(usage.open_fds, b.fds_hard) {
+            Some(format!("open fds {} >= hard budget {}", usage.open_fds.unwrap_or(0), b.fds_hard))
+        } else {
+            None
+        };
+        let soft = if over(usage.rss_bytes, b.rss_soft_bytes) {
+            Some(format!("rss {} >= soft budget {}", usage.rss_bytes.unwrap_or(0), b.rss_soft_bytes))
+        } else if over(usage.open_fds, b.fds_soft) {
+            Some(format!("open fds {} >= soft budget {}", usage.open_fds.unwrap_or(0), b.fds_soft))
+        } else {
+            None
+        };
+
+        let current = self.pressure();
+        let (next, reason) = match (hard, soft) {
+            (Some(reason), _) => (Pressure::Hard, reason),
+            // 硬阈值触发后，只有回落到软阈值以下才解除拒绝
+            (None, Some(reason)) if current == Pressure::Hard => (Pressure::Hard, reason),
+            (None, Some(reason)) => (Pressure::Soft, reason),
+            (None, None) => (Pressure::Normal, String::new()),
+        };
+        if next != current {
+            self.pressure.store(next as u8, Ordering::Relaxed);
+            println!(
+                "Resource watchdog: {:?} -> {:?} ({}) degradations={:?}",
+                current,
+                next,
+                if reason.is_empty() { "recovered" } else { reason.as_str() },
+                self.degradations()
+            );
+        }
+        *self.reason.write().unwrap() = reason;
+    }
+
+    // 后台采样循环；没有配置任何预算时直接返回
+    pub async fn run(self: Arc<Self>, sampler: Box<dyn ResourceSampler>) {
+        if !self.budgets.enabled() {
+            return;
+        }
+        loop {
+            self.observe(sampler.sample());
+            tokio::time::sleep(self.budgets.interval).await;
+        }
+    }
+}
+
+fn env_u64(key: &str) -> u64 {
+    std::env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+#[cfg(target_os = "linux")]
+fn page_size() -> u64 {
+    // SAFETY: sysconf 只读取系统常量
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn page_size() -> u64 {
+    4096
+}
@@ -0,0 +1,71 @@
+#[link(wasm_import_module = "env")]
+extern "C" {
+    fn agw_get_header(
+        name_ptr: *const u8,
+        name_len: usize,
+        value_ptr: *mut u8,
+        value_max_len: usize,
+    ) -> i32;
+
+    fn agw_http_fetch(req_ptr: *const u8, req_len: usize, out_ptr: *mut u8, out_max: usize) -> i32;
+}
+
+// External auth service; the gateway's resources.http_client must allow this host.
+const AUTH_URL: &str = "http://auth-service:8080/check";
+
+#[no_mangle]
+pub fn on_request() -> i32 {
+    // 1. No credentials -> Deny without calling the auth service
+    let authorization = get_header("authorization");
+    if authorization.is_empty() {
+        return 1;
+    }
+
+    // 2. Ask the auth service: GET /check with the client's Authorization header
+    let request = format!(
+        "{{\"url\": \"{}\", \"method\": \"GET\", \"headers\": [[\"authorization\", \"{}\"]]}}",
+        AUTH_URL,
+        json_escape(&authorization)
+    );
+
+    // 3. Allow only on a 2xx answer. A failed call ({"error": ...}) or any other
+    //    status denies the request (fail closed).
+    match http_fetch(&request) {
+        Ok(result) if result.starts_with("{\"status\":2") => 0,
+        _ => 1,
+    }
+}
+
+fn get_header(name: &str) -> String {
+    let mut buf = [0u8; 1024];
+    let len = unsafe { agw_get_header(name.as_ptr(), name.len(), buf.as_mut_ptr(), buf.len()) };
+    if len > 0 {
+        String::from_utf8_lossy(&buf[..len as usize]).to_string()
+    } else {
+        String::new()
+    }
+}
+
+fn http_fetch(request: &str) -> Result<String, String> {
+    let mut buf = vec![0u8; 16 * 1024];
+    let len =
+        unsafe { agw_http_fetch(request.as_ptr(), request.len(), buf.as_mut_ptr(), buf.len()) };
+    if len >= 0 {
+        Ok(String::from_utf8_lossy(&buf[..len as usize]).to_string())
+    } else {
+        Err(format!("Error code: {}", len))
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
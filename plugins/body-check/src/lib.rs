@@ -0,0 +1,66 @@
+// 校验请求体：必须是一个带 "user_id" 字段的 JSON 对象，否则拒绝。
+// 没有请求体 (GET 等) 的请求直接放行；请求体拿不到 (chunked 或超过 AGW_PLUGIN_MAX_BODY_BYTES) 时拒绝，
+// 校验不了的请求不能当作校验通过。
+#[link(wasm_import_module = "env")]
+extern "C" {
+    fn agw_get_body(out_ptr: *mut u8, out_max: usize) -> i32;
+}
+
+const REQUIRED_FIELD: &str = "user_id";
+
+#[no_mangle]
+pub fn on_request() -> i32 {
+    let mut buf = vec![0u8; 64 * 1024];
+    let len = unsafe { agw_get_body(buf.as_mut_ptr(), buf.len()) };
+    if len < 0 {
+        return 1; // 请求体不可用 -> Deny
+    }
+    let body = &buf[..len as usize];
+    if body.is_empty() {
+        return 0;
+    }
+    match top_level_keys(body) {
+        Some(keys) if keys.iter().any(|k| k == REQUIRED_FIELD) => 0,
+        _ => 1, // 不是 JSON 对象，或缺少必填字段 -> Deny
+    }
+}
+
+// 取出 JSON 对象的顶层 key (不解析值，只跟踪字符串和嵌套层级)。
+// 不是对象或者括号不匹配时返回 None。
+fn top_level_keys(body: &[u8]) -> Option<Vec<String>> {
+    let text = std::str::from_utf8(body).ok()?.trim();
+    if !text.starts_with('{') || !text.ends_with('}') {
+        return None;
+    }
+    let mut keys = Vec::new();
+    let mut depth = 0usize;
+    // 顶层期待下一个字符串是 key (在 '{' 或 ',' 之后)
+    let mut expect_key = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '[' => {
+                depth += 1;
+                expect_key = depth == 1 && c == '{';
+            }
+            '}' | ']' => depth = depth.checked_sub(1)?,
+            ',' => expect_key = depth == 1,
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => s.push(chars.next()?),
+                        other => s.push(other),
+                    }
+                }
+                if expect_key {
+                    keys.push(s);
+                    expect_key = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    (depth == 0).then_some(keys)
+}
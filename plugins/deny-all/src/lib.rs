@@ -9,6 +9,15 @@ extern "C" {
         value_ptr: *mut u8,   // 结果指针 (Buffer, mut)
         value_max_len: usize, // 结果 Buffer 最大容量
     ) -> i32; // 返回实际读到的长度
+
+    // 登记拒绝时返回的响应 (状态码、JSON 数组格式的响应头、响应体)，不调用时网关默认返回 403
+    fn agw_set_response(
+        status: i32,
+        headers_ptr: *const u8,
+        headers_len: usize,
+        body_ptr: *const u8,
+        body_len: usize,
+    ) -> i32;
 }
 
 // 声明插件入口函数
@@ -38,6 +47,18 @@ pub extern "C" fn on_request() -> i32 {
         if let Ok(value_str) = std::str::from_utf8(value) {
             // 业务逻辑：如果 User-Agent 包含 "curl"，就拦截
             if value_str.contains("curl") {
+                // 告诉客户端为什么被拦截，而不是一个没有说明的 403
+                let headers = r#"[["Content-Type", "text/plain; charset=utf-8"]]"#;
+                let body = "curl is not allowed on this route\n";
+                unsafe {
+                    agw_set_response(
+                        403,
+                        headers.as_ptr(),
+                        headers.len(),
+                        body.as_ptr(),
+                        body.len(),
+                    );
+                }
                 return 1; // 返回 1 表示 Deny (拦截)
             }
         }
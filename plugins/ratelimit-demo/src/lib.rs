@@ -0,0 +1,48 @@
+// 按 X-User-ID 做进程内限流：每个用户的桶容量 10，每秒补充 5 个令牌。
+// 和 redis-demo 的思路一样，但不需要 Redis (限额按网关实例分别计算)。
+#[link(wasm_import_module = "env")]
+extern "C" {
+    fn agw_get_header(
+        name_ptr: *const u8,
+        name_len: usize,
+        value_ptr: *mut u8,
+        value_max_len: usize,
+    ) -> i32;
+
+    fn agw_ratelimit(
+        bucket_ptr: *const u8,
+        bucket_len: usize,
+        capacity: i64,
+        refill_per_sec: i64,
+        tokens: i64,
+    ) -> i32;
+}
+
+const CAPACITY: i64 = 10;
+const REFILL_PER_SEC: i64 = 5;
+
+#[no_mangle]
+pub fn on_request() -> i32 {
+    let user_id = get_header("x-user-id");
+    if user_id.is_empty() {
+        return 0; // Allow if no user id
+    }
+
+    let bucket = format!("ratelimit-demo:{}", user_id);
+    let result =
+        unsafe { agw_ratelimit(bucket.as_ptr(), bucket.len(), CAPACITY, REFILL_PER_SEC, 1) };
+    match result {
+        0 => 1, // 令牌用完 -> Deny
+        _ => 0, // 放行；参数错误 (负数) 时也放行，限流失效好过拒绝所有请求
+    }
+}
+
+fn get_header(name: &str) -> String {
+    let mut buf = [0u8; 128];
+    let len = unsafe { agw_get_header(name.as_ptr(), name.len(), buf.as_mut_ptr(), buf.len()) };
+    if len > 0 {
+        String::from_utf8_lossy(&buf[..len as usize]).to_string()
+    } else {
+        String::new()
+    }
+}
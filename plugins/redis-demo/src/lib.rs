@@ -9,6 +9,13 @@ extern "C" {
         value_max_len: usize,
     ) -> i32;
 
+    fn agw_get_attribute(
+        key_ptr: *const u8,
+        key_len: usize,
+        value_ptr: *mut u8,
+        value_max_len: usize,
+    ) -> i32;
+
     fn agw_redis_command(
         name_ptr: *const u8,
         name_len: usize,
@@ -17,8 +24,23 @@ extern "C" {
         out_ptr: *mut u8,
         out_max: usize,
     ) -> i32;
+
+    fn agw_set_response(
+        status: i32,
+        headers_ptr: *const u8,
+        headers_len: usize,
+        body_ptr: *const u8,
+        body_len: usize,
+    ) -> i32;
 }
 
+// 超过限额时的响应：429 + Retry-After，客户端据此退避
+const LIMITED_HEADERS: &str = r#"[["Retry-After", "1"], ["Content-Type", "application/json"]]"#;
+const LIMITED_BODY: &str = r#"{"error": "rate limit exceeded"}"#;
+
+// 路由 metadata 没有配置 rate_limit (或不是数字) 时的限额
+const DEFAULT_LIMIT: i32 = 5;
+
 #[no_mangle]
 pub fn on_request() -> i32 {
     // 1. Get Header "X-User-ID"
@@ -40,10 +62,22 @@ pub fn on_request() -> i32 {
     // -> redis::cmd(&args[0]) [对应 wasm.rs:188!]
     let result = redis_command(redis_name, &cmd_json);
 
-    // 3. Check limit (e.g. > 5)
+    // 3. Check limit: 路由 metadata 的 rate_limit，同一个插件挂在不同路由上可以有不同的限额
+    let limit = get_attribute("route.metadata.rate_limit")
+        .and_then(|v| v.trim().parse::<i32>().ok())
+        .unwrap_or(DEFAULT_LIMIT);
     if let Ok(count_str) = result {
         if let Ok(count) = count_str.trim().parse::<i32>() {
-            if count > 5 {
+            if count > limit {
+                unsafe {
+                    agw_set_response(
+                        429,
+                        LIMITED_HEADERS.as_ptr(),
+                        LIMITED_HEADERS.len(),
+                        LIMITED_BODY.as_ptr(),
+                        LIMITED_BODY.len(),
+                    );
+                }
                 return 1; // Deny
             }
         }
@@ -62,6 +96,17 @@ fn get_header(name: &str) -> String {
     }
 }
 
+// 属性不存在 (-4) 或读取失败时返回 None
+fn get_attribute(key: &str) -> Option<String> {
+    let mut buf = [0u8; 128];
+    let len = unsafe { agw_get_attribute(key.as_ptr(), key.len(), buf.as_mut_ptr(), buf.len()) };
+    if len >= 0 {
+        Some(String::from_utf8_lossy(&buf[..len as usize]).to_string())
+    } else {
+        None
+    }
+}
+
 fn redis_command(name: &str, cmd_json: &str) -> Result<String, String> {
     let mut buf = [0u8; 1024];
     let len = unsafe {